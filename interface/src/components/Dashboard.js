@@ -5,16 +5,42 @@ import { CopyToClipboard } from "react-copy-to-clipboard";
 import toast, { Toaster } from "react-hot-toast";
 import { Link, useNavigate } from "react-router-dom";
 import { Button, Grid, Label, Table } from "semantic-ui-react";
+import { web3FromSource } from "@polkadot/extension-dapp";
+import { stringToHex, u8aToHex } from "@polkadot/util";
 import { u8aToHexCompact, useSubstrateState } from "../substrate-lib";
 
 const Dashboard = ({ state }) => {
-  const { apiState, currentAccount } = useSubstrateState();
+  const { apiState, currentAccount, keyring } = useSubstrateState();
   const navigate = useNavigate();
 
   let [transactions, setTransactions] = useState([]);
   let [mutated, setMutated] = useState(true);
   let [bankAccount, setBankAccount] = useState(null);
 
+  // the oracle only returns the account to its owner, requests are signed by the account
+  const signedAuth = async (hex_pub_key, method, params) => {
+    const timestamp = Math.floor(Date.now() / 1000);
+    const payload = JSON.stringify([method, params, timestamp]);
+    const {
+      meta: { source, isInjected },
+    } = currentAccount;
+
+    let signature;
+    if (isInjected) {
+      const injector = await web3FromSource(source);
+      const result = await injector.signer.signRaw({
+        address: currentAccount.address,
+        data: stringToHex(payload),
+        type: "bytes",
+      });
+      signature = result.signature;
+    } else {
+      signature = u8aToHex(keyring.getPair(currentAccount.address).sign(payload));
+    }
+
+    return { signed: { signer: hex_pub_key, timestamp, signature } };
+  };
+
   useEffect(() => {
     // Update the current currentAccount
     const fetchAccount = async () => {
@@ -24,7 +50,7 @@ const Dashboard = ({ state }) => {
 
       const bankAccount = await state.oracleRpc.send(
         "pcidss_get_bank_account",
-        [hex_pub_key]
+        [hex_pub_key, await signedAuth(hex_pub_key, "pcidss_get_bank_account", [hex_pub_key])]
       );
 
       if (!bankAccount) {
@@ -35,7 +61,7 @@ const Dashboard = ({ state }) => {
 
       const transactions = await state.oracleRpc.send(
         "pcidss_get_transactions",
-        [hex_pub_key]
+        [hex_pub_key, await signedAuth(hex_pub_key, "pcidss_get_transactions", [hex_pub_key])]
       );
 
       setBankAccount(bankAccount);
@@ -45,6 +71,26 @@ const Dashboard = ({ state }) => {
     setMutated(false);
   }, [mutated, currentAccount, apiState]);
 
+  useEffect(() => {
+    // the oracle pushes the transactions of the account, the account is fetched again on each
+    if (!currentAccount || apiState !== "READY" || !state.oracleRpc) return;
+
+    let hex_pub_key = u8aToHexCompact(currentAccount.publicKey);
+    let subscription = (async () =>
+      state.oracleRpc.subscribe(
+        "transaction",
+        "pcidss_subscribeTransactions",
+        [hex_pub_key, await signedAuth(hex_pub_key, "pcidss_subscribeTransactions", [hex_pub_key])],
+        (error) => !error && setMutated(true)
+      ))();
+    subscription.catch((error) => console.error("Subscription failed", error));
+
+    return () =>
+      subscription.then((id) =>
+        state.oracleRpc.unsubscribe("transaction", "pcidss_unsubscribeTransactions", id)
+      );
+  }, [currentAccount, apiState, state.oracleRpc]);
+
   const DEV_MODE = process.env.MODE === "dev";
 
   const onReverse = async (hash, amount) => {
@@ -138,7 +184,7 @@ const Dashboard = ({ state }) => {
                     <Table.Cell width={3}>
                       {bankAccount?.card_expiration_date?.slice(0, 7)}
                     </Table.Cell>
-                    <Table.Cell width={3}>${bankAccount.balance}</Table.Cell>
+                    <Table.Cell width={3}>${bankAccount.balance.minor_units}</Table.Cell>
                     <Table.Cell width={10}>
                       <span
                         style={{
@@ -213,7 +259,7 @@ const Dashboard = ({ state }) => {
                       <Table.Cell width={3}>
                         {transaction.reversed.toString()}
                       </Table.Cell>
-                      <Table.Cell width={3}>${transaction.amount}</Table.Cell>
+                      <Table.Cell width={3}>${transaction.amount.minor_units}</Table.Cell>
                       <Table.Cell width={3}>
                         {currentAccount.id === transaction.from
                           ? "Credit"
@@ -229,7 +275,7 @@ const Dashboard = ({ state }) => {
                           <Button
                             color="blue"
                             onClick={() =>
-                              onReverse(transaction.hash, transaction.amount)
+                              onReverse(transaction.hash, transaction.amount.minor_units)
                             }
                             disabled={transaction.reversed}
                           >
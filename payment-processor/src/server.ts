@@ -3,32 +3,8 @@ import cors from "cors";
 import express, { Router } from "express";
 import helmet from "helmet";
 import morgan from "morgan";
-import { MTI, ProcessingCode, RequestBody } from "./types";
-import { ensurePadded, responseCodeToMessage } from "./utils";
-// @ts-ignore
-import iso8583 from "iso_8583";
-
-// Custom format for `126` field
-let CUSTOM_FORMATS = {
-  "4": {
-    ContentType: "n",
-    Label: "Amount",
-    LenType: "fixed",
-    MaxLen: 20,
-  },
-  "126": {
-    ContentType: "ans",
-    Label: "Private data",
-    LenType: "llvar",
-    MaxLen: 100,
-  },
-  "127": {
-    ContentType: "ans",
-    Label: "Private data 2",
-    LenType: "llvar",
-    MaxLen: 100,
-  },
-};
+import { IsoFields, MTI, ProcessingCode, RequestBody } from "./types";
+import { ensurePadded } from "./utils";
 
 /**
  * Represents the server
@@ -114,23 +90,25 @@ export default class Server {
     res: express.Response
   ) {
     try {
-      let accounts = req.body?.accounts || [];
+      // SCALE encoded balance request signed by the offchain worker
+      let payload = req.body?.payload || "";
       let signature = req.body?.signature || "";
 
-      if (accounts.length === 0 || signature === "") {
+      if (payload === "" || signature === "") {
         res.status(400).json({
           status: false,
-          message: "Accounts are required",
+          message: "Signed payload is required",
         });
         return;
       }
 
-      // convert signature to ArrayBuffer, it is string in hex format now
-      signature = Array.from(Buffer.from(signature, "hex"));
+      // convert payload and signature to ArrayBuffer, they are strings in hex format now
+      payload = Array.from(Buffer.from(payload.replace(/^0x/, ""), "hex"));
+      signature = Array.from(Buffer.from(signature.replace(/^0x/, ""), "hex"));
 
       let msgResponse = await this.oracle_rpc.send(
         "pcidss_get_batch_balances",
-        [signature.slice(1), accounts]
+        [signature.slice(1), payload]
       );
 
       res.status(200).json(
@@ -152,19 +130,21 @@ export default class Server {
   //
   // 1. Extract variables from the request
   // 2. Do some validation
-  // 3. Form ISO-8583 message
-  // 4. Send the message to the PCIDSS compliant oracle
+  // 3. Form ISO-8583 message fields
+  // 4. Send the fields to the PCIDSS compliant oracle, which composes the message with its spec
   // 5. Wait for the response
   // 6. Send the response back to the client
   private async submitIso8583(req: express.Request, res: express.Response) {
-    const data = this.formIsoData(req.body);
-    const isopack = new iso8583(data, CUSTOM_FORMATS);
+    const { 0: mti, ...fields } = this.formIsoData(req.body);
 
     try {
-      let msgResponse = await this.oracle_rpc.send("pcidss_submit_iso8583", [
-        // slicing the first two bytes, because they are the length of the message
-        // RPC doesn't expect it
-        Array.from(isopack.getBufferMessage().slice(2)),
+      let msgResponse = await this.oracle_rpc.send("pcidss_submit_iso8583_fields", [
+        mti,
+        fields,
+        // default spec
+        null,
+        // merchant API key of the oracle
+        { api_key: process.env.ORACLE_API_KEY || "dev-api-key" },
       ]);
       await this.processResponse(msgResponse, res);
     } catch {
@@ -176,34 +156,17 @@ export default class Server {
   }
 
   // Processes the response from the oracle
-  private async processResponse(isoMsg: any[], res: express.Response) {
-    // convert length of message to two bytes u16
-    let len = isoMsg.length;
-    let lenBytes: any[] = [];
-    lenBytes[0] = len >> 8;
-    lenBytes[1] = len & 0xff;
-
-    let isopack = new iso8583({}, CUSTOM_FORMATS);
-    let msg: any = isopack.getIsoJSON(Buffer.from(lenBytes.concat(isoMsg)), {
-      lenEncoding: "hex",
-      bitmapEncoding: "hex",
-      secondaryBitmap: true,
-    });
-
-    console.log("Response from oracle", msg);
-
-    const responseCode = msg["39"];
-
-    let message = responseCodeToMessage(responseCode);
+  private async processResponse(response: IsoFields, res: express.Response) {
+    console.log("Response from oracle", response.mti, response.response_code);
 
     res.status(200).json({
-      status: responseCode === "00",
-      message,
-      result: msg["126"],
+      status: response.response_code === "00",
+      message: response.response_description ?? "Unknown error",
+      result: response.fields["126"],
     });
   }
 
-  // Forms a custom data to be passed to `ISO8583` pack
+  // Forms the ISO-8583 fields by position, `0` is the MTI
   private formIsoData(body: RequestBody): Record<string, string> {
     const isoNow = new Date();
     const now = isoNow.toISOString();
@@ -24,6 +24,15 @@ export interface RequestBody {
   accountId: string | null;
 }
 
+// ISO-8583 message returned by the oracle as its fields
+export interface IsoFields {
+  mti: string;
+  // Field values by position in the bitmap
+  fields: Record<string, string>;
+  response_code: string | null;
+  response_description: string | null;
+}
+
 // Common MTI types
 export enum MTI {
   // Authorization Request from the POS, for any online transactions
@@ -1,5 +1,3 @@
-import { ResponseCode } from "./types";
-
 /**
  * Checks if the given value is hex string.
  * @param str
@@ -25,28 +23,3 @@ export function ensurePadded(value: string, length: number): string {
   return value;
 }
 
-/**
- * Response code to message
- * @param code
- * @returns string
- */
-export function responseCodeToMessage(code: ResponseCode): string {
-  switch (code) {
-    case "00":
-      return "Approved";
-    case "05":
-      return "Declined";
-    case "51":
-      return "Insufficient funds";
-    case "54":
-      return "Expired card";
-    case "12":
-      return "Invalid transaction";
-    case "14":
-      return "Invalid card";
-    case "13":
-      return "Invalid amount";
-    default:
-      return "Unknown error";
-  }
-}
@@ -1,25 +1,142 @@
 //! Defines the [`PgBankAccount`] type and its traits.
 use async_trait::async_trait;
-use deadpool_postgres::Pool;
+use deadpool_postgres::{GenericClient, Pool};
 use std::sync::Arc;
 use uuid::Uuid;
 
 use op_core::{
 	bank_account::{
-		models::{BankAccount, BankAccountCreate, BankAccountUpdate},
+		models::{BankAccount, BankAccountCreate, BankAccountUpdate, CardStatus, CardStatusChange},
 		traits::BankAccountTrait,
 	},
 	error::DomainError,
+	ledger::models::{LedgerAccount, Posting},
+	types::TransactionType,
+	vault::{
+		models::{CardData, EncryptedCardData, LEGACY_KEY_VERSION},
+		Vault,
+	},
 };
 
+use crate::ledger;
+
 /// Type that will be used to interact with the database.
+///
+/// Card data is tokenized with the vault before it reaches the database.
 pub struct PgBankAccount {
 	pool: Arc<Pool>,
+	vault: Arc<Vault>,
 }
 
 impl PgBankAccount {
-	pub fn new(pool: Arc<Pool>) -> Self {
-		Self { pool }
+	pub fn new(pool: Arc<Pool>, vault: Arc<Vault>) -> Self {
+		Self { pool, vault }
+	}
+
+	/// Tokenize card data of bank accounts created before the vault was introduced
+	///
+	/// Plaintext card data is cleared, returns the number of tokenized bank accounts.
+	pub async fn tokenize_plaintext_cards(&self) -> Result<usize, DomainError> {
+		let mut client = self.pool.get().await?;
+		let db_transaction = client.transaction().await?;
+
+		let rows = db_transaction
+			.query(r#"SELECT * FROM bank_account WHERE card_number IS NOT NULL FOR UPDATE;"#, &[])
+			.await?;
+
+		let stmt = db_transaction
+			.prepare(
+				r#"UPDATE bank_account SET card_number_hash = $1, card_number_masked = $2, card_cvv_hash = $3, card_number = NULL, card_cvv = NULL WHERE id = $4;"#,
+			)
+			.await?;
+
+		for row in rows.iter() {
+			let id: Uuid = row.get("id");
+			let card_cvv: String = row.get("card_cvv");
+
+			let card_token = self
+				.vault
+				.tokenize(&CardData {
+					card_number: row.get("card_number"),
+					card_holder_first_name: row.get("card_holder_first_name"),
+					card_holder_last_name: row.get("card_holder_last_name"),
+				})
+				.await?;
+
+			db_transaction
+				.execute(
+					&stmt,
+					&[
+						&card_token.hash,
+						&card_token.masked,
+						&self.vault.hash_cvv(&card_cvv).await?,
+						&id,
+					],
+				)
+				.await?;
+
+			save_card_data(&db_transaction, &id, &card_token.encrypted).await?;
+		}
+
+		db_transaction.commit().await?;
+
+		Ok(rows.len())
+	}
+
+	/// Re-encrypt card data of up to `batch_size` bank accounts with the current key version
+	///
+	/// Rows are locked only for the duration of the batch, so it can run while the oracle is
+	/// serving requests. Returns the number of re-encrypted bank accounts, zero once all of them
+	/// are on the current key version.
+	pub async fn reencrypt_batch(&self, batch_size: i64) -> Result<usize, DomainError> {
+		let key_version = self.vault.current_key_version().await?;
+
+		let mut client = self.pool.get().await?;
+		let db_transaction = client.transaction().await?;
+
+		let stmt = db_transaction
+			.prepare(
+				r#"SELECT * FROM bank_account WHERE key_version <> $1 AND card_number_encrypted IS NOT NULL ORDER BY id LIMIT $2 FOR UPDATE;"#,
+			)
+			.await?;
+
+		let rows = db_transaction.query(&stmt, &[&(key_version as i32), &batch_size]).await?;
+
+		for row in rows.iter() {
+			let bank_account: BankAccount = row.into();
+
+			let mut card = self.vault.detokenize(&bank_account.card_data).await?;
+
+			// names are not encrypted in the legacy format
+			if bank_account.card_data.key_version == LEGACY_KEY_VERSION {
+				card.card_holder_first_name =
+					row.get::<&str, Option<String>>("card_holder_first_name").unwrap_or_default();
+				card.card_holder_last_name =
+					row.get::<&str, Option<String>>("card_holder_last_name").unwrap_or_default();
+			}
+
+			let card_data = self.vault.seal(&card).await?;
+
+			save_card_data(&db_transaction, &bank_account.id, &card_data).await?;
+		}
+
+		db_transaction.commit().await?;
+
+		Ok(rows.len())
+	}
+
+	/// Number of bank accounts that are not on the current key version
+	pub async fn count_stale_cards(&self) -> Result<i64, DomainError> {
+		let key_version = self.vault.current_key_version().await?;
+
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare(r#"SELECT COUNT(*) FROM bank_account WHERE key_version <> $1;"#)
+			.await?;
+
+		let row = client.query_one(&stmt, &[&(key_version as i32)]).await?;
+
+		Ok(row.get(0))
 	}
 }
 
@@ -41,9 +158,13 @@ impl BankAccountTrait for PgBankAccount {
 		card_number: &str,
 	) -> Result<Option<BankAccount>, DomainError> {
 		let client = self.pool.get().await?;
-		let stmt = client.prepare(r#"SELECT * FROM bank_account WHERE card_number = $1;"#).await?;
+		let stmt = client
+			.prepare(r#"SELECT * FROM bank_account WHERE card_number_hash = $1;"#)
+			.await?;
+
+		let card_number_hash = self.vault.card_number_hash(card_number).await?;
 
-		if let Some(result) = client.query_opt(&stmt, &[&card_number]).await? {
+		if let Some(result) = client.query_opt(&stmt, &[&card_number_hash]).await? {
 			return Ok(Some((&result).into()));
 		}
 
@@ -55,64 +176,122 @@ impl BankAccountTrait for PgBankAccount {
 		id: &Uuid,
 		bank_account_update: &BankAccountUpdate,
 	) -> Result<BankAccount, DomainError> {
-		let client = self.pool.get().await?;
+		let mut client = self.pool.get().await?;
+		let db_transaction = client.transaction().await?;
 
-		let mut bank_account = self
-			.find_by_id(id)
+		let mut bank_account = lock_for_update(&db_transaction, &[*id])
 			.await?
+			.pop()
 			.ok_or(DomainError::NotFound("Bank account not found".to_string()))?;
 
+		let previous = bank_account.clone();
 		bank_account.try_update(bank_account_update).await?;
 
-		let stmt = client
-            .prepare(
-                r#"UPDATE bank_account SET balance = $1, nonce = $2, account_id = $3, updated_at = $4 WHERE id = $5 RETURNING *;"#,
-            )
-            .await?;
+		match bank_account_update {
+			BankAccountUpdate::Renew { card_cvv: Some(card_cvv), .. } => {
+				bank_account.card_cvv_hash = self.vault.hash_cvv(card_cvv).await?;
+			},
+			BankAccountUpdate::Reissue { card_number, card_cvv, .. } => {
+				let card = self.vault.detokenize(&bank_account.card_data).await?;
+				let card_token = self
+					.vault
+					.tokenize(&CardData { card_number: card_number.clone(), ..card })
+					.await?;
 
-		let result = client
-			.query_one(
-				&stmt,
-				&[
-					&(bank_account.balance as i32),
-					&(bank_account.nonce as i32),
-					&bank_account.account_id,
-					&chrono::Utc::now(),
-					&id,
-				],
-			)
-			.await?;
+				bank_account.card_number_hash = card_token.hash;
+				bank_account.card_number_masked = card_token.masked;
+				bank_account.card_data = card_token.encrypted;
+				bank_account.card_cvv_hash = self.vault.hash_cvv(card_cvv).await?;
+
+				save_card_data(&db_transaction, id, &bank_account.card_data).await?;
+			},
+			_ => {},
+		}
 
-		Ok((&result).into())
+		// status changes and reissues are recorded with the card they apply to
+		let reason = match bank_account_update {
+			BankAccountUpdate::Status { reason, .. } => Some(reason.clone()),
+			BankAccountUpdate::Reissue { .. } =>
+				Some(Some(format!("Reissued as {}", bank_account.card_number_masked))),
+			_ => None,
+		};
+		if let Some(reason) = reason {
+			record_status_change(&db_transaction, &previous, bank_account.card_status, reason)
+				.await?;
+		}
+
+		// balances are adjusted against the settlement account
+		if let BankAccountUpdate::Balance { amount, transaction_type } = bank_account_update {
+			let account = LedgerAccount::BankAccount(*id);
+			let posting = match transaction_type {
+				TransactionType::Debit =>
+					Posting::new(None).payment(LedgerAccount::Settlement, *amount, account, *amount),
+				TransactionType::Credit =>
+					Posting::new(None).payment(account, *amount, LedgerAccount::Settlement, *amount),
+			};
+
+			ledger::record(&db_transaction, &posting).await?;
+		}
+
+		let bank_account = save(&db_transaction, &bank_account).await?;
+		db_transaction.commit().await?;
+
+		Ok(bank_account)
 	}
 
 	async fn create(
 		&self,
 		bank_account_create: &BankAccountCreate,
 	) -> Result<BankAccount, DomainError> {
-		let client = self.pool.get().await?;
+		let mut client = self.pool.get().await?;
 
-		let query_string = r#"INSERT INTO bank_account (id, card_number, card_holder_first_name, card_holder_last_name, card_expiration_date, card_cvv, balance, nonce, account_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *;"#;
+		let card_token = self
+			.vault
+			.tokenize(&CardData {
+				card_number: bank_account_create.card_number.clone(),
+				card_holder_first_name: bank_account_create.card_holder_first_name.clone(),
+				card_holder_last_name: bank_account_create.card_holder_last_name.clone(),
+			})
+			.await?;
+		let card_cvv_hash = self.vault.hash_cvv(&bank_account_create.card_cvv).await?;
+
+		let query_string = r#"INSERT INTO bank_account (id, card_number_hash, card_number_masked, key_version, data_key, card_number_encrypted, card_holder_first_name_encrypted, card_holder_last_name_encrypted, card_expiration_date, card_cvv_hash, balance, available_balance, currency, nonce, account_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $11, $12, $13, $14) RETURNING *;"#;
 
-		let stmt = client.prepare(query_string).await?;
+		let db_transaction = client.transaction().await?;
+		let stmt = db_transaction.prepare(query_string).await?;
 
-		let row = &client
+		let row = &db_transaction
 			.query_one(
 				&stmt,
 				&[
 					&bank_account_create.id,
-					&bank_account_create.card_number,
-					&bank_account_create.card_holder_first_name,
-					&bank_account_create.card_holder_last_name,
+					&card_token.hash,
+					&card_token.masked,
+					&(card_token.encrypted.key_version as i32),
+					&card_token.encrypted.data_key,
+					&card_token.encrypted.card_number,
+					&card_token.encrypted.card_holder_first_name,
+					&card_token.encrypted.card_holder_last_name,
 					&bank_account_create.card_expiration_date,
-					&bank_account_create.card_cvv,
-					&(bank_account_create.balance as i32), // Initial balance is 0
-					&0_i32,                                // Initial nonce is 0
+					&card_cvv_hash,
+					&bank_account_create.balance.minor_units, // Initial balance is 0
+					&bank_account_create.balance.currency.alpha(),
+					&0_i32, // Initial nonce is 0
 					&bank_account_create.account_id,
 				],
 			)
 			.await?;
 
+		if !bank_account_create.balance.is_zero() {
+			ledger::record(
+				&db_transaction,
+				&Posting::opening(bank_account_create.id, bank_account_create.balance),
+			)
+			.await?;
+		}
+
+		db_transaction.commit().await?;
+
 		Ok((row).into())
 	}
 
@@ -136,4 +315,146 @@ impl BankAccountTrait for PgBankAccount {
 
 		Ok(None)
 	}
+
+	async fn find_status_history(&self, id: &Uuid) -> Result<Vec<CardStatusChange>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare(
+				r#"SELECT * FROM card_status_history WHERE bank_account_id = $1 ORDER BY created_at, id;"#,
+			)
+			.await?;
+
+		let result = client.query(&stmt, &[&id]).await?;
+
+		Ok(result.iter().map(|row| row.into()).collect())
+	}
+
+	async fn find_registered(&self) -> Result<Vec<BankAccount>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare(r#"SELECT * FROM bank_account WHERE account_id IS NOT NULL ORDER BY id;"#)
+			.await?;
+
+		let result = client.query(&stmt, &[]).await?;
+
+		Ok(result.iter().map(|row| row.into()).collect())
+	}
+}
+
+/// Select bank accounts by unique identifiers and lock them until the end of the database
+/// transaction.
+///
+/// Rows are locked in the order of their ids, so that concurrent transfers between the same bank
+/// accounts can't deadlock.
+pub(crate) async fn lock_for_update<C: GenericClient + Sync>(
+	client: &C,
+	ids: &[Uuid],
+) -> Result<Vec<BankAccount>, DomainError> {
+	let stmt = client
+		.prepare(r#"SELECT * FROM bank_account WHERE id = ANY($1) ORDER BY id FOR UPDATE;"#)
+		.await?;
+
+	let rows = client.query(&stmt, &[&ids]).await?;
+
+	Ok(rows.iter().map(|row| row.into()).collect())
+}
+
+/// Persist balances, nonce, on-chain account id and card of a bank account.
+///
+/// Sealed card data is persisted with [`save_card_data`].
+pub(crate) async fn save<C: GenericClient + Sync>(
+	client: &C,
+	bank_account: &BankAccount,
+) -> Result<BankAccount, DomainError> {
+	let stmt = client
+		.prepare(
+			r#"UPDATE bank_account SET balance = $1, available_balance = $2, nonce = $3, account_id = $4, card_status = $5, card_expiration_date = $6, card_cvv_hash = $7, card_number_hash = $8, card_number_masked = $9, updated_at = $10 WHERE id = $11 RETURNING *;"#,
+		)
+		.await?;
+
+	let card_status: i32 = bank_account.card_status.into();
+	let result = client
+		.query_one(
+			&stmt,
+			&[
+				&bank_account.balance.minor_units,
+				&bank_account.available_balance.minor_units,
+				&(bank_account.nonce as i32),
+				&bank_account.account_id,
+				&card_status,
+				&bank_account.card_expiration_date,
+				&bank_account.card_cvv_hash,
+				&bank_account.card_number_hash,
+				&bank_account.card_number_masked,
+				&chrono::Utc::now(),
+				&bank_account.id,
+			],
+		)
+		.await?;
+
+	Ok((&result).into())
+}
+
+/// Record a change of the card status of a bank account, `previous` is the bank account before
+/// the change.
+async fn record_status_change<C: GenericClient + Sync>(
+	client: &C,
+	previous: &BankAccount,
+	to_status: CardStatus,
+	reason: Option<String>,
+) -> Result<(), DomainError> {
+	let stmt = client
+		.prepare(
+			r#"INSERT INTO card_status_history (id, bank_account_id, from_status, to_status, reason, card_number_masked) VALUES ($1, $2, $3, $4, $5, $6);"#,
+		)
+		.await?;
+
+	let from_status: i32 = previous.card_status.into();
+	let to_status: i32 = to_status.into();
+
+	client
+		.execute(
+			&stmt,
+			&[
+				&Uuid::new_v4(),
+				&previous.id,
+				&from_status,
+				&to_status,
+				&reason,
+				&previous.card_number_masked,
+			],
+		)
+		.await?;
+
+	Ok(())
+}
+
+/// Persist sealed card data of a bank account, plaintext card holder names are cleared.
+async fn save_card_data<C: GenericClient + Sync>(
+	client: &C,
+	id: &Uuid,
+	card_data: &EncryptedCardData,
+) -> Result<BankAccount, DomainError> {
+	let stmt = client
+		.prepare(
+			r#"UPDATE bank_account SET key_version = $1, data_key = $2, card_number_encrypted = $3, card_holder_first_name_encrypted = $4, card_holder_last_name_encrypted = $5, card_holder_first_name = NULL, card_holder_last_name = NULL, updated_at = $6 WHERE id = $7 RETURNING *;"#,
+		)
+		.await?;
+
+	let result = client
+		.query_one(
+			&stmt,
+			&[
+				&(card_data.key_version as i32),
+				&card_data.data_key,
+				&card_data.card_number,
+				&card_data.card_holder_first_name,
+				&card_data.card_holder_last_name,
+				&chrono::Utc::now(),
+				id,
+			],
+		)
+		.await?;
+
+	Ok((&result).into())
 }
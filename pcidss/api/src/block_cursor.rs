@@ -0,0 +1,127 @@
+//! Defines the [`PgBlockCursor`] type and its traits.
+use async_trait::async_trait;
+use deadpool_postgres::Pool;
+use std::sync::Arc;
+
+use op_core::{
+	block_cursor::{
+		models::{FailedRetraction, TrackedBlock},
+		traits::BlockCursorTrait,
+	},
+	error::DomainError,
+};
+
+/// Type that will be used to interact with the database.
+pub struct PgBlockCursor {
+	pool: Arc<Pool>,
+}
+
+impl PgBlockCursor {
+	pub fn new(pool: Arc<Pool>) -> Self {
+		Self { pool }
+	}
+}
+
+#[async_trait]
+impl BlockCursorTrait for PgBlockCursor {
+	async fn get(&self, name: &str) -> Result<Option<u32>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client.prepare("SELECT block_number FROM block_cursor WHERE name = $1").await?;
+
+		let result = client.query_opt(&stmt, &[&name]).await?;
+
+		Ok(result.map(|row| row.get::<&str, i64>("block_number") as u32))
+	}
+
+	async fn advance(&self, name: &str, block_number: u32) -> Result<(), DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("INSERT INTO block_cursor (name, block_number, updated_at) VALUES ($1, $2, $3) ON CONFLICT (name) DO UPDATE SET block_number = GREATEST(block_cursor.block_number, EXCLUDED.block_number), updated_at = EXCLUDED.updated_at")
+			.await?;
+
+		client
+			.execute(&stmt, &[&name, &(block_number as i64), &chrono::Utc::now()])
+			.await?;
+
+		Ok(())
+	}
+
+	async fn rewind(&self, name: &str, block_number: u32) -> Result<(), DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("UPDATE block_cursor SET block_number = LEAST(block_number, $1), updated_at = $2 WHERE name = $3")
+			.await?;
+
+		client
+			.execute(&stmt, &[&(block_number as i64), &chrono::Utc::now(), &name])
+			.await?;
+
+		Ok(())
+	}
+
+	async fn track(&self, block: &TrackedBlock) -> Result<(), DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("INSERT INTO tracked_block (block_number, block_hash) VALUES ($1, $2) ON CONFLICT (block_number) DO UPDATE SET block_hash = EXCLUDED.block_hash, created_at = now()")
+			.await?;
+
+		client
+			.execute(&stmt, &[&(block.block_number as i64), &block.block_hash])
+			.await?;
+
+		Ok(())
+	}
+
+	async fn find_tracked(&self) -> Result<Vec<TrackedBlock>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client.prepare("SELECT * FROM tracked_block ORDER BY block_number").await?;
+
+		let rows = client.query(&stmt, &[]).await?;
+
+		Ok(rows.iter().map(|row| row.into()).collect())
+	}
+
+	async fn untrack(&self, block_number: u32) -> Result<(), DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client.prepare("DELETE FROM tracked_block WHERE block_number <= $1").await?;
+
+		client.execute(&stmt, &[&(block_number as i64)]).await?;
+
+		Ok(())
+	}
+
+	async fn untrack_from(&self, block_number: u32) -> Result<(), DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client.prepare("DELETE FROM tracked_block WHERE block_number >= $1").await?;
+
+		client.execute(&stmt, &[&(block_number as i64)]).await?;
+
+		Ok(())
+	}
+
+	async fn record_failed_retraction(
+		&self,
+		block: &TrackedBlock,
+		error: &str,
+	) -> Result<(), DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("INSERT INTO failed_retraction (block_number, block_hash, error) VALUES ($1, $2, $3) ON CONFLICT (block_hash) DO UPDATE SET error = EXCLUDED.error, created_at = now()")
+			.await?;
+
+		client
+			.execute(&stmt, &[&(block.block_number as i64), &block.block_hash, &error])
+			.await?;
+
+		Ok(())
+	}
+
+	async fn find_failed_retractions(&self) -> Result<Vec<FailedRetraction>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client.prepare("SELECT * FROM failed_retraction ORDER BY created_at").await?;
+
+		let rows = client.query(&stmt, &[]).await?;
+
+		Ok(rows.iter().map(|row| row.into()).collect())
+	}
+}
@@ -0,0 +1,292 @@
+//! Defines the [`PgHold`] type and its traits.
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{GenericClient, Pool};
+use std::{collections::HashMap, sync::Arc};
+use uuid::Uuid;
+
+use op_core::{
+	bank_account::models::BankAccountUpdate,
+	error::DomainError,
+	hold::{
+		models::{Hold, HoldCreate, HoldStatus},
+		traits::HoldTrait,
+	},
+	ledger::models::Posting,
+	transaction::models::{Transaction, TransactionCreate},
+	types::TransactionType,
+};
+
+use crate::{bank_account, ledger, transaction};
+
+/// Type that will be used to interact with the database.
+pub struct PgHold {
+	pool: Arc<Pool>,
+}
+
+impl PgHold {
+	pub fn new(pool: Arc<Pool>) -> Self {
+		Self { pool }
+	}
+}
+
+#[async_trait]
+impl HoldTrait for PgHold {
+	async fn find_by_id(&self, id: &Uuid) -> Result<Option<Hold>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client.prepare("SELECT * FROM hold WHERE id = $1").await?;
+
+		if let Some(result) = client.query_opt(&stmt, &[&id]).await? {
+			return Ok(Some((&result).into()));
+		}
+
+		Ok(None)
+	}
+
+	async fn find_by_hash(&self, hash: &str) -> Result<Option<Hold>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client.prepare("SELECT * FROM hold WHERE hash = $1").await?;
+
+		if let Some(result) = client.query_opt(&stmt, &[&hash]).await? {
+			return Ok(Some((&result).into()));
+		}
+
+		Ok(None)
+	}
+
+	async fn find_by_bank_account_id(&self, source: &Uuid) -> Result<Vec<Hold>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client.prepare("SELECT * FROM hold WHERE source = $1").await?;
+
+		let result = client.query(&stmt, &[&source]).await?;
+
+		Ok(result.iter().map(|row| (row).into()).collect())
+	}
+
+	async fn create(&self, hold_create: &HoldCreate) -> Result<Hold, DomainError> {
+		let mut client = self.pool.get().await?;
+		let db_transaction = client.transaction().await?;
+
+		let mut bank_account = bank_account::lock_for_update(&db_transaction, &[hold_create.from])
+			.await?
+			.pop()
+			.ok_or(DomainError::NotFound("Bank account not found".to_string()))?;
+
+		if bank_account.available_balance < hold_create.amount {
+			return Err(DomainError::InsufficientFunds);
+		}
+
+		// hash is computed with the nonce of the locked row, see `TransactionTrait::transfer`
+		let hold: Hold = (&HoldCreate { nonce: bank_account.nonce, ..hold_create.clone() }).into();
+
+		bank_account
+			.try_update(&BankAccountUpdate::Hold { amount: hold.amount })
+			.await?;
+		bank_account::save(&db_transaction, &bank_account).await?;
+
+		let stmt = db_transaction
+			.prepare(
+				"INSERT INTO hold (id, hash, source, recipient, amount, currency, status, expires_at, original_amount, original_currency, fx_rate, fx_rate_version) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING *",
+			)
+			.await?;
+
+		let conversion = hold.conversion.as_ref();
+
+		let row = db_transaction
+			.query_one(
+				&stmt,
+				&[
+					&hold.id,
+					&hold.hash,
+					&hold.from,
+					&hold.to,
+					&hold.amount.minor_units,
+					&hold.amount.currency.alpha(),
+					&Into::<i32>::into(hold.status),
+					&hold.expires_at,
+					&conversion.map(|conversion| conversion.amount.minor_units),
+					&conversion.map(|conversion| conversion.amount.currency.alpha()),
+					&conversion.map(|conversion| conversion.rate.to_field()),
+					&conversion.map(|conversion| conversion.rate_version as i32),
+				],
+			)
+			.await?;
+
+		db_transaction.commit().await?;
+
+		Ok((&row).into())
+	}
+
+	async fn capture(
+		&self,
+		id: &Uuid,
+		transaction_create: &TransactionCreate,
+	) -> Result<Transaction, DomainError> {
+		let mut client = self.pool.get().await?;
+		let db_transaction = client.transaction().await?;
+
+		let hold = lock_active(&db_transaction, id, Utc::now()).await?;
+
+		if transaction_create.from != hold.from {
+			return Err(DomainError::BadRequest("Hold belongs to another bank account".to_string()));
+		}
+
+		if transaction_create.amount > hold.amount {
+			return Err(DomainError::BadRequest("Capture amount exceeds the hold".to_string()));
+		}
+
+		let ids: Vec<Uuid> = std::iter::once(hold.from).chain(transaction_create.to).collect();
+		let mut bank_accounts = bank_account::lock_for_update(&db_transaction, &ids).await?;
+
+		let source = bank_accounts
+			.iter_mut()
+			.find(|bank_account| bank_account.id == hold.from)
+			.ok_or(DomainError::NotFound("Bank account not found".to_string()))?;
+
+		// release the whole hold, then post the captured amount
+		source.try_update(&BankAccountUpdate::Release { amount: hold.amount }).await?;
+
+		let transaction: Transaction =
+			(&TransactionCreate { nonce: source.nonce, ..transaction_create.clone() }).into();
+
+		let posting = Posting::for_transaction(
+			&transaction,
+			&TransactionType::Credit,
+			transaction.amount,
+			transaction.recipient_amount(),
+		);
+
+		ledger::post(&db_transaction, &mut bank_accounts, &posting).await?;
+
+		let transaction = transaction::insert(&db_transaction, &transaction).await?;
+
+		set_status(&db_transaction, &hold.id, HoldStatus::Captured, Some(&transaction.id)).await?;
+		db_transaction.commit().await?;
+
+		Ok(transaction)
+	}
+
+	async fn release(&self, id: &Uuid) -> Result<Hold, DomainError> {
+		let mut client = self.pool.get().await?;
+		let db_transaction = client.transaction().await?;
+
+		let hold = lock_active(&db_transaction, id, Utc::now()).await?;
+		let hold = release(&db_transaction, &hold, HoldStatus::Released).await?;
+
+		db_transaction.commit().await?;
+
+		Ok(hold)
+	}
+
+	async fn expire(&self, now: DateTime<Utc>) -> Result<Vec<Hold>, DomainError> {
+		let mut client = self.pool.get().await?;
+		let db_transaction = client.transaction().await?;
+
+		// skip holds that are being captured or released right now, they will be picked up by
+		// the next sweep if still active
+		let stmt = db_transaction
+			.prepare(
+				"SELECT * FROM hold WHERE status = $1 AND expires_at <= $2 ORDER BY id FOR UPDATE SKIP LOCKED",
+			)
+			.await?;
+
+		let rows = db_transaction
+			.query(&stmt, &[&Into::<i32>::into(HoldStatus::Active), &now])
+			.await?;
+
+		let holds: Vec<Hold> = rows.iter().map(|row| row.into()).collect();
+
+		// lock the source accounts at once in the order of their ids, like transfers do, instead
+		// of one hold at a time in the order of the holds
+		let mut source_ids: Vec<Uuid> = holds.iter().map(|hold| hold.from).collect();
+		source_ids.sort();
+		source_ids.dedup();
+
+		let mut bank_accounts: HashMap<Uuid, _> =
+			bank_account::lock_for_update(&db_transaction, &source_ids)
+				.await?
+				.into_iter()
+				.map(|bank_account| (bank_account.id, bank_account))
+				.collect();
+
+		let mut expired = Vec::with_capacity(holds.len());
+		for hold in holds.iter() {
+			bank_accounts
+				.get_mut(&hold.from)
+				.ok_or(DomainError::NotFound("Bank account not found".to_string()))?
+				.try_update(&BankAccountUpdate::Release { amount: hold.amount })
+				.await?;
+			expired.push(set_status(&db_transaction, &hold.id, HoldStatus::Expired, None).await?);
+		}
+
+		for bank_account in bank_accounts.values() {
+			bank_account::save(&db_transaction, bank_account).await?;
+		}
+
+		db_transaction.commit().await?;
+
+		Ok(expired)
+	}
+}
+
+/// Select a hold and lock it until the end of the database transaction.
+///
+/// Fails if the hold is not active or has already expired at given time.
+async fn lock_active<C: GenericClient + Sync>(
+	client: &C,
+	id: &Uuid,
+	now: DateTime<Utc>,
+) -> Result<Hold, DomainError> {
+	let stmt = client.prepare("SELECT * FROM hold WHERE id = $1 FOR UPDATE").await?;
+
+	let hold: Hold = client
+		.query_opt(&stmt, &[&id])
+		.await?
+		.map(|row| (&row).into())
+		.ok_or(DomainError::NotFound("Hold not found".to_string()))?;
+
+	if !hold.is_capturable(now) {
+		return Err(DomainError::BadRequest("Hold is not active".to_string()));
+	}
+
+	Ok(hold)
+}
+
+/// Return reserved funds of a locked hold to the bank account and close it with given status.
+async fn release<C: GenericClient + Sync>(
+	client: &C,
+	hold: &Hold,
+	status: HoldStatus,
+) -> Result<Hold, DomainError> {
+	let mut bank_account = bank_account::lock_for_update(client, &[hold.from])
+		.await?
+		.pop()
+		.ok_or(DomainError::NotFound("Bank account not found".to_string()))?;
+
+	bank_account
+		.try_update(&BankAccountUpdate::Release { amount: hold.amount })
+		.await?;
+	bank_account::save(client, &bank_account).await?;
+
+	set_status(client, &hold.id, status, None).await
+}
+
+/// Update status of a hold.
+async fn set_status<C: GenericClient + Sync>(
+	client: &C,
+	id: &Uuid,
+	status: HoldStatus,
+	transaction_id: Option<&Uuid>,
+) -> Result<Hold, DomainError> {
+	let stmt = client
+		.prepare(
+			"UPDATE hold SET status = $1, transaction_id = $2, updated_at = $3 WHERE id = $4 RETURNING *",
+		)
+		.await?;
+
+	let row = client
+		.query_one(&stmt, &[&Into::<i32>::into(status), &transaction_id, &Utc::now(), &id])
+		.await?;
+
+	Ok((&row).into())
+}
@@ -0,0 +1,136 @@
+//! Defines the [`FileKeyProvider`] type and its traits.
+use async_trait::async_trait;
+use rand::RngCore;
+use std::{collections::BTreeMap, path::Path};
+
+use op_core::{
+	error::DomainError,
+	vault::{self, models::LEGACY_KEY_VERSION, traits::KeyProvider, KEY_LENGTH},
+};
+
+/// Key provider backed by a local keyfile.
+///
+/// The keyfile has one hex-encoded 32 byte master key per line, formatted as
+/// `<version>:<key>`, a line without a version is version 1. The highest version is used for new
+/// data keys, the lowest one also derives the hashing key, so it must never be removed.
+pub struct FileKeyProvider {
+	keys: BTreeMap<u32, [u8; KEY_LENGTH]>,
+}
+
+impl FileKeyProvider {
+	/// Create a new key provider from master keys by version.
+	pub fn new(keys: BTreeMap<u32, [u8; KEY_LENGTH]>) -> Result<Self, DomainError> {
+		if keys.is_empty() || keys.contains_key(&LEGACY_KEY_VERSION) {
+			return Err(DomainError::InternalServerError(
+				"Key versions must start from 1".to_string(),
+			));
+		}
+
+		Ok(Self { keys })
+	}
+
+	/// Read master keys from a keyfile.
+	pub fn from_file(path: impl AsRef<Path>) -> Result<Self, DomainError> {
+		let content = std::fs::read_to_string(path.as_ref()).map_err(|e| {
+			DomainError::InternalServerError(format!(
+				"Could not read keyfile {:?}: {}",
+				path.as_ref(),
+				e
+			))
+		})?;
+
+		let mut keys = BTreeMap::new();
+
+		for line in content.lines().map(str::trim).filter(|line| !line.is_empty()) {
+			let (version, key) = match line.split_once(':') {
+				Some((version, key)) => (version.trim().parse()?, key.trim()),
+				None => (1, line),
+			};
+
+			let key: [u8; KEY_LENGTH] = hex::decode(key)
+				.ok()
+				.and_then(|key| key.try_into().ok())
+				.ok_or(DomainError::InternalServerError(format!(
+				"Key version {} must be 32 hex-encoded bytes",
+				version
+			)))?;
+
+			if keys.insert(version, key).is_some() {
+				return Err(DomainError::InternalServerError(format!(
+					"Duplicate key version {}",
+					version
+				)));
+			}
+		}
+
+		Self::new(keys)
+	}
+
+	/// Generate a keyfile with a random master key of version 1.
+	///
+	/// Fails if the keyfile already exists.
+	pub fn generate_file(path: impl AsRef<Path>) -> Result<Self, DomainError> {
+		let mut key = [0u8; KEY_LENGTH];
+		rand::thread_rng().fill_bytes(&mut key);
+
+		std::fs::OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.open(path.as_ref())
+			.and_then(|mut file| {
+				std::io::Write::write_all(&mut file, format!("1:{}\n", hex::encode(key)).as_bytes())
+			})
+			.map_err(|e| {
+				DomainError::InternalServerError(format!(
+					"Could not write keyfile {:?}: {}",
+					path.as_ref(),
+					e
+				))
+			})?;
+
+		Self::new(BTreeMap::from([(1, key)]))
+	}
+
+	/// Master key of the given version.
+	fn master_key(&self, version: u32) -> Result<&[u8; KEY_LENGTH], DomainError> {
+		self.keys
+			.get(&version)
+			.ok_or(DomainError::InternalServerError(format!("Key version {} not found", version)))
+	}
+
+	/// Master key of the lowest version.
+	fn first_master_key(&self) -> &[u8; KEY_LENGTH] {
+		self.keys.values().next().expect("validated on creation; qed")
+	}
+
+	/// Key-encryption key of the given version.
+	fn key_encryption_key(&self, version: u32) -> Result<[u8; KEY_LENGTH], DomainError> {
+		Ok(vault::derive_key(self.master_key(version)?, b"key-encryption"))
+	}
+}
+
+#[async_trait]
+impl KeyProvider for FileKeyProvider {
+	async fn current_version(&self) -> Result<u32, DomainError> {
+		Ok(*self.keys.keys().next_back().expect("validated on creation; qed"))
+	}
+
+	async fn wrap_key(&self, version: u32, data_key: &[u8]) -> Result<Vec<u8>, DomainError> {
+		vault::encrypt(&self.key_encryption_key(version)?, data_key)
+	}
+
+	async fn unwrap_key(&self, version: u32, wrapped_key: &[u8]) -> Result<Vec<u8>, DomainError> {
+		// legacy card numbers are encrypted directly with a key derived from the master key
+		if version == LEGACY_KEY_VERSION {
+			return Ok(
+				vault::derive_key(self.first_master_key(), b"card-number-encryption").to_vec()
+			);
+		}
+
+		vault::decrypt(&self.key_encryption_key(version)?, wrapped_key)
+	}
+
+	async fn hashing_key(&self) -> Result<[u8; KEY_LENGTH], DomainError> {
+		Ok(vault::derive_key(self.first_master_key(), b"card-data-hashing"))
+	}
+}
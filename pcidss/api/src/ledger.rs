@@ -0,0 +1,188 @@
+//! Defines the [`PgLedger`] type and its traits.
+use async_trait::async_trait;
+use deadpool_postgres::{GenericClient, Pool};
+use std::sync::Arc;
+use tokio_postgres::IsolationLevel;
+use uuid::Uuid;
+
+use op_core::{
+	bank_account::models::{BankAccount, BankAccountUpdate},
+	error::DomainError,
+	ledger::{
+		models::{BalanceMismatch, LedgerAccount, LedgerEntry, LedgerReport, Posting},
+		traits::LedgerTrait,
+	},
+	money::Money,
+	types::TransactionType,
+};
+
+use crate::bank_account;
+
+/// Type that will be used to interact with the database.
+pub struct PgLedger {
+	pool: Arc<Pool>,
+}
+
+impl PgLedger {
+	pub fn new(pool: Arc<Pool>) -> Self {
+		Self { pool }
+	}
+}
+
+#[async_trait]
+impl LedgerTrait for PgLedger {
+	async fn find_by_account(
+		&self,
+		account: &LedgerAccount,
+	) -> Result<Vec<LedgerEntry>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("SELECT * FROM ledger_entry WHERE account = $1 ORDER BY created_at, id")
+			.await?;
+
+		let result = client.query(&stmt, &[&account.to_string()]).await?;
+
+		Ok(result.iter().map(|row| row.into()).collect())
+	}
+
+	async fn find_by_transaction_id(&self, id: &Uuid) -> Result<Vec<LedgerEntry>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("SELECT * FROM ledger_entry WHERE transaction_id = $1 ORDER BY created_at, id")
+			.await?;
+
+		let result = client.query(&stmt, &[&id]).await?;
+
+		Ok(result.iter().map(|row| row.into()).collect())
+	}
+
+	async fn check(&self) -> Result<LedgerReport, DomainError> {
+		let mut client = self.pool.get().await?;
+
+		// both queries see the same snapshot, so the oracle can keep serving requests
+		let db_transaction = client
+			.build_transaction()
+			.isolation_level(IsolationLevel::RepeatableRead)
+			.read_only(true)
+			.start()
+			.await?;
+
+		let rows = db_transaction
+			.query(
+				r#"SELECT b.id, b.balance, b.available_balance, b.currency,
+					COALESCE((SELECT SUM(e.amount) FROM ledger_entry e WHERE e.account = 'bank_account:' || b.id), 0)::bigint AS ledger_balance,
+					COALESCE((SELECT SUM(h.amount) FROM hold h WHERE h.source = b.id AND h.status = 0), 0)::bigint AS held
+				FROM bank_account b ORDER BY b.id"#,
+				&[],
+			)
+			.await?;
+
+		let mut report = LedgerReport { bank_accounts: rows.len(), ..Default::default() };
+
+		for row in rows.iter() {
+			let balance = Money::from_row(row, "balance");
+			let available_balance = Money::from_row(row, "available_balance");
+			let ledger_balance = Money::from_row(row, "ledger_balance");
+			let expected_available_balance = Money::new(
+				ledger_balance.minor_units - row.get::<&str, i64>("held"),
+				balance.currency,
+			);
+
+			if balance != ledger_balance || available_balance != expected_available_balance {
+				report.mismatches.push(BalanceMismatch {
+					bank_account_id: row.get("id"),
+					balance,
+					ledger_balance,
+					available_balance,
+					expected_available_balance,
+				});
+			}
+		}
+
+		let rows = db_transaction
+			.query(
+				"SELECT DISTINCT posting_id FROM ledger_entry GROUP BY posting_id, currency HAVING SUM(amount) <> 0 ORDER BY posting_id",
+				&[],
+			)
+			.await?;
+
+		report.unbalanced_postings = rows.iter().map(|row| row.get("posting_id")).collect();
+
+		db_transaction.commit().await?;
+
+		Ok(report)
+	}
+}
+
+/// Apply a posting to already locked bank accounts, persist them and record the posting.
+///
+/// Entries that deduct from a bank account fail if its available balance is not enough.
+pub(crate) async fn post<C: GenericClient + Sync>(
+	client: &C,
+	bank_accounts: &mut [BankAccount],
+	posting: &Posting,
+) -> Result<(), DomainError> {
+	for entry in posting.entries.iter() {
+		let LedgerAccount::BankAccount(id) = entry.account else {
+			continue;
+		};
+
+		let bank_account = bank_accounts
+			.iter_mut()
+			.find(|bank_account| bank_account.id == id)
+			.ok_or(DomainError::NotFound("Bank account not found".to_string()))?;
+
+		if entry.entry_type == TransactionType::Credit &&
+			bank_account.available_balance < entry.amount
+		{
+			return Err(DomainError::InsufficientFunds);
+		}
+
+		bank_account
+			.try_update(&BankAccountUpdate::Balance {
+				amount: entry.amount,
+				transaction_type: entry.entry_type.clone(),
+			})
+			.await?;
+	}
+
+	for bank_account in bank_accounts.iter() {
+		bank_account::save(client, bank_account).await?;
+	}
+
+	record(client, posting).await
+}
+
+/// Record the entries of a posting, balances are expected to be updated already.
+pub(crate) async fn record<C: GenericClient + Sync>(
+	client: &C,
+	posting: &Posting,
+) -> Result<(), DomainError> {
+	if !posting.is_balanced() {
+		return Err(DomainError::ApiError(format!("Posting {} is not balanced", posting.id)));
+	}
+
+	let stmt = client
+		.prepare(
+			"INSERT INTO ledger_entry (id, posting_id, transaction_id, account, amount, currency) VALUES ($1, $2, $3, $4, $5, $6)",
+		)
+		.await?;
+
+	for entry in posting.entries.iter() {
+		client
+			.execute(
+				&stmt,
+				&[
+					&Uuid::new_v4(),
+					&posting.id,
+					&posting.transaction_id,
+					&entry.account.to_string(),
+					&entry.signed_minor_units(),
+					&entry.amount.currency.alpha(),
+				],
+			)
+			.await?;
+	}
+
+	Ok(())
+}
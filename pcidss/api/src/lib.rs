@@ -1,3 +1,11 @@
 //! Controllers for the
 pub mod bank_account;
+pub mod block_cursor;
+pub mod hold;
+pub mod key_provider;
+pub mod ledger;
+pub mod outbox;
+pub mod processed_message;
+pub mod risk;
+pub mod settlement;
 pub mod transaction;
@@ -0,0 +1,220 @@
+//! Defines the [`PgOutbox`] type and its traits.
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{GenericClient, Pool};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use op_core::{
+	error::DomainError,
+	outbox::{
+		models::{Finality, FinalityCreate, FinalityStatus},
+		traits::OutboxTrait,
+	},
+};
+
+/// Type that will be used to interact with the database.
+pub struct PgOutbox {
+	pool: Arc<Pool>,
+}
+
+impl PgOutbox {
+	pub fn new(pool: Arc<Pool>) -> Self {
+		Self { pool }
+	}
+}
+
+#[async_trait]
+impl OutboxTrait for PgOutbox {
+	async fn create(&self, finality: &FinalityCreate) -> Result<Finality, DomainError> {
+		let client = self.pool.get().await?;
+
+		insert(&client, finality).await
+	}
+
+	async fn find_by_event_id(&self, event_id: &str) -> Result<Option<Finality>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client.prepare("SELECT * FROM finality_outbox WHERE event_id = $1").await?;
+
+		if let Some(result) = client.query_opt(&stmt, &[&event_id]).await? {
+			return Ok(Some((&result).into()));
+		}
+
+		Ok(None)
+	}
+
+	async fn find_by_event_block_hash(
+		&self,
+		block_hash: &str,
+	) -> Result<Vec<Finality>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare(
+				"SELECT * FROM finality_outbox WHERE event_block_hash = $1 ORDER BY created_at",
+			)
+			.await?;
+
+		let rows = client.query(&stmt, &[&block_hash]).await?;
+
+		Ok(rows.iter().map(|row| row.into()).collect())
+	}
+
+	async fn cancel(&self, event_id: &str) -> Result<bool, DomainError> {
+		let client = self.pool.get().await?;
+
+		delete_pending(&client, event_id).await
+	}
+
+	async fn find_due(&self, now: DateTime<Utc>, limit: i64) -> Result<Vec<Finality>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("SELECT * FROM finality_outbox WHERE status IN ($1, $2) AND next_attempt_at <= $3 ORDER BY next_attempt_at, created_at LIMIT $4")
+			.await?;
+
+		let pending: i32 = FinalityStatus::Pending.into();
+		let submitted: i32 = FinalityStatus::Submitted.into();
+
+		let rows = client.query(&stmt, &[&pending, &submitted, &now, &limit]).await?;
+
+		Ok(rows.iter().map(|row| row.into()).collect())
+	}
+
+	async fn mark_submitted(
+		&self,
+		id: &Uuid,
+		extrinsic_hash: &str,
+		retry_at: DateTime<Utc>,
+	) -> Result<Finality, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("UPDATE finality_outbox SET status = $1, extrinsic_hash = $2, next_attempt_at = $3, updated_at = $4 WHERE id = $5 RETURNING *")
+			.await?;
+
+		let submitted: i32 = FinalityStatus::Submitted.into();
+
+		let row = client
+			.query_opt(&stmt, &[&submitted, &extrinsic_hash, &retry_at, &Utc::now(), &id])
+			.await?
+			.ok_or(DomainError::NotFound("Finality not found".to_string()))?;
+
+		Ok((&row).into())
+	}
+
+	async fn mark_finalized(&self, id: &Uuid, block_hash: &str) -> Result<Finality, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("UPDATE finality_outbox SET status = $1, block_hash = $2, last_error = NULL, updated_at = $3 WHERE id = $4 RETURNING *")
+			.await?;
+
+		let finalized: i32 = FinalityStatus::Finalized.into();
+
+		let row = client
+			.query_opt(&stmt, &[&finalized, &block_hash, &Utc::now(), &id])
+			.await?
+			.ok_or(DomainError::NotFound("Finality not found".to_string()))?;
+
+		Ok((&row).into())
+	}
+
+	async fn mark_failed_attempt(
+		&self,
+		id: &Uuid,
+		error: &str,
+		retry_at: Option<DateTime<Utc>>,
+	) -> Result<Finality, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("UPDATE finality_outbox SET status = $1, attempts = attempts + 1, last_error = $2, next_attempt_at = COALESCE($3, next_attempt_at), updated_at = $4 WHERE id = $5 AND status <> $6 RETURNING *")
+			.await?;
+
+		let status: i32 = match retry_at {
+			Some(_) => FinalityStatus::Pending,
+			None => FinalityStatus::Failed,
+		}
+		.into();
+		let finalized: i32 = FinalityStatus::Finalized.into();
+
+		let row = client
+			.query_opt(&stmt, &[&status, &error, &retry_at, &Utc::now(), &id, &finalized])
+			.await?
+			.ok_or(DomainError::NotFound("Finality not found".to_string()))?;
+
+		Ok((&row).into())
+	}
+
+	async fn find_failed(&self) -> Result<Vec<Finality>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("SELECT * FROM finality_outbox WHERE status = $1 ORDER BY created_at")
+			.await?;
+
+		let failed: i32 = FinalityStatus::Failed.into();
+
+		let rows = client.query(&stmt, &[&failed]).await?;
+
+		Ok(rows.iter().map(|row| row.into()).collect())
+	}
+
+	async fn retry_failed(&self) -> Result<u64, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("UPDATE finality_outbox SET status = $1, attempts = 0, next_attempt_at = $2, updated_at = $2 WHERE status = $3")
+			.await?;
+
+		let pending: i32 = FinalityStatus::Pending.into();
+		let failed: i32 = FinalityStatus::Failed.into();
+
+		Ok(client.execute(&stmt, &[&pending, &Utc::now(), &failed]).await?)
+	}
+}
+
+/// Enqueue a finality, the existing one is returned if the event is already enqueued.
+pub(crate) async fn insert<C: GenericClient + Sync>(
+	client: &C,
+	finality: &FinalityCreate,
+) -> Result<Finality, DomainError> {
+	let stmt = client
+		.prepare(
+			"INSERT INTO finality_outbox (id, event_id, event_block_hash, source, recipient, amount, response_code, transaction_hash) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT (event_id) DO NOTHING RETURNING *",
+		)
+		.await?;
+
+	let row = client
+		.query_opt(
+			&stmt,
+			&[
+				&finality.id,
+				&finality.event_id,
+				&finality.event_block_hash,
+				&finality.from,
+				&finality.to,
+				&finality.amount.to_string(),
+				&finality.response_code,
+				&finality.transaction_hash,
+			],
+		)
+		.await?;
+
+	if let Some(row) = row {
+		return Ok((&row).into());
+	}
+
+	let stmt = client.prepare("SELECT * FROM finality_outbox WHERE event_id = $1").await?;
+	let row = client.query_one(&stmt, &[&finality.event_id]).await?;
+
+	Ok((&row).into())
+}
+
+/// Drop the finality of an event if it is not submitted yet, returns whether it is dropped.
+pub(crate) async fn delete_pending<C: GenericClient + Sync>(
+	client: &C,
+	event_id: &str,
+) -> Result<bool, DomainError> {
+	let stmt = client
+		.prepare("DELETE FROM finality_outbox WHERE event_id = $1 AND status = $2")
+		.await?;
+
+	let pending: i32 = FinalityStatus::Pending.into();
+
+	Ok(client.execute(&stmt, &[&event_id, &pending]).await? == 1)
+}
@@ -0,0 +1,93 @@
+//! Defines the [`PgProcessedMessage`] type and its traits.
+use async_trait::async_trait;
+use deadpool_postgres::Pool;
+use std::sync::Arc;
+
+use op_core::{
+	error::DomainError,
+	processed_message::{
+		models::{Claim, ProcessedMessage},
+		traits::ProcessedMessageTrait,
+	},
+};
+
+/// Type that will be used to interact with the database.
+pub struct PgProcessedMessage {
+	pool: Arc<Pool>,
+}
+
+impl PgProcessedMessage {
+	pub fn new(pool: Arc<Pool>) -> Self {
+		Self { pool }
+	}
+}
+
+#[async_trait]
+impl ProcessedMessageTrait for PgProcessedMessage {
+	async fn find_by_key(&self, key: &str) -> Result<Option<ProcessedMessage>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("SELECT * FROM processed_message WHERE idempotency_key = $1")
+			.await?;
+
+		if let Some(result) = client.query_opt(&stmt, &[&key]).await? {
+			return Ok(Some((&result).into()));
+		}
+
+		Ok(None)
+	}
+
+	async fn claim(&self, key: &str) -> Result<Claim, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare(
+				"INSERT INTO processed_message (idempotency_key) VALUES ($1) ON CONFLICT DO NOTHING",
+			)
+			.await?;
+
+		if client.execute(&stmt, &[&key]).await? == 1 {
+			return Ok(Claim::Claimed);
+		}
+
+		// the claim might have been released in the meantime, retrying is up to the sender
+		Ok(self.find_by_key(key).await?.as_ref().map(Claim::from).unwrap_or(Claim::Pending))
+	}
+
+	async fn complete(&self, key: &str, response: &[u8]) -> Result<ProcessedMessage, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("UPDATE processed_message SET response = $1, updated_at = $2 WHERE idempotency_key = $3 AND response IS NULL RETURNING *")
+			.await?;
+
+		let row = client
+			.query_opt(&stmt, &[&response, &chrono::Utc::now(), &key])
+			.await?
+			.ok_or(DomainError::NotFound("Claim not found".to_string()))?;
+
+		Ok((&row).into())
+	}
+
+	async fn release(&self, key: &str) -> Result<(), DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare(
+				"DELETE FROM processed_message WHERE idempotency_key = $1 AND response IS NULL",
+			)
+			.await?;
+
+		client.execute(&stmt, &[&key]).await?;
+
+		Ok(())
+	}
+
+	async fn forget(&self, key: &str) -> Result<(), DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("DELETE FROM processed_message WHERE idempotency_key = $1")
+			.await?;
+
+		client.execute(&stmt, &[&key]).await?;
+
+		Ok(())
+	}
+}
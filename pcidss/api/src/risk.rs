@@ -0,0 +1,100 @@
+//! Defines the [`PgRisk`] type and its traits.
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Pool;
+use uuid::Uuid;
+
+use op_core::{
+	error::DomainError,
+	risk::{
+		models::{CvvFailures, Spending},
+		traits::RiskTrait,
+	},
+};
+
+/// Type that will be used to interact with the database.
+pub struct PgRisk {
+	pool: Arc<Pool>,
+}
+
+impl PgRisk {
+	pub fn new(pool: Arc<Pool>) -> Self {
+		Self { pool }
+	}
+}
+
+#[async_trait]
+impl RiskTrait for PgRisk {
+	async fn spending_since(
+		&self,
+		bank_account_id: &Uuid,
+		since: DateTime<Utc>,
+	) -> Result<Spending, DomainError> {
+		let client = self.pool.get().await?;
+
+		// debits of the bank account, refunds are credits with a parent and holds are counted
+		// until they are captured
+		let stmt = client
+			.prepare(
+				r#"SELECT b.currency,
+					(SELECT COUNT(*) FROM bank_transaction t WHERE t.source = b.id AND t.created_at >= $2 AND t.transaction_type = 1 AND t.parent_id IS NULL AND NOT t.retracted)::int +
+					(SELECT COUNT(*) FROM hold h WHERE h.source = b.id AND h.created_at >= $2 AND h.status = 0)::int AS transactions_number,
+					(SELECT COALESCE(SUM(t.amount), 0) FROM bank_transaction t WHERE t.source = b.id AND t.created_at >= $2 AND t.transaction_type = 1 AND t.parent_id IS NULL AND NOT t.retracted)::bigint +
+					(SELECT COALESCE(SUM(h.amount), 0) FROM hold h WHERE h.source = b.id AND h.created_at >= $2 AND h.status = 0)::bigint AS amount
+				FROM bank_account b WHERE b.id = $1"#,
+			)
+			.await?;
+
+		client
+			.query_opt(&stmt, &[&bank_account_id, &since])
+			.await?
+			.map(|row| Spending::from(&row))
+			.ok_or(DomainError::NotFound("Bank account not found".to_string()))
+	}
+
+	async fn find_cvv_failures(
+		&self,
+		bank_account_id: &Uuid,
+	) -> Result<Option<CvvFailures>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client.prepare("SELECT * FROM cvv_failure WHERE bank_account_id = $1").await?;
+
+		if let Some(result) = client.query_opt(&stmt, &[&bank_account_id]).await? {
+			return Ok(Some((&result).into()));
+		}
+
+		Ok(None)
+	}
+
+	async fn record_cvv_failure(
+		&self,
+		bank_account_id: &Uuid,
+		reset_before: DateTime<Utc>,
+	) -> Result<CvvFailures, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare(
+				r#"INSERT INTO cvv_failure (bank_account_id, attempts, last_failed_at) VALUES ($1, 1, now())
+				ON CONFLICT (bank_account_id) DO UPDATE SET
+					attempts = CASE WHEN cvv_failure.last_failed_at < $2 THEN 1 ELSE cvv_failure.attempts + 1 END,
+					last_failed_at = now()
+				RETURNING *"#,
+			)
+			.await?;
+
+		let row = client.query_one(&stmt, &[&bank_account_id, &reset_before]).await?;
+
+		Ok((&row).into())
+	}
+
+	async fn reset_cvv_failures(&self, bank_account_id: &Uuid) -> Result<(), DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client.prepare("DELETE FROM cvv_failure WHERE bank_account_id = $1").await?;
+
+		client.execute(&stmt, &[&bank_account_id]).await?;
+
+		Ok(())
+	}
+}
@@ -0,0 +1,149 @@
+//! Defines the [`PgSettlement`] type and its traits.
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::Pool;
+use uuid::Uuid;
+
+use op_core::{
+	error::DomainError,
+	settlement::{
+		models::{SettlementBatch, SettlementTotals},
+		traits::SettlementTrait,
+	},
+	transaction::models::Transaction,
+};
+
+/// Transactions of acquirers that are not settled yet, for a `bank_transaction` aliased `t`.
+const UNSETTLED: &str =
+	"t.settlement_batch_id IS NULL AND t.on_chain_id IS NULL AND t.recipient IS NOT NULL";
+
+/// Totals of the transactions `t` from the point of view of the acquirer, credits are the ones
+/// of type credit. Amounts are in the currency of the transaction, the one of the acquirer.
+const TOTALS_COLUMNS: &str = r#"
+	COUNT(t.id) FILTER (WHERE t.transaction_type = 1)::int AS credits_number,
+	COALESCE(SUM(COALESCE(t.original_amount, t.amount)) FILTER (WHERE t.transaction_type = 1), 0)::bigint AS credits_amount,
+	COUNT(t.id) FILTER (WHERE t.transaction_type <> 1)::int AS debits_number,
+	COALESCE(SUM(COALESCE(t.original_amount, t.amount)) FILTER (WHERE t.transaction_type <> 1), 0)::bigint AS debits_amount"#;
+
+/// Type that will be used to interact with the database.
+pub struct PgSettlement {
+	pool: Arc<Pool>,
+}
+
+impl PgSettlement {
+	pub fn new(pool: Arc<Pool>) -> Self {
+		Self { pool }
+	}
+}
+
+#[async_trait]
+impl SettlementTrait for PgSettlement {
+	async fn cut_off(&self, cutoff: DateTime<Utc>) -> Result<Vec<SettlementBatch>, DomainError> {
+		let mut client = self.pool.get().await?;
+		let db_transaction = client.transaction().await?;
+
+		// locked rows can't be settled by a concurrent cut-off, it skips them once they are
+		let rows = db_transaction
+			.query(
+				&format!(
+					"SELECT t.id, t.recipient FROM bank_transaction t WHERE {} AND t.created_at < $1 ORDER BY t.created_at FOR UPDATE",
+					UNSETTLED
+				),
+				&[&cutoff],
+			)
+			.await?;
+
+		let mut transaction_ids: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+		for row in rows.iter() {
+			transaction_ids.entry(row.get("recipient")).or_default().push(row.get("id"));
+		}
+
+		let settlement_date = (cutoff - Duration::nanoseconds(1)).date_naive();
+
+		let insert_stmt = db_transaction
+			.prepare(&format!(
+				r#"INSERT INTO settlement_batch (id, acquirer_id, settlement_date, cutoff, currency, credits_number, credits_amount, debits_number, debits_amount)
+				SELECT $1, $2, $3, $4, b.currency, {}
+				FROM bank_transaction t JOIN bank_account b ON b.id = t.recipient
+				WHERE t.id = ANY($5) GROUP BY b.currency RETURNING *"#,
+				TOTALS_COLUMNS
+			))
+			.await?;
+		let update_stmt = db_transaction
+			.prepare("UPDATE bank_transaction SET settlement_batch_id = $1 WHERE id = ANY($2)")
+			.await?;
+
+		let mut batches = vec![];
+
+		for (acquirer_id, ids) in transaction_ids.iter() {
+			let id = Uuid::new_v4();
+
+			let row = db_transaction
+				.query_one(&insert_stmt, &[&id, acquirer_id, &settlement_date, &cutoff, ids])
+				.await?;
+			db_transaction.execute(&update_stmt, &[&id, ids]).await?;
+
+			batches.push(SettlementBatch::from(&row));
+		}
+
+		db_transaction.commit().await?;
+
+		batches.sort_by_key(|batch| batch.acquirer_id);
+
+		Ok(batches)
+	}
+
+	async fn find_by_id(&self, id: &Uuid) -> Result<Option<SettlementBatch>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client.prepare("SELECT * FROM settlement_batch WHERE id = $1").await?;
+
+		if let Some(result) = client.query_opt(&stmt, &[&id]).await? {
+			return Ok(Some((&result).into()));
+		}
+
+		Ok(None)
+	}
+
+	async fn find_by_acquirer(
+		&self,
+		acquirer_id: &Uuid,
+	) -> Result<Vec<SettlementBatch>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("SELECT * FROM settlement_batch WHERE acquirer_id = $1 ORDER BY cutoff DESC, created_at DESC")
+			.await?;
+
+		let result = client.query(&stmt, &[&acquirer_id]).await?;
+
+		Ok(result.iter().map(|row| row.into()).collect())
+	}
+
+	async fn find_transactions(&self, batch_id: &Uuid) -> Result<Vec<Transaction>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("SELECT * FROM bank_transaction WHERE settlement_batch_id = $1 ORDER BY created_at, id")
+			.await?;
+
+		let result = client.query(&stmt, &[&batch_id]).await?;
+
+		Ok(result.iter().map(|row| row.into()).collect())
+	}
+
+	async fn open_totals(&self, acquirer_id: &Uuid) -> Result<SettlementTotals, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare(&format!(
+				"SELECT b.currency, {} FROM bank_account b LEFT JOIN bank_transaction t ON t.recipient = b.id AND {} WHERE b.id = $1 GROUP BY b.currency",
+				TOTALS_COLUMNS, UNSETTLED
+			))
+			.await?;
+
+		client
+			.query_opt(&stmt, &[&acquirer_id])
+			.await?
+			.map(|row| SettlementTotals::from_row(&row))
+			.ok_or(DomainError::NotFound("Bank account not found".to_string()))
+	}
+}
@@ -1,16 +1,26 @@
 use async_trait::async_trait;
-use deadpool_postgres::Pool;
+use deadpool_postgres::{GenericClient, Pool};
 use std::sync::Arc;
+use tokio_postgres::types::ToSql;
 use uuid::Uuid;
 
 use op_core::{
 	error::DomainError,
+	ledger::models::Posting,
+	money::Money,
+	outbox::models::FinalityCreate,
 	transaction::{
-		models::{Transaction, TransactionCreate},
+		models::{
+			SortOrder, Transaction, TransactionCreate, TransactionDirection, TransactionPage,
+			TransactionQuery,
+		},
 		traits::TransactionTrait,
 	},
+	types::TransactionType,
 };
 
+use crate::{bank_account, ledger, outbox};
+
 /// Type that will be used to interact with the database.
 pub struct PgTransaction {
 	pool: Arc<Pool>,
@@ -41,7 +51,7 @@ impl TransactionTrait for PgTransaction {
 	) -> Result<Vec<Transaction>, DomainError> {
 		let client = self.pool.get().await?;
 		let stmt = client
-			.prepare("SELECT * FROM bank_transaction WHERE source = $1 OR recipient = $1")
+			.prepare("SELECT * FROM bank_transaction WHERE source = $1 OR recipient = $1 ORDER BY created_at, id")
 			.await?;
 
 		let result = client.query(&stmt, &[&source]).await?;
@@ -49,6 +59,65 @@ impl TransactionTrait for PgTransaction {
 		Ok(result.iter().map(|row| (row).into()).collect())
 	}
 
+	async fn find_page(
+		&self,
+		bank_account_id: &Uuid,
+		query: &TransactionQuery,
+	) -> Result<TransactionPage, DomainError> {
+		let client = self.pool.get().await?;
+		let page_size = query.page_size();
+		let limit = page_size as i64 + 1;
+
+		let mut params: Vec<&(dyn ToSql + Sync)> = vec![bank_account_id];
+		let mut bind = |param: &'static str, value| {
+			params.push(value);
+			param.replace('?', &format!("${}", params.len()))
+		};
+
+		let mut conditions = vec![match query.direction {
+			None => "(source = $1 OR recipient = $1)".to_string(),
+			Some(TransactionDirection::Outgoing) => "source = $1".to_string(),
+			Some(TransactionDirection::Incoming) => "recipient = $1".to_string(),
+		}];
+
+		if let Some(since) = &query.since {
+			conditions.push(bind("created_at >= ?", since));
+		}
+		if let Some(until) = &query.until {
+			conditions.push(bind("created_at < ?", until));
+		}
+		if let Some(reversed) = &query.reversed {
+			conditions.push(bind("reversed = ?", reversed));
+		}
+		if let Some(min_amount) = &query.min_amount {
+			conditions.push(bind("amount >= ?", min_amount));
+		}
+		if let Some(max_amount) = &query.max_amount {
+			conditions.push(bind("amount <= ?", max_amount));
+		}
+
+		let (comparison, order) = match query.sort_order() {
+			SortOrder::Asc => (">", "ASC"),
+			SortOrder::Desc => ("<", "DESC"),
+		};
+		if let Some(after) = &query.after {
+			let created_at = bind("?", &after.created_at);
+			let id = bind("?", &after.id);
+			conditions.push(format!("(created_at, id) {} ({}, {})", comparison, created_at, id));
+		}
+
+		let limit = bind("?", &limit);
+		let sql = format!(
+			"SELECT * FROM bank_transaction WHERE {} ORDER BY created_at {order}, id {order} LIMIT {}",
+			conditions.join(" AND "),
+			limit,
+		);
+
+		let rows = client.query(&sql, &params).await?;
+
+		Ok(TransactionPage::new(rows.iter().map(|row| row.into()).collect(), page_size))
+	}
+
 	async fn find_by_hash(&self, hash: &str) -> Result<Option<Transaction>, DomainError> {
 		let client = self.pool.get().await?;
 		let stmt = client.prepare("SELECT * FROM bank_transaction WHERE hash = $1").await?;
@@ -60,43 +129,316 @@ impl TransactionTrait for PgTransaction {
 		Ok(None)
 	}
 
+	async fn find_by_on_chain_id(
+		&self,
+		on_chain_id: &str,
+	) -> Result<Option<Transaction>, DomainError> {
+		let client = self.pool.get().await?;
+		let stmt = client
+			.prepare("SELECT * FROM bank_transaction WHERE on_chain_id = $1 AND NOT retracted")
+			.await?;
+
+		if let Some(result) = client.query_opt(&stmt, &[&on_chain_id]).await? {
+			return Ok(Some((&result).into()));
+		}
+
+		Ok(None)
+	}
+
 	async fn create(
 		&self,
 		transaction_create: &TransactionCreate,
 	) -> Result<Transaction, DomainError> {
 		let client = self.pool.get().await?;
-		let stmt = client
-            .prepare(
-                "INSERT INTO bank_transaction (id, hash, source, recipient, amount, transaction_type) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
-            )
-            .await?;
-		let transaction: Transaction = transaction_create.into();
-
-		let row = client
-			.query_one(
+
+		insert(&client, &transaction_create.into()).await
+	}
+
+	async fn transfer(
+		&self,
+		transaction_create: &TransactionCreate,
+	) -> Result<Transaction, DomainError> {
+		let mut client = self.pool.get().await?;
+		let db_transaction = client.transaction().await?;
+
+		let ids: Vec<Uuid> =
+			std::iter::once(transaction_create.from).chain(transaction_create.to).collect();
+		let mut bank_accounts = bank_account::lock_for_update(&db_transaction, &ids).await?;
+
+		let source = bank_accounts
+			.iter()
+			.find(|bank_account| bank_account.id == transaction_create.from)
+			.ok_or(DomainError::NotFound("Bank account not found".to_string()))?;
+
+		// hash is computed with the nonce of the locked row, so that concurrent transfers
+		// with the same ISO message still get unique hashes
+		let transaction: Transaction =
+			(&TransactionCreate { nonce: source.nonce, ..transaction_create.clone() }).into();
+
+		let posting = Posting::for_transaction(
+			&transaction,
+			&transaction_create.transaction_type,
+			transaction.amount,
+			transaction.recipient_amount(),
+		);
+
+		ledger::post(&db_transaction, &mut bank_accounts, &posting).await?;
+
+		let transaction = insert(&db_transaction, &transaction).await?;
+
+		if let Some(finality) = &transaction_create.finality {
+			outbox::insert(
+				&db_transaction,
+				&FinalityCreate { transaction_hash: transaction.hash.clone(), ..finality.clone() },
+			)
+			.await?;
+		}
+
+		db_transaction.commit().await?;
+
+		Ok(transaction)
+	}
+
+	async fn revert(
+		&self,
+		id: &Uuid,
+		refund: &TransactionCreate,
+	) -> Result<Transaction, DomainError> {
+		let mut client = self.pool.get().await?;
+		let db_transaction = client.transaction().await?;
+
+		let stmt = db_transaction
+			.prepare("SELECT * FROM bank_transaction WHERE id = $1 FOR UPDATE")
+			.await?;
+
+		let parent: Transaction = db_transaction
+			.query_opt(&stmt, &[&id])
+			.await?
+			.map(|row| (&row).into())
+			.ok_or(DomainError::NotFound("Transaction not found".to_string()))?;
+
+		if parent.reversed {
+			return Err(DomainError::BadRequest("Transaction already reversed".to_string()));
+		}
+
+		if parent.retracted {
+			return Err(DomainError::BadRequest("Transaction is retracted".to_string()));
+		}
+
+		if parent.parent_id.is_some() {
+			return Err(DomainError::BadRequest("Refunds can not be reversed".to_string()));
+		}
+
+		if refund.from != parent.from || refund.to != parent.to {
+			return Err(DomainError::BadRequest(
+				"Refund parties do not match the transaction".to_string(),
+			));
+		}
+
+		if refund.amount.is_zero() || refund.amount > parent.refundable_amount() {
+			return Err(DomainError::BadRequest(
+				"Refund amount exceeds refundable amount".to_string(),
+			));
+		}
+
+		let ids: Vec<Uuid> = std::iter::once(parent.from).chain(parent.to).collect();
+		let mut bank_accounts = bank_account::lock_for_update(&db_transaction, &ids).await?;
+
+		let source = bank_accounts
+			.iter()
+			.find(|bank_account| bank_account.id == parent.from)
+			.ok_or(DomainError::NotFound("Bank account not found".to_string()))?;
+
+		// refund moves the funds in the opposite direction of the original transaction
+		let transaction_type = TransactionType::from(parent.transaction_type).inverse();
+
+		let mut transaction: Transaction = (&TransactionCreate {
+			nonce: source.nonce,
+			transaction_type: transaction_type.clone(),
+			..refund.clone()
+		})
+			.into();
+		transaction.parent_id = Some(parent.id);
+
+		if transaction.recipient_amount() >
+			unrefunded_recipient_amount(&db_transaction, &parent).await?
+		{
+			return Err(DomainError::BadRequest(
+				"Refund amount exceeds refundable amount".to_string(),
+			));
+		}
+
+		// funds move between the parties of the original transaction
+		let posting = Posting {
+			transaction_id: Some(transaction.id),
+			..Posting::for_transaction(
+				&parent,
+				&transaction_type,
+				transaction.amount,
+				transaction.recipient_amount(),
+			)
+		};
+
+		ledger::post(&db_transaction, &mut bank_accounts, &posting).await?;
+
+		let transaction = insert(&db_transaction, &transaction).await?;
+
+		let refunded_amount = parent.refunded_amount.checked_add(&transaction.amount)?;
+		let stmt = db_transaction
+			.prepare("UPDATE bank_transaction SET refunded_amount = $1, reversed = $2, updated_at = $3 WHERE id = $4")
+			.await?;
+
+		db_transaction
+			.execute(
 				&stmt,
 				&[
-					&transaction.id,
-					&transaction.hash,
-					&transaction.from,
-					&transaction.to,
-					&(transaction.amount as i32),
-					&(transaction.transaction_type as i32),
+					&refunded_amount.minor_units,
+					&(refunded_amount == parent.amount),
+					&chrono::Utc::now(),
+					&id,
 				],
 			)
 			.await?;
 
-		Ok((&row).into())
+		// the chain refers to the reverted transaction by its hash
+		if let Some(finality) = &refund.finality {
+			outbox::insert(
+				&db_transaction,
+				&FinalityCreate { transaction_hash: parent.hash.clone(), ..finality.clone() },
+			)
+			.await?;
+		}
+
+		db_transaction.commit().await?;
+
+		Ok(transaction)
 	}
 
-	async fn update(&self, id: &Uuid) -> Result<Transaction, DomainError> {
-		let client = self.pool.get().await?;
-		let stmt = client
-			.prepare("UPDATE bank_transaction SET reversed = true WHERE id = $1 RETURNING *")
+	async fn retract(&self, id: &Uuid) -> Result<Transaction, DomainError> {
+		let mut client = self.pool.get().await?;
+		let db_transaction = client.transaction().await?;
+
+		let stmt = db_transaction
+			.prepare("SELECT * FROM bank_transaction WHERE id = $1 FOR UPDATE")
+			.await?;
+
+		let transaction: Transaction = db_transaction
+			.query_opt(&stmt, &[&id])
+			.await?
+			.map(|row| (&row).into())
+			.ok_or(DomainError::NotFound("Transaction not found".to_string()))?;
+
+		if transaction.retracted {
+			return Err(DomainError::BadRequest("Transaction already retracted".to_string()));
+		}
+
+		// refunds of the transaction stay in place, only the rest is moved back
+		let (amount, recipient_amount) = match transaction.parent_id {
+			Some(_) => (transaction.amount, transaction.recipient_amount()),
+			None => (
+				transaction.refundable_amount(),
+				unrefunded_recipient_amount(&db_transaction, &transaction).await?,
+			),
+		};
+
+		if !amount.is_zero() {
+			let ids: Vec<Uuid> = std::iter::once(transaction.from).chain(transaction.to).collect();
+			let mut bank_accounts = bank_account::lock_for_update(&db_transaction, &ids).await?;
+
+			let transaction_type = TransactionType::from(transaction.transaction_type);
+
+			let posting = Posting::for_transaction(
+				&transaction,
+				&transaction_type.inverse(),
+				amount,
+				recipient_amount,
+			);
+
+			ledger::post(&db_transaction, &mut bank_accounts, &posting).await?;
+		}
+
+		// the parent of a retracted refund is refundable again
+		if let Some(parent_id) = transaction.parent_id {
+			let stmt = db_transaction
+				.prepare("UPDATE bank_transaction SET refunded_amount = refunded_amount - $1, reversed = false, updated_at = $2 WHERE id = $3")
+				.await?;
+
+			db_transaction
+				.execute(&stmt, &[&transaction.amount.minor_units, &chrono::Utc::now(), &parent_id])
+				.await?;
+		}
+
+		let stmt = db_transaction
+			.prepare("UPDATE bank_transaction SET retracted = true, updated_at = $1 WHERE id = $2 RETURNING *")
 			.await?;
 
-		let row = client.query_one(&stmt, &[&id]).await?;
+		let row = db_transaction.query_one(&stmt, &[&chrono::Utc::now(), &id]).await?;
+
+		if let Some(on_chain_id) = &transaction.on_chain_id {
+			outbox::delete_pending(&db_transaction, on_chain_id).await?;
+		}
+
+		db_transaction.commit().await?;
 
 		Ok((&row).into())
 	}
 }
+
+/// Insert a transaction into the database.
+pub(crate) async fn insert<C: GenericClient + Sync>(
+	client: &C,
+	transaction: &Transaction,
+) -> Result<Transaction, DomainError> {
+	let stmt = client
+		.prepare(
+			"INSERT INTO bank_transaction (id, hash, source, recipient, amount, currency, transaction_type, parent_id, on_chain_id, block_hash, original_amount, original_currency, fx_rate, fx_rate_version) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14) RETURNING *",
+		)
+		.await?;
+
+	let conversion = transaction.conversion.as_ref();
+
+	let row = client
+		.query_one(
+			&stmt,
+			&[
+				&transaction.id,
+				&transaction.hash,
+				&transaction.from,
+				&transaction.to,
+				&transaction.amount.minor_units,
+				&transaction.amount.currency.alpha(),
+				&(transaction.transaction_type as i32),
+				&transaction.parent_id,
+				&transaction.on_chain_id,
+				&transaction.block_hash,
+				&conversion.map(|conversion| conversion.amount.minor_units),
+				&conversion.map(|conversion| conversion.amount.currency.alpha()),
+				&conversion.map(|conversion| conversion.rate.to_field()),
+				&conversion.map(|conversion| conversion.rate_version as i32),
+			],
+		)
+		.await?;
+
+	Ok((&row).into())
+}
+
+/// Amount of the receiving bank account that is not refunded yet.
+///
+/// Refunds of a converted transaction are converted with its rate, the amounts of the
+/// receiving bank account are summed up in the currency of the transaction.
+async fn unrefunded_recipient_amount<C: GenericClient + Sync>(
+	client: &C,
+	transaction: &Transaction,
+) -> Result<Money, DomainError> {
+	let Some(conversion) = transaction.conversion else {
+		return Ok(transaction.refundable_amount());
+	};
+
+	let stmt = client
+		.prepare("SELECT COALESCE(SUM(original_amount), 0)::bigint FROM bank_transaction WHERE parent_id = $1 AND NOT retracted")
+		.await?;
+
+	let refunded: i64 = client.query_one(&stmt, &[&transaction.id]).await?.get(0);
+
+	conversion.amount.checked_sub(&Money::new(refunded, conversion.amount.currency))
+}
@@ -0,0 +1,15 @@
+alter table bank_transaction add column if not exists block_hash varchar(66);
+alter table bank_transaction add column if not exists retracted boolean not null default false;
+
+-- events of a retracted block are emitted again with the same ids by the block replacing it
+drop index if exists on_chain_id_unique_index;
+create unique index if not exists on_chain_id_unique_index on bank_transaction (on_chain_id) where on_chain_id is not null and not retracted;
+
+alter table finality_outbox add column if not exists event_block_hash varchar(66) not null default '';
+create index if not exists finality_outbox_event_block_hash_index on finality_outbox (event_block_hash);
+
+create table if not exists tracked_block (
+    block_number bigint primary key,
+    block_hash varchar(66) not null,
+    created_at timestamptz default now()
+);
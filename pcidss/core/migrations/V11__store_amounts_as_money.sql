@@ -0,0 +1,8 @@
+alter table bank_account alter column balance type bigint, alter column available_balance type bigint;
+alter table bank_account add column if not exists currency char(3) not null default 'USD';
+
+alter table bank_transaction alter column amount type bigint, alter column refunded_amount type bigint;
+alter table bank_transaction add column if not exists currency char(3) not null default 'USD';
+
+alter table hold alter column amount type bigint;
+alter table hold add column if not exists currency char(3) not null default 'USD';
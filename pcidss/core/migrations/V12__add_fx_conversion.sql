@@ -0,0 +1,10 @@
+-- amount in the currency of the transaction, if it is converted to the currency of the bank account
+alter table bank_transaction add column if not exists original_amount bigint;
+alter table bank_transaction add column if not exists original_currency char(3);
+alter table bank_transaction add column if not exists fx_rate char(8);
+alter table bank_transaction add column if not exists fx_rate_version integer;
+
+alter table hold add column if not exists original_amount bigint;
+alter table hold add column if not exists original_currency char(3);
+alter table hold add column if not exists fx_rate char(8);
+alter table hold add column if not exists fx_rate_version integer;
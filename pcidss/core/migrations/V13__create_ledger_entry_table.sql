@@ -0,0 +1,23 @@
+create table if not exists ledger_entry (
+    id uuid primary key,
+    posting_id uuid not null,
+    transaction_id uuid,
+    account varchar(64) not null,
+    -- change of the balance of the account, negative if deducted
+    amount bigint not null,
+    currency char(3) not null,
+    created_at timestamptz not null default now()
+);
+
+create index if not exists ledger_entry_account_idx on ledger_entry (account, created_at);
+create index if not exists ledger_entry_posting_idx on ledger_entry (posting_id);
+create index if not exists ledger_entry_transaction_idx on ledger_entry (transaction_id);
+
+-- current balances are the opening balances of the journal
+with opening as (
+    select id, balance, currency, gen_random_uuid() as posting_id from bank_account where balance <> 0
+)
+insert into ledger_entry (id, posting_id, account, amount, currency)
+select gen_random_uuid(), posting_id, 'bank_account:' || id, balance, currency from opening
+union all
+select gen_random_uuid(), posting_id, 'opening', -balance, currency from opening;
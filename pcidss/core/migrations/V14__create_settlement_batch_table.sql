@@ -0,0 +1,20 @@
+create table if not exists settlement_batch (
+    id uuid primary key,
+    acquirer_id uuid not null,
+    settlement_date date not null,
+    cutoff timestamptz not null,
+    currency char(3) not null,
+    credits_number int not null,
+    credits_amount bigint not null,
+    debits_number int not null,
+    debits_amount bigint not null,
+    created_at timestamptz not null default now()
+);
+
+create index if not exists settlement_batch_acquirer_idx on settlement_batch (acquirer_id, settlement_date);
+
+alter table bank_transaction add column if not exists settlement_batch_id uuid references settlement_batch(id);
+
+-- transactions of acquirers that are not settled yet
+create index if not exists bank_transaction_unsettled_idx on bank_transaction (recipient, created_at)
+    where settlement_batch_id is null and on_chain_id is null;
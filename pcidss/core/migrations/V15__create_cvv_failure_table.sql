@@ -0,0 +1,9 @@
+create table if not exists cvv_failure (
+    bank_account_id uuid primary key references bank_account(id),
+    attempts int not null,
+    last_failed_at timestamptz not null default now()
+);
+
+-- spending of the bank accounts assessed by the risk rules
+create index if not exists bank_transaction_source_created_at_idx on bank_transaction (source, created_at);
+create index if not exists hold_source_created_at_idx on hold (source, created_at) where status = 0;
@@ -0,0 +1,13 @@
+alter table bank_account add column if not exists card_status int not null default 0;
+
+create table if not exists card_status_history (
+    id uuid primary key,
+    bank_account_id uuid not null references bank_account(id),
+    from_status int not null,
+    to_status int not null,
+    reason text,
+    card_number_masked varchar(63) not null,
+    created_at timestamptz not null default now()
+);
+
+create index if not exists card_status_history_bank_account_idx on card_status_history (bank_account_id, created_at);
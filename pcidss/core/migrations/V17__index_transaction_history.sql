@@ -0,0 +1,11 @@
+-- timestamps of the transactions are exposed, they are always set
+update bank_transaction set created_at = now() where created_at is null;
+update bank_transaction set updated_at = created_at where updated_at is null;
+alter table bank_transaction alter column created_at set not null, alter column updated_at set not null;
+
+-- transaction history of the bank accounts, paginated by (created_at, id)
+create index if not exists bank_transaction_source_history_idx on bank_transaction (source, created_at, id);
+create index if not exists bank_transaction_recipient_history_idx on bank_transaction (recipient, created_at, id);
+
+-- superseded by bank_transaction_source_history_idx
+drop index if exists bank_transaction_source_created_at_idx;
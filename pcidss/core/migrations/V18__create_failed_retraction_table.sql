@@ -0,0 +1,7 @@
+-- retracted blocks whose events couldn't be compensated, they need manual intervention
+create table if not exists failed_retraction (
+    block_hash varchar(66) primary key,
+    block_number bigint not null,
+    error text not null,
+    created_at timestamptz not null default now()
+);
@@ -0,0 +1,20 @@
+alter table bank_account add column if not exists available_balance int default 0;
+update bank_account set available_balance = balance;
+
+create table if not exists hold (
+    id uuid primary key,
+    hash varchar(256) not null unique,
+    source uuid not null,
+    recipient uuid,
+    amount int not null,
+    status int not null default 0,
+    transaction_id uuid,
+    expires_at timestamptz not null,
+    created_at timestamptz default now(),
+    updated_at timestamptz default now(),
+    foreign key (source) references bank_account(id),
+    foreign key (recipient) references bank_account(id),
+    foreign key (transaction_id) references bank_transaction(id)
+);
+
+CREATE INDEX hold_active_expires_at_index ON hold (expires_at) WHERE status = 0;
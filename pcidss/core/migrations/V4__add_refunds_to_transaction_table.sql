@@ -0,0 +1,6 @@
+alter table bank_transaction add column if not exists parent_id uuid references bank_transaction(id);
+alter table bank_transaction add column if not exists refunded_amount int not null default 0;
+
+update bank_transaction set refunded_amount = amount where reversed = true;
+
+CREATE INDEX bank_transaction_parent_id_index ON bank_transaction (parent_id) WHERE parent_id IS NOT NULL;
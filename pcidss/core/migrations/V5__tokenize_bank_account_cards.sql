@@ -0,0 +1,9 @@
+alter table bank_account add column if not exists card_number_hash char(64) unique;
+alter table bank_account add column if not exists card_number_encrypted bytea;
+alter table bank_account add column if not exists card_number_masked varchar(63);
+alter table bank_account add column if not exists card_cvv_hash varchar(128);
+
+-- plaintext card data of existing rows is tokenized by the oracle on startup, since the vault
+-- key is not available to the database, and cleared afterwards
+alter table bank_account alter column card_number drop not null;
+alter table bank_account alter column card_cvv drop not null;
@@ -0,0 +1,12 @@
+-- existing card numbers are encrypted directly with a key derived from the vault master key,
+-- they are sealed with envelope encryption by `pcidss-oracle reencrypt`
+alter table bank_account add column if not exists key_version int not null default 0;
+alter table bank_account add column if not exists data_key bytea;
+alter table bank_account add column if not exists card_holder_first_name_encrypted bytea;
+alter table bank_account add column if not exists card_holder_last_name_encrypted bytea;
+
+-- plaintext names are cleared once the row is re-encrypted
+alter table bank_account alter column card_holder_first_name drop not null;
+alter table bank_account alter column card_holder_last_name drop not null;
+
+CREATE INDEX bank_account_key_version_index ON bank_account (key_version);
@@ -0,0 +1,6 @@
+create table if not exists processed_message (
+    idempotency_key char(64) primary key,
+    response bytea,
+    created_at timestamptz default now(),
+    updated_at timestamptz default now()
+);
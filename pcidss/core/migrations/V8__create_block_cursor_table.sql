@@ -0,0 +1,5 @@
+create table if not exists block_cursor (
+    name varchar(64) primary key,
+    block_number bigint not null,
+    updated_at timestamptz default now()
+);
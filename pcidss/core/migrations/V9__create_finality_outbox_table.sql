@@ -0,0 +1,19 @@
+create table if not exists finality_outbox (
+    id uuid primary key,
+    event_id varchar(64) not null unique,
+    source char(64) not null,
+    recipient char(64) not null,
+    amount varchar(40) not null,
+    response_code char(2) not null,
+    transaction_hash varchar(256) not null,
+    status integer not null default 0,
+    attempts integer not null default 0,
+    last_error text,
+    extrinsic_hash varchar(66),
+    block_hash varchar(66),
+    next_attempt_at timestamptz not null default now(),
+    created_at timestamptz default now(),
+    updated_at timestamptz default now()
+);
+
+create index if not exists finality_outbox_due_idx on finality_outbox (next_attempt_at) where status in (0, 1);
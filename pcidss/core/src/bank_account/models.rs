@@ -4,25 +4,31 @@ use chrono::{DateTime, Months, Utc};
 use tokio_postgres::Row;
 use uuid::Uuid;
 
-use crate::{error::DomainError, types::TransactionType};
+use crate::{
+	error::DomainError,
+	money::{Iso4217, Money},
+	types::TransactionType,
+	vault::models::{CardToken, EncryptedCardData},
+};
 
 /// `BankAccountCreate` is a model for creating a bank account.
 #[derive(Debug, Clone)]
 pub struct BankAccountCreate {
 	/// Unique identifier of the bank account.
 	pub id: Uuid,
-	/// Card number linked to the bank account, should be 16 digits.
+	/// Card number linked to the bank account, should be 16 digits, tokenized on creation.
 	pub card_number: String,
-	/// Card holder first name.
+	/// Card holder first name, encrypted on creation.
 	pub card_holder_first_name: String,
-	/// Card holder last name.
+	/// Card holder last name, encrypted on creation.
 	pub card_holder_last_name: String,
 	/// Card expiration date.
 	pub card_expiration_date: DateTime<Utc>,
-	/// Card CVV.
+	/// Card CVV, only its salted hash is persisted.
 	pub card_cvv: String,
-	/// Balance of the bank account, can be set in test mode.
-	pub balance: u32,
+	/// Balance of the bank account, can be set in test mode. Its currency is the currency of the
+	/// bank account.
+	pub balance: Money,
 	/// Account ID on the blockchain.
 	pub account_id: Option<String>,
 }
@@ -46,27 +52,149 @@ impl BankAccountCreate {
 				.expect("valid date"),
 			card_holder_last_name,
 			card_cvv,
-			balance: 0,
+			balance: Money::zero(Iso4217::default()),
 			account_id,
 		}
 	}
 }
 
+/// `CardStatus` is an enum for the lifecycle of the card of a bank account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CardStatus {
+	/// Card can be used.
+	#[default]
+	Active,
+	/// Card is frozen temporarily, e.g. by its holder.
+	Blocked,
+	/// Card is reported lost, it is reissued with a new card number.
+	Lost,
+	/// Card is reported stolen, it is reissued with a new card number.
+	Stolen,
+	/// Card is closed for good.
+	Closed,
+}
+
+impl CardStatus {
+	/// Whether the status can be changed to `to` by a status update.
+	///
+	/// Closed cards can't change anymore, lost and stolen cards can only be closed or reissued.
+	pub fn can_change_to(&self, to: CardStatus) -> bool {
+		match (self, to) {
+			(CardStatus::Closed, _) => false,
+			(CardStatus::Lost | CardStatus::Stolen, to) => to == CardStatus::Closed,
+			(from, to) => *from != to,
+		}
+	}
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<i32> for CardStatus {
+	fn into(self) -> i32 {
+		match self {
+			CardStatus::Active => 0,
+			CardStatus::Blocked => 1,
+			CardStatus::Lost => 2,
+			CardStatus::Stolen => 3,
+			CardStatus::Closed => 4,
+		}
+	}
+}
+
+impl From<i32> for CardStatus {
+	fn from(value: i32) -> Self {
+		match value {
+			0 => CardStatus::Active,
+			1 => CardStatus::Blocked,
+			2 => CardStatus::Lost,
+			3 => CardStatus::Stolen,
+			_ => CardStatus::Closed,
+		}
+	}
+}
+
+/// `CardStatusChange` is a model for an entry of the card status history of a bank account.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct CardStatusChange {
+	/// Unique identifier of the change.
+	pub id: Uuid,
+	/// Unique identifier of the bank account.
+	pub bank_account_id: Uuid,
+	/// Status before the change.
+	pub from_status: CardStatus,
+	/// Status after the change.
+	pub to_status: CardStatus,
+	/// Reason of the change, if given.
+	pub reason: Option<String>,
+	/// Masked card number the change applies to, the previous one if the card is reissued.
+	pub card_number_masked: String,
+	/// Time of the change.
+	pub created_at: DateTime<Utc>,
+}
+
+impl From<&Row> for CardStatusChange {
+	fn from(row: &Row) -> Self {
+		Self {
+			id: row.get("id"),
+			bank_account_id: row.get("bank_account_id"),
+			from_status: row.get::<&str, i32>("from_status").into(),
+			to_status: row.get::<&str, i32>("to_status").into(),
+			reason: row.get("reason"),
+			card_number_masked: row.get("card_number_masked"),
+			created_at: row.get("created_at"),
+		}
+	}
+}
+
 /// `BankAccountUpdate` is a model for updating a bank account.
 #[derive(Debug, Clone)]
 pub enum BankAccountUpdate {
 	/// Balance update.
 	Balance {
 		/// Amount of change to the balance.
-		amount: u32,
+		amount: Money,
 		/// Type of change to the balance.
 		transaction_type: TransactionType,
 	},
+	/// Reserve funds for an authorization, only available balance is affected.
+	Hold {
+		/// Amount to reserve.
+		amount: Money,
+	},
+	/// Return reserved funds back to the available balance.
+	Release {
+		/// Amount to release.
+		amount: Money,
+	},
 	/// Update bank account info.
 	Info {
 		/// AccountId on the blockchain.
 		account_id: Option<String>,
 	},
+	/// Change the status of the card.
+	Status {
+		/// New status of the card.
+		status: CardStatus,
+		/// Reason of the change, recorded in the history.
+		reason: Option<String>,
+	},
+	/// Renew the card with a later expiration date, the card number is kept.
+	Renew {
+		/// New card expiration date.
+		card_expiration_date: DateTime<Utc>,
+		/// New card CVV, the current one is kept if not set.
+		card_cvv: Option<String>,
+	},
+	/// Reissue the card with a new card number, e.g. after it is lost or stolen. The card is
+	/// active again.
+	Reissue {
+		/// New card number, tokenized on update.
+		card_number: String,
+		/// New card CVV, only its salted hash is persisted.
+		card_cvv: String,
+		/// New card expiration date.
+		card_expiration_date: DateTime<Utc>,
+	},
 }
 
 /// Extremely simplified, dummy version of a bank account model.
@@ -74,45 +202,52 @@ pub enum BankAccountUpdate {
 pub struct BankAccount {
 	/// Unique identifier of the bank account.
 	pub id: Uuid,
-	/// Card number linked to the bank account, should be 16 digits.
-	pub card_number: String,
-	/// Card holder first name.
-	pub card_holder_first_name: String,
-	/// Card holder last name.
-	pub card_holder_last_name: String,
+	/// Masked card number, only the first 6 and the last 4 digits are visible.
+	pub card_number_masked: String,
+	/// Keyed hash of the card number, used for lookups.
+	#[serde(skip)]
+	pub card_number_hash: String,
+	/// Card number and card holder names sealed by the vault.
+	#[serde(skip)]
+	pub card_data: EncryptedCardData,
 	/// Card expiration date.
 	pub card_expiration_date: DateTime<Utc>,
-	/// Card CVV.
-	pub card_cvv: String,
-	/// Balance of the bank account.
-	pub balance: u32,
+	/// Salted hash of the card CVV, the CVV itself is never stored.
+	#[serde(skip)]
+	pub card_cvv_hash: String,
+	/// Ledger balance of the bank account, in the currency of the bank account.
+	pub balance: Money,
+	/// Available balance of the bank account, ledger balance minus active holds.
+	pub available_balance: Money,
 	/// Nonce of the bank account.
 	pub nonce: u32,
 	/// Account ID on the blockchain.
 	pub account_id: Option<String>,
+	/// Status of the card.
+	pub card_status: CardStatus,
 }
 
 impl BankAccount {
 	/// Creates a new `BankAccount`.
 	pub fn new(
-		card_number: String,
-		card_holder_first_name: String,
-		card_holder_last_name: String,
+		card_token: CardToken,
 		card_expiration_date: DateTime<Utc>,
-		card_cvv: String,
-		balance: u32,
+		card_cvv_hash: String,
+		balance: Money,
 		nonce: u32,
 	) -> Self {
 		Self {
 			id: Uuid::new_v4(),
-			card_number,
-			card_holder_first_name,
-			card_holder_last_name,
+			card_number_masked: card_token.masked,
+			card_number_hash: card_token.hash,
+			card_data: card_token.encrypted,
 			card_expiration_date,
-			card_cvv,
+			card_cvv_hash,
 			balance,
+			available_balance: balance,
 			nonce,
 			account_id: None,
+			card_status: CardStatus::Active,
 		}
 	}
 
@@ -125,18 +260,38 @@ impl BankAccount {
 	) -> Result<(), DomainError> {
 		match bank_account_update {
 			BankAccountUpdate::Balance { amount, transaction_type } => {
-				self.balance = match transaction_type {
-					TransactionType::Debit => self.balance.checked_add(*amount),
-					TransactionType::Credit => self.balance.checked_sub(*amount),
+				let (balance, available_balance) = match transaction_type {
+					TransactionType::Debit => (
+						self.balance.checked_add(amount)?,
+						self.available_balance.checked_add(amount)?,
+					),
+					TransactionType::Credit => (
+						self.balance.checked_sub(amount)?,
+						self.available_balance.checked_sub(amount)?,
+					),
+				};
+
+				self.balance = balance;
+				self.available_balance = available_balance;
+
+				self.increment_nonce()
+			},
+			BankAccountUpdate::Hold { amount } => {
+				self.available_balance = self.available_balance.checked_sub(amount)?;
+
+				self.increment_nonce()
+			},
+			BankAccountUpdate::Release { amount } => {
+				let available_balance = self.available_balance.checked_add(amount)?;
+				if available_balance > self.balance {
+					return Err(DomainError::ApiError(String::from(
+						"Arithmetic underflow/overflow",
+					)));
 				}
-				.ok_or(DomainError::ApiError(String::from("Arithmetic underflow/overflow")))?;
 
-				self.nonce = self
-					.nonce
-					.checked_add(1)
-					.ok_or(DomainError::ApiError(String::from("Arithmetic underflow/overflow")))?;
+				self.available_balance = available_balance;
 
-				Ok(())
+				self.increment_nonce()
 			},
 			BankAccountUpdate::Info { account_id } => {
 				if let Some(account_id) = account_id {
@@ -146,13 +301,66 @@ impl BankAccount {
 							"Account ID must be 64 characters long",
 						)));
 					}
+					if !account_id.trim_start_matches("0x").bytes().all(|b| b.is_ascii_hexdigit()) {
+						return Err(DomainError::ApiError(String::from("Account ID must be hex")));
+					}
 				}
 
 				self.account_id = account_id.clone();
 				Ok(())
 			},
+			BankAccountUpdate::Status { status, .. } => {
+				if !self.card_status.can_change_to(*status) {
+					return Err(DomainError::BadRequest(format!(
+						"Card status can't change from {:?} to {:?}",
+						self.card_status, status
+					)));
+				}
+
+				self.card_status = *status;
+				Ok(())
+			},
+			BankAccountUpdate::Renew { card_expiration_date, .. } => {
+				if !matches!(self.card_status, CardStatus::Active | CardStatus::Blocked) {
+					return Err(DomainError::BadRequest(format!(
+						"{:?} card can't be renewed",
+						self.card_status
+					)));
+				}
+
+				if *card_expiration_date <= self.card_expiration_date {
+					return Err(DomainError::BadRequest(
+						"Card expiration date must be later than the current one".to_string(),
+					));
+				}
+
+				self.card_expiration_date = *card_expiration_date;
+				Ok(())
+			},
+			BankAccountUpdate::Reissue { card_expiration_date, .. } => {
+				if self.card_status == CardStatus::Closed {
+					return Err(DomainError::BadRequest(
+						"Closed card can't be reissued".to_string(),
+					));
+				}
+
+				// card data is tokenized by the controller
+				self.card_status = CardStatus::Active;
+				self.card_expiration_date = *card_expiration_date;
+				Ok(())
+			},
 		}
 	}
+
+	/// Increment the nonce of the bank account
+	fn increment_nonce(&mut self) -> Result<(), DomainError> {
+		self.nonce = self
+			.nonce
+			.checked_add(1)
+			.ok_or(DomainError::ApiError(String::from("Arithmetic underflow/overflow")))?;
+
+		Ok(())
+	}
 }
 
 /// Implement `From` trait for `BankAccount` from `Row`.
@@ -161,14 +369,16 @@ impl From<&Row> for BankAccount {
 	fn from(row: &Row) -> Self {
 		Self {
 			id: row.get("id"),
-			card_holder_first_name: row.get("card_holder_first_name"),
-			card_holder_last_name: row.get("card_holder_last_name"),
-			card_cvv: row.get("card_cvv"),
+			card_cvv_hash: row.get("card_cvv_hash"),
 			card_expiration_date: row.get("card_expiration_date"),
-			card_number: row.get("card_number"),
-			balance: row.get::<&str, i32>("balance") as u32,
+			card_number_masked: row.get("card_number_masked"),
+			card_number_hash: row.get("card_number_hash"),
+			card_data: row.into(),
+			balance: Money::from_row(row, "balance"),
+			available_balance: Money::from_row(row, "available_balance"),
 			nonce: row.get::<&str, i32>("nonce") as u32,
 			account_id: row.get("account_id"),
+			card_status: row.get::<&str, i32>("card_status").into(),
 		}
 	}
 }
@@ -178,61 +388,56 @@ mod tests {
 	use super::*;
 	use chrono::Utc;
 
+	fn usd(minor_units: i64) -> Money {
+		Money::new(minor_units, Iso4217::Usd)
+	}
+
+	fn card_token() -> CardToken {
+		CardToken {
+			hash: String::new(),
+			masked: "123412******1234".to_string(),
+			encrypted: EncryptedCardData::default(),
+		}
+	}
+
 	#[tokio::test]
 	async fn test_successful_debit() {
-		let mut bank_account = BankAccount::new(
-			"1234123412341234".to_string(),
-			"Alice".to_string(),
-			"Smith".to_string(),
-			Utc::now(),
-			"123".to_string(),
-			1000,
-			0,
-		);
+		let mut bank_account =
+			BankAccount::new(card_token(), Utc::now(), String::new(), usd(1000), 0);
 
-		let update =
-			BankAccountUpdate::Balance { transaction_type: TransactionType::Debit, amount: 500 };
+		let update = BankAccountUpdate::Balance {
+			transaction_type: TransactionType::Debit,
+			amount: usd(500),
+		};
 
 		bank_account.try_update(&update).await.expect("Debit failed");
-		assert_eq!(bank_account.balance, 1500);
+		assert_eq!(bank_account.balance, usd(1500));
 		assert_eq!(bank_account.nonce, 1);
 	}
 
 	#[tokio::test]
 	async fn test_successful_credit() {
-		let mut bank_account = BankAccount::new(
-			"1234123412341234".to_string(),
-			"Alice".to_string(),
-			"Smith".to_string(),
-			Utc::now(),
-			"123".to_string(),
-			1000,
-			0,
-		);
+		let mut bank_account =
+			BankAccount::new(card_token(), Utc::now(), String::new(), usd(1000), 0);
 
-		let update =
-			BankAccountUpdate::Balance { transaction_type: TransactionType::Credit, amount: 500 };
+		let update = BankAccountUpdate::Balance {
+			transaction_type: TransactionType::Credit,
+			amount: usd(500),
+		};
 
 		bank_account.try_update(&update).await.expect("Credit failed");
-		assert_eq!(bank_account.balance, 500);
+		assert_eq!(bank_account.balance, usd(500));
 		assert_eq!(bank_account.nonce, 1);
 	}
 
 	#[tokio::test]
 	async fn test_arithmetic_overflow_balance() {
-		let mut bank_account = BankAccount::new(
-			"1234123412341234".to_string(),
-			"Alice".to_string(),
-			"Smith".to_string(),
-			Utc::now(),
-			"123".to_string(),
-			1000,
-			0,
-		);
+		let mut bank_account =
+			BankAccount::new(card_token(), Utc::now(), String::new(), usd(1000), 0);
 
 		let update = BankAccountUpdate::Balance {
 			transaction_type: TransactionType::Credit,
-			amount: 2000, // More than the available balance
+			amount: usd(2000), // More than the available balance
 		};
 
 		match bank_account.try_update(&update).await {
@@ -244,18 +449,13 @@ mod tests {
 
 	#[tokio::test]
 	async fn test_arithmetic_overflow_nonce() {
-		let mut bank_account = BankAccount::new(
-			"1234123412341234".to_string(),
-			"Alice".to_string(),
-			"Smith".to_string(),
-			Utc::now(),
-			"123".to_string(),
-			1000,
-			u32::MAX,
-		);
+		let mut bank_account =
+			BankAccount::new(card_token(), Utc::now(), String::new(), usd(1000), u32::MAX);
 
-		let update =
-			BankAccountUpdate::Balance { transaction_type: TransactionType::Debit, amount: 500 };
+		let update = BankAccountUpdate::Balance {
+			transaction_type: TransactionType::Debit,
+			amount: usd(500),
+		};
 
 		match bank_account.try_update(&update).await {
 			Ok(_) => panic!("Expected an error due to arithmetic overflow"),
@@ -264,17 +464,55 @@ mod tests {
 		}
 	}
 	#[tokio::test]
-	async fn test_info_update() {
-		let mut bank_account = BankAccount::new(
-			"1234123412341234".to_string(),
-			"Alice".to_string(),
-			"Smith".to_string(),
-			Utc::now(),
-			"123".to_string(),
-			1000,
-			0,
+	async fn test_hold_and_release() {
+		let mut bank_account =
+			BankAccount::new(card_token(), Utc::now(), String::new(), usd(1000), 0);
+
+		bank_account
+			.try_update(&BankAccountUpdate::Hold { amount: usd(300) })
+			.await
+			.unwrap();
+		assert_eq!(bank_account.balance, usd(1000));
+		assert_eq!(bank_account.available_balance, usd(700));
+
+		// held funds can't be spent
+		let update = BankAccountUpdate::Balance {
+			transaction_type: TransactionType::Credit,
+			amount: usd(800),
+		};
+		assert_eq!(
+			bank_account.try_update(&update).await,
+			Err(DomainError::ApiError(String::from("Arithmetic underflow/overflow")))
 		);
 
+		let update = BankAccountUpdate::Balance {
+			transaction_type: TransactionType::Credit,
+			amount: usd(700),
+		};
+		bank_account.try_update(&update).await.unwrap();
+		assert_eq!(bank_account.balance, usd(300));
+		assert_eq!(bank_account.available_balance, usd(0));
+
+		bank_account
+			.try_update(&BankAccountUpdate::Release { amount: usd(300) })
+			.await
+			.unwrap();
+		assert_eq!(bank_account.balance, usd(300));
+		assert_eq!(bank_account.available_balance, usd(300));
+
+		// can't release more than the ledger balance
+		assert_eq!(
+			bank_account.try_update(&BankAccountUpdate::Release { amount: usd(1) }).await,
+			Err(DomainError::ApiError(String::from("Arithmetic underflow/overflow")))
+		);
+		assert_eq!(bank_account.nonce, 3);
+	}
+
+	#[tokio::test]
+	async fn test_info_update() {
+		let mut bank_account =
+			BankAccount::new(card_token(), Utc::now(), String::new(), usd(1000), 0);
+
 		let update = BankAccountUpdate::Info { account_id: Some("1234".to_string()) };
 
 		let invalid_length_account = bank_account.try_update(&update).await;
@@ -283,6 +521,14 @@ mod tests {
 			Err(DomainError::ApiError(String::from("Account ID must be 64 characters long")))
 		);
 
+		let update = BankAccountUpdate::Info { account_id: Some("z".repeat(64)) };
+
+		let invalid_hex_account = bank_account.try_update(&update).await;
+		assert_eq!(
+			invalid_hex_account,
+			Err(DomainError::ApiError(String::from("Account ID must be hex")))
+		);
+
 		let update = BankAccountUpdate::Info {
 			account_id: Some(
 				"0x1234123412341234123412341234123412341234123412341234123412341234".to_string(),
@@ -292,4 +538,45 @@ mod tests {
 		let valid_account = bank_account.try_update(&update).await;
 		assert_eq!(valid_account, Ok(()));
 	}
+
+	#[tokio::test]
+	async fn test_card_lifecycle() {
+		let mut bank_account =
+			BankAccount::new(card_token(), Utc::now(), String::new(), usd(1000), 0);
+		let status = |status| BankAccountUpdate::Status { status, reason: None };
+
+		bank_account.try_update(&status(CardStatus::Blocked)).await.unwrap();
+		bank_account.try_update(&status(CardStatus::Active)).await.unwrap();
+		assert!(bank_account.try_update(&status(CardStatus::Active)).await.is_err());
+
+		// renewed with a later expiration date only
+		let renew = |card_expiration_date| BankAccountUpdate::Renew {
+			card_expiration_date,
+			card_cvv: None,
+		};
+		assert!(bank_account.try_update(&renew(Utc::now() - Months::new(1))).await.is_err());
+
+		let card_expiration_date = Utc::now() + Months::new(48);
+		bank_account.try_update(&renew(card_expiration_date)).await.unwrap();
+		assert_eq!(bank_account.card_expiration_date, card_expiration_date);
+
+		// lost cards are reissued, not reactivated
+		bank_account.try_update(&status(CardStatus::Lost)).await.unwrap();
+		assert!(bank_account.try_update(&status(CardStatus::Active)).await.is_err());
+		assert!(bank_account.try_update(&renew(Utc::now() + Months::new(60))).await.is_err());
+
+		let reissue = BankAccountUpdate::Reissue {
+			card_number: "4169812345678999".to_string(),
+			card_cvv: "321".to_string(),
+			card_expiration_date: Utc::now() + Months::new(60),
+		};
+		bank_account.try_update(&reissue).await.unwrap();
+		assert_eq!(bank_account.card_status, CardStatus::Active);
+
+		// closed cards stay closed
+		bank_account.try_update(&status(CardStatus::Closed)).await.unwrap();
+		assert!(bank_account.try_update(&status(CardStatus::Active)).await.is_err());
+		assert!(bank_account.try_update(&reissue).await.is_err());
+		assert_eq!(bank_account.nonce, 0);
+	}
 }
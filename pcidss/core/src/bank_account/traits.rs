@@ -3,7 +3,7 @@
 use async_trait::async_trait;
 use uuid::Uuid;
 
-use super::models::{BankAccount, BankAccountCreate, BankAccountUpdate};
+use super::models::{BankAccount, BankAccountCreate, BankAccountUpdate, CardStatusChange};
 use crate::error::DomainError;
 
 /// `BankAccountTrait` is a trait for bank account operations.
@@ -27,6 +27,8 @@ pub trait BankAccountTrait: Send + Sync {
 	) -> Result<BankAccount, DomainError>;
 
 	/// Update a bank account by unique identifier.
+	///
+	/// Status changes and reissues are recorded in the card status history.
 	async fn update(
 		&self,
 		id: &Uuid,
@@ -41,4 +43,10 @@ pub trait BankAccountTrait: Send + Sync {
 		&self,
 		on_chain_account_id: &str,
 	) -> Result<Option<BankAccount>, DomainError>;
+
+	/// Find all bank accounts registered on-chain, ordered by unique identifier.
+	async fn find_registered(&self) -> Result<Vec<BankAccount>, DomainError>;
+
+	/// Find the card status history of a bank account, oldest first.
+	async fn find_status_history(&self, id: &Uuid) -> Result<Vec<CardStatusChange>, DomainError>;
 }
@@ -0,0 +1,39 @@
+//! Models to represent the blocks processed by chain consumers.
+use chrono::{DateTime, Utc};
+
+/// `TrackedBlock` is a model for a processed block that is not finalized yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackedBlock {
+	/// Number of the block.
+	pub block_number: u32,
+	/// Hex encoded hash of the block.
+	pub block_hash: String,
+}
+
+impl From<&tokio_postgres::Row> for TrackedBlock {
+	fn from(row: &tokio_postgres::Row) -> Self {
+		Self {
+			block_number: row.get::<&str, i64>("block_number") as u32,
+			block_hash: row.get("block_hash"),
+		}
+	}
+}
+
+/// `FailedRetraction` is a model for a retracted block whose events couldn't be compensated.
+///
+/// The block is not tracked anymore, its ledger changes have to be compensated manually.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedRetraction {
+	/// Retracted block.
+	pub block: TrackedBlock,
+	/// Error the compensation failed with.
+	pub error: String,
+	/// Time of the failure.
+	pub created_at: DateTime<Utc>,
+}
+
+impl From<&tokio_postgres::Row> for FailedRetraction {
+	fn from(row: &tokio_postgres::Row) -> Self {
+		Self { block: row.into(), error: row.get("error"), created_at: row.get("created_at") }
+	}
+}
@@ -0,0 +1,43 @@
+//! Defines trait for block cursor operations.
+use async_trait::async_trait;
+
+use super::models::{FailedRetraction, TrackedBlock};
+use crate::error::DomainError;
+
+/// `BlockCursorTrait` is a trait for persisting the progress of chain consumers.
+///
+/// A cursor is the number of the last block whose events are fully processed, so that a
+/// consumer can resume after it.
+#[async_trait]
+pub trait BlockCursorTrait: Send + Sync {
+	/// Last fully processed block of the named cursor, `None` if nothing is processed yet.
+	async fn get(&self, name: &str) -> Result<Option<u32>, DomainError>;
+
+	/// Advance the named cursor to the block, it never moves backwards.
+	async fn advance(&self, name: &str, block_number: u32) -> Result<(), DomainError>;
+
+	/// Move the named cursor back to the block, so that the blocks after it are processed again.
+	async fn rewind(&self, name: &str, block_number: u32) -> Result<(), DomainError>;
+
+	/// Record the hash of a processed block that is not finalized yet.
+	async fn track(&self, block: &TrackedBlock) -> Result<(), DomainError>;
+
+	/// Processed blocks that are not finalized yet, lowest first.
+	async fn find_tracked(&self) -> Result<Vec<TrackedBlock>, DomainError>;
+
+	/// Forget the processed blocks up to and including the block.
+	async fn untrack(&self, block_number: u32) -> Result<(), DomainError>;
+
+	/// Forget the processed blocks from the block onwards.
+	async fn untrack_from(&self, block_number: u32) -> Result<(), DomainError>;
+
+	/// Record a retracted block whose events couldn't be compensated.
+	async fn record_failed_retraction(
+		&self,
+		block: &TrackedBlock,
+		error: &str,
+	) -> Result<(), DomainError>;
+
+	/// Retracted blocks whose events couldn't be compensated, oldest first.
+	async fn find_failed_retractions(&self) -> Result<Vec<FailedRetraction>, DomainError>;
+}
@@ -3,7 +3,6 @@
 use std::num::ParseIntError;
 
 use iso8583_rs::iso8583::IsoError;
-use log::error;
 use thiserror::Error;
 
 #[derive(Debug, Error, PartialEq, Eq)]
@@ -19,6 +18,9 @@ pub enum DomainError {
 
 	#[error("{}", _0)]
 	ApiError(String),
+
+	#[error("Insufficient funds")]
+	InsufficientFunds,
 }
 
 impl From<tokio_postgres::Error> for DomainError {
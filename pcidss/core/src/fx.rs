@@ -0,0 +1,258 @@
+//! Foreign exchange rates and conversions of amounts between currencies.
+
+use std::{fmt, str::FromStr};
+
+use tokio_postgres::Row;
+
+use crate::{
+	error::DomainError,
+	money::{Iso4217, Money},
+};
+
+/// Maximum number of significant digits of a rate, as in field 10 of ISO-8583 messages.
+const RATE_DIGITS: u32 = 7;
+
+/// `FxRate` is a conversion rate, major units of the target currency per major unit of the
+/// source currency.
+///
+/// The rate is `value / 10^decimals`, with at most 7 significant digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct FxRate {
+	/// Significant digits of the rate.
+	pub value: u32,
+	/// Number of decimal places of the rate.
+	pub decimals: u32,
+}
+
+impl FxRate {
+	/// Parses a rate in the format of field 10 of ISO-8583 messages, e.g. `61085000`.
+	///
+	/// The first digit is the number of decimal places, the other 7 are the digits of the rate.
+	pub fn from_field(value: &str) -> Result<Self, DomainError> {
+		let invalid = || DomainError::BadRequest(format!("Invalid conversion rate: {}", value));
+
+		if value.len() != RATE_DIGITS as usize + 1 || !value.chars().all(|c| c.is_ascii_digit()) {
+			return Err(invalid());
+		}
+
+		let (decimals, digits) = value.split_at(1);
+		let rate = Self {
+			value: digits.parse().map_err(|_| invalid())?,
+			decimals: decimals.parse().map_err(|_| invalid())?,
+		};
+
+		if rate.value == 0 || rate.decimals > RATE_DIGITS {
+			return Err(invalid());
+		}
+
+		Ok(rate)
+	}
+
+	/// Formats the rate for field 10 of ISO-8583 messages, the inverse of
+	/// [`FxRate::from_field`].
+	pub fn to_field(&self) -> String {
+		format!("{}{:07}", self.decimals, self.value)
+	}
+
+	/// Converts an amount to the target currency, rounded half up to its minor units.
+	pub fn convert(&self, amount: Money, to: Iso4217) -> Result<Money, DomainError> {
+		let overflow = || DomainError::ApiError(String::from("Arithmetic underflow/overflow"));
+
+		let numerator = (amount.minor_units as u128)
+			.checked_mul(self.value as u128)
+			.and_then(|value| value.checked_mul(10_u128.pow(to.exponent())))
+			.ok_or_else(overflow)?;
+		let denominator = 10_u128.pow(self.decimals + amount.currency.exponent());
+
+		let minor_units = numerator
+			.checked_add(denominator / 2)
+			.map(|value| value / denominator)
+			.ok_or_else(overflow)?;
+
+		Ok(Money::new(i64::try_from(minor_units).map_err(|_| overflow())?, to))
+	}
+}
+
+/// Parses a decimal rate, e.g. `1.0850`. Trailing zeros don't count as significant digits.
+impl FromStr for FxRate {
+	type Err = DomainError;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let invalid = || DomainError::BadRequest(format!("Invalid conversion rate: {}", value));
+
+		let (integer, fraction) = value.trim().split_once('.').unwrap_or((value.trim(), ""));
+		let fraction = fraction.trim_end_matches('0');
+
+		if integer.is_empty() ||
+			!format!("{}{}", integer, fraction).chars().all(|c| c.is_ascii_digit())
+		{
+			return Err(invalid());
+		}
+
+		let digits = format!("{}{}", integer, fraction);
+		let digits = digits.trim_start_matches('0');
+
+		if digits.is_empty() ||
+			digits.len() > RATE_DIGITS as usize ||
+			fraction.len() > RATE_DIGITS as usize
+		{
+			return Err(invalid());
+		}
+
+		Ok(Self { value: digits.parse().map_err(|_| invalid())?, decimals: fraction.len() as u32 })
+	}
+}
+
+/// Formats the rate as a decimal, e.g. `1.085`.
+impl fmt::Display for FxRate {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if self.decimals == 0 {
+			return write!(f, "{}", self.value);
+		}
+
+		let factor = 10_u32.pow(self.decimals);
+		write!(
+			f,
+			"{}.{:0width$}",
+			self.value / factor,
+			self.value % factor,
+			width = self.decimals as usize
+		)
+	}
+}
+
+/// `Conversion` of a transaction amount to the currency of the bank account.
+///
+/// The converted amount is the billing amount of the bank account (field 6 of the ISO-8583
+/// message), `amount` is the one in the currency of the transaction (fields 4 and 49).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Conversion {
+	/// Amount in the currency of the transaction.
+	pub amount: Money,
+	/// Rate the amount is converted with.
+	pub rate: FxRate,
+	/// Version of the rate table the rate is taken from.
+	pub rate_version: u32,
+}
+
+impl Conversion {
+	/// Billing amount of a part of the converted amount, e.g. of a partial capture or reversal.
+	///
+	/// The part is converted with the original rate, never exceeding `billing`, the billing
+	/// amount of the whole. The whole is billed exactly as it was.
+	pub fn billing_part(&self, billing: Money, part: Money) -> Result<Money, DomainError> {
+		if part.currency != self.amount.currency {
+			return Err(DomainError::BadRequest(format!(
+				"Currency mismatch: {} and {}",
+				part.currency, self.amount.currency
+			)));
+		}
+
+		if part > self.amount {
+			return Err(DomainError::BadRequest("Amount exceeds the converted amount".to_string()));
+		}
+
+		if part == self.amount {
+			return Ok(billing);
+		}
+
+		let converted = self.rate.convert(part, billing.currency)?;
+
+		Ok(if converted > billing { billing } else { converted })
+	}
+
+	/// Reads the conversion columns of a row, `None` if the amount is not converted.
+	pub fn from_row(row: &Row) -> Option<Self> {
+		let minor_units: Option<i64> = row.get("original_amount");
+		let currency: Option<String> = row.get("original_currency");
+		let rate: Option<String> = row.get("fx_rate");
+		let rate_version: Option<i32> = row.get("fx_rate_version");
+
+		Some(Self {
+			amount: Money::new(
+				minor_units?,
+				currency?.parse().expect("only supported currencies are stored"),
+			),
+			rate: FxRate::from_field(&rate?).expect("only valid rates are stored"),
+			rate_version: rate_version? as u32,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_rate_formats() {
+		let rate: FxRate = "1.0850".parse().unwrap();
+
+		assert_eq!(rate, FxRate { value: 1085, decimals: 3 });
+		assert_eq!(rate.to_string(), "1.085");
+		assert_eq!(rate.to_field(), "30001085");
+		assert_eq!(FxRate::from_field("30001085"), Ok(rate));
+
+		assert_eq!("150".parse(), Ok(FxRate { value: 150, decimals: 0 }));
+		assert_eq!("0.0067".parse(), Ok(FxRate { value: 67, decimals: 4 }));
+
+		// more than 7 significant digits
+		assert!("1.23456789".parse::<FxRate>().is_err());
+		assert!("0".parse::<FxRate>().is_err());
+		assert!("-1.2".parse::<FxRate>().is_err());
+		assert!(FxRate::from_field("90001085").is_err());
+		assert!(FxRate::from_field("3000108").is_err());
+	}
+
+	#[test]
+	fn test_convert() {
+		let rate: FxRate = "1.0850".parse().unwrap();
+
+		// 10.00 EUR is 10.85 USD
+		assert_eq!(
+			rate.convert(Money::new(1000, Iso4217::Eur), Iso4217::Usd),
+			Ok(Money::new(1085, Iso4217::Usd))
+		);
+
+		// 0.05 EUR is 0.05425 USD, rounded half up
+		assert_eq!(
+			rate.convert(Money::new(5, Iso4217::Eur), Iso4217::Usd),
+			Ok(Money::new(5, Iso4217::Usd))
+		);
+		assert_eq!(
+			rate.convert(Money::new(7, Iso4217::Eur), Iso4217::Usd),
+			Ok(Money::new(8, Iso4217::Usd))
+		);
+
+		// currencies without minor units
+		let rate: FxRate = "150.25".parse().unwrap();
+		assert_eq!(
+			rate.convert(Money::new(100, Iso4217::Usd), Iso4217::Jpy),
+			Ok(Money::new(150, Iso4217::Jpy))
+		);
+
+		let rate: FxRate = "0.0067".parse().unwrap();
+		assert_eq!(
+			rate.convert(Money::new(1000, Iso4217::Jpy), Iso4217::Usd),
+			Ok(Money::new(670, Iso4217::Usd))
+		);
+	}
+
+	#[test]
+	fn test_billing_part() {
+		let conversion = Conversion {
+			amount: Money::new(1000, Iso4217::Eur),
+			rate: "1.0855".parse().unwrap(),
+			rate_version: 1,
+		};
+		let billing = Money::new(1086, Iso4217::Usd);
+
+		assert_eq!(conversion.billing_part(billing, conversion.amount), Ok(billing));
+		assert_eq!(
+			conversion.billing_part(billing, Money::new(500, Iso4217::Eur)),
+			Ok(Money::new(543, Iso4217::Usd))
+		);
+
+		assert!(conversion.billing_part(billing, Money::new(1001, Iso4217::Eur)).is_err());
+		assert!(conversion.billing_part(billing, Money::new(500, Iso4217::Usd)).is_err());
+	}
+}
@@ -0,0 +1,153 @@
+//! Models to represent an authorization hold and its lifecycle.
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{fx::Conversion, money::Money, utils::hash_iso_msg};
+
+/// `HoldStatus` is an enum for the state of a hold.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum HoldStatus {
+	/// Funds are reserved, waiting for completion.
+	Active,
+	/// Hold is captured by a completion message.
+	Captured,
+	/// Hold is released by a reversal.
+	Released,
+	/// Hold is released after it has expired.
+	Expired,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<i32> for HoldStatus {
+	fn into(self) -> i32 {
+		match self {
+			HoldStatus::Active => 0,
+			HoldStatus::Captured => 1,
+			HoldStatus::Released => 2,
+			HoldStatus::Expired => 3,
+		}
+	}
+}
+
+impl From<i32> for HoldStatus {
+	fn from(value: i32) -> Self {
+		match value {
+			0 => HoldStatus::Active,
+			1 => HoldStatus::Captured,
+			2 => HoldStatus::Released,
+			_ => HoldStatus::Expired,
+		}
+	}
+}
+
+/// `HoldCreate` is a model for placing a hold on a bank account.
+#[derive(Debug, Clone)]
+pub struct HoldCreate {
+	/// Unique identifier of the hold.
+	pub id: Uuid,
+	/// Unique identifier of the bank account the funds are reserved on.
+	pub from: Uuid,
+	/// Unique identifier of the bank account that will receive the funds, if any.
+	pub to: Option<Uuid>,
+	/// Amount reserved, in the currency of the bank account.
+	pub amount: Money,
+	/// Conversion from the currency of the authorization, captures are converted with its rate.
+	pub conversion: Option<Conversion>,
+	/// Nonce of the bank account.
+	pub nonce: u32,
+	/// Raw ISO message of the authorization.
+	pub iso_msg_raw: Vec<u8>,
+	/// Time after which the hold is released if not captured.
+	pub expires_at: DateTime<Utc>,
+}
+
+/// `Hold` is a model for funds reserved by an authorization.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Hold {
+	/// Unique identifier of the hold.
+	pub id: Uuid,
+	/// Unique hash of the hold, used as authorization reference by completions.
+	pub hash: String,
+	/// Unique identifier of the bank account the funds are reserved on.
+	pub from: Uuid,
+	/// Unique identifier of the bank account that will receive the funds, if any.
+	pub to: Option<Uuid>,
+	/// Amount reserved, in the currency of the bank account.
+	pub amount: Money,
+	/// Conversion from the currency of the authorization, captures are converted with its rate.
+	pub conversion: Option<Conversion>,
+	/// State of the hold.
+	pub status: HoldStatus,
+	/// Transaction that captured the hold, if any.
+	pub transaction_id: Option<Uuid>,
+	/// Time after which the hold is released if not captured.
+	pub expires_at: DateTime<Utc>,
+}
+
+impl Hold {
+	/// Whether the hold can still be captured at given time.
+	pub fn is_capturable(&self, now: DateTime<Utc>) -> bool {
+		self.status == HoldStatus::Active && self.expires_at > now
+	}
+}
+
+impl From<&HoldCreate> for Hold {
+	fn from(value: &HoldCreate) -> Self {
+		Self {
+			id: value.id,
+			hash: hash_iso_msg(&value.iso_msg_raw, value.nonce),
+			from: value.from,
+			to: value.to,
+			amount: value.amount,
+			conversion: value.conversion,
+			status: HoldStatus::Active,
+			transaction_id: None,
+			expires_at: value.expires_at,
+		}
+	}
+}
+
+impl From<&tokio_postgres::Row> for Hold {
+	fn from(row: &tokio_postgres::Row) -> Self {
+		Self {
+			id: row.get("id"),
+			hash: row.get("hash"),
+			from: row.get("source"),
+			to: row.get("recipient"),
+			amount: Money::from_row(row, "amount"),
+			conversion: Conversion::from_row(row),
+			status: row.get::<&str, i32>("status").into(),
+			transaction_id: row.get("transaction_id"),
+			expires_at: row.get("expires_at"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::Duration;
+
+	#[test]
+	fn test_is_capturable() {
+		let now = Utc::now();
+		let mut hold: Hold = (&HoldCreate {
+			id: Uuid::new_v4(),
+			from: Uuid::new_v4(),
+			to: None,
+			amount: Money::new(100, crate::money::Iso4217::Usd),
+			conversion: None,
+			nonce: 0,
+			iso_msg_raw: vec![48, 49, 48, 48],
+			expires_at: now + Duration::hours(1),
+		})
+			.into();
+
+		assert_eq!(hold.status, HoldStatus::Active);
+		assert!(hold.is_capturable(now));
+		assert!(!hold.is_capturable(now + Duration::hours(2)));
+
+		hold.status = HoldStatus::Captured;
+		assert!(!hold.is_capturable(now));
+	}
+}
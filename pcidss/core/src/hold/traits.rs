@@ -0,0 +1,46 @@
+//! Defines trait for authorization hold operations.
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::models::{Hold, HoldCreate};
+use crate::{
+	error::DomainError,
+	transaction::models::{Transaction, TransactionCreate},
+};
+
+/// `HoldTrait` is a trait for authorization hold operations.
+///
+/// This should be implemented by any hold controller.
+#[async_trait]
+pub trait HoldTrait: Send + Sync {
+	/// Find a hold by unique identifier.
+	async fn find_by_id(&self, id: &Uuid) -> Result<Option<Hold>, DomainError>;
+
+	/// Find a hold by hash.
+	async fn find_by_hash(&self, hash: &str) -> Result<Option<Hold>, DomainError>;
+
+	/// Find holds placed on a bank account.
+	async fn find_by_bank_account_id(&self, source: &Uuid) -> Result<Vec<Hold>, DomainError>;
+
+	/// Reserve funds on the bank account and record the hold atomically.
+	///
+	/// Returns [`DomainError::InsufficientFunds`] if available balance can't cover the amount.
+	async fn create(&self, hold: &HoldCreate) -> Result<Hold, DomainError>;
+
+	/// Capture an active hold with given transaction atomically.
+	///
+	/// The whole hold is released and `transaction` is posted instead, so the captured amount
+	/// can be smaller than the reserved one.
+	async fn capture(
+		&self,
+		id: &Uuid,
+		transaction: &TransactionCreate,
+	) -> Result<Transaction, DomainError>;
+
+	/// Release an active hold, reserved funds become available again.
+	async fn release(&self, id: &Uuid) -> Result<Hold, DomainError>;
+
+	/// Release all active holds that have expired at given time.
+	async fn expire(&self, now: DateTime<Utc>) -> Result<Vec<Hold>, DomainError>;
+}
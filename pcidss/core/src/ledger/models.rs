@@ -0,0 +1,368 @@
+//! Models to represent the double-entry journal of the ledger.
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{
+	error::DomainError,
+	money::{Iso4217, Money},
+	transaction::models::Transaction,
+	types::TransactionType,
+};
+
+/// `LedgerAccount` is an account of the journal.
+///
+/// Besides bank accounts, system accounts are the counterparts of the funds that enter or leave
+/// the bank accounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LedgerAccount {
+	/// Bank account of a card holder or an acquirer.
+	BankAccount(Uuid),
+	/// Funds moved to or from the chain, e.g. to on-chain accounts without a bank account.
+	Settlement,
+	/// Currency conversions, it exchanges the currency of the transaction for the currency of
+	/// the bank account.
+	FxConversion,
+	/// Opening balances of the bank accounts.
+	Opening,
+}
+
+/// Formats the account as it is stored, e.g. `bank_account:<uuid>` or `settlement`.
+impl fmt::Display for LedgerAccount {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			LedgerAccount::BankAccount(id) => write!(f, "bank_account:{}", id),
+			LedgerAccount::Settlement => f.write_str("settlement"),
+			LedgerAccount::FxConversion => f.write_str("fx_conversion"),
+			LedgerAccount::Opening => f.write_str("opening"),
+		}
+	}
+}
+
+impl FromStr for LedgerAccount {
+	type Err = DomainError;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		match value {
+			"settlement" => Ok(LedgerAccount::Settlement),
+			"fx_conversion" => Ok(LedgerAccount::FxConversion),
+			"opening" => Ok(LedgerAccount::Opening),
+			_ => value
+				.strip_prefix("bank_account:")
+				.and_then(|id| Uuid::parse_str(id).ok())
+				.map(LedgerAccount::BankAccount)
+				.ok_or(DomainError::BadRequest(format!("Unknown ledger account: {}", value))),
+		}
+	}
+}
+
+/// `LedgerEntryCreate` is a model for a line of a posting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerEntryCreate {
+	/// Account the entry is posted to.
+	pub account: LedgerAccount,
+	/// Amount of the entry.
+	pub amount: Money,
+	/// Whether the amount is added to or deducted from the balance of the account.
+	pub entry_type: TransactionType,
+}
+
+impl LedgerEntryCreate {
+	/// Change of the balance of the account, negative if the amount is deducted.
+	pub fn signed_minor_units(&self) -> i64 {
+		match self.entry_type {
+			TransactionType::Debit => self.amount.minor_units,
+			TransactionType::Credit => -self.amount.minor_units,
+		}
+	}
+}
+
+/// `Posting` is a set of entries that are recorded together, they add up to zero in every
+/// currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posting {
+	/// Unique identifier of the posting.
+	pub id: Uuid,
+	/// Transaction the posting is recorded for, if any.
+	pub transaction_id: Option<Uuid>,
+	/// Entries of the posting.
+	pub entries: Vec<LedgerEntryCreate>,
+}
+
+impl Posting {
+	/// Creates a new empty `Posting`.
+	pub fn new(transaction_id: Option<Uuid>) -> Self {
+		Self { id: Uuid::new_v4(), transaction_id, entries: vec![] }
+	}
+
+	/// Posting that moves funds between the parties of a transaction.
+	///
+	/// `amount` is deducted from the sender for a credit and added for a debit, the receiving
+	/// side gets `recipient_amount` the other way around. Funds of transactions without a
+	/// receiving bank account are settled on-chain.
+	pub fn for_transaction(
+		transaction: &Transaction,
+		transaction_type: &TransactionType,
+		amount: Money,
+		recipient_amount: Money,
+	) -> Self {
+		let sender = LedgerAccount::BankAccount(transaction.from);
+		let recipient = transaction
+			.to
+			.map(LedgerAccount::BankAccount)
+			.unwrap_or(LedgerAccount::Settlement);
+
+		let posting = Self::new(Some(transaction.id));
+
+		match transaction_type {
+			TransactionType::Credit => posting.payment(sender, amount, recipient, recipient_amount),
+			TransactionType::Debit => posting.payment(recipient, recipient_amount, sender, amount),
+		}
+	}
+
+	/// Opening balance of a bank account.
+	pub fn opening(id: Uuid, balance: Money) -> Self {
+		Self::new(None).payment(
+			LedgerAccount::Opening,
+			balance,
+			LedgerAccount::BankAccount(id),
+			balance,
+		)
+	}
+
+	/// Move `amount` from the payer to the payee, who gets `payee_amount`.
+	///
+	/// If the amounts differ, e.g. they are in different currencies, the funds are exchanged
+	/// through the currency conversion account.
+	pub fn payment(
+		mut self,
+		payer: LedgerAccount,
+		amount: Money,
+		payee: LedgerAccount,
+		payee_amount: Money,
+	) -> Self {
+		if amount.is_zero() && payee_amount.is_zero() {
+			return self;
+		}
+
+		let mut transfer = |from: LedgerAccount, to: LedgerAccount, amount: Money| {
+			self.entries.push(LedgerEntryCreate {
+				account: from,
+				amount,
+				entry_type: TransactionType::Credit,
+			});
+			self.entries.push(LedgerEntryCreate {
+				account: to,
+				amount,
+				entry_type: TransactionType::Debit,
+			});
+		};
+
+		if amount == payee_amount {
+			transfer(payer, payee, amount);
+		} else {
+			transfer(payer, LedgerAccount::FxConversion, amount);
+			transfer(LedgerAccount::FxConversion, payee, payee_amount);
+		}
+
+		self
+	}
+
+	/// Whether the entries add up to zero in every currency.
+	pub fn is_balanced(&self) -> bool {
+		let mut totals: HashMap<Iso4217, i64> = HashMap::new();
+
+		for entry in self.entries.iter() {
+			*totals.entry(entry.amount.currency).or_default() += entry.signed_minor_units();
+		}
+
+		totals.values().all(|total| *total == 0)
+	}
+}
+
+/// `LedgerEntry` is a model for a recorded entry of the journal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerEntry {
+	/// Unique identifier of the entry.
+	pub id: Uuid,
+	/// Posting the entry is recorded with.
+	pub posting_id: Uuid,
+	/// Transaction the entry is recorded for, if any.
+	pub transaction_id: Option<Uuid>,
+	/// Account the entry is posted to.
+	pub account: LedgerAccount,
+	/// Change of the balance of the account, negative if the amount is deducted.
+	pub amount: i64,
+	/// Currency of the amount.
+	pub currency: Iso4217,
+	/// Time the entry is recorded.
+	pub created_at: DateTime<Utc>,
+}
+
+impl From<&tokio_postgres::Row> for LedgerEntry {
+	fn from(row: &tokio_postgres::Row) -> Self {
+		let currency: String = row.get("currency");
+
+		Self {
+			id: row.get("id"),
+			posting_id: row.get("posting_id"),
+			transaction_id: row.get("transaction_id"),
+			account: row
+				.get::<&str, String>("account")
+				.parse()
+				.expect("only valid accounts are stored"),
+			amount: row.get("amount"),
+			currency: currency.parse().expect("only supported currencies are stored"),
+			created_at: row.get("created_at"),
+		}
+	}
+}
+
+/// `BalanceMismatch` is a bank account whose balances differ from the ones derived from the
+/// journal.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BalanceMismatch {
+	/// Unique identifier of the bank account.
+	pub bank_account_id: Uuid,
+	/// Stored balance.
+	pub balance: Money,
+	/// Sum of the journal entries of the bank account.
+	pub ledger_balance: Money,
+	/// Stored available balance.
+	pub available_balance: Money,
+	/// Balance derived from the journal minus the active holds.
+	pub expected_available_balance: Money,
+}
+
+/// `LedgerReport` is the result of a consistency check of the ledger.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct LedgerReport {
+	/// Number of checked bank accounts.
+	pub bank_accounts: usize,
+	/// Bank accounts whose balances don't match the journal.
+	pub mismatches: Vec<BalanceMismatch>,
+	/// Postings whose entries don't add up to zero.
+	pub unbalanced_postings: Vec<Uuid>,
+}
+
+impl LedgerReport {
+	/// Whether the balances match the journal and every posting is balanced.
+	pub fn is_consistent(&self) -> bool {
+		self.mismatches.is_empty() && self.unbalanced_postings.is_empty()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{fx::Conversion, transaction::models::TransactionCreate};
+
+	fn transaction(to: Option<Uuid>) -> Transaction {
+		(&TransactionCreate {
+			id: Uuid::new_v4(),
+			from: Uuid::new_v4(),
+			to,
+			amount: Money::new(922, Iso4217::Eur),
+			conversion: None,
+			transaction_type: TransactionType::Credit,
+			nonce: 0,
+			iso_msg_raw: vec![48, 50, 48, 48],
+			on_chain_id: None,
+			finality: None,
+		})
+			.into()
+	}
+
+	#[test]
+	fn test_ledger_account_format() {
+		let id = Uuid::new_v4();
+
+		for account in [
+			LedgerAccount::BankAccount(id),
+			LedgerAccount::Settlement,
+			LedgerAccount::FxConversion,
+			LedgerAccount::Opening,
+		] {
+			assert_eq!(account.to_string().parse(), Ok(account));
+		}
+
+		assert!("bank_account:1234".parse::<LedgerAccount>().is_err());
+		assert!("unknown".parse::<LedgerAccount>().is_err());
+	}
+
+	#[test]
+	fn test_transaction_posting() {
+		let to = Uuid::new_v4();
+		let transaction = transaction(Some(to));
+		let eur = Money::new(922, Iso4217::Eur);
+
+		let posting = Posting::for_transaction(&transaction, &TransactionType::Credit, eur, eur);
+
+		assert!(posting.is_balanced());
+		assert_eq!(posting.transaction_id, Some(transaction.id));
+		assert_eq!(
+			posting.entries,
+			vec![
+				LedgerEntryCreate {
+					account: LedgerAccount::BankAccount(transaction.from),
+					amount: eur,
+					entry_type: TransactionType::Credit,
+				},
+				LedgerEntryCreate {
+					account: LedgerAccount::BankAccount(to),
+					amount: eur,
+					entry_type: TransactionType::Debit,
+				},
+			]
+		);
+
+		// refunds move the funds back
+		let posting = Posting::for_transaction(&transaction, &TransactionType::Debit, eur, eur);
+
+		assert!(posting.is_balanced());
+		assert_eq!(posting.entries[0].account, LedgerAccount::BankAccount(to));
+		assert_eq!(posting.entries[0].entry_type, TransactionType::Credit);
+
+		// without a receiving bank account funds are settled on-chain
+		let posting =
+			Posting::for_transaction(&self::transaction(None), &TransactionType::Credit, eur, eur);
+
+		assert!(posting.is_balanced());
+		assert_eq!(posting.entries[1].account, LedgerAccount::Settlement);
+	}
+
+	#[test]
+	fn test_converted_posting() {
+		let mut transaction = transaction(Some(Uuid::new_v4()));
+		let usd = Money::new(1000, Iso4217::Usd);
+		transaction.conversion =
+			Some(Conversion { amount: usd, rate: "0.9216".parse().unwrap(), rate_version: 1 });
+
+		let posting = Posting::for_transaction(
+			&transaction,
+			&TransactionType::Credit,
+			transaction.amount,
+			transaction.recipient_amount(),
+		);
+
+		// balanced in both currencies through the conversion account
+		assert!(posting.is_balanced());
+		assert_eq!(posting.entries.len(), 4);
+		assert_eq!(
+			posting
+				.entries
+				.iter()
+				.filter(|entry| entry.account == LedgerAccount::FxConversion)
+				.map(LedgerEntryCreate::signed_minor_units)
+				.collect::<Vec<_>>(),
+			vec![922, -1000]
+		);
+
+		let mut posting = Posting::opening(Uuid::new_v4(), usd);
+		assert!(posting.is_balanced());
+
+		posting.entries.pop();
+		assert!(!posting.is_balanced());
+	}
+}
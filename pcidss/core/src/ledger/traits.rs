@@ -0,0 +1,26 @@
+//! Defines trait for the journal of the ledger.
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use super::models::{LedgerAccount, LedgerEntry, LedgerReport};
+use crate::error::DomainError;
+
+/// `LedgerTrait` is a trait for reading and checking the double-entry journal.
+///
+/// Entries are recorded by the bank account, transaction and hold controllers in the same
+/// database transaction as the balance change they explain.
+#[async_trait]
+pub trait LedgerTrait: Send + Sync {
+	/// Find the entries of an account, oldest first.
+	async fn find_by_account(
+		&self,
+		account: &LedgerAccount,
+	) -> Result<Vec<LedgerEntry>, DomainError>;
+
+	/// Find the entries recorded for a transaction.
+	async fn find_by_transaction_id(&self, id: &Uuid) -> Result<Vec<LedgerEntry>, DomainError>;
+
+	/// Recompute the balances of the bank accounts from the journal and compare them to the
+	/// stored ones.
+	async fn check(&self) -> Result<LedgerReport, DomainError>;
+}
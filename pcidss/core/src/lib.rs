@@ -1,7 +1,17 @@
 //! Core types and traits for the domain layer
 pub mod bank_account;
+pub mod block_cursor;
 pub mod error;
+pub mod fx;
+pub mod hold;
+pub mod ledger;
+pub mod money;
+pub mod outbox;
 pub mod postgres;
+pub mod processed_message;
+pub mod risk;
+pub mod settlement;
 pub mod transaction;
 pub mod types;
 pub mod utils;
+pub mod vault;
@@ -0,0 +1,337 @@
+//! Money type, amounts in the minor units of an ISO-4217 currency.
+
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use tokio_postgres::Row;
+
+use crate::error::DomainError;
+
+/// `Iso4217` is an enum for the supported ISO-4217 currencies.
+#[derive(
+	Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Iso4217 {
+	/// US dollar, currency of the bank accounts unless stated otherwise.
+	#[default]
+	Usd,
+	/// Euro.
+	Eur,
+	/// Pound sterling.
+	Gbp,
+	/// Swiss franc.
+	Chf,
+	/// Japanese yen, it has no minor units.
+	Jpy,
+}
+
+impl Iso4217 {
+	/// All supported currencies.
+	pub const ALL: [Iso4217; 5] =
+		[Iso4217::Usd, Iso4217::Eur, Iso4217::Gbp, Iso4217::Chf, Iso4217::Jpy];
+
+	/// Alphabetic code of the currency, e.g. `USD`.
+	pub fn alpha(&self) -> &'static str {
+		match self {
+			Iso4217::Usd => "USD",
+			Iso4217::Eur => "EUR",
+			Iso4217::Gbp => "GBP",
+			Iso4217::Chf => "CHF",
+			Iso4217::Jpy => "JPY",
+		}
+	}
+
+	/// Numeric code of the currency, e.g. `840`. ISO-8583 messages carry it in field 49.
+	pub fn numeric(&self) -> &'static str {
+		match self {
+			Iso4217::Usd => "840",
+			Iso4217::Eur => "978",
+			Iso4217::Gbp => "826",
+			Iso4217::Chf => "756",
+			Iso4217::Jpy => "392",
+		}
+	}
+
+	/// Number of minor units in a major unit, as a power of 10.
+	pub fn exponent(&self) -> u32 {
+		match self {
+			Iso4217::Jpy => 0,
+			_ => 2,
+		}
+	}
+
+	/// Currency with the given numeric code, `None` if it is not supported.
+	pub fn from_numeric(code: &str) -> Option<Self> {
+		Self::ALL.into_iter().find(|currency| currency.numeric() == code)
+	}
+}
+
+/// Parses both the alphabetic and the numeric code.
+impl FromStr for Iso4217 {
+	type Err = DomainError;
+
+	fn from_str(value: &str) -> Result<Self, Self::Err> {
+		let value = value.trim();
+
+		Self::ALL
+			.into_iter()
+			.find(|currency| currency.alpha().eq_ignore_ascii_case(value))
+			.or_else(|| Self::from_numeric(value))
+			.ok_or(DomainError::BadRequest(format!("Unsupported currency: {}", value)))
+	}
+}
+
+impl fmt::Display for Iso4217 {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.alpha())
+	}
+}
+
+/// `Money` is an amount in the minor units of a currency, e.g. cents.
+///
+/// Amounts are never negative, arithmetic is checked and only allowed within the same currency.
+/// Amounts of different currencies are not comparable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Money {
+	/// Amount in the minor units of the currency.
+	pub minor_units: i64,
+	/// Currency of the amount.
+	pub currency: Iso4217,
+}
+
+impl Money {
+	/// Creates a new `Money`.
+	pub fn new(minor_units: i64, currency: Iso4217) -> Self {
+		Self { minor_units, currency }
+	}
+
+	/// Zero amount of the currency.
+	pub fn zero(currency: Iso4217) -> Self {
+		Self::new(0, currency)
+	}
+
+	/// Whether the amount is zero.
+	pub fn is_zero(&self) -> bool {
+		self.minor_units == 0
+	}
+
+	/// Parses an amount in minor units, e.g. field 4 of an ISO-8583 message.
+	pub fn parse(minor_units: &str, currency: Iso4217) -> Result<Self, DomainError> {
+		let minor_units: i64 = minor_units
+			.trim()
+			.parse()
+			.map_err(|_| DomainError::BadRequest(format!("Invalid amount: {}", minor_units)))?;
+
+		if minor_units < 0 {
+			return Err(DomainError::BadRequest("Amount can't be negative".to_string()));
+		}
+
+		Ok(Self::new(minor_units, currency))
+	}
+
+	/// Sum of the amounts.
+	pub fn checked_add(&self, other: &Money) -> Result<Self, DomainError> {
+		self.ensure_same_currency(other)?;
+
+		self.minor_units
+			.checked_add(other.minor_units)
+			.map(|minor_units| Self::new(minor_units, self.currency))
+			.ok_or(DomainError::ApiError(String::from("Arithmetic underflow/overflow")))
+	}
+
+	/// Difference of the amounts, it fails if the result would be negative.
+	pub fn checked_sub(&self, other: &Money) -> Result<Self, DomainError> {
+		self.ensure_same_currency(other)?;
+
+		self.minor_units
+			.checked_sub(other.minor_units)
+			.filter(|minor_units| *minor_units >= 0)
+			.map(|minor_units| Self::new(minor_units, self.currency))
+			.ok_or(DomainError::ApiError(String::from("Arithmetic underflow/overflow")))
+	}
+
+	/// Converts an on-chain amount, the chain has `chain_decimals` decimals per major unit.
+	///
+	/// Amounts more precise than the minor units of the currency are rejected instead of
+	/// truncated.
+	pub fn from_chain_units(
+		units: u128,
+		currency: Iso4217,
+		chain_decimals: u32,
+	) -> Result<Self, DomainError> {
+		let minor_units = if chain_decimals >= currency.exponent() {
+			let factor = pow10(chain_decimals - currency.exponent())?;
+
+			if !units.is_multiple_of(factor) {
+				return Err(DomainError::BadRequest(format!(
+					"Amount {} is more precise than the minor units of {}",
+					units, currency
+				)));
+			}
+
+			units / factor
+		} else {
+			units
+				.checked_mul(pow10(currency.exponent() - chain_decimals)?)
+				.ok_or(DomainError::ApiError(String::from("Arithmetic underflow/overflow")))?
+		};
+
+		let minor_units = i64::try_from(minor_units)
+			.map_err(|_| DomainError::ApiError(String::from("Arithmetic underflow/overflow")))?;
+
+		Ok(Self::new(minor_units, currency))
+	}
+
+	/// Converts the amount to the on-chain units, the inverse of [`Money::from_chain_units`].
+	pub fn to_chain_units(&self, chain_decimals: u32) -> Result<u128, DomainError> {
+		let minor_units = self.minor_units as u128;
+
+		if chain_decimals >= self.currency.exponent() {
+			minor_units
+				.checked_mul(pow10(chain_decimals - self.currency.exponent())?)
+				.ok_or(DomainError::ApiError(String::from("Arithmetic underflow/overflow")))
+		} else {
+			let factor = pow10(self.currency.exponent() - chain_decimals)?;
+
+			if !minor_units.is_multiple_of(factor) {
+				return Err(DomainError::BadRequest(format!(
+					"Amount {} can't be represented with {} decimals on-chain",
+					self, chain_decimals
+				)));
+			}
+
+			Ok(minor_units / factor)
+		}
+	}
+
+	/// Reads an amount column of a row, the currency is read from its `currency` column.
+	pub fn from_row(row: &Row, column: &str) -> Self {
+		let currency: String = row.get("currency");
+
+		Self::new(
+			row.get::<&str, i64>(column),
+			currency.parse().expect("only supported currencies are stored"),
+		)
+	}
+
+	/// Fails unless both amounts are in the same currency.
+	fn ensure_same_currency(&self, other: &Money) -> Result<(), DomainError> {
+		if self.currency != other.currency {
+			return Err(DomainError::BadRequest(format!(
+				"Currency mismatch: {} and {}",
+				self.currency, other.currency
+			)));
+		}
+
+		Ok(())
+	}
+}
+
+impl PartialOrd for Money {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		if self.currency != other.currency {
+			return None;
+		}
+
+		Some(self.minor_units.cmp(&other.minor_units))
+	}
+}
+
+/// Formats the amount in major units, e.g. `10.00 USD`.
+impl fmt::Display for Money {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let exponent = self.currency.exponent();
+		if exponent == 0 {
+			return write!(f, "{} {}", self.minor_units, self.currency);
+		}
+
+		let factor = 10_i64.pow(exponent);
+		write!(
+			f,
+			"{}.{:0width$} {}",
+			self.minor_units / factor,
+			self.minor_units % factor,
+			self.currency,
+			width = exponent as usize
+		)
+	}
+}
+
+/// `10^exponent`, fails if it doesn't fit.
+fn pow10(exponent: u32) -> Result<u128, DomainError> {
+	10_u128
+		.checked_pow(exponent)
+		.ok_or(DomainError::ApiError(String::from("Arithmetic underflow/overflow")))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_currency_codes() {
+		assert_eq!(Iso4217::from_numeric("840"), Some(Iso4217::Usd));
+		assert_eq!(Iso4217::from_numeric("999"), None);
+		assert_eq!("eur".parse::<Iso4217>(), Ok(Iso4217::Eur));
+		assert_eq!("392".parse::<Iso4217>(), Ok(Iso4217::Jpy));
+		assert!("XXX".parse::<Iso4217>().is_err());
+	}
+
+	#[test]
+	fn test_checked_arithmetic() {
+		let ten = Money::new(1000, Iso4217::Usd);
+		let one = Money::new(100, Iso4217::Usd);
+
+		assert_eq!(ten.checked_add(&one), Ok(Money::new(1100, Iso4217::Usd)));
+		assert_eq!(ten.checked_sub(&one), Ok(Money::new(900, Iso4217::Usd)));
+		assert!(ten > one);
+
+		// never negative
+		assert_eq!(
+			one.checked_sub(&ten),
+			Err(DomainError::ApiError(String::from("Arithmetic underflow/overflow")))
+		);
+		assert!(Money::new(i64::MAX, Iso4217::Usd).checked_add(&one).is_err());
+
+		// currencies don't mix
+		let euro = Money::new(100, Iso4217::Eur);
+		assert!(ten.checked_add(&euro).is_err());
+		assert_eq!(ten.partial_cmp(&euro), None);
+	}
+
+	#[test]
+	fn test_parse() {
+		assert_eq!(
+			Money::parse("00000000000000000100", Iso4217::Usd),
+			Ok(Money::new(100, Iso4217::Usd))
+		);
+		assert!(Money::parse("-100", Iso4217::Usd).is_err());
+		assert!(Money::parse("99999999999999999999", Iso4217::Usd).is_err());
+		assert!(Money::parse("1O0", Iso4217::Usd).is_err());
+	}
+
+	#[test]
+	fn test_chain_units() {
+		// 8 decimals on-chain, 1 cent is 10^6 units
+		let amount = Money::from_chain_units(100_000_000, Iso4217::Usd, 8).unwrap();
+		assert_eq!(amount, Money::new(100, Iso4217::Usd));
+		assert_eq!(amount.to_chain_units(8), Ok(100_000_000));
+
+		// leftover precision is rejected instead of truncated
+		assert!(Money::from_chain_units(100_000_001, Iso4217::Usd, 8).is_err());
+
+		// fewer decimals than the currency
+		assert_eq!(Money::from_chain_units(5, Iso4217::Usd, 1), Ok(Money::new(50, Iso4217::Usd)));
+		assert_eq!(Money::new(50, Iso4217::Usd).to_chain_units(1), Ok(5));
+		assert!(Money::new(55, Iso4217::Usd).to_chain_units(1).is_err());
+
+		// larger than the ledger can hold
+		assert!(Money::from_chain_units(u128::MAX, Iso4217::Jpy, 0).is_err());
+	}
+
+	#[test]
+	fn test_display() {
+		assert_eq!(Money::new(1005, Iso4217::Usd).to_string(), "10.05 USD");
+		assert_eq!(Money::new(1005, Iso4217::Jpy).to_string(), "1005 JPY");
+	}
+}
@@ -0,0 +1,221 @@
+//! Models to represent a finality waiting to be submitted on-chain.
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+/// Number of failed submissions after which a finality needs manual intervention.
+pub const MAX_SUBMISSION_ATTEMPTS: u32 = 12;
+
+/// Delay before the first retry, doubled with every failed submission.
+pub const BASE_RETRY_DELAY_SECS: i64 = 6;
+
+/// Longest delay between retries.
+pub const MAX_RETRY_DELAY_SECS: i64 = 3600;
+
+/// `FinalityStatus` is an enum for the state of a finality in the outbox.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum FinalityStatus {
+	/// Waiting to be submitted, or to be retried.
+	Pending,
+	/// Submitted, waiting to be finalized.
+	Submitted,
+	/// Finalized on-chain.
+	Finalized,
+	/// Failed too many times, needs manual intervention.
+	Failed,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<i32> for FinalityStatus {
+	fn into(self) -> i32 {
+		match self {
+			FinalityStatus::Pending => 0,
+			FinalityStatus::Submitted => 1,
+			FinalityStatus::Finalized => 2,
+			FinalityStatus::Failed => 3,
+		}
+	}
+}
+
+impl From<i32> for FinalityStatus {
+	fn from(value: i32) -> Self {
+		match value {
+			0 => FinalityStatus::Pending,
+			1 => FinalityStatus::Submitted,
+			2 => FinalityStatus::Finalized,
+			_ => FinalityStatus::Failed,
+		}
+	}
+}
+
+/// `FinalityCreate` is a model for enqueuing the finality of an on-chain event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinalityCreate {
+	/// Unique identifier of the finality.
+	pub id: Uuid,
+	/// On-chain id of the event, it is `block_number` - `event_index`.
+	pub event_id: String,
+	/// Hex encoded hash of the block the event is emitted in.
+	pub event_block_hash: String,
+	/// Hex encoded on-chain account the funds are moved from.
+	pub from: String,
+	/// Hex encoded on-chain account the funds are moved to.
+	pub to: String,
+	/// On-chain amount.
+	pub amount: u128,
+	/// ISO-8583 response code of the processed event.
+	pub response_code: String,
+	/// Hash of the transaction, set by the storage if the finality is posted with it.
+	pub transaction_hash: String,
+}
+
+/// `Finality` is a model for a finality in the outbox.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Finality {
+	/// Unique identifier of the finality.
+	pub id: Uuid,
+	/// On-chain id of the event, it is `block_number` - `event_index`.
+	pub event_id: String,
+	/// Hex encoded hash of the block the event is emitted in.
+	pub event_block_hash: String,
+	/// Hex encoded on-chain account the funds are moved from.
+	pub from: String,
+	/// Hex encoded on-chain account the funds are moved to.
+	pub to: String,
+	/// On-chain amount.
+	pub amount: u128,
+	/// ISO-8583 response code of the processed event.
+	pub response_code: String,
+	/// Hash of the transaction.
+	pub transaction_hash: String,
+	/// State of the finality.
+	pub status: FinalityStatus,
+	/// Number of failed submissions.
+	pub attempts: u32,
+	/// Error of the last failed submission.
+	pub last_error: Option<String>,
+	/// Hash of the last submitted extrinsic.
+	pub extrinsic_hash: Option<String>,
+	/// Hash of the block the extrinsic is finalized in.
+	pub block_hash: Option<String>,
+	/// Time after which the finality is (re)submitted.
+	pub next_attempt_at: DateTime<Utc>,
+}
+
+impl Finality {
+	/// Number of the block the event is emitted in, `None` if the event id is malformed.
+	pub fn event_block_number(&self) -> Option<u32> {
+		self.event_id.split_once('-')?.0.parse().ok()
+	}
+
+	/// Time of the next attempt after a failed one, `None` if the finality has failed for good.
+	///
+	/// Delay doubles with every attempt, starting at [`BASE_RETRY_DELAY_SECS`].
+	pub fn retry_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+		let attempts = self.attempts + 1;
+
+		if attempts >= MAX_SUBMISSION_ATTEMPTS {
+			return None;
+		}
+
+		let delay = BASE_RETRY_DELAY_SECS
+			.saturating_mul(1 << (attempts - 1).min(30))
+			.min(MAX_RETRY_DELAY_SECS);
+
+		Some(now + Duration::seconds(delay))
+	}
+}
+
+impl From<&FinalityCreate> for Finality {
+	fn from(value: &FinalityCreate) -> Self {
+		Self {
+			id: value.id,
+			event_id: value.event_id.clone(),
+			event_block_hash: value.event_block_hash.clone(),
+			from: value.from.clone(),
+			to: value.to.clone(),
+			amount: value.amount,
+			response_code: value.response_code.clone(),
+			transaction_hash: value.transaction_hash.clone(),
+			status: FinalityStatus::Pending,
+			attempts: 0,
+			last_error: None,
+			extrinsic_hash: None,
+			block_hash: None,
+			next_attempt_at: Utc::now(),
+		}
+	}
+}
+
+impl From<&tokio_postgres::Row> for Finality {
+	fn from(row: &tokio_postgres::Row) -> Self {
+		Self {
+			id: row.get("id"),
+			event_id: row.get("event_id"),
+			event_block_hash: row.get("event_block_hash"),
+			from: row.get("source"),
+			to: row.get("recipient"),
+			amount: row.get::<&str, String>("amount").parse().unwrap_or_default(),
+			response_code: row.get("response_code"),
+			transaction_hash: row.get("transaction_hash"),
+			status: row.get::<&str, i32>("status").into(),
+			attempts: row.get::<&str, i32>("attempts") as u32,
+			last_error: row.get("last_error"),
+			extrinsic_hash: row.get("extrinsic_hash"),
+			block_hash: row.get("block_hash"),
+			next_attempt_at: row.get("next_attempt_at"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_retry_at() {
+		let now = Utc::now();
+		let mut finality: Finality = (&FinalityCreate {
+			id: Uuid::new_v4(),
+			event_id: "1-1".to_string(),
+			event_block_hash: format!("0x{}", "0".repeat(64)),
+			from: "00".repeat(32),
+			to: "01".repeat(32),
+			amount: 100_000_000,
+			response_code: "00".to_string(),
+			transaction_hash: "0".repeat(64),
+		})
+			.into();
+
+		assert_eq!(finality.retry_at(now), Some(now + Duration::seconds(6)));
+
+		finality.attempts = 1;
+		assert_eq!(finality.retry_at(now), Some(now + Duration::seconds(12)));
+
+		finality.attempts = 10;
+		assert_eq!(finality.retry_at(now), Some(now + Duration::seconds(MAX_RETRY_DELAY_SECS)));
+
+		// gives up after the last attempt
+		finality.attempts = MAX_SUBMISSION_ATTEMPTS - 1;
+		assert_eq!(finality.retry_at(now), None);
+	}
+
+	#[test]
+	fn test_event_block_number() {
+		let mut finality: Finality = (&FinalityCreate {
+			id: Uuid::new_v4(),
+			event_id: "1024-3".to_string(),
+			event_block_hash: format!("0x{}", "0".repeat(64)),
+			from: "00".repeat(32),
+			to: "01".repeat(32),
+			amount: 100_000_000,
+			response_code: "00".to_string(),
+			transaction_hash: "0".repeat(64),
+		})
+			.into();
+
+		assert_eq!(finality.event_block_number(), Some(1024));
+
+		finality.event_id = "unknown".to_string();
+		assert_eq!(finality.event_block_number(), None);
+	}
+}
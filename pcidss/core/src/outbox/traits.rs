@@ -0,0 +1,57 @@
+//! Defines trait for the finality outbox.
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::models::{Finality, FinalityCreate};
+use crate::error::DomainError;
+
+/// `OutboxTrait` is a trait for finalities waiting to be submitted on-chain.
+///
+/// Finalities of approved events are enqueued in the same database transaction as the ledger
+/// change, so that the chain eventually learns about every posted transaction.
+#[async_trait]
+pub trait OutboxTrait: Send + Sync {
+	/// Enqueue a finality, the existing one is returned if the event is already enqueued.
+	async fn create(&self, finality: &FinalityCreate) -> Result<Finality, DomainError>;
+
+	/// Find a finality by on-chain event id.
+	async fn find_by_event_id(&self, event_id: &str) -> Result<Option<Finality>, DomainError>;
+
+	/// Find the finalities of the events emitted in a block.
+	async fn find_by_event_block_hash(
+		&self,
+		block_hash: &str,
+	) -> Result<Vec<Finality>, DomainError>;
+
+	/// Drop the finality of an event if it is not submitted yet, returns whether it is dropped.
+	async fn cancel(&self, event_id: &str) -> Result<bool, DomainError>;
+
+	/// Find pending and submitted finalities due for (re)submission, oldest first.
+	async fn find_due(&self, now: DateTime<Utc>, limit: i64) -> Result<Vec<Finality>, DomainError>;
+
+	/// Mark a finality as submitted, it is resubmitted after `retry_at` unless finalized.
+	async fn mark_submitted(
+		&self,
+		id: &Uuid,
+		extrinsic_hash: &str,
+		retry_at: DateTime<Utc>,
+	) -> Result<Finality, DomainError>;
+
+	/// Mark a finality as finalized in the given block.
+	async fn mark_finalized(&self, id: &Uuid, block_hash: &str) -> Result<Finality, DomainError>;
+
+	/// Record a failed submission, it is retried after `retry_at` or failed for good if `None`.
+	async fn mark_failed_attempt(
+		&self,
+		id: &Uuid,
+		error: &str,
+		retry_at: Option<DateTime<Utc>>,
+	) -> Result<Finality, DomainError>;
+
+	/// Find finalities that failed for good.
+	async fn find_failed(&self) -> Result<Vec<Finality>, DomainError>;
+
+	/// Requeue finalities that failed for good, returns the number of requeued finalities.
+	async fn retry_failed(&self) -> Result<u64, DomainError>;
+}
@@ -0,0 +1,46 @@
+//! Models to represent a processed ISO-8583 message, used to answer retransmissions.
+use chrono::{DateTime, Utc};
+
+/// `ProcessedMessage` is a model for the stored response of a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessedMessage {
+	/// Idempotency key of the request.
+	pub idempotency_key: String,
+	/// Raw ISO-8583 response, `None` while the request is being processed.
+	pub response: Option<Vec<u8>>,
+	/// Time the request was first received.
+	pub created_at: DateTime<Utc>,
+	/// Time the response was stored.
+	pub updated_at: DateTime<Utc>,
+}
+
+impl From<&tokio_postgres::Row> for ProcessedMessage {
+	fn from(row: &tokio_postgres::Row) -> Self {
+		Self {
+			idempotency_key: row.get("idempotency_key"),
+			response: row.get("response"),
+			created_at: row.get("created_at"),
+			updated_at: row.get("updated_at"),
+		}
+	}
+}
+
+/// `Claim` is the outcome of claiming an idempotency key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Claim {
+	/// Key is new, the request has to be processed.
+	Claimed,
+	/// Request with the same key is being processed, or its processing was interrupted.
+	Pending,
+	/// Request with the same key is already processed, holds its raw response.
+	Processed(Vec<u8>),
+}
+
+impl From<&ProcessedMessage> for Claim {
+	fn from(value: &ProcessedMessage) -> Self {
+		match &value.response {
+			Some(response) => Claim::Processed(response.clone()),
+			None => Claim::Pending,
+		}
+	}
+}
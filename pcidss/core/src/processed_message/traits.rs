@@ -0,0 +1,32 @@
+//! Defines trait for idempotent message processing.
+use async_trait::async_trait;
+
+use super::models::{Claim, ProcessedMessage};
+use crate::error::DomainError;
+
+/// `ProcessedMessageTrait` is a trait for storing responses of processed messages.
+///
+/// A request is claimed by its idempotency key before it is processed and completed with its
+/// response afterwards, so that retransmissions get the original response instead of being
+/// processed again.
+#[async_trait]
+pub trait ProcessedMessageTrait: Send + Sync {
+	/// Find a processed message by idempotency key.
+	async fn find_by_key(&self, key: &str) -> Result<Option<ProcessedMessage>, DomainError>;
+
+	/// Claim the idempotency key of a request before processing it.
+	///
+	/// Only one of concurrent claims of the same key gets [`Claim::Claimed`].
+	async fn claim(&self, key: &str) -> Result<Claim, DomainError>;
+
+	/// Store the response of a claimed request.
+	async fn complete(&self, key: &str, response: &[u8]) -> Result<ProcessedMessage, DomainError>;
+
+	/// Release the claim of a request that failed without a response, so that it can be retried.
+	async fn release(&self, key: &str) -> Result<(), DomainError>;
+
+	/// Forget a processed request, so that it is processed again.
+	///
+	/// Used when the on-chain event a request is composed from is retracted.
+	async fn forget(&self, key: &str) -> Result<(), DomainError>;
+}
@@ -0,0 +1,71 @@
+//! Models to represent the activity of a bank account assessed by the risk rules.
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::money::Money;
+
+/// `Spending` is the spending of a bank account in a time window.
+///
+/// Transactions debited from the bank account and active authorization holds are counted, in the
+/// currency of the bank account. Refunds don't restore the spending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Spending {
+	/// Number of transactions and holds.
+	pub transactions_number: u32,
+	/// Total amount of the transactions and holds.
+	pub amount: Money,
+}
+
+impl From<&tokio_postgres::Row> for Spending {
+	fn from(row: &tokio_postgres::Row) -> Self {
+		Self {
+			transactions_number: row.get::<&str, i32>("transactions_number") as u32,
+			amount: Money::from_row(row, "amount"),
+		}
+	}
+}
+
+/// `CvvFailures` are the consecutive failed CVV verifications of a bank account.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CvvFailures {
+	/// Unique identifier of the bank account.
+	pub bank_account_id: Uuid,
+	/// Number of consecutive failed verifications.
+	pub attempts: u32,
+	/// Time of the last failed verification.
+	pub last_failed_at: DateTime<Utc>,
+}
+
+impl CvvFailures {
+	/// Whether the card is locked out, i.e. it failed at least `max_attempts` times and the last
+	/// failure is less than `lockout` ago.
+	pub fn is_locked_out(&self, max_attempts: u32, lockout: Duration, now: DateTime<Utc>) -> bool {
+		self.attempts >= max_attempts && now < self.last_failed_at + lockout
+	}
+}
+
+impl From<&tokio_postgres::Row> for CvvFailures {
+	fn from(row: &tokio_postgres::Row) -> Self {
+		Self {
+			bank_account_id: row.get("bank_account_id"),
+			attempts: row.get::<&str, i32>("attempts") as u32,
+			last_failed_at: row.get("last_failed_at"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_cvv_lockout() {
+		let now = Utc::now();
+		let failures =
+			CvvFailures { bank_account_id: Uuid::new_v4(), attempts: 3, last_failed_at: now };
+
+		assert!(failures.is_locked_out(3, Duration::minutes(30), now));
+		assert!(!failures.is_locked_out(4, Duration::minutes(30), now));
+		assert!(!failures.is_locked_out(3, Duration::minutes(30), now + Duration::minutes(30)));
+	}
+}
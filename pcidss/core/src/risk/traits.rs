@@ -0,0 +1,37 @@
+//! Defines trait for the activity of bank accounts assessed by the risk rules.
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::models::{CvvFailures, Spending};
+use crate::error::DomainError;
+
+/// `RiskTrait` is a trait for the activity of bank accounts assessed by the risk rules.
+#[async_trait]
+pub trait RiskTrait: Send + Sync {
+	/// Spending of a bank account since the given time.
+	async fn spending_since(
+		&self,
+		bank_account_id: &Uuid,
+		since: DateTime<Utc>,
+	) -> Result<Spending, DomainError>;
+
+	/// Find the consecutive failed CVV verifications of a bank account.
+	async fn find_cvv_failures(
+		&self,
+		bank_account_id: &Uuid,
+	) -> Result<Option<CvvFailures>, DomainError>;
+
+	/// Record a failed CVV verification of a bank account.
+	///
+	/// Failures are counted again from one if the last one is before `reset_before`, e.g. once a
+	/// lockout is over.
+	async fn record_cvv_failure(
+		&self,
+		bank_account_id: &Uuid,
+		reset_before: DateTime<Utc>,
+	) -> Result<CvvFailures, DomainError>;
+
+	/// Forget the failed CVV verifications of a bank account, after a successful one.
+	async fn reset_cvv_failures(&self, bank_account_id: &Uuid) -> Result<(), DomainError>;
+}
@@ -0,0 +1,236 @@
+//! Models to represent settlement batches of acquirers and their clearing files.
+use std::fmt::Write;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use uuid::Uuid;
+
+use crate::{
+	error::DomainError,
+	money::{Iso4217, Money},
+	transaction::models::Transaction,
+	types::TransactionType,
+};
+
+/// `SettlementTotals` are the totals of the transactions of an acquirer, from its point of view.
+///
+/// Credits are the amounts the acquirer is paid, e.g. purchases, debits the ones it pays back,
+/// e.g. reversals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SettlementTotals {
+	/// Number of credits.
+	pub credits_number: u32,
+	/// Total amount of the credits.
+	pub credits_amount: Money,
+	/// Number of debits.
+	pub debits_number: u32,
+	/// Total amount of the debits.
+	pub debits_amount: Money,
+}
+
+impl SettlementTotals {
+	/// Totals without any transaction.
+	pub fn zero(currency: Iso4217) -> Self {
+		Self {
+			credits_number: 0,
+			credits_amount: Money::zero(currency),
+			debits_number: 0,
+			debits_amount: Money::zero(currency),
+		}
+	}
+
+	/// Sum of the totals, e.g. of several batches.
+	pub fn checked_add(&self, other: &SettlementTotals) -> Result<Self, DomainError> {
+		Ok(Self {
+			credits_number: self.credits_number + other.credits_number,
+			credits_amount: self.credits_amount.checked_add(&other.credits_amount)?,
+			debits_number: self.debits_number + other.debits_number,
+			debits_amount: self.debits_amount.checked_add(&other.debits_amount)?,
+		})
+	}
+
+	/// Net position of the acquirer in minor units, positive if it is owed funds.
+	pub fn net_minor_units(&self) -> i64 {
+		self.credits_amount.minor_units - self.debits_amount.minor_units
+	}
+
+	/// Reads the totals columns of a row, amounts are in the currency of its `currency` column.
+	pub fn from_row(row: &tokio_postgres::Row) -> Self {
+		Self {
+			credits_number: row.get::<&str, i32>("credits_number") as u32,
+			credits_amount: Money::from_row(row, "credits_amount"),
+			debits_number: row.get::<&str, i32>("debits_number") as u32,
+			debits_amount: Money::from_row(row, "debits_amount"),
+		}
+	}
+}
+
+/// `SettlementBatch` is a model for the transactions of an acquirer settled at a cut-off.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SettlementBatch {
+	/// Unique identifier of the batch.
+	pub id: Uuid,
+	/// Bank account of the acquirer, identified by field 32 of the ISO-8583 messages.
+	pub acquirer_id: Uuid,
+	/// Business day the batch is settled for, the day of the last instant before the cut-off.
+	pub settlement_date: NaiveDate,
+	/// End of the window, transactions created before it that are not settled yet are in the
+	/// batch.
+	pub cutoff: DateTime<Utc>,
+	/// Totals of the transactions in the batch, in the currency of the acquirer.
+	pub totals: SettlementTotals,
+	/// Time the batch is created.
+	pub created_at: DateTime<Utc>,
+}
+
+impl SettlementBatch {
+	/// Clearing file of the batch, a CSV file with a header, a detail and a trailer record type.
+	///
+	/// The header identifies the batch: id, acquirer, settlement date, cut-off and currency.
+	/// There is a detail record per transaction: id, hash, refunded transaction if it is a
+	/// refund, indicator, amount in the currency of the acquirer and amount billed to the card
+	/// holder with its currency. The trailer carries the totals and the net position. Credits
+	/// are marked `C` and debits `D`, the net position is signed the same way.
+	pub fn clearing_file(&self, transactions: &[Transaction]) -> String {
+		let currency = self.totals.credits_amount.currency;
+		let mut file = String::new();
+
+		let _ = writeln!(
+			file,
+			"H,{},{},{},{},{}",
+			self.id,
+			self.acquirer_id,
+			self.settlement_date.format("%Y%m%d"),
+			self.cutoff.to_rfc3339(),
+			currency.alpha()
+		);
+
+		for transaction in transactions {
+			let indicator = match TransactionType::from(transaction.transaction_type) {
+				TransactionType::Credit => 'C',
+				TransactionType::Debit => 'D',
+			};
+
+			let _ = writeln!(
+				file,
+				"D,{},{},{},{},{},{},{}",
+				transaction.id,
+				transaction.hash,
+				transaction.parent_id.map(|id| id.to_string()).unwrap_or_default(),
+				indicator,
+				transaction.recipient_amount().minor_units,
+				transaction.amount.minor_units,
+				transaction.amount.currency.alpha()
+			);
+		}
+
+		let net = self.totals.net_minor_units();
+		let _ = writeln!(
+			file,
+			"T,{},{},{},{},{}{}",
+			self.totals.credits_number,
+			self.totals.credits_amount.minor_units,
+			self.totals.debits_number,
+			self.totals.debits_amount.minor_units,
+			if net < 0 { 'D' } else { 'C' },
+			net.abs()
+		);
+
+		file
+	}
+}
+
+impl From<&tokio_postgres::Row> for SettlementBatch {
+	fn from(row: &tokio_postgres::Row) -> Self {
+		Self {
+			id: row.get("id"),
+			acquirer_id: row.get("acquirer_id"),
+			settlement_date: row.get("settlement_date"),
+			cutoff: row.get("cutoff"),
+			totals: SettlementTotals::from_row(row),
+			created_at: row.get("created_at"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::transaction::models::TransactionCreate;
+
+	#[test]
+	fn test_clearing_file() {
+		let usd = |minor_units| Money::new(minor_units, Iso4217::Usd);
+		let acquirer_id = Uuid::new_v4();
+
+		let purchase: Transaction = (&TransactionCreate {
+			id: Uuid::new_v4(),
+			from: Uuid::new_v4(),
+			to: Some(acquirer_id),
+			amount: usd(1000),
+			conversion: None,
+			transaction_type: TransactionType::Credit,
+			nonce: 0,
+			iso_msg_raw: vec![48, 50, 48, 48],
+			on_chain_id: None,
+			finality: None,
+		})
+			.into();
+
+		let mut reversal: Transaction = (&TransactionCreate {
+			id: Uuid::new_v4(),
+			from: purchase.from,
+			to: purchase.to,
+			amount: usd(400),
+			conversion: None,
+			transaction_type: TransactionType::Debit,
+			nonce: 1,
+			iso_msg_raw: vec![48, 52, 48, 48],
+			on_chain_id: None,
+			finality: None,
+		})
+			.into();
+		reversal.parent_id = Some(purchase.id);
+
+		let batch = SettlementBatch {
+			id: Uuid::new_v4(),
+			acquirer_id,
+			settlement_date: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+			cutoff: "2024-03-02T00:00:00Z".parse().unwrap(),
+			totals: SettlementTotals {
+				credits_number: 1,
+				credits_amount: usd(1000),
+				debits_number: 1,
+				debits_amount: usd(400),
+			},
+			created_at: Utc::now(),
+		};
+
+		assert_eq!(batch.totals.net_minor_units(), 600);
+		assert_eq!(
+			SettlementTotals::zero(Iso4217::Usd).checked_add(&batch.totals),
+			Ok(batch.totals)
+		);
+		assert!(SettlementTotals::zero(Iso4217::Eur).checked_add(&batch.totals).is_err());
+
+		let file = batch.clearing_file(&[purchase.clone(), reversal.clone()]);
+		let lines: Vec<&str> = file.lines().collect();
+
+		assert_eq!(
+			lines,
+			vec![
+				format!("H,{},{},20240301,2024-03-02T00:00:00+00:00,USD", batch.id, acquirer_id),
+				format!("D,{},{},,C,1000,1000,USD", purchase.id, purchase.hash),
+				format!("D,{},{},{},D,400,400,USD", reversal.id, reversal.hash, purchase.id),
+				"T,1,1000,1,400,C600".to_string(),
+			]
+		);
+
+		// acquirer owes funds if it paid back more than it is paid
+		let batch = SettlementBatch {
+			totals: SettlementTotals { credits_number: 0, credits_amount: usd(0), ..batch.totals },
+			..batch
+		};
+
+		assert!(batch.clearing_file(&[reversal]).ends_with("T,0,0,1,400,D400\n"));
+	}
+}
@@ -0,0 +1,35 @@
+//! Defines trait for settlement operations.
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::models::{SettlementBatch, SettlementTotals};
+use crate::{error::DomainError, transaction::models::Transaction};
+
+/// `SettlementTrait` is a trait for settlement operations.
+///
+/// Transactions of acquirers are the ones composed from ISO-8583 messages with a receiving bank
+/// account, identified by field 32. On-chain transfers are settled on-chain.
+#[async_trait]
+pub trait SettlementTrait: Send + Sync {
+	/// Close the window of every acquirer at the cut-off.
+	///
+	/// Transactions created before `cutoff` that are not settled yet are assigned to a new batch
+	/// per acquirer. Acquirers without such transactions get no batch.
+	async fn cut_off(&self, cutoff: DateTime<Utc>) -> Result<Vec<SettlementBatch>, DomainError>;
+
+	/// Find a batch by unique identifier.
+	async fn find_by_id(&self, id: &Uuid) -> Result<Option<SettlementBatch>, DomainError>;
+
+	/// Find the batches of an acquirer, latest first.
+	async fn find_by_acquirer(
+		&self,
+		acquirer_id: &Uuid,
+	) -> Result<Vec<SettlementBatch>, DomainError>;
+
+	/// Find the transactions of a batch, in the order they are created.
+	async fn find_transactions(&self, batch_id: &Uuid) -> Result<Vec<Transaction>, DomainError>;
+
+	/// Totals of the transactions of an acquirer that are not settled yet.
+	async fn open_totals(&self, acquirer_id: &Uuid) -> Result<SettlementTotals, DomainError>;
+}
@@ -1,8 +1,11 @@
 //! Models to represent a transaction and its operations.
-use sha2::{Digest, Sha256};
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
-use crate::{bank_account::models::BankAccountUpdate, types::TransactionType};
+use crate::{
+	bank_account::models::BankAccountUpdate, fx::Conversion, money::Money,
+	outbox::models::FinalityCreate, types::TransactionType, utils::hash_iso_msg,
+};
 
 #[derive(Debug, Clone)]
 pub struct TransactionCreate {
@@ -12,8 +15,11 @@ pub struct TransactionCreate {
 	pub from: Uuid,
 	/// Unique identifier of the receiving bank account, if any.
 	pub to: Option<Uuid>,
-	/// Amount of the transaction.
-	pub amount: u32,
+	/// Amount of the transaction, in the currency of the sender bank account.
+	pub amount: Money,
+	/// Conversion from the currency of the transaction, if it differs from the one of the
+	/// sender bank account.
+	pub conversion: Option<Conversion>,
 	/// Type of the transaction.
 	pub transaction_type: TransactionType,
 	/// Nonce of the transaction.
@@ -22,6 +28,10 @@ pub struct TransactionCreate {
 	pub iso_msg_raw: Vec<u8>,
 	/// On-chain id of the transaction, it is `block_number` - `event_index`.
 	pub on_chain_id: Option<String>,
+	/// Finality of the on-chain event, enqueued together with the transaction.
+	///
+	/// Its `transaction_hash` is set to the hash of the posted transaction.
+	pub finality: Option<FinalityCreate>,
 }
 
 /// `Transaction` is a model for a transaction.
@@ -35,32 +45,72 @@ pub struct Transaction {
 	pub from: Uuid,
 	/// Unique identifier of the receiving bank account, if any.
 	pub to: Option<Uuid>,
-	/// Amount of the transaction.
-	pub amount: u32,
+	/// Amount of the transaction, in the currency of the sender bank account.
+	pub amount: Money,
+	/// Conversion from the currency of the transaction, if it differs from the one of the
+	/// sender bank account.
+	pub conversion: Option<Conversion>,
 	/// Type of the transaction.
 	pub transaction_type: u32,
-	/// Is it reversed?
+	/// Is it fully reversed?
 	pub reversed: bool,
 	/// On-chain id of the transaction, it is `block_number` - `event_index`.
 	pub on_chain_id: Option<String>,
+	/// Transaction that is refunded by this one, if it is a refund.
+	pub parent_id: Option<Uuid>,
+	/// Total amount refunded by child transactions.
+	pub refunded_amount: Money,
+	/// Hash of the block the on-chain event of the transaction is emitted in.
+	pub block_hash: Option<String>,
+	/// Is it compensated because its block was retracted?
+	pub retracted: bool,
+	/// Time the transaction is posted at.
+	pub created_at: DateTime<Utc>,
+	/// Time the transaction is last updated at, e.g. refunded.
+	pub updated_at: DateTime<Utc>,
+}
+
+impl Transaction {
+	/// Amount that can still be refunded, refunds themselves can't be refunded.
+	pub fn refundable_amount(&self) -> Money {
+		if self.parent_id.is_some() {
+			return Money::zero(self.amount.currency);
+		}
+
+		self.amount
+			.checked_sub(&self.refunded_amount)
+			.unwrap_or(Money::zero(self.amount.currency))
+	}
+
+	/// Amount of the receiving bank account, it is in the currency of the transaction.
+	pub fn recipient_amount(&self) -> Money {
+		self.conversion.map(|conversion| conversion.amount).unwrap_or(self.amount)
+	}
+
+	/// Position of the transaction in the history of a bank account.
+	pub fn cursor(&self) -> TransactionCursor {
+		TransactionCursor { created_at: self.created_at, id: self.id }
+	}
 }
 
 impl From<&TransactionCreate> for Transaction {
 	fn from(value: &TransactionCreate) -> Self {
-		let mut hasher = Sha256::new();
-		hasher.update(value.iso_msg_raw.clone());
-		hasher.update(value.nonce.to_be_bytes());
-		let hash = hasher.finalize();
-
 		Self {
 			id: value.id,
-			hash: format!("{:x}", hash),
+			hash: hash_iso_msg(&value.iso_msg_raw, value.nonce),
 			from: value.from,
 			to: value.to,
 			amount: value.amount,
+			conversion: value.conversion,
 			transaction_type: value.transaction_type.clone().into(),
 			reversed: false,
 			on_chain_id: value.on_chain_id.clone(),
+			parent_id: None,
+			refunded_amount: Money::zero(value.amount.currency),
+			block_hash: value.finality.as_ref().map(|finality| finality.event_block_hash.clone()),
+			retracted: false,
+			created_at: Utc::now(),
+			updated_at: Utc::now(),
 		}
 	}
 }
@@ -72,23 +122,133 @@ impl From<&tokio_postgres::Row> for Transaction {
 			hash: row.get("hash"),
 			from: row.get("source"),
 			to: row.get("recipient"),
-			amount: row.get::<&str, i32>("amount") as u32,
+			amount: Money::from_row(row, "amount"),
+			conversion: Conversion::from_row(row),
 			transaction_type: row.get::<&str, i32>("transaction_type") as u32,
 			reversed: row.get("reversed"),
 			on_chain_id: row.get("on_chain_id"),
+			parent_id: row.get("parent_id"),
+			refunded_amount: Money::from_row(row, "refunded_amount"),
+			block_hash: row.get("block_hash"),
+			retracted: row.get("retracted"),
+			created_at: row.get("created_at"),
+			updated_at: row.get("updated_at"),
 		}
 	}
 }
 
+/// Default number of transactions in a page of the history.
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+
+/// Maximum number of transactions in a page of the history.
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+/// Direction of a transaction for a bank account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionDirection {
+	/// The bank account is the source of the transaction.
+	Outgoing,
+	/// The bank account is the recipient of the transaction.
+	Incoming,
+}
+
+/// Sort order of the history, by time the transactions are posted at.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+	/// Oldest first.
+	Asc,
+	/// Newest first.
+	#[default]
+	Desc,
+}
+
+/// Position of a transaction in the history, pages resume after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransactionCursor {
+	/// Time the transaction is posted at.
+	pub created_at: DateTime<Utc>,
+	/// Unique identifier of the transaction, to break ties.
+	pub id: Uuid,
+}
+
+/// Query of the transaction history of a bank account, no filter is applied if not set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransactionQuery {
+	/// Cursor of the last transaction of the previous page.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub after: Option<TransactionCursor>,
+	/// Number of transactions in the page, `DEFAULT_PAGE_SIZE` if not set.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub limit: Option<u32>,
+	/// Transactions posted at or after this time.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub since: Option<DateTime<Utc>>,
+	/// Transactions posted before this time.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub until: Option<DateTime<Utc>>,
+	/// Outgoing or incoming transactions.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub direction: Option<TransactionDirection>,
+	/// Fully reversed transactions, or not.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub reversed: Option<bool>,
+	/// Transactions of at least this amount, in minor units.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub min_amount: Option<i64>,
+	/// Transactions of at most this amount, in minor units.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub max_amount: Option<i64>,
+	/// Sort order, newest first if not set.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub order: Option<SortOrder>,
+}
+
+impl TransactionQuery {
+	/// Number of transactions in the page, at most `MAX_PAGE_SIZE`.
+	pub fn page_size(&self) -> u32 {
+		self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+	}
+
+	/// Sort order of the page.
+	pub fn sort_order(&self) -> SortOrder {
+		self.order.unwrap_or_default()
+	}
+}
+
+/// Page of the transaction history of a bank account.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TransactionPage {
+	/// Transactions of the page.
+	pub transactions: Vec<Transaction>,
+	/// Cursor of the next page, `None` if it is the last one.
+	pub next_cursor: Option<TransactionCursor>,
+}
+
+impl TransactionPage {
+	/// Page of the transactions fetched with one more than the page size, to know if there is a
+	/// next page.
+	pub fn new(mut transactions: Vec<Transaction>, page_size: u32) -> Self {
+		let next_cursor = if transactions.len() > page_size as usize {
+			transactions.truncate(page_size as usize);
+			transactions.last().map(Transaction::cursor)
+		} else {
+			None
+		};
+
+		Self { transactions, next_cursor }
+	}
+}
+
 #[allow(clippy::from_over_into)]
 impl Into<BankAccountUpdate> for &Transaction {
 	fn into(self) -> BankAccountUpdate {
 		BankAccountUpdate::Balance {
 			amount: self.amount,
-			transaction_type: match self.transaction_type {
-				1 => TransactionType::Credit,
-				_ => TransactionType::Debit,
-			},
+			transaction_type: self.transaction_type.into(),
 		}
 	}
 }
@@ -96,6 +256,7 @@ impl Into<BankAccountUpdate> for &Transaction {
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use crate::money::Iso4217;
 	use sha2::{Digest, Sha256};
 
 	#[test]
@@ -124,9 +285,11 @@ mod tests {
 			nonce: 12345,
 			from: Uuid::new_v4(),
 			to: Some(Uuid::new_v4()),
-			amount: 1000,
+			amount: Money::new(1000, Iso4217::Usd),
+			conversion: None,
 			transaction_type: TransactionType::Debit,
 			on_chain_id: None,
+			finality: None,
 		};
 
 		let transaction: Transaction = (&transaction_create).into();
@@ -143,4 +306,102 @@ mod tests {
 		assert_eq!(transaction.to, transaction_create.to);
 		assert_eq!(transaction.amount, transaction_create.amount);
 	}
+
+	#[test]
+	fn test_refundable_amount() {
+		let transaction_create = TransactionCreate {
+			id: Uuid::new_v4(),
+			iso_msg_raw: vec![48, 49, 48, 48],
+			nonce: 0,
+			from: Uuid::new_v4(),
+			to: None,
+			amount: Money::new(100, Iso4217::Usd),
+			conversion: None,
+			transaction_type: TransactionType::Credit,
+			on_chain_id: None,
+			finality: None,
+		};
+
+		let usd = |minor_units| Money::new(minor_units, Iso4217::Usd);
+
+		let mut transaction: Transaction = (&transaction_create).into();
+		assert_eq!(transaction.refundable_amount(), usd(100));
+
+		transaction.refunded_amount = usd(30);
+		assert_eq!(transaction.refundable_amount(), usd(70));
+
+		transaction.refunded_amount = usd(100);
+		assert_eq!(transaction.refundable_amount(), usd(0));
+
+		// refunds can't be refunded
+		let mut refund: Transaction = (&transaction_create).into();
+		refund.parent_id = Some(transaction.id);
+		assert_eq!(refund.refundable_amount(), usd(0));
+	}
+
+	#[test]
+	fn test_transaction_page() {
+		let transactions: Vec<Transaction> = (0..3)
+			.map(|_| {
+				(&TransactionCreate {
+					id: Uuid::new_v4(),
+					iso_msg_raw: vec![48, 49, 48, 48],
+					nonce: 0,
+					from: Uuid::new_v4(),
+					to: None,
+					amount: Money::new(100, Iso4217::Usd),
+					conversion: None,
+					transaction_type: TransactionType::Credit,
+					on_chain_id: None,
+					finality: None,
+				})
+					.into()
+			})
+			.collect();
+
+		// one more than the page size, there is a next page after the last one of the page
+		let page = TransactionPage::new(transactions.clone(), 2);
+		assert_eq!(page.transactions, transactions[..2]);
+		assert_eq!(page.next_cursor, Some(transactions[1].cursor()));
+
+		let page = TransactionPage::new(transactions.clone(), 3);
+		assert_eq!(page.transactions, transactions);
+		assert_eq!(page.next_cursor, None);
+
+		// page sizes are bounded
+		let query = TransactionQuery { limit: Some(10_000), ..Default::default() };
+		assert_eq!(query.page_size(), MAX_PAGE_SIZE);
+		assert_eq!(TransactionQuery { limit: Some(0), ..query }.page_size(), 1);
+		assert_eq!(TransactionQuery::default().page_size(), DEFAULT_PAGE_SIZE);
+		assert_eq!(TransactionQuery::default().sort_order(), SortOrder::Desc);
+	}
+
+	#[test]
+	fn test_recipient_amount() {
+		let mut transaction: Transaction = (&TransactionCreate {
+			id: Uuid::new_v4(),
+			iso_msg_raw: vec![48, 49, 48, 48],
+			nonce: 0,
+			from: Uuid::new_v4(),
+			to: Some(Uuid::new_v4()),
+			amount: Money::new(1085, Iso4217::Usd),
+			conversion: None,
+			transaction_type: TransactionType::Credit,
+			on_chain_id: None,
+			finality: None,
+		})
+			.into();
+
+		assert_eq!(transaction.recipient_amount(), transaction.amount);
+
+		// recipient gets the amount in the currency of the transaction
+		let conversion = Conversion {
+			amount: Money::new(1000, Iso4217::Eur),
+			rate: "1.085".parse().unwrap(),
+			rate_version: 1,
+		};
+		transaction.conversion = Some(conversion);
+
+		assert_eq!(transaction.recipient_amount(), conversion.amount);
+	}
 }
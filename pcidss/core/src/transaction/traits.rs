@@ -2,7 +2,7 @@
 use async_trait::async_trait;
 use uuid::Uuid;
 
-use super::models::{Transaction, TransactionCreate};
+use super::models::{Transaction, TransactionCreate, TransactionPage, TransactionQuery};
 use crate::error::DomainError;
 
 /// `TransactionTrait` is a trait for transaction operations.
@@ -17,13 +17,53 @@ pub trait TransactionTrait: Send + Sync {
 	async fn find_by_bank_account_id(&self, source: &Uuid)
 		-> Result<Vec<Transaction>, DomainError>;
 
+	/// Find a page of the transaction history of a bank account.
+	///
+	/// The history has the transactions the bank account is the source or the recipient of,
+	/// filtered and sorted by the query. Pages are sorted by time and identifier, and resume
+	/// after the cursor of the query.
+	async fn find_page(
+		&self,
+		bank_account_id: &Uuid,
+		query: &TransactionQuery,
+	) -> Result<TransactionPage, DomainError>;
+
 	/// Find a transaction by hash.
 	async fn find_by_hash(&self, hash: &str) -> Result<Option<Transaction>, DomainError>;
 
+	/// Find the transaction posted for an on-chain event, retracted ones are ignored.
+	async fn find_by_on_chain_id(
+		&self,
+		on_chain_id: &str,
+	) -> Result<Option<Transaction>, DomainError>;
+
 	/// Create a new transaction.
 	async fn create(&self, transaction: &TransactionCreate) -> Result<Transaction, DomainError>;
 
-	/// Update a transaction.
-	/// Mainly used to update `reversed` field.
-	async fn update(&self, id: &Uuid) -> Result<Transaction, DomainError>;
+	/// Move funds between bank accounts and record the transaction atomically.
+	///
+	/// Both bank accounts are locked for the duration of the transfer, `from` is updated with
+	/// `transaction_type` and `to` (if any) with the opposite one. Returns
+	/// [`DomainError::InsufficientFunds`] if `from` can't cover the amount. The finality of the
+	/// on-chain event, if any, is enqueued within the same database transaction.
+	async fn transfer(&self, transaction: &TransactionCreate) -> Result<Transaction, DomainError>;
+
+	/// Refund a transaction fully or partially and record the refund atomically.
+	///
+	/// `refund` is posted as a child transaction that moves `refund.amount` back from the
+	/// recipient to the source, the parent is flagged as reversed once fully refunded. Fails if
+	/// the amount exceeds the refundable amount of the parent. The finality of the on-chain
+	/// event, if any, is enqueued within the same database transaction.
+	async fn revert(
+		&self,
+		id: &Uuid,
+		refund: &TransactionCreate,
+	) -> Result<Transaction, DomainError>;
+
+	/// Compensate a transaction whose on-chain event was retracted by a reorg.
+	///
+	/// Moves the funds back, the refundable amount for a transaction and the whole amount for a
+	/// refund, whose parent becomes refundable again. The transaction is flagged as retracted and
+	/// its pending finality is dropped within the same database transaction.
+	async fn retract(&self, id: &Uuid) -> Result<Transaction, DomainError>;
 }
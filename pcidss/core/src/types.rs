@@ -9,6 +9,16 @@ pub enum TransactionType {
 	Credit,
 }
 
+impl TransactionType {
+	/// Returns the opposite transaction type, i.e. the one applied to the counterparty.
+	pub fn inverse(&self) -> Self {
+		match self {
+			TransactionType::Debit => TransactionType::Credit,
+			TransactionType::Credit => TransactionType::Debit,
+		}
+	}
+}
+
 #[allow(clippy::from_over_into)]
 impl Into<u32> for TransactionType {
 	fn into(self) -> u32 {
@@ -18,3 +28,12 @@ impl Into<u32> for TransactionType {
 		}
 	}
 }
+
+impl From<u32> for TransactionType {
+	fn from(value: u32) -> Self {
+		match value {
+			1 => TransactionType::Credit,
+			_ => TransactionType::Debit,
+		}
+	}
+}
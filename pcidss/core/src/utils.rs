@@ -1,3 +1,4 @@
+use sha2::{Digest, Sha256};
 use std::{
 	cell::RefCell,
 	sync::{
@@ -28,3 +29,13 @@ pub async fn block_until_sigint() {
 
 	ctrlc_oneshot.await.unwrap();
 }
+
+/// Hash of a raw ISO-8583 message together with the nonce of the bank account
+///
+/// Used as a unique reference to the ledger records created by the message.
+pub fn hash_iso_msg(iso_msg_raw: &[u8], nonce: u32) -> String {
+	let mut hasher = Sha256::new();
+	hasher.update(iso_msg_raw);
+	hasher.update(nonce.to_be_bytes());
+	format!("{:x}", hasher.finalize())
+}
@@ -0,0 +1,300 @@
+//! Tokenization vault for card data
+//!
+//! Card data is sealed with envelope encryption: every card gets its own random data key, which
+//! is wrapped by a versioned key-encryption key of the [`traits::KeyProvider`]. Cards are looked
+//! up by the keyed hash of their number, CVVs are never stored, only a salted keyed hash of them
+//! is kept to verify incoming requests.
+
+pub mod models;
+pub mod traits;
+
+use aes_gcm::{
+	aead::{Aead, KeyInit},
+	Aes256Gcm, Key, Nonce,
+};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::sync::Arc;
+
+use self::{
+	models::{CardData, CardToken, EncryptedCardData, LEGACY_KEY_VERSION},
+	traits::KeyProvider,
+};
+use crate::error::DomainError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of the keys in bytes
+pub const KEY_LENGTH: usize = 32;
+/// Length of the AES-GCM nonce prepended to the ciphertext
+const NONCE_LENGTH: usize = 12;
+/// Length of the salt used for CVV hashes
+const SALT_LENGTH: usize = 16;
+
+/// Encrypts, hashes and verifies card data with keys of the key provider
+pub struct Vault {
+	/// Provider of the key-encryption keys
+	key_provider: Arc<dyn KeyProvider>,
+}
+
+impl Vault {
+	/// Create a new vault on top of the key provider
+	pub fn new(key_provider: Arc<dyn KeyProvider>) -> Self {
+		Self { key_provider }
+	}
+
+	/// Version of the key-encryption key used for new card data
+	pub async fn current_key_version(&self) -> Result<u32, DomainError> {
+		self.key_provider.current_version().await
+	}
+
+	/// Tokenize card data with a new data key
+	pub async fn tokenize(&self, card: &CardData) -> Result<CardToken, DomainError> {
+		Ok(CardToken {
+			hash: self.card_number_hash(&card.card_number).await?,
+			masked: mask_card_number(&card.card_number),
+			encrypted: self.seal(card).await?,
+		})
+	}
+
+	/// Decrypt card data sealed by [`Self::tokenize`]
+	///
+	/// Names of [`LEGACY_KEY_VERSION`] card data are not encrypted, so they are left empty.
+	pub async fn detokenize(&self, encrypted: &EncryptedCardData) -> Result<CardData, DomainError> {
+		let data_key =
+			self.key_provider.unwrap_key(encrypted.key_version, &encrypted.data_key).await?;
+
+		let decrypt_field = |ciphertext: &[u8]| {
+			String::from_utf8(decrypt(&data_key, ciphertext)?)
+				.map_err(|_| DomainError::InternalServerError("Invalid card data".to_string()))
+		};
+
+		if encrypted.key_version == LEGACY_KEY_VERSION {
+			return Ok(CardData {
+				card_number: decrypt_field(&encrypted.card_number)?,
+				card_holder_first_name: String::new(),
+				card_holder_last_name: String::new(),
+			});
+		}
+
+		Ok(CardData {
+			card_number: decrypt_field(&encrypted.card_number)?,
+			card_holder_first_name: decrypt_field(&encrypted.card_holder_first_name)?,
+			card_holder_last_name: decrypt_field(&encrypted.card_holder_last_name)?,
+		})
+	}
+
+	/// Seal card data with a new data key wrapped by the current key-encryption key
+	pub async fn seal(&self, card: &CardData) -> Result<EncryptedCardData, DomainError> {
+		let mut data_key = [0u8; KEY_LENGTH];
+		rand::thread_rng().fill_bytes(&mut data_key);
+
+		let key_version = self.key_provider.current_version().await?;
+
+		Ok(EncryptedCardData {
+			key_version,
+			data_key: self.key_provider.wrap_key(key_version, &data_key).await?,
+			card_number: encrypt(&data_key, card.card_number.as_bytes())?,
+			card_holder_first_name: encrypt(&data_key, card.card_holder_first_name.as_bytes())?,
+			card_holder_last_name: encrypt(&data_key, card.card_holder_last_name.as_bytes())?,
+		})
+	}
+
+	/// Keyed hash of a card number, deterministic so that it can be used for lookups
+	pub async fn card_number_hash(&self, card_number: &str) -> Result<String, DomainError> {
+		let mut mac = self.mac().await?;
+		mac.update(card_number.as_bytes());
+		Ok(hex::encode(mac.finalize().into_bytes()))
+	}
+
+	/// Salted keyed hash of a CVV, formatted as `<salt>$<hash>`
+	pub async fn hash_cvv(&self, cvv: &str) -> Result<String, DomainError> {
+		let mut salt = [0u8; SALT_LENGTH];
+		rand::thread_rng().fill_bytes(&mut salt);
+
+		let mut mac = self.mac().await?;
+		mac.update(&salt);
+		mac.update(cvv.as_bytes());
+
+		Ok(format!("{}${}", hex::encode(salt), hex::encode(mac.finalize().into_bytes())))
+	}
+
+	/// Verify a CVV against a hash produced by [`Self::hash_cvv`] in constant time
+	pub async fn verify_cvv(&self, cvv: &str, cvv_hash: &str) -> Result<bool, DomainError> {
+		let Some((salt, hash)) = cvv_hash.split_once('$') else {
+			return Ok(false);
+		};
+
+		let (Ok(salt), Ok(hash)) = (hex::decode(salt), hex::decode(hash)) else {
+			return Ok(false);
+		};
+
+		let mut mac = self.mac().await?;
+		mac.update(&salt);
+		mac.update(cvv.as_bytes());
+		Ok(mac.verify_slice(&hash).is_ok())
+	}
+
+	async fn mac(&self) -> Result<HmacSha256, DomainError> {
+		let hashing_key = self.key_provider.hashing_key().await?;
+		Ok(<HmacSha256 as Mac>::new_from_slice(&hashing_key).expect("any key length is valid"))
+	}
+}
+
+/// Mask a card number, only the first 6 and the last 4 digits are kept
+pub fn mask_card_number(card_number: &str) -> String {
+	let len = card_number.len();
+
+	if len <= 10 {
+		return "*".repeat(len);
+	}
+
+	format!("{}{}{}", &card_number[..6], "*".repeat(len - 10), &card_number[len - 4..])
+}
+
+/// Derive a purpose specific key from a key
+pub fn derive_key(key: &[u8], purpose: &[u8]) -> [u8; KEY_LENGTH] {
+	let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("any key length is valid");
+	mac.update(purpose);
+	mac.finalize().into_bytes().into()
+}
+
+/// Encrypt with AES-256-GCM and a random nonce, the nonce is prepended to the ciphertext
+pub fn encrypt(key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, DomainError> {
+	let mut nonce = [0u8; NONCE_LENGTH];
+	rand::thread_rng().fill_bytes(&mut nonce);
+
+	let ciphertext = cipher(key)?
+		.encrypt(Nonce::from_slice(&nonce), plaintext)
+		.map_err(|_| DomainError::InternalServerError("Could not encrypt".to_string()))?;
+
+	Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+/// Decrypt data encrypted by [`encrypt`]
+pub fn decrypt(key: &[u8], data: &[u8]) -> Result<Vec<u8>, DomainError> {
+	if data.len() <= NONCE_LENGTH {
+		return Err(DomainError::InternalServerError("Invalid ciphertext".to_string()));
+	}
+
+	let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+
+	cipher(key)?
+		.decrypt(Nonce::from_slice(nonce), ciphertext)
+		.map_err(|_| DomainError::InternalServerError("Could not decrypt".to_string()))
+}
+
+fn cipher(key: &[u8]) -> Result<Aes256Gcm, DomainError> {
+	if key.len() != KEY_LENGTH {
+		return Err(DomainError::InternalServerError("Invalid key length".to_string()));
+	}
+
+	Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use async_trait::async_trait;
+
+	/// Key provider with a single in-memory key-encryption key per version
+	struct StaticKeyProvider {
+		current_version: u32,
+	}
+
+	#[async_trait]
+	impl KeyProvider for StaticKeyProvider {
+		async fn current_version(&self) -> Result<u32, DomainError> {
+			Ok(self.current_version)
+		}
+
+		async fn wrap_key(&self, version: u32, data_key: &[u8]) -> Result<Vec<u8>, DomainError> {
+			encrypt(&[version as u8; KEY_LENGTH], data_key)
+		}
+
+		async fn unwrap_key(
+			&self,
+			version: u32,
+			wrapped_key: &[u8],
+		) -> Result<Vec<u8>, DomainError> {
+			decrypt(&[version as u8; KEY_LENGTH], wrapped_key)
+		}
+
+		async fn hashing_key(&self) -> Result<[u8; KEY_LENGTH], DomainError> {
+			Ok([0u8; KEY_LENGTH])
+		}
+	}
+
+	fn vault(current_version: u32) -> Vault {
+		Vault::new(Arc::new(StaticKeyProvider { current_version }))
+	}
+
+	fn card() -> CardData {
+		CardData {
+			card_number: "4169812345678901".to_string(),
+			card_holder_first_name: "Alice".to_string(),
+			card_holder_last_name: "Smith".to_string(),
+		}
+	}
+
+	#[tokio::test]
+	async fn test_tokenize() {
+		let vault = vault(1);
+
+		let token = vault.tokenize(&card()).await.unwrap();
+
+		assert_eq!(token.masked, "416981******8901");
+		assert_eq!(token.hash, vault.card_number_hash("4169812345678901").await.unwrap());
+		assert_eq!(token.encrypted.key_version, 1);
+		assert_ne!(token.encrypted.card_number, b"4169812345678901".to_vec());
+		assert_ne!(token.encrypted.card_holder_first_name, b"Alice".to_vec());
+		assert_eq!(vault.detokenize(&token.encrypted).await.unwrap(), card());
+
+		// every card gets its own data key, but the lookup hash is deterministic
+		let other = vault.tokenize(&card()).await.unwrap();
+		assert_ne!(token.encrypted.data_key, other.encrypted.data_key);
+		assert_ne!(token.encrypted.card_number, other.encrypted.card_number);
+		assert_eq!(token.hash, other.hash);
+	}
+
+	#[tokio::test]
+	async fn test_key_rotation() {
+		let token = vault(1).tokenize(&card()).await.unwrap();
+
+		// old versions stay readable after rotation
+		let rotated_vault = vault(2);
+		assert_eq!(rotated_vault.detokenize(&token.encrypted).await.unwrap(), card());
+
+		let resealed = rotated_vault.seal(&card()).await.unwrap();
+		assert_eq!(resealed.key_version, 2);
+		assert_eq!(rotated_vault.detokenize(&resealed).await.unwrap(), card());
+
+		// data key can't be unwrapped with another key-encryption key
+		let tampered = EncryptedCardData { key_version: 3, ..token.encrypted };
+		assert!(rotated_vault.detokenize(&tampered).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn test_cvv_hash() {
+		let vault = vault(1);
+
+		let cvv_hash = vault.hash_cvv("123").await.unwrap();
+
+		assert!(vault.verify_cvv("123", &cvv_hash).await.unwrap());
+		assert!(!vault.verify_cvv("124", &cvv_hash).await.unwrap());
+		assert!(!vault.verify_cvv("123", "invalid").await.unwrap());
+
+		// salted, so the same CVV hashes differently
+		assert_ne!(vault.hash_cvv("123").await.unwrap(), cvv_hash);
+
+		// hashes survive key rotation
+		assert!(self::vault(2).verify_cvv("123", &cvv_hash).await.unwrap());
+	}
+
+	#[test]
+	fn test_mask_card_number() {
+		assert_eq!(mask_card_number("1234567812345678"), "123456******5678");
+		assert_eq!(mask_card_number("1234567890"), "**********");
+	}
+}
@@ -0,0 +1,63 @@
+//! Models to represent card data in and out of the vault.
+
+use tokio_postgres::Row;
+
+/// Key version of card data encrypted before envelope encryption was introduced.
+///
+/// Such card data has no wrapped data key, names are not encrypted either.
+pub const LEGACY_KEY_VERSION: u32 = 0;
+
+/// Card data in clear, it is never persisted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CardData {
+	/// Card number, should be 16 digits.
+	pub card_number: String,
+	/// Card holder first name.
+	pub card_holder_first_name: String,
+	/// Card holder last name.
+	pub card_holder_last_name: String,
+}
+
+/// Card data sealed with a data-encryption key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncryptedCardData {
+	/// Version of the key-encryption key that wraps the data key.
+	pub key_version: u32,
+	/// Data-encryption key, wrapped by the key-encryption key.
+	pub data_key: Vec<u8>,
+	/// Encrypted card number.
+	pub card_number: Vec<u8>,
+	/// Encrypted card holder first name.
+	pub card_holder_first_name: Vec<u8>,
+	/// Encrypted card holder last name.
+	pub card_holder_last_name: Vec<u8>,
+}
+
+/// Tokenized card as it is persisted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CardToken {
+	/// Keyed hash of the card number, used for lookups.
+	pub hash: String,
+	/// Masked card number, first 6 and last 4 digits.
+	pub masked: String,
+	/// Encrypted card data.
+	pub encrypted: EncryptedCardData,
+}
+
+/// Implement `From` trait for `EncryptedCardData` from `Row`.
+/// Helps with parsing database results.
+impl From<&Row> for EncryptedCardData {
+	fn from(row: &Row) -> Self {
+		Self {
+			key_version: row.get::<&str, i32>("key_version") as u32,
+			data_key: row.get::<&str, Option<Vec<u8>>>("data_key").unwrap_or_default(),
+			card_number: row.get("card_number_encrypted"),
+			card_holder_first_name: row
+				.get::<&str, Option<Vec<u8>>>("card_holder_first_name_encrypted")
+				.unwrap_or_default(),
+			card_holder_last_name: row
+				.get::<&str, Option<Vec<u8>>>("card_holder_last_name_encrypted")
+				.unwrap_or_default(),
+		}
+	}
+}
@@ -0,0 +1,29 @@
+//! Defines trait for key material of the vault.
+
+use async_trait::async_trait;
+
+use super::KEY_LENGTH;
+use crate::error::DomainError;
+
+/// `KeyProvider` is a trait for key-encryption keys of the vault.
+///
+/// Key-encryption keys never leave the provider, so that it can be backed by a KMS or an HSM.
+#[async_trait]
+pub trait KeyProvider: Send + Sync {
+	/// Version of the key-encryption key used for new data keys.
+	async fn current_version(&self) -> Result<u32, DomainError>;
+
+	/// Wrap a data-encryption key with the key-encryption key of the given version.
+	async fn wrap_key(&self, version: u32, data_key: &[u8]) -> Result<Vec<u8>, DomainError>;
+
+	/// Unwrap a data-encryption key with the key-encryption key of the given version.
+	///
+	/// Data key of [`super::models::LEGACY_KEY_VERSION`] is not wrapped, `wrapped_key` is empty.
+	async fn unwrap_key(&self, version: u32, wrapped_key: &[u8]) -> Result<Vec<u8>, DomainError>;
+
+	/// Key for keyed hashes of card data.
+	///
+	/// It is not rotated, since card number hashes are used for lookups and CVV hashes can't be
+	/// recomputed.
+	async fn hashing_key(&self) -> Result<[u8; KEY_LENGTH], DomainError>;
+}
@@ -3,7 +3,7 @@
 #![allow(clippy::needless_borrows_for_generic_args)]
 
 use jsonrpsee::core::client::ClientT;
-use op_core::bank_account::models::BankAccount;
+use op_core::{bank_account::models::BankAccount, money::Money};
 use std::{str::FromStr, sync::Arc};
 use subxt::{config::substrate::H256, utils::AccountId32, OnlineClient, SubstrateConfig};
 
@@ -44,9 +44,41 @@ impl TestEnv {
 	}
 }
 
-/// Simply append 6 zeros to the balance
-fn format_balance(balance: u32) -> u32 {
-	balance * 1_000_000
+/// Credentials of a request to the oracle, signed by the owner of the account
+fn signed_auth(
+	keypair: &subxt_signer::sr25519::Keypair,
+	method: &str,
+	params: serde_json::Value,
+) -> serde_json::Value {
+	let timestamp = chrono::Utc::now().timestamp();
+	let payload = serde_json::to_vec(&(method, params, timestamp)).unwrap();
+
+	serde_json::json!({
+		"signed": {
+			"signer": hex::encode(keypair.public_key().0),
+			"timestamp": timestamp,
+			"signature": hex::encode(keypair.sign(&payload).0),
+		}
+	})
+}
+
+/// Request to the oracle about the account of the keypair, signed by it
+async fn request_own<T: serde::de::DeserializeOwned>(env: &TestEnv, method: &str) -> T {
+	let account_id = hex::encode(env.keypair.public_key().0);
+	let auth = signed_auth(&env.keypair, method, serde_json::json!([account_id]));
+
+	env.oracle
+		.request(method, jsonrpsee::rpc_params![account_id, auth])
+		.await
+		.expect("ok")
+}
+
+/// Decimals of on-chain amounts, the default `--chain-decimals` of the oracle
+const CHAIN_DECIMALS: u32 = 8;
+
+/// Convert the balance to on-chain units
+fn format_balance(balance: Money) -> u128 {
+	balance.to_chain_units(CHAIN_DECIMALS).expect("representable on-chain")
 }
 
 #[tokio::test]
@@ -55,11 +87,7 @@ async fn test_full_lifecycle() {
 	let charlie = hex::decode(CHARLIE).unwrap();
 
 	// get initial balance
-	let initial_bank_account: BankAccount = env
-		.oracle
-		.request("pcidss_get_bank_account", [hex::encode(env.keypair.public_key().0)])
-		.await
-		.expect("ok");
+	let initial_bank_account: BankAccount = request_own(&env, "pcidss_get_bank_account").await;
 
 	let balance_query =
 		iso_8583_chain::storage().system().account(&env.keypair.public_key().into());
@@ -76,10 +104,7 @@ async fn test_full_lifecycle() {
 		.unwrap()
 		.unwrap();
 
-	assert_eq!(
-		initial_on_chain_account.data.free as u32,
-		format_balance(initial_bank_account.balance)
-	);
+	assert_eq!(initial_on_chain_account.data.free, format_balance(initial_bank_account.balance));
 
 	// initiate transfer
 	let transfer = iso_8583_chain::tx().iso8583().initiate_transfer(
@@ -120,16 +145,13 @@ async fn test_full_lifecycle() {
 
 	println!("on_chain_account.data.free: {:?}", on_chain_account.data.free);
 	assert_eq!(
-		on_chain_account.data.free as u32,
-		format_balance(initial_bank_account.balance) - 10_000_000_u128 as u32
+		on_chain_account.data.free,
+		format_balance(initial_bank_account.balance) - 10_000_000_u128
 	);
 
 	// get list of transactions
-	let transactions: Vec<op_core::transaction::models::Transaction> = env
-		.oracle
-		.request("pcidss_get_transactions", [hex::encode(env.keypair.public_key().0)])
-		.await
-		.expect("ok");
+	let transactions: Vec<op_core::transaction::models::Transaction> =
+		request_own(&env, "pcidss_get_transactions").await;
 
 	// revert transfer
 
@@ -155,11 +177,7 @@ async fn test_full_lifecycle() {
 	}
 
 	// check balance
-	let bank_account: BankAccount = env
-		.oracle
-		.request("pcidss_get_bank_account", [hex::encode(env.keypair.public_key().0)])
-		.await
-		.expect("ok");
+	let bank_account: BankAccount = request_own(&env, "pcidss_get_bank_account").await;
 
-	assert_eq!(format_balance(bank_account.balance), initial_on_chain_account.data.free as u32);
+	assert_eq!(format_balance(bank_account.balance), initial_on_chain_account.data.free);
 }
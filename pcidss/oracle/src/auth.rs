@@ -0,0 +1,476 @@
+//! Authentication and authorization of the RPC requests
+//!
+//! Requests carry their credentials in a trailing `auth` parameter, either an API key or an
+//! sr25519 signature of the request. API keys and signers are mapped to roles in a YAML or JSON
+//! file, API keys are only stored as SHA-256 hashes:
+//!
+//! ```yaml
+//! clock_skew_secs: 60
+//! api_keys:
+//!   - { name: acquirer-a, key_sha256: "9f86d081...", role: acquirer }
+//! signers:
+//!   - { name: backoffice, public_key: "d43593c7...", role: admin }
+//! permissions:
+//!   pcidss_submit_iso8583: [acquirer, merchant]
+//! ```
+//!
+//! Any other signer is authenticated as the owner of its on-chain account, without a role.
+//! Signed requests sign the compact JSON array `[method, params, timestamp]`, where `params` are
+//! the parameters of the request without `auth` (omitted ones as `null`, objects with their keys
+//! sorted and without unset fields) and `timestamp` is in seconds since the epoch. Signatures of
+//! wallets wrapping the payload in `<Bytes>` are accepted too. Requests older or newer than the
+//! clock skew, or whose signature is reused, are rejected.
+//!
+//! Balance requests of the offchain workers are authenticated separately, by the signature of a
+//! SCALE encoded [`BalancesRequest`] from one of the oracle accounts registered on-chain.
+
+use std::{
+	collections::{HashMap, HashSet},
+	sync::{
+		atomic::{AtomicU32, Ordering},
+		Mutex, RwLock,
+	},
+};
+
+use chrono::{DateTime, Utc};
+use op_core::error::DomainError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subxt::ext::codec::{Decode, DecodeAll, Encode};
+use subxt_signer::sr25519::{self, PublicKey, Signature};
+
+/// Role of an API key or signer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+	/// Acquirer submitting ISO-8583 messages
+	Acquirer,
+	/// Merchant submitting ISO-8583 messages
+	Merchant,
+	/// Offchain worker of the chain
+	Ocw,
+	/// Back office managing the cards
+	Admin,
+}
+
+/// API key of the RPC
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKey {
+	/// Name of the client, for the logs
+	pub name: String,
+	/// SHA-256 hash of the key, hex encoded
+	pub key_sha256: String,
+	/// Role of the key
+	pub role: Role,
+}
+
+/// Signer of the RPC requests with a role
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Signer {
+	/// Name of the client, for the logs
+	pub name: String,
+	/// sr25519 public key, hex encoded
+	pub public_key: String,
+	/// Role of the signer
+	pub role: Role,
+}
+
+/// Configuration of the authentication of the RPC
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+	/// Maximum difference in seconds between the timestamp of a signed request and now
+	pub clock_skew_secs: i64,
+	/// API keys
+	pub api_keys: Vec<ApiKey>,
+	/// Signers with a role
+	pub signers: Vec<Signer>,
+	/// Roles allowed to call a method, by method name. Replaces the default roles of the method
+	pub permissions: HashMap<String, HashSet<Role>>,
+}
+
+impl Default for AuthConfig {
+	fn default() -> Self {
+		Self { clock_skew_secs: 60, api_keys: vec![], signers: vec![], permissions: HashMap::new() }
+	}
+}
+
+impl AuthConfig {
+	/// Load the configuration from a YAML or JSON file
+	pub fn load(path: &str) -> Result<Self, DomainError> {
+		let content = std::fs::read_to_string(path).map_err(|e| {
+			DomainError::InternalServerError(format!(
+				"Could not read RPC auth file {}: {}",
+				path, e
+			))
+		})?;
+
+		Self::parse(&content)
+	}
+
+	/// Parse the configuration from YAML, or JSON
+	pub fn parse(content: &str) -> Result<Self, DomainError> {
+		serde_yaml::from_str(content)
+			.map_err(|e| DomainError::InternalServerError(format!("Invalid RPC auth file: {}", e)))
+	}
+
+	/// Add an API key with the given role
+	pub fn with_api_key(mut self, name: &str, key: &str, role: Role) -> Self {
+		self.api_keys
+			.push(ApiKey { name: name.to_string(), key_sha256: hash_api_key(key), role });
+		self
+	}
+}
+
+/// Credentials of an RPC request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum RpcAuth {
+	/// API key
+	ApiKey(String),
+	/// sr25519 signature of the request
+	Signed {
+		/// Public key of the signer, hex encoded
+		signer: String,
+		/// Time the request is signed at, in seconds since the epoch
+		timestamp: i64,
+		/// Signature of the payload, hex encoded
+		signature: String,
+	},
+}
+
+/// Authenticated client of the RPC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+	/// Name of the client, the public key of signers without a role
+	pub name: String,
+	/// Role of the client, `None` for account owners
+	pub role: Option<Role>,
+	/// On-chain account of signed requests, hex encoded
+	pub account_id: Option<String>,
+}
+
+/// Errors of the authentication and authorization of a request
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AuthError {
+	/// Missing, invalid, stale or replayed credentials
+	#[error("Unauthorized: {0}")]
+	Unauthorized(String),
+	/// The client is not allowed to call the method
+	#[error("Forbidden: {0}")]
+	Forbidden(String),
+}
+
+/// Default roles allowed to call a method, account owners are checked separately
+fn default_roles(method: &str) -> &'static [Role] {
+	match method {
+		"pcidss_submit_iso8583" | "pcidss_submit_iso8583_fields" =>
+			&[Role::Acquirer, Role::Merchant, Role::Admin],
+		"pcidss_get_transactions" | "pcidss_query_transactions" => &[Role::Admin],
+		"pcidss_set_card_status" |
+		"pcidss_renew_card" |
+		"pcidss_reissue_card" |
+		"pcidss_get_card_status_history" => &[Role::Admin],
+		_ => &[],
+	}
+}
+
+/// Hash of an API key, as stored in the configuration
+pub fn hash_api_key(key: &str) -> String {
+	hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Payload signed by the clients: the compact JSON array `[method, params, timestamp]`
+pub fn signing_payload(method: &str, params: &serde_json::Value, timestamp: i64) -> Vec<u8> {
+	serde_json::to_vec(&(method, params, timestamp)).expect("serializable; qed")
+}
+
+/// Normalize a hex encoded on-chain account
+fn normalize_account_id(account_id: &str) -> String {
+	account_id.trim_start_matches("0x").to_lowercase()
+}
+
+/// Authenticator of the RPC requests
+pub struct Authenticator {
+	/// API keys by hash
+	api_keys: HashMap<String, ApiKey>,
+	/// Signers with a role by public key
+	signers: HashMap<[u8; 32], Signer>,
+	/// Roles allowed to call a method, instead of the default ones
+	permissions: HashMap<String, HashSet<Role>>,
+	/// Maximum difference in seconds between the timestamp of a signed request and now
+	clock_skew_secs: i64,
+	/// Signatures used within the clock skew, with their timestamp
+	used_signatures: Mutex<HashMap<[u8; 64], i64>>,
+}
+
+impl Authenticator {
+	/// Create an authenticator with the configuration
+	pub fn new(config: AuthConfig) -> Result<Self, DomainError> {
+		let mut signers = HashMap::new();
+		for signer in config.signers {
+			let public_key = parse_hex::<32>(&signer.public_key).ok_or_else(|| {
+				DomainError::InternalServerError(format!(
+					"Invalid public key of RPC signer {}",
+					signer.name
+				))
+			})?;
+			signers.insert(public_key, signer);
+		}
+
+		Ok(Self {
+			api_keys: config
+				.api_keys
+				.into_iter()
+				.map(|api_key| (api_key.key_sha256.to_lowercase(), api_key))
+				.collect(),
+			signers,
+			permissions: config.permissions,
+			clock_skew_secs: config.clock_skew_secs,
+			used_signatures: Mutex::new(HashMap::new()),
+		})
+	}
+
+	/// Authenticate a request and check that the client may call the method
+	///
+	/// Clients with one of the roles of the method are allowed, and so is the owner of
+	/// `owner_of`, the on-chain account the request is about, if any.
+	pub fn authorize(
+		&self,
+		method: &str,
+		params: &serde_json::Value,
+		auth: Option<&RpcAuth>,
+		owner_of: Option<&str>,
+		now: DateTime<Utc>,
+	) -> Result<Principal, AuthError> {
+		let principal = self.authenticate(method, params, auth, now)?;
+
+		let has_role = principal.role.is_some_and(|role| match self.permissions.get(method) {
+			Some(roles) => roles.contains(&role),
+			None => default_roles(method).contains(&role),
+		});
+		let is_owner = owner_of.is_some_and(|account_id| {
+			principal.account_id.as_deref() == Some(normalize_account_id(account_id).as_str())
+		});
+
+		if !has_role && !is_owner {
+			log::warn!("{} is not allowed to call {}", principal.name, method);
+			return Err(AuthError::Forbidden(method.to_string()));
+		}
+
+		log::debug!("{} authorized to call {}", principal.name, method);
+		Ok(principal)
+	}
+
+	/// Authenticate a request with its credentials
+	pub fn authenticate(
+		&self,
+		method: &str,
+		params: &serde_json::Value,
+		auth: Option<&RpcAuth>,
+		now: DateTime<Utc>,
+	) -> Result<Principal, AuthError> {
+		match auth {
+			None => Err(AuthError::Unauthorized("missing credentials".to_string())),
+			Some(RpcAuth::ApiKey(key)) => self
+				.api_keys
+				.get(&hash_api_key(key))
+				.map(|api_key| Principal {
+					name: api_key.name.clone(),
+					role: Some(api_key.role),
+					account_id: None,
+				})
+				.ok_or_else(|| AuthError::Unauthorized("invalid API key".to_string())),
+			Some(RpcAuth::Signed { signer, timestamp, signature }) => {
+				let public_key = parse_hex::<32>(signer)
+					.ok_or_else(|| AuthError::Unauthorized("invalid signer".to_string()))?;
+				let signature = parse_hex::<64>(signature)
+					.ok_or_else(|| AuthError::Unauthorized("invalid signature".to_string()))?;
+
+				if (now.timestamp() - timestamp).abs() > self.clock_skew_secs {
+					return Err(AuthError::Unauthorized("stale request".to_string()));
+				}
+
+				let payload = signing_payload(method, params, *timestamp);
+				let wrapped = [b"<Bytes>".as_slice(), &payload, b"</Bytes>"].concat();
+				let verified = [payload, wrapped].iter().any(|message| {
+					sr25519::verify(&Signature(signature), message, &PublicKey(public_key))
+				});
+				if !verified {
+					return Err(AuthError::Unauthorized("invalid signature".to_string()));
+				}
+
+				self.use_signature(signature, *timestamp, now)?;
+
+				let account_id = hex::encode(public_key);
+				Ok(match self.signers.get(&public_key) {
+					Some(signer) => Principal {
+						name: signer.name.clone(),
+						role: Some(signer.role),
+						account_id: Some(account_id),
+					},
+					None => Principal {
+						name: account_id.clone(),
+						role: None,
+						account_id: Some(account_id),
+					},
+				})
+			},
+		}
+	}
+
+	/// Mark a signature as used, fails if it is already used
+	///
+	/// Signatures are kept as long as their timestamp is within the clock skew, older requests
+	/// are rejected as stale anyway.
+	fn use_signature(
+		&self,
+		signature: [u8; 64],
+		timestamp: i64,
+		now: DateTime<Utc>,
+	) -> Result<(), AuthError> {
+		let mut used_signatures = self.used_signatures.lock().expect("not poisoned; qed");
+		used_signatures.retain(|_, used_at| now.timestamp() - *used_at <= self.clock_skew_secs);
+
+		if used_signatures.insert(signature, timestamp).is_some() {
+			return Err(AuthError::Unauthorized("replayed request".to_string()));
+		}
+
+		Ok(())
+	}
+}
+
+/// Parse a hex encoded array
+fn parse_hex<const N: usize>(value: &str) -> Option<[u8; N]> {
+	hex::decode(value.trim_start_matches("0x")).ok()?.try_into().ok()
+}
+
+/// Version of the balance requests of the offchain workers
+pub const BALANCES_REQUEST_VERSION: u8 = 1;
+
+/// Domain tag of the balance requests, so that their signatures are not valid for anything else
+pub const BALANCES_REQUEST_DOMAIN: &[u8] = b"pcidss:get_batch_balances";
+
+/// Balance request of an offchain worker, signed SCALE encoded
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[codec(crate = subxt::ext::codec)]
+pub struct BalancesRequest {
+	/// Version of the request, `BALANCES_REQUEST_VERSION`
+	pub version: u8,
+	/// Domain tag, `BALANCES_REQUEST_DOMAIN`
+	pub domain: Vec<u8>,
+	/// Public key of the offchain worker
+	pub signer: [u8; 32],
+	/// Block the request is made at
+	pub block_number: u32,
+	/// Unique number of the request for the signer
+	pub nonce: u64,
+	/// On-chain accounts of the requested balances
+	pub account_ids: Vec<[u8; 32]>,
+}
+
+impl BalancesRequest {
+	/// Create a balance request of the current version
+	pub fn new(
+		signer: [u8; 32],
+		block_number: u32,
+		nonce: u64,
+		account_ids: Vec<[u8; 32]>,
+	) -> Self {
+		Self {
+			version: BALANCES_REQUEST_VERSION,
+			domain: BALANCES_REQUEST_DOMAIN.to_vec(),
+			signer,
+			block_number,
+			nonce,
+			account_ids,
+		}
+	}
+}
+
+/// Verifier of the balance requests of the offchain workers
+///
+/// The authorized keys and the best block are synced from the chain, requests made more than
+/// `max_age_blocks` away from the best block are stale. Nonces are unique per signer as long as
+/// their requests are not stale.
+pub struct BalancesRequestVerifier {
+	/// Maximum number of blocks between the block of a request and the best block
+	max_age_blocks: u32,
+	/// Public keys of the authorized offchain workers
+	keys: RwLock<HashSet<[u8; 32]>>,
+	/// Best block of the chain
+	best_block: AtomicU32,
+	/// Nonces used by the signers, with the block of their request
+	used_nonces: Mutex<HashMap<([u8; 32], u64), u32>>,
+}
+
+impl BalancesRequestVerifier {
+	/// Create a verifier without authorized keys
+	pub fn new(max_age_blocks: u32) -> Self {
+		Self {
+			max_age_blocks,
+			keys: RwLock::new(HashSet::new()),
+			best_block: AtomicU32::new(0),
+			used_nonces: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Replace the authorized keys
+	pub fn set_keys(&self, keys: HashSet<[u8; 32]>) {
+		let mut current = self.keys.write().expect("not poisoned; qed");
+		if *current != keys {
+			log::info!("Authorized offchain worker keys: {:?}", keys.iter().map(hex::encode));
+			*current = keys;
+		}
+	}
+
+	/// Set the best block of the chain
+	pub fn set_best_block(&self, block_number: u32) {
+		self.best_block.store(block_number, Ordering::Relaxed);
+	}
+
+	/// Verify a signed balance request, returns the decoded request
+	pub fn verify(&self, payload: &[u8], signature: &[u8]) -> Result<BalancesRequest, AuthError> {
+		if payload.first() != Some(&BALANCES_REQUEST_VERSION) {
+			return Err(AuthError::Unauthorized("unsupported request version".to_string()));
+		}
+
+		let request = BalancesRequest::decode_all(&mut &payload[..])
+			.map_err(|_| AuthError::Unauthorized("invalid request".to_string()))?;
+
+		if request.domain != BALANCES_REQUEST_DOMAIN {
+			return Err(AuthError::Unauthorized("invalid domain".to_string()));
+		}
+
+		if !self.keys.read().expect("not poisoned; qed").contains(&request.signer) {
+			return Err(AuthError::Unauthorized("unknown signer".to_string()));
+		}
+
+		let signature: [u8; 64] = signature
+			.try_into()
+			.map_err(|_| AuthError::Unauthorized("invalid signature".to_string()))?;
+		if !sr25519::verify(&Signature(signature), payload, &PublicKey(request.signer)) {
+			return Err(AuthError::Unauthorized("invalid signature".to_string()));
+		}
+
+		let best_block = self.best_block.load(Ordering::Relaxed);
+		if request.block_number.abs_diff(best_block) > self.max_age_blocks {
+			return Err(AuthError::Unauthorized("stale request".to_string()));
+		}
+
+		let mut used_nonces = self.used_nonces.lock().expect("not poisoned; qed");
+		used_nonces
+			.retain(|_, block_number| block_number.abs_diff(best_block) <= self.max_age_blocks);
+
+		if used_nonces
+			.insert((request.signer, request.nonce), request.block_number)
+			.is_some()
+		{
+			return Err(AuthError::Unauthorized("replayed request".to_string()));
+		}
+
+		Ok(request)
+	}
+}
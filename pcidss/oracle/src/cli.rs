@@ -1,10 +1,17 @@
 //! CLI configuration
 
-use clap::Parser;
+use chrono::NaiveTime;
+use clap::{Parser, Subcommand};
 use op_core::postgres::PostgresConfig;
 
+use crate::services::tcp::LengthHeader;
+
+/// PCIDSS Gateway Oracle
 #[derive(Debug, Clone, Parser)]
 pub struct Cli {
+	/// Maintenance command to run instead of the oracle
+	#[command(subcommand)]
+	pub command: Option<Command>,
 	/// Path to the Postgres database
 	#[arg(long, default_value = "localhost")]
 	pub database_host: String,
@@ -20,27 +27,114 @@ pub struct Cli {
 	/// Substrate chain websocket endpoint
 	#[arg(long, default_value = "ws://localhost:9944")]
 	pub chain_endpoint: String,
-	/// ISO-8583 specification file
-	#[arg(long, default_value = "spec.yaml")]
-	pub iso8583_spec: String,
+	/// ISO-8583 specification file as `[name=]path`, can be repeated to load several named
+	/// specifications. The first one is the default, the embedded one is used if none is given
+	#[arg(long)]
+	pub iso8583_spec: Vec<String>,
 	/// RPC port
 	#[arg(long, default_value = "3030")]
 	pub rpc_port: u16,
+	/// Host the native ISO-8583 TCP listener binds to
+	#[arg(long, default_value = "127.0.0.1")]
+	pub tcp_host: String,
+	/// Port of the native ISO-8583 TCP listener, disabled if not set
+	#[arg(long)]
+	pub tcp_port: Option<u16>,
+	/// Length header of the messages on the TCP listener
+	#[arg(long, value_enum, default_value = "binary2")]
+	pub tcp_length_header: LengthHeader,
+	/// Name of the ISO-8583 specification of the TCP connections that don't select one after their
+	/// API key, the default one if not set
+	#[arg(long)]
+	pub tcp_spec: Option<String>,
+	/// Maximum number of messages processed concurrently per TCP connection
+	#[arg(long, default_value = "64")]
+	pub tcp_max_in_flight: usize,
 	/// Development mode
 	#[arg(long)]
 	pub dev: bool,
+	/// YAML or JSON file with the API keys and signers of the RPC, their roles and the roles
+	/// allowed per method. Only account owners can authenticate if not set, and the
+	/// `dev-api-key` merchant API key in development mode
+	#[arg(long)]
+	pub rpc_auth_file: Option<String>,
 	/// Seed phrase for signing transactions
 	#[arg(long, default_value = "//Alice")]
 	pub seed: String,
 	/// Substrate chain websocket endpoint
 	#[arg(long, default_value = "ws://localhost:9944")]
 	pub ws_url: String,
-	/// OCW signer
-	#[arg(
-		long,
-		default_value = "d2bf4b844dfefd6772a8843e669f943408966a977e3ae2af1dd78e0f55f4df67"
-	)]
-	pub ocw_signer: String,
+	/// Block the watcher starts from on the first run, the finalized head if not set. Later runs
+	/// resume after the last processed block
+	#[arg(long)]
+	pub watcher_start_block: Option<u32>,
+	/// Process best blocks once they have this many confirmations instead of waiting for
+	/// finality. Events of blocks retracted by a reorg are compensated
+	#[arg(long)]
+	pub watcher_confirmations: Option<u32>,
+	/// Decimals of on-chain amounts per major unit of the currency, e.g. 8 if one cent is 10^6
+	/// units on-chain. On-chain amounts more precise than the currency are rejected
+	#[arg(long, default_value = "8")]
+	pub chain_decimals: u32,
+	/// JSON or CSV file with the versioned conversion rates between currencies, only amounts in
+	/// the currency of the bank account are accepted if not set
+	#[arg(long)]
+	pub fx_rates_file: Option<String>,
+	/// YAML or JSON file with the rules of the risk engine: amount limits, velocity, blocklists
+	/// and CVV lockout. No rule is applied if not set
+	#[arg(long)]
+	pub risk_rules_file: Option<String>,
+	/// Time in hours after which uncaptured authorization holds expire
+	#[arg(long, default_value = "168")]
+	pub hold_ttl: i64,
+	/// Time of the day (UTC) the settlement window of the acquirers is closed at, as `HH:MM`
+	#[arg(long, default_value = "00:00", value_parser = parse_cutoff_time)]
+	pub settlement_cutoff: NaiveTime,
+	/// Directory the clearing files of the settlement batches are written to
+	#[arg(long, default_value = "clearing")]
+	pub clearing_dir: String,
+	/// Interval in seconds between reconciliations of the ledger with the on-chain balances,
+	/// disabled if not set
+	#[arg(long)]
+	pub reconciliation_interval: Option<u64>,
+	/// Shell command run with the JSON report on its standard input when a reconciliation finds
+	/// discrepancies
+	#[arg(long)]
+	pub reconciliation_alert: Option<String>,
+	/// Keyfile with the versioned master keys of the card vault, generated in development mode
+	/// if missing
+	#[arg(long, default_value = "vault.key")]
+	pub vault_key_file: String,
+	/// Maximum number of blocks between the block of a balance request of the offchain worker
+	/// and the best block
+	#[arg(long, default_value = "10")]
+	pub ocw_request_max_age: u32,
+}
+
+/// Maintenance commands
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+	/// Re-encrypt card data of all bank accounts with the current key version
+	Reencrypt {
+		/// Number of bank accounts re-encrypted per database transaction
+		#[arg(long, default_value = "100")]
+		batch_size: i64,
+	},
+	/// Requeue the finalities that failed to be submitted on-chain
+	RetryFinalities,
+	/// Close the settlement window of the acquirers now and write the clearing files, instead
+	/// of waiting for the cut-off
+	Settle,
+	/// Check that the balances of the bank accounts match the ledger journal, exits with an
+	/// error if they don't
+	CheckLedger,
+	/// Compare the balances of the registered bank accounts with their on-chain balances and
+	/// write a JSON report of the discrepancies, exits with an error if there are any
+	Reconcile {
+		/// File the report is written to, standard output if not set
+		#[arg(long)]
+		output: Option<String>,
+	},
 }
 
 impl Cli {
@@ -51,13 +145,6 @@ impl Cli {
 			self.database_user, self.database_user, self.database_host, self.database_port
 		)
 	}
-
-	/// Set env variables
-	/// TODO: this is because of the weird way of how `iso8583-rs` loads the spec file
-	pub fn set_env(&self) {
-		std::env::set_var("SPEC_FILE", self.iso8583_spec.clone());
-		// std::env::set_var("RUST_LOG", "info");
-	}
 }
 
 #[allow(clippy::from_over_into)]
@@ -72,3 +159,8 @@ impl Into<PostgresConfig> for Cli {
 		}
 	}
 }
+
+/// Parses a time of the day as `HH:MM`
+fn parse_cutoff_time(value: &str) -> Result<NaiveTime, String> {
+	NaiveTime::parse_from_str(value, "%H:%M").map_err(|e| format!("expected HH:MM: {}", e))
+}
@@ -0,0 +1,76 @@
+//! Events of the ledger published by the processor to the RPC subscriptions
+
+use op_core::{money::Money, transaction::models::Transaction};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Change of the ledger an event is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionEventKind {
+	/// Transaction is posted, including captures of authorization holds
+	Posted,
+	/// Transaction is refunded fully or partially, the event carries the refund
+	Reversed,
+	/// Transaction is compensated because its block was retracted by a reorg
+	Retracted,
+}
+
+/// Transaction posted or reversed by the processor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransactionEvent {
+	/// Change of the ledger
+	pub kind: TransactionEventKind,
+	/// Transaction as it is after the change
+	pub transaction: Transaction,
+}
+
+impl TransactionEvent {
+	/// Is the bank account the source or the recipient of the transaction?
+	pub fn concerns(&self, bank_account_id: &Uuid) -> bool {
+		self.transaction.from == *bank_account_id ||
+			self.transaction.to.as_ref() == Some(bank_account_id)
+	}
+}
+
+/// Balance of a bank account after a transaction event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalanceUpdate {
+	/// On-chain account id of the bank account
+	pub account_id: String,
+	/// Posted balance
+	pub balance: Money,
+	/// Balance minus the pending authorization holds
+	pub available_balance: Money,
+	/// Hash of the transaction that changed the balance
+	pub transaction_hash: String,
+}
+
+/// Broadcast bus of the transaction events
+///
+/// Events are only delivered to the receivers subscribed before they are published, receivers
+/// lagging behind by more than the capacity of the bus miss the oldest ones.
+#[derive(Debug)]
+pub struct EventBus {
+	sender: broadcast::Sender<TransactionEvent>,
+}
+
+impl EventBus {
+	/// Create a bus keeping up to `capacity` events per receiver
+	pub fn new(capacity: usize) -> Self {
+		let (sender, _) = broadcast::channel(capacity);
+		Self { sender }
+	}
+
+	/// Publish an event to all the receivers, if any
+	pub fn publish(&self, kind: TransactionEventKind, transaction: &Transaction) {
+		// no receivers is not an error, nobody is subscribed
+		let _ = self.sender.send(TransactionEvent { kind, transaction: transaction.clone() });
+	}
+
+	/// Receive the events published from now on
+	pub fn subscribe(&self) -> broadcast::Receiver<TransactionEvent> {
+		self.sender.subscribe()
+	}
+}
@@ -0,0 +1,119 @@
+//! Foreign exchange rate table loading
+
+use std::collections::HashMap;
+
+use op_core::{error::DomainError, fx::FxRate, money::Iso4217};
+
+/// Rate of a currency pair in a version of the rate table
+#[derive(Debug, Clone, serde::Deserialize)]
+struct RateEntry {
+	/// Version of the rate table
+	version: u32,
+	/// Currency of the transaction
+	from: Iso4217,
+	/// Currency of the bank account
+	to: Iso4217,
+	/// Decimal rate, major units of `to` per major unit of `from`
+	rate: String,
+}
+
+/// Conversion rates between currencies
+///
+/// The rate file keeps every version of the table, new versions are appended. Only the latest
+/// version is used for new conversions, converted transactions keep the rate and version they
+/// were converted with.
+#[derive(Debug, Clone, Default)]
+pub struct RateTable {
+	/// Latest version of the table, `None` if there are no rates
+	version: Option<u32>,
+	/// Rates of the latest version by currency pair
+	rates: HashMap<(Iso4217, Iso4217), FxRate>,
+}
+
+impl RateTable {
+	/// Load rates from a JSON or, if the file has a `.csv` extension, a CSV file
+	pub fn load(path: &str) -> Result<Self, DomainError> {
+		let content = std::fs::read_to_string(path).map_err(|e| {
+			DomainError::InternalServerError(format!("Could not read rates {}: {}", path, e))
+		})?;
+
+		if path.ends_with(".csv") {
+			Self::parse_csv(&content)
+		} else {
+			Self::parse_json(&content)
+		}
+	}
+
+	/// Parse rates from a JSON array of `{ "version", "from", "to", "rate" }` objects
+	pub fn parse_json(json: &str) -> Result<Self, DomainError> {
+		let entries: Vec<RateEntry> = serde_json::from_str(json)
+			.map_err(|e| DomainError::InternalServerError(format!("Invalid rates: {}", e)))?;
+
+		Self::from_entries(entries)
+	}
+
+	/// Parse rates from CSV with a `version,from,to,rate` header
+	pub fn parse_csv(csv: &str) -> Result<Self, DomainError> {
+		let mut lines = csv.lines().map(str::trim).filter(|line| !line.is_empty());
+
+		if lines.next().map(|header| header.replace(' ', "")) != Some("version,from,to,rate".into())
+		{
+			return Err(DomainError::InternalServerError(
+				"Invalid rates: expected a version,from,to,rate header".to_string(),
+			));
+		}
+
+		let entries = lines
+			.map(|line| {
+				let invalid =
+					|| DomainError::InternalServerError(format!("Invalid rates line: {}", line));
+
+				match line.split(',').map(str::trim).collect::<Vec<_>>()[..] {
+					[version, from, to, rate] => Ok(RateEntry {
+						version: version.parse().map_err(|_| invalid())?,
+						from: from.parse().map_err(|_| invalid())?,
+						to: to.parse().map_err(|_| invalid())?,
+						rate: rate.to_string(),
+					}),
+					_ => Err(invalid()),
+				}
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		Self::from_entries(entries)
+	}
+
+	/// Keep the rates of the latest version
+	fn from_entries(entries: Vec<RateEntry>) -> Result<Self, DomainError> {
+		let version = entries.iter().map(|entry| entry.version).max();
+
+		let mut rates = HashMap::new();
+		for entry in entries.into_iter().filter(|entry| Some(entry.version) == version) {
+			let rate = entry.rate.parse().map_err(|e| {
+				DomainError::InternalServerError(format!(
+					"Invalid rate {}/{}: {}",
+					entry.from, entry.to, e
+				))
+			})?;
+
+			if rates.insert((entry.from, entry.to), rate).is_some() {
+				return Err(DomainError::InternalServerError(format!(
+					"Duplicate rate {}/{} in version {}",
+					entry.from, entry.to, entry.version
+				)));
+			}
+		}
+
+		Ok(Self { version, rates })
+	}
+
+	/// Latest version of the table, `None` if there are no rates
+	pub fn version(&self) -> Option<u32> {
+		self.version
+	}
+
+	/// Rate to convert from one currency to another and its version, `None` if it is not known
+	pub fn rate(&self, from: Iso4217, to: Iso4217) -> Option<(FxRate, u32)> {
+		Some((*self.rates.get(&(from, to))?, self.version?))
+	}
+}
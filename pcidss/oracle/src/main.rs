@@ -5,11 +5,18 @@ use dotenv::dotenv;
 use op_core::postgres::{self, run_migrations, PostgresConfig};
 use std::{io, sync::Arc};
 
+pub mod auth;
 pub mod cli;
+pub mod events;
+pub mod fx;
+pub mod risk;
 pub mod services;
+pub mod spec;
 pub mod types;
 
-use crate::services::start_oracle;
+use crate::services::{
+	check_ledger, reconcile, reencrypt_bank_accounts, retry_finalities, settle, start_oracle,
+};
 
 #[cfg(test)]
 mod tests;
@@ -19,8 +26,6 @@ mod tests;
 async fn main() -> io::Result<()> {
 	dotenv().ok();
 	let args = cli::Cli::parse();
-	// TODO: this is because of the weird way of how `iso8583-rs` loads the spec file
-	args.set_env();
 
 	env_logger::init();
 
@@ -38,14 +43,97 @@ async fn main() -> io::Result<()> {
 		std::process::exit(1)
 	}
 
-	if pg_pool_result.is_err() {
-		log::error!("Could not initialize Postgres DB: {}", pg_pool_result.unwrap_err());
-		std::process::exit(1)
-	}
+	let pg_pool = match pg_pool_result {
+		Ok(pg_pool) => Arc::new(pg_pool),
+		Err(e) => {
+			log::error!("Could not initialize Postgres DB: {}", e);
+			std::process::exit(1)
+		},
+	};
 
 	log::info!("Connected to Postgres database");
 
-	let pg_pool = Arc::new(pg_pool_result.unwrap());
+	match args.command {
+		Some(cli::Command::Reencrypt { batch_size }) => {
+			match reencrypt_bank_accounts(&args, pg_pool, batch_size).await {
+				Ok(total) => log::info!("Re-encryption finished, {} bank accounts updated", total),
+				Err(e) => {
+					log::error!("Could not re-encrypt bank accounts: {}", e);
+					std::process::exit(1)
+				},
+			}
+
+			return Ok(());
+		},
+		Some(cli::Command::RetryFinalities) => {
+			match retry_finalities(pg_pool).await {
+				Ok(total) => log::info!("Requeued {} failed finalities", total),
+				Err(e) => {
+					log::error!("Could not requeue failed finalities: {}", e);
+					std::process::exit(1)
+				},
+			}
+
+			return Ok(());
+		},
+		Some(cli::Command::Settle) => {
+			match settle(&args, pg_pool).await {
+				Ok(batches) => log::info!("Settlement finished, {} batches settled", batches.len()),
+				Err(e) => {
+					log::error!("Could not settle the transactions: {}", e);
+					std::process::exit(1)
+				},
+			}
+
+			return Ok(());
+		},
+		Some(cli::Command::CheckLedger) => {
+			match check_ledger(pg_pool).await {
+				Ok(report) if report.is_consistent() => log::info!(
+					"Ledger is consistent, {} bank accounts checked",
+					report.bank_accounts
+				),
+				Ok(report) => {
+					log::error!(
+						"Ledger is inconsistent, {} of {} bank accounts mismatch, {} postings are unbalanced",
+						report.mismatches.len(),
+						report.bank_accounts,
+						report.unbalanced_postings.len()
+					);
+					std::process::exit(1)
+				},
+				Err(e) => {
+					log::error!("Could not check the ledger: {}", e);
+					std::process::exit(1)
+				},
+			}
+
+			return Ok(());
+		},
+		Some(cli::Command::Reconcile { ref output }) => {
+			match reconcile(&args, pg_pool, output.as_deref()).await {
+				Ok(report) if report.is_consistent() => log::info!(
+					"Ledger reconciled, {} on-chain balances match",
+					report.bank_accounts
+				),
+				Ok(report) => {
+					log::error!(
+						"{} of {} on-chain balances don't match the ledger",
+						report.discrepancies.len(),
+						report.bank_accounts
+					);
+					std::process::exit(1)
+				},
+				Err(e) => {
+					log::error!("Could not reconcile the ledger: {}", e);
+					std::process::exit(1)
+				},
+			}
+
+			return Ok(());
+		},
+		None => {},
+	}
 
 	start_oracle(&args, pg_pool).await.unwrap();
 
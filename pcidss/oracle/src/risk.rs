@@ -0,0 +1,307 @@
+//! Risk engine assessing the payments before they are posted
+//!
+//! Rules are loaded from a YAML or JSON file. Amounts are in the minor units of the currency of
+//! the bank account:
+//!
+//! ```yaml
+//! max_amount: { USD: 500000 }
+//! daily_limit: { USD: 1000000 }
+//! velocity: { max_transactions: 10, window_secs: 3600 }
+//! blocked_acquirers: ["123456"]
+//! blocked_merchants: ["TERM0001"]
+//! cvv_lockout: { max_attempts: 3, lockout_secs: 1800 }
+//! ```
+
+use std::{
+	collections::{HashMap, HashSet},
+	sync::Arc,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use op_core::{
+	bank_account::models::BankAccount,
+	error::DomainError,
+	money::{Iso4217, Money},
+	risk::traits::RiskTrait,
+};
+use uuid::Uuid;
+
+use crate::types::ResponseCodes;
+
+/// Limit of the number of transactions of a card in a sliding window
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VelocityRule {
+	/// Maximum number of transactions in the window
+	pub max_transactions: u32,
+	/// Length of the window in seconds
+	pub window_secs: i64,
+}
+
+/// Lockout of a card after consecutive failed CVV verifications
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CvvLockoutRule {
+	/// Number of consecutive failures that locks the card out
+	pub max_attempts: u32,
+	/// Time in seconds the card is locked out after the last failure
+	pub lockout_secs: i64,
+}
+
+/// Rules of the risk engine, no rule is applied if not set
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RiskRules {
+	/// Maximum amount of a single payment by currency
+	pub max_amount: HashMap<Iso4217, i64>,
+	/// Maximum amount spent per card per day (UTC) by currency
+	pub daily_limit: HashMap<Iso4217, i64>,
+	/// Limit of the number of payments per card
+	pub velocity: Option<VelocityRule>,
+	/// Blocked acquirers, by field 32
+	pub blocked_acquirers: HashSet<String>,
+	/// Blocked merchants, by terminal (field 41)
+	pub blocked_merchants: HashSet<String>,
+	/// Lockout after failed CVV verifications
+	pub cvv_lockout: Option<CvvLockoutRule>,
+}
+
+impl RiskRules {
+	/// Load rules from a YAML or JSON file
+	pub fn load(path: &str) -> Result<Self, DomainError> {
+		let content = std::fs::read_to_string(path).map_err(|e| {
+			DomainError::InternalServerError(format!("Could not read risk rules {}: {}", path, e))
+		})?;
+
+		Self::parse(&content)
+	}
+
+	/// Parse rules from YAML, or JSON
+	pub fn parse(content: &str) -> Result<Self, DomainError> {
+		serde_yaml::from_str(content)
+			.map_err(|e| DomainError::InternalServerError(format!("Invalid risk rules: {}", e)))
+	}
+}
+
+/// Payment assessed by the risk engine
+#[derive(Debug, Clone)]
+pub struct RiskRequest<'a> {
+	/// Bank account of the card
+	pub bank_account: &'a BankAccount,
+	/// Amount billed to the card, in the currency of the bank account
+	pub amount: Money,
+	/// Acquirer of the payment, field 32
+	pub acquirer: String,
+	/// Terminal of the merchant, field 41 if set
+	pub terminal: Option<String>,
+	/// Time of the assessment
+	pub now: DateTime<Utc>,
+}
+
+/// Check of the risk engine
+///
+/// Returns the response code to decline the payment with, `None` if the check passes.
+#[async_trait]
+pub trait RiskCheck: Send + Sync {
+	/// Assess the payment
+	async fn assess(&self, request: &RiskRequest<'_>)
+		-> Result<Option<ResponseCodes>, DomainError>;
+}
+
+/// Declines payments of blocked acquirers or merchants as suspected fraud (59)
+pub struct BlocklistCheck {
+	acquirers: HashSet<String>,
+	merchants: HashSet<String>,
+}
+
+#[async_trait]
+impl RiskCheck for BlocklistCheck {
+	async fn assess(
+		&self,
+		request: &RiskRequest<'_>,
+	) -> Result<Option<ResponseCodes>, DomainError> {
+		let blocked = self.acquirers.contains(&request.acquirer) ||
+			request
+				.terminal
+				.as_ref()
+				.is_some_and(|terminal| self.merchants.contains(terminal));
+
+		Ok(blocked.then_some(ResponseCodes::SuspectedFraud))
+	}
+}
+
+/// Declines payments above the maximum amount of their currency (61)
+pub struct MaxAmountCheck {
+	limits: HashMap<Iso4217, i64>,
+}
+
+#[async_trait]
+impl RiskCheck for MaxAmountCheck {
+	async fn assess(
+		&self,
+		request: &RiskRequest<'_>,
+	) -> Result<Option<ResponseCodes>, DomainError> {
+		let exceeded = self
+			.limits
+			.get(&request.amount.currency)
+			.is_some_and(|limit| request.amount.minor_units > *limit);
+
+		Ok(exceeded.then_some(ResponseCodes::ExceedsAmountLimit))
+	}
+}
+
+/// Declines payments that take the spending of the card on the day above the limit (61)
+pub struct DailyLimitCheck {
+	limits: HashMap<Iso4217, i64>,
+	risk: Arc<dyn RiskTrait>,
+}
+
+#[async_trait]
+impl RiskCheck for DailyLimitCheck {
+	async fn assess(
+		&self,
+		request: &RiskRequest<'_>,
+	) -> Result<Option<ResponseCodes>, DomainError> {
+		let Some(limit) = self.limits.get(&request.amount.currency) else {
+			return Ok(None);
+		};
+
+		let start_of_day = request.now.date_naive().and_time(chrono::NaiveTime::MIN).and_utc();
+		let spending = self.risk.spending_since(&request.bank_account.id, start_of_day).await?;
+
+		let exceeded = spending
+			.amount
+			.checked_add(&request.amount)
+			.map_or(true, |spent| spent.minor_units > *limit);
+
+		Ok(exceeded.then_some(ResponseCodes::ExceedsAmountLimit))
+	}
+}
+
+/// Declines payments above the number of payments of the card in the window (65)
+pub struct VelocityCheck {
+	rule: VelocityRule,
+	risk: Arc<dyn RiskTrait>,
+}
+
+#[async_trait]
+impl RiskCheck for VelocityCheck {
+	async fn assess(
+		&self,
+		request: &RiskRequest<'_>,
+	) -> Result<Option<ResponseCodes>, DomainError> {
+		let since = request.now - Duration::seconds(self.rule.window_secs);
+		let spending = self.risk.spending_since(&request.bank_account.id, since).await?;
+
+		let exceeded = spending.transactions_number >= self.rule.max_transactions;
+
+		Ok(exceeded.then_some(ResponseCodes::ExceedsFrequencyLimit))
+	}
+}
+
+/// Risk engine, runs its checks in order and declines with the first failing one
+pub struct RiskEngine {
+	/// Checks of the payments
+	checks: Vec<Box<dyn RiskCheck>>,
+	/// Lockout after failed CVV verifications
+	cvv_lockout: Option<CvvLockoutRule>,
+	/// Activity of the bank accounts
+	risk: Arc<dyn RiskTrait>,
+}
+
+impl RiskEngine {
+	/// Create a risk engine with the checks of the rules
+	///
+	/// Blocklists are checked first, then the amount limits and the velocity.
+	pub fn new(rules: RiskRules, risk: Arc<dyn RiskTrait>) -> Self {
+		let mut checks: Vec<Box<dyn RiskCheck>> = vec![];
+
+		if !rules.blocked_acquirers.is_empty() || !rules.blocked_merchants.is_empty() {
+			checks.push(Box::new(BlocklistCheck {
+				acquirers: rules.blocked_acquirers,
+				merchants: rules.blocked_merchants,
+			}));
+		}
+		if !rules.max_amount.is_empty() {
+			checks.push(Box::new(MaxAmountCheck { limits: rules.max_amount }));
+		}
+		if !rules.daily_limit.is_empty() {
+			checks
+				.push(Box::new(DailyLimitCheck { limits: rules.daily_limit, risk: risk.clone() }));
+		}
+		if let Some(rule) = rules.velocity {
+			checks.push(Box::new(VelocityCheck { rule, risk: risk.clone() }));
+		}
+
+		Self { checks, cvv_lockout: rules.cvv_lockout, risk }
+	}
+
+	/// Add a check, run after the ones of the rules
+	pub fn with_check(mut self, check: impl RiskCheck + 'static) -> Self {
+		self.checks.push(Box::new(check));
+		self
+	}
+
+	/// Assess a payment, returns the response code of the first failing check or approved
+	pub async fn assess(&self, request: &RiskRequest<'_>) -> Result<ResponseCodes, DomainError> {
+		for check in self.checks.iter() {
+			if let Some(response_code) = check.assess(request).await? {
+				log::info!(
+					"Payment of bank account {} declined by the risk engine: {:?}",
+					request.bank_account.id,
+					response_code
+				);
+				return Ok(response_code);
+			}
+		}
+
+		Ok(ResponseCodes::Approved)
+	}
+
+	/// Whether CVV verifications of the bank account are locked out
+	pub async fn is_locked_out(
+		&self,
+		bank_account_id: &Uuid,
+		now: DateTime<Utc>,
+	) -> Result<bool, DomainError> {
+		let Some(rule) = &self.cvv_lockout else {
+			return Ok(false);
+		};
+
+		Ok(self.risk.find_cvv_failures(bank_account_id).await?.is_some_and(|failures| {
+			failures.is_locked_out(rule.max_attempts, Duration::seconds(rule.lockout_secs), now)
+		}))
+	}
+
+	/// Record the result of a CVV verification of the bank account
+	pub async fn record_cvv(
+		&self,
+		bank_account_id: &Uuid,
+		verified: bool,
+		now: DateTime<Utc>,
+	) -> Result<(), DomainError> {
+		let Some(rule) = &self.cvv_lockout else {
+			return Ok(());
+		};
+
+		if verified {
+			return self.risk.reset_cvv_failures(bank_account_id).await;
+		}
+
+		let failures = self
+			.risk
+			.record_cvv_failure(bank_account_id, now - Duration::seconds(rule.lockout_secs))
+			.await?;
+
+		if failures.attempts >= rule.max_attempts {
+			log::warn!(
+				"CVV verifications of bank account {} are locked out after {} failures",
+				bank_account_id,
+				failures.attempts
+			);
+		}
+
+		Ok(())
+	}
+}
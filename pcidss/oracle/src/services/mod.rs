@@ -1,46 +1,150 @@
 use std::{str::FromStr, sync::Arc};
 
 use deadpool_postgres::Pool;
-use op_api::{bank_account::PgBankAccount, transaction::PgTransaction};
-use op_core::{bank_account::traits::BankAccountTrait, transaction::traits::TransactionTrait};
-use subxt::{OnlineClient, SubstrateConfig};
-use subxt_signer::{
-	sr25519::{Keypair, PublicKey},
-	SecretUri,
+use op_api::{
+	bank_account::PgBankAccount, block_cursor::PgBlockCursor, hold::PgHold,
+	key_provider::FileKeyProvider, ledger::PgLedger, outbox::PgOutbox,
+	processed_message::PgProcessedMessage, risk::PgRisk, settlement::PgSettlement,
+	transaction::PgTransaction,
 };
+use op_core::{
+	bank_account::traits::BankAccountTrait,
+	block_cursor::traits::BlockCursorTrait,
+	hold::traits::HoldTrait,
+	ledger::{models::LedgerReport, traits::LedgerTrait},
+	outbox::traits::OutboxTrait,
+	processed_message::traits::ProcessedMessageTrait,
+	settlement::{models::SettlementBatch, traits::SettlementTrait},
+	transaction::traits::TransactionTrait,
+	vault::Vault,
+};
+use subxt::{
+	backend::{legacy::LegacyRpcMethods, rpc::RpcClient},
+	OnlineClient, SubstrateConfig,
+};
+use subxt_signer::{sr25519::Keypair, SecretUri};
 
-use crate::cli::Cli;
+use crate::{
+	auth::{AuthConfig, Authenticator, BalancesRequestVerifier, Role},
+	cli::Cli,
+	events::EventBus,
+	fx::RateTable,
+	risk::{RiskEngine, RiskRules},
+	spec::{SpecLoader, DEFAULT_SPEC_NAME},
+	types::constants::{
+		DEV_API_KEY, EVENT_BUS_CAPACITY, HOLD_EXPIRY_INTERVAL_SECS, OCW_KEYS_SYNC_INTERVAL_SECS,
+	},
+};
 
-use self::processor::Iso8583MessageProcessor;
+use self::{processor::Iso8583MessageProcessor, reconciliation::ReconciliationReport};
 
+pub mod ocw;
+pub mod outbox;
 pub mod processor;
+pub mod reconciliation;
 pub mod rpc;
+pub mod settlement;
+pub mod submitter;
+pub mod tcp;
 pub mod watcher;
 
 /// Start the suite of services for the oracle
 ///
 /// 1. Start the ISO8583 message processor
-/// 2. Start the RPC server
-/// 3. Start the watcher service
+/// 2. Start the sync of the offchain worker keys
+/// 3. Start the RPC server
+/// 4. Start the watcher service
+/// 5. Start the authorization hold expiry sweeper
+/// 6. Start the ISO-8583 TCP listener, if a port is given
+/// 7. Start the finality outbox worker
+/// 8. Start the settlement worker
+/// 9. Start the reconciliation of the ledger with the on-chain balances, if an interval is given
 pub async fn start_oracle(args: &Cli, pg_pool: Arc<Pool>) -> anyhow::Result<()> {
-	let iso8583_spec = iso8583_rs::iso8583::iso_spec::spec("");
+	let specs = load_specs(args)?;
+	log::info!("Loaded ISO-8583 specs: {:?}", specs.names());
+	specs.get(args.tcp_spec.as_deref())?;
+
+	let vault = Arc::new(load_vault(args)?);
+
+	let rates = match &args.fx_rates_file {
+		Some(path) => RateTable::load(path)?,
+		None => RateTable::default(),
+	};
+	if let Some(version) = rates.version() {
+		log::info!("Loaded conversion rates version {}", version);
+	}
+
+	let risk_rules = match &args.risk_rules_file {
+		Some(path) => RiskRules::load(path)?,
+		None => RiskRules::default(),
+	};
+
+	let mut auth_config = match &args.rpc_auth_file {
+		Some(path) => AuthConfig::load(path)?,
+		None => AuthConfig::default(),
+	};
+	if args.dev {
+		auth_config = auth_config.with_api_key("dev", DEV_API_KEY, Role::Merchant);
+	}
+	let authenticator = Arc::new(Authenticator::new(auth_config)?);
+
+	let bank_account = PgBankAccount::new(pg_pool.clone(), vault.clone());
+
+	let tokenized = bank_account.tokenize_plaintext_cards().await?;
+	if tokenized > 0 {
+		log::info!("Tokenized card data of {} bank accounts", tokenized);
+	}
+
+	let stale = bank_account.count_stale_cards().await?;
+	if stale > 0 {
+		log::warn!(
+			"Card data of {} bank accounts is not on the current key version, run `pcidss-oracle reencrypt`",
+			stale
+		);
+	}
 
-	let bank_account_trait: Arc<dyn BankAccountTrait> =
-		Arc::new(PgBankAccount::new(pg_pool.clone()));
+	let bank_account_trait: Arc<dyn BankAccountTrait> = Arc::new(bank_account);
 	let transaction_trait: Arc<dyn TransactionTrait> =
 		Arc::new(PgTransaction::new(pg_pool.clone()));
+	let hold_trait: Arc<dyn HoldTrait> = Arc::new(PgHold::new(pg_pool.clone()));
+	let processed_message_trait: Arc<dyn ProcessedMessageTrait> =
+		Arc::new(PgProcessedMessage::new(pg_pool.clone()));
+	let outbox_trait: Arc<dyn OutboxTrait> = Arc::new(PgOutbox::new(pg_pool.clone()));
+	let settlement_trait: Arc<dyn SettlementTrait> = Arc::new(PgSettlement::new(pg_pool.clone()));
+
+	let failed = outbox_trait.find_failed().await?;
+	if !failed.is_empty() {
+		log::warn!(
+			"Finalities of {} events failed to be submitted, run `pcidss-oracle retry-finalities`",
+			failed.len()
+		);
+	}
 
 	// Message processor
 	let processor = Arc::new(Iso8583MessageProcessor {
-		spec: iso8583_spec,
+		specs,
 		bank_account_controller: bank_account_trait.clone(),
 		transaction_controller: transaction_trait.clone(),
+		hold_controller: hold_trait,
+		processed_message_controller: processed_message_trait,
+		outbox_controller: outbox_trait.clone(),
+		settlement_controller: settlement_trait.clone(),
+		hold_ttl: chrono::Duration::hours(args.hold_ttl),
+		vault,
+		rates: Arc::new(rates),
+		risk: Arc::new(RiskEngine::new(risk_rules, Arc::new(PgRisk::new(pg_pool.clone())))),
+		events: Arc::new(EventBus::new(EVENT_BUS_CAPACITY)),
 	});
 
 	let args = args.clone();
 
+	let rpc_client = RpcClient::from_url(&args.ws_url)
+		.await
+		.map_err(|_| format!("Could not connect to Substrate node at: {}", args.ws_url))
+		.unwrap();
+
 	let client = Arc::new(
-		OnlineClient::<SubstrateConfig>::from_url(&args.ws_url)
+		OnlineClient::<SubstrateConfig>::from_rpc_client(rpc_client.clone())
 			.await
 			.map_err(|_| format!("Could not connect to Substrate node at: {}", args.ws_url))
 			.unwrap(),
@@ -50,33 +154,125 @@ pub async fn start_oracle(args: &Cli, pg_pool: Arc<Pool>) -> anyhow::Result<()>
 	let keypair = Keypair::from_uri(&seed).map_err(|_| "Invalid seed phrase").unwrap();
 	log::info!("Using keypair: {:?}", hex::encode(keypair.public_key()));
 
+	// the only signer of the oracle, shared by all services
+	let submitter = Arc::new(submitter::TransactionSubmitter::new(
+		Arc::clone(&client),
+		rpc_client.clone(),
+		keypair,
+	));
+
+	// spawn the sync of the offchain worker keys
+	let balances_verifier = Arc::new(BalancesRequestVerifier::new(args.ocw_request_max_age));
+	tokio::spawn(
+		ocw::OcwKeysSync::new(Arc::clone(&client), Arc::clone(&balances_verifier))
+			.start(OCW_KEYS_SYNC_INTERVAL_SECS),
+	);
+
 	// spawn the RPC server
 	tokio::spawn({
 		let processor = Arc::clone(&processor);
-		let client = Arc::clone(&client);
-		let keypair = keypair.clone();
+		let submitter = Arc::clone(&submitter);
+		let authenticator = Arc::clone(&authenticator);
+		let balances_verifier = Arc::clone(&balances_verifier);
 		async move {
-			let ocw_signer = PublicKey(
-				hex::decode(&args.ocw_signer).unwrap().try_into().expect("valid public key"),
-			);
-			let result =
-				rpc::run(processor, client, keypair, args.rpc_port, args.dev, ocw_signer).await;
-			if result.is_err() {
-				log::error!("Could not start RPC: {}", result.unwrap_err().to_string());
+			let result = rpc::run(
+				processor,
+				submitter,
+				args.rpc_port,
+				args.dev,
+				authenticator,
+				balances_verifier,
+			)
+			.await;
+			if let Err(e) = result {
+				log::error!("Could not start RPC: {}", e.to_string());
 				std::process::exit(1)
 			}
 		}
 	});
 
+	// spawn the authorization hold expiry sweeper
+	tokio::spawn({
+		let processor = Arc::clone(&processor);
+		async move {
+			let mut interval =
+				tokio::time::interval(std::time::Duration::from_secs(HOLD_EXPIRY_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				if let Err(e) = processor.expire_holds().await {
+					log::error!("Could not expire authorization holds: {}", e);
+				}
+			}
+		}
+	});
+
+	// spawn the ISO-8583 TCP listener
+	if let Some(tcp_port) = args.tcp_port {
+		let listener = tcp::TcpListenerService::new(
+			Arc::clone(&processor),
+			Arc::clone(&authenticator),
+			args.tcp_length_header,
+		)
+		.with_spec(args.tcp_spec.clone())
+		.with_max_in_flight(args.tcp_max_in_flight)
+		.with_chain(Arc::clone(&submitter));
+
+		let tcp_host = args.tcp_host.clone();
+		tokio::spawn(async move {
+			if let Err(e) = listener.start(&tcp_host, tcp_port).await {
+				log::error!("Could not start ISO-8583 TCP listener: {}", e);
+				std::process::exit(1)
+			}
+		});
+	}
+
+	// spawn the finality outbox worker
+	tokio::spawn(outbox::OutboxWorker::new(Arc::clone(&client), submitter, outbox_trait).start());
+
+	// spawn the settlement worker
+	tokio::spawn(
+		settlement::SettlementWorker::new(
+			settlement_trait,
+			args.settlement_cutoff,
+			&args.clearing_dir,
+		)
+		.start(),
+	);
+
+	// spawn the reconciliation of the ledger with the on-chain balances
+	if let Some(interval) = args.reconciliation_interval {
+		let reconciler = reconciliation::Reconciler::new(
+			Arc::clone(&client),
+			bank_account_trait,
+			args.chain_decimals,
+		);
+
+		tokio::spawn(reconciler.start(interval, args.reconciliation_alert.clone()));
+	}
+
 	// spawn the watcher service
 	tokio::spawn({
 		let processor = Arc::clone(&processor);
 		let client = Arc::clone(&client);
+		let block_cursor: Arc<dyn BlockCursorTrait> = Arc::new(PgBlockCursor::new(pg_pool));
+		let start_block = args.watcher_start_block;
+		let confirmations = args.watcher_confirmations;
+		let chain_decimals = args.chain_decimals;
 		async move {
-			let watcher = watcher::WatcherService::new(keypair, processor, client).await.unwrap();
+			let watcher = watcher::WatcherService::new(
+				processor,
+				client,
+				LegacyRpcMethods::new(rpc_client),
+				block_cursor,
+				start_block,
+				chain_decimals,
+			)
+			.await
+			.unwrap()
+			.with_confirmations(confirmations);
 			let result = watcher.start().await;
-			if result.is_err() {
-				log::error!("Could not start watcher: {}", result.unwrap_err().to_string());
+			if let Err(e) = result {
+				log::error!("Could not start watcher: {}", e.to_string());
 				std::process::exit(1)
 			}
 		}
@@ -84,3 +280,158 @@ pub async fn start_oracle(args: &Cli, pg_pool: Arc<Pool>) -> anyhow::Result<()>
 
 	Ok(())
 }
+
+/// Re-encrypt card data of all bank accounts with the current key version
+///
+/// Works in batches, so that the oracle can keep serving requests in the meantime. Returns the
+/// number of re-encrypted bank accounts.
+pub async fn reencrypt_bank_accounts(
+	args: &Cli,
+	pg_pool: Arc<Pool>,
+	batch_size: i64,
+) -> anyhow::Result<usize> {
+	let vault = Arc::new(load_vault(args)?);
+	let bank_account = PgBankAccount::new(pg_pool, vault.clone());
+
+	bank_account.tokenize_plaintext_cards().await?;
+
+	log::info!("Re-encrypting card data with key version {}", vault.current_key_version().await?);
+
+	let mut total = 0;
+	loop {
+		let reencrypted = bank_account.reencrypt_batch(batch_size).await?;
+		if reencrypted == 0 {
+			break;
+		}
+
+		total += reencrypted;
+		log::info!("Re-encrypted card data of {} bank accounts", total);
+	}
+
+	Ok(total)
+}
+
+/// Requeue the finalities that failed to be submitted, returns the number of requeued ones
+pub async fn retry_finalities(pg_pool: Arc<Pool>) -> anyhow::Result<u64> {
+	let outbox = PgOutbox::new(pg_pool);
+
+	for finality in outbox.find_failed().await? {
+		log::info!(
+			"Requeueing finality of event {}, last error: {}",
+			finality.event_id,
+			finality.last_error.unwrap_or_default()
+		);
+	}
+
+	Ok(outbox.retry_failed().await?)
+}
+
+/// Close the settlement window now and write the clearing files, returns the settled batches
+pub async fn settle(args: &Cli, pg_pool: Arc<Pool>) -> anyhow::Result<Vec<SettlementBatch>> {
+	let worker = settlement::SettlementWorker::new(
+		Arc::new(PgSettlement::new(pg_pool)),
+		args.settlement_cutoff,
+		&args.clearing_dir,
+	);
+
+	worker.settle(chrono::Utc::now()).await
+}
+
+/// Compare the balances of the bank accounts with the ledger journal, mismatches are logged
+pub async fn check_ledger(pg_pool: Arc<Pool>) -> anyhow::Result<LedgerReport> {
+	let report = PgLedger::new(pg_pool).check().await?;
+
+	for mismatch in report.mismatches.iter() {
+		log::warn!(
+			"Bank account {} has balance {} and available balance {}, the ledger has {} and {}",
+			mismatch.bank_account_id,
+			mismatch.balance,
+			mismatch.available_balance,
+			mismatch.ledger_balance,
+			mismatch.expected_available_balance
+		);
+	}
+
+	for posting_id in report.unbalanced_postings.iter() {
+		log::warn!("Posting {} is not balanced", posting_id);
+	}
+
+	Ok(report)
+}
+
+/// Compare the balances of the registered bank accounts with their on-chain balances
+///
+/// The JSON report is written to the output file, or to the standard output. If there are
+/// discrepancies they are logged and the alert command is run.
+pub async fn reconcile(
+	args: &Cli,
+	pg_pool: Arc<Pool>,
+	output: Option<&str>,
+) -> anyhow::Result<ReconciliationReport> {
+	let vault = Arc::new(load_vault(args)?);
+	let bank_account = Arc::new(PgBankAccount::new(pg_pool, vault));
+
+	let client = Arc::new(OnlineClient::<SubstrateConfig>::from_url(&args.ws_url).await?);
+
+	let reconciler =
+		reconciliation::Reconciler::new(Arc::clone(&client), bank_account, args.chain_decimals);
+	let report = reconciler.reconcile().await?;
+
+	let json = serde_json::to_string_pretty(&report)?;
+	match output {
+		Some(path) => std::fs::write(path, json)?,
+		None => println!("{}", json),
+	}
+
+	if report.is_consistent() {
+		return Ok(report);
+	}
+
+	reconciliation::log_discrepancies(&report);
+
+	if let Some(command) = &args.reconciliation_alert {
+		reconciliation::alert(command, &report).await?;
+	}
+
+	Ok(report)
+}
+
+/// Load the card vault from the keyfile, a new keyfile is generated in development mode
+fn load_vault(args: &Cli) -> anyhow::Result<Vault> {
+	let path = std::path::Path::new(&args.vault_key_file);
+
+	let key_provider = if !path.exists() && args.dev {
+		log::warn!("Vault keyfile not found, generating a new one at {:?}", path);
+		FileKeyProvider::generate_file(path)?
+	} else {
+		FileKeyProvider::from_file(path)?
+	};
+
+	Ok(Vault::new(Arc::new(key_provider)))
+}
+
+/// Load the ISO-8583 specs given as `[name=]path`, the first one is the default
+///
+/// Unnamed specs are named after their position, `default` for the first one.
+fn load_specs(args: &Cli) -> anyhow::Result<SpecLoader> {
+	let mut entries =
+		args.iso8583_spec
+			.iter()
+			.enumerate()
+			.map(|(i, entry)| match entry.split_once('=') {
+				Some((name, path)) => (name.to_string(), path),
+				None if i == 0 => (DEFAULT_SPEC_NAME.to_string(), entry.as_str()),
+				None => (i.to_string(), entry.as_str()),
+			});
+
+	let Some((name, path)) = entries.next() else {
+		return Ok(SpecLoader::embedded()?);
+	};
+
+	let mut specs = SpecLoader::new(&name, SpecLoader::load(path)?);
+	for (name, path) in entries {
+		specs = specs.with_spec(&name, SpecLoader::load(path)?);
+	}
+
+	Ok(specs)
+}
@@ -0,0 +1,64 @@
+//! Sync of the offchain worker keys from the chain
+//!
+//! Balance requests of the offchain workers are signed by the oracle accounts registered
+//! on-chain with `register_oracle`. Their keys and the best block are synced periodically into the
+//! [`BalancesRequestVerifier`] of the RPC.
+
+use std::{collections::HashSet, sync::Arc};
+
+use subxt::{OnlineClient, SubstrateConfig};
+
+use super::watcher::iso_8583_chain;
+use crate::auth::BalancesRequestVerifier;
+
+/// Sync of the offchain worker keys and the best block
+pub struct OcwKeysSync {
+	/// Substrate client
+	client: Arc<OnlineClient<SubstrateConfig>>,
+	/// Verifier of the balance requests
+	verifier: Arc<BalancesRequestVerifier>,
+}
+
+impl OcwKeysSync {
+	/// Create a new sync
+	pub fn new(
+		client: Arc<OnlineClient<SubstrateConfig>>,
+		verifier: Arc<BalancesRequestVerifier>,
+	) -> Self {
+		Self { client, verifier }
+	}
+
+	/// Sync at the given interval
+	pub async fn start(self, interval_secs: u64) {
+		let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+		loop {
+			interval.tick().await;
+
+			if let Err(e) = self.sync().await {
+				log::error!("Could not sync the offchain worker keys: {}", e);
+			}
+		}
+	}
+
+	/// Fetch the oracle accounts and the number of the best block
+	pub async fn sync(&self) -> anyhow::Result<()> {
+		let block = self.client.blocks().at_latest().await?;
+		let query = iso_8583_chain::storage().iso8583().oracle_accounts_iter();
+		let mut oracle_accounts = block.storage().iter(query).await?;
+
+		let mut keys = HashSet::new();
+		while let Some((key, _)) = oracle_accounts.next().await.transpose()? {
+			// `Blake2_128Concat` keys end with the account
+			let account: [u8; 32] = key[key.len().saturating_sub(32)..]
+				.try_into()
+				.map_err(|_| anyhow::anyhow!("invalid oracle account key {}", hex::encode(&key)))?;
+			keys.insert(account);
+		}
+
+		self.verifier.set_keys(keys);
+		self.verifier.set_best_block(block.number());
+
+		Ok(())
+	}
+}
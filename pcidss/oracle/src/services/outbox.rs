@@ -0,0 +1,209 @@
+//! Outbox worker submits the enqueued finalities on-chain
+//!
+//! Finalities are submitted one by one through the shared
+//! [`TransactionSubmitter`](super::submitter::TransactionSubmitter), without waiting for each
+//! other, and tracked until they are finalized. Failed submissions are retried with an exponential
+//! backoff until they fail for good, then they wait for `pcidss-oracle retry-finalities`.
+//!
+//! Finalities of events in blocks that are not finalized yet wait until their block is, since the
+//! watcher may still retract them if the block is reorged out.
+
+use std::{str::FromStr, sync::Arc};
+
+use chrono::Utc;
+use op_core::{
+	error::DomainError,
+	outbox::{
+		models::{Finality, FinalityStatus},
+		traits::OutboxTrait,
+	},
+};
+use subxt::{
+	config::substrate::H256, tx::TxProgress, utils::AccountId32, OnlineClient, SubstrateConfig,
+};
+
+use super::{
+	submitter::TransactionSubmitter,
+	watcher::iso_8583_chain::{
+		self,
+		runtime_types::{
+			bounded_collections::bounded_vec::BoundedVec,
+			pallet_iso_8583::types::{FinalisedTransaction, ISO8583FailureReason, ISO8583Status},
+		},
+	},
+};
+use crate::types::constants::{
+	FINALIZATION_TIMEOUT_SECS, OUTBOX_BATCH_SIZE, OUTBOX_POLL_INTERVAL_SECS,
+};
+
+/// Worker that submits the finalities of the outbox on-chain
+pub struct OutboxWorker {
+	/// Substrate client, used to look up the finalized head
+	client: Arc<OnlineClient<SubstrateConfig>>,
+	/// Submitter of the extrinsics signed by the oracle
+	submitter: Arc<TransactionSubmitter>,
+	/// Outbox of the finalities
+	outbox: Arc<dyn OutboxTrait>,
+}
+
+impl OutboxWorker {
+	/// Create a new outbox worker
+	pub fn new(
+		client: Arc<OnlineClient<SubstrateConfig>>,
+		submitter: Arc<TransactionSubmitter>,
+		outbox: Arc<dyn OutboxTrait>,
+	) -> Self {
+		Self { client, submitter, outbox }
+	}
+
+	/// Poll the outbox and submit the due finalities
+	pub async fn start(self) {
+		let worker = Arc::new(self);
+		let mut interval =
+			tokio::time::interval(std::time::Duration::from_secs(OUTBOX_POLL_INTERVAL_SECS));
+
+		loop {
+			interval.tick().await;
+			if let Err(e) = worker.submit_due().await {
+				log::error!("Could not submit finalities: {}", e);
+			}
+		}
+	}
+
+	/// Submit the pending finalities, submitted ones that timed out are retried first
+	///
+	/// Pending finalities of events in blocks after the finalized head are skipped.
+	async fn submit_due(self: &Arc<Self>) -> anyhow::Result<()> {
+		let finalized = self.client.blocks().at_latest().await?.number();
+
+		for finality in self.outbox.find_due(Utc::now(), OUTBOX_BATCH_SIZE).await? {
+			if finality.status == FinalityStatus::Submitted {
+				self.record_failure(&finality, "Not finalized in time").await?;
+				continue;
+			}
+
+			if finality
+				.event_block_number()
+				.is_some_and(|block_number| block_number > finalized)
+			{
+				continue;
+			}
+
+			self.submit(finality).await?;
+		}
+
+		Ok(())
+	}
+
+	/// Submit a finality and track it in the background
+	async fn submit(self: &Arc<Self>, finality: Finality) -> Result<(), DomainError> {
+		let finalised_transaction = match finalised_transaction(&finality) {
+			Ok(finalised_transaction) => finalised_transaction,
+			Err(e) => return self.record_failure(&finality, &e.to_string()).await,
+		};
+
+		log::debug!("Submitting finality: {:?}", finalised_transaction);
+
+		let tx = iso_8583_chain::tx().iso8583().submit_finality(finalised_transaction);
+
+		let progress = match self.submitter.submit(&tx).await {
+			Ok(progress) => progress,
+			Err(e) => return self.record_failure(&finality, &e.to_string()).await,
+		};
+
+		let retry_at = Utc::now() + chrono::Duration::seconds(FINALIZATION_TIMEOUT_SECS);
+		let finality = self
+			.outbox
+			.mark_submitted(&finality.id, &format!("{:?}", progress.extrinsic_hash()), retry_at)
+			.await?;
+
+		tokio::spawn({
+			let worker = Arc::clone(self);
+			async move {
+				if let Err(e) = worker.track(finality, progress).await {
+					log::error!("Could not track finality: {}", e);
+				}
+			}
+		});
+
+		Ok(())
+	}
+
+	/// Wait for a submitted finality to be finalized
+	async fn track(
+		&self,
+		finality: Finality,
+		progress: TxProgress<SubstrateConfig, OnlineClient<SubstrateConfig>>,
+	) -> Result<(), DomainError> {
+		match progress.wait_for_finalized_success().await {
+			Ok(events) => {
+				let block_hash = format!("{:?}", events.block_hash());
+				log::info!("Finality of event {} finalized in {}", finality.event_id, block_hash);
+				self.outbox.mark_finalized(&finality.id, &block_hash).await?;
+				Ok(())
+			},
+			Err(e) => {
+				// dropped or invalidated extrinsics leave a gap in the nonces
+				if !matches!(e, subxt::Error::Runtime(_)) {
+					self.submitter.resync().await;
+				}
+				self.record_failure(&finality, &e.to_string()).await
+			},
+		}
+	}
+
+	/// Record a failed attempt, the finality fails for good after too many attempts
+	async fn record_failure(&self, finality: &Finality, error: &str) -> Result<(), DomainError> {
+		let retry_at = finality.retry_at(Utc::now());
+		let finality = self.outbox.mark_failed_attempt(&finality.id, error, retry_at).await?;
+
+		if finality.status == FinalityStatus::Failed {
+			log::error!(
+				"Finality of event {} failed after {} attempts: {}",
+				finality.event_id,
+				finality.attempts,
+				error
+			);
+		} else {
+			log::warn!(
+				"Finality of event {} failed, retrying at {}: {}",
+				finality.event_id,
+				finality.next_attempt_at,
+				error
+			);
+		}
+
+		Ok(())
+	}
+}
+
+/// On-chain finality of an enqueued one
+fn finalised_transaction(
+	finality: &Finality,
+) -> anyhow::Result<FinalisedTransaction<AccountId32, u128>> {
+	let account_id = |hex_account: &str| -> anyhow::Result<AccountId32> {
+		let bytes: [u8; 32] = hex::decode(hex_account)?
+			.try_into()
+			.map_err(|_| anyhow::anyhow!("Invalid account id: {}", hex_account))?;
+		Ok(AccountId32(bytes))
+	};
+
+	let status = match &finality.response_code[..] {
+		"00" => ISO8583Status::Approved,
+		"05" => ISO8583Status::Failed(ISO8583FailureReason::DoNotHonor),
+		"12" => ISO8583Status::Failed(ISO8583FailureReason::InvalidTransaction),
+		"14" => ISO8583Status::Failed(ISO8583FailureReason::InvalidCardNumber),
+		"51" => ISO8583Status::Failed(ISO8583FailureReason::InsufficientFunds),
+		"54" => ISO8583Status::Failed(ISO8583FailureReason::ExpiredCard),
+		_ => ISO8583Status::Failed(ISO8583FailureReason::Other),
+	};
+
+	Ok(FinalisedTransaction {
+		hash: H256::from_str(&finality.transaction_hash)?,
+		event_id: BoundedVec::<u8>(finality.event_id.as_bytes().to_vec()),
+		from: account_id(&finality.from)?,
+		to: account_id(&finality.to)?,
+		amount: finality.amount,
+		status,
+	})
+}
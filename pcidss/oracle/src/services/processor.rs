@@ -1,87 +1,389 @@
 //! ISO-8583 message parsing and formatting.
 
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
-use chrono::Utc;
+use chrono::{Duration, Utc};
 use iso8583_rs::iso8583::iso_spec::{new_msg, IsoMsg, Spec};
 use log::info;
+use sha2::{Digest, Sha256};
 use tracing::debug;
 
 use op_core::{
 	bank_account::{
-		models::{BankAccount, BankAccountUpdate},
+		models::{BankAccount, BankAccountUpdate, CardStatus},
 		traits::BankAccountTrait,
 	},
 	error::DomainError,
+	fx::Conversion,
+	hold::{
+		models::{Hold, HoldCreate},
+		traits::HoldTrait,
+	},
+	money::{Iso4217, Money},
+	outbox::{
+		models::{Finality, FinalityCreate},
+		traits::OutboxTrait,
+	},
+	processed_message::{models::Claim, traits::ProcessedMessageTrait},
+	settlement::{models::SettlementTotals, traits::SettlementTrait},
 	transaction::{models::TransactionCreate, traits::TransactionTrait},
 	types::TransactionType,
+	vault::Vault,
+};
+
+use crate::{
+	events::{EventBus, TransactionEventKind},
+	fx::RateTable,
+	risk::{RiskEngine, RiskRequest},
+	spec::SpecLoader,
+	types::{constants::*, *},
 };
 
-use crate::types::{constants::*, *};
+/// Fields of the responses that aren't stored for retransmissions, the card number and the
+/// track-2 data with the CVV. They are echoed from the retransmitted request instead.
+const UNSTORED_FIELD_NUMBERS: [u32; 2] = [2, 35];
 
 /// ISO-8583 message processor
 #[derive(Clone)]
 pub struct Iso8583MessageProcessor {
-	/// Spec for the ISO-8583 message
-	pub spec: &'static Spec,
+	/// Specs for the ISO-8583 messages
+	pub specs: SpecLoader,
 	/// Bank account controller
 	pub bank_account_controller: Arc<dyn BankAccountTrait>,
 	/// Transaction controller
 	pub transaction_controller: Arc<dyn TransactionTrait>,
+	/// Authorization hold controller
+	pub hold_controller: Arc<dyn HoldTrait>,
+	/// Processed message controller, answers retransmissions with the stored responses
+	pub processed_message_controller: Arc<dyn ProcessedMessageTrait>,
+	/// Outbox of the finalities of on-chain events
+	pub outbox_controller: Arc<dyn OutboxTrait>,
+	/// Settlement controller, answers reconciliation requests of the acquirers
+	pub settlement_controller: Arc<dyn SettlementTrait>,
+	/// Time after which uncaptured authorization holds expire
+	pub hold_ttl: Duration,
+	/// Vault for card data verification
+	pub vault: Arc<Vault>,
+	/// Rates to convert amounts in other currencies than the one of the bank account
+	pub rates: Arc<RateTable>,
+	/// Risk engine assessing the payments before they are posted
+	pub risk: Arc<RiskEngine>,
+	/// Bus the posted and reversed transactions are published to
+	pub events: Arc<EventBus>,
 }
 
 impl Iso8583MessageProcessor {
+	/// Default spec for the ISO-8583 messages
+	pub fn spec(&self) -> &'static Spec {
+		self.specs.default_spec()
+	}
+
 	/// Process the encoded ISO-8583 message and return the response
 	pub async fn process(&self, msg: &mut Vec<u8>) -> Result<(Vec<u8>, IsoMsg), DomainError> {
-		match self.spec.parse(msg) {
-			Ok(iso_msg) => {
-				debug!("parsed incoming request - message = \"{}\" successfully. \n : parsed message: \n --- \n {} \n ----\n",
-                       iso_msg.msg.name(), iso_msg);
-
-				let req_msg_type = iso_msg.get_field_value(&"message_type".to_string())?;
-
-				let res_msg_type = match req_msg_type.as_str().try_into() {
-					Ok(MTI::AuthorizationRequest) => MTI::AuthorizationResponse,
-					Ok(MTI::ReversalRequest) => MTI::ReversalResponse,
-					Ok(MTI::NetworkManagementRequest) => MTI::NetworkManagementResponse,
-					_ => return Err(DomainError::ApiError("Unsupported message type".to_string())),
-				};
+		self.process_from(self.spec(), msg, MessageOrigin::External, None).await
+	}
 
-				// Create a new response message
-				let mut res_iso_msg =
-					new_msg(self.spec, self.spec.get_message_from_header(res_msg_type.into())?);
-
-				// don't copy the fields that we have already set
-				res_iso_msg.echo_from(&iso_msg, &POPULATED_ISO_MSG_FIELD_NUMBERS[1..])?;
-
-				// handle authorization request
-				match req_msg_type.as_str().try_into().expect("Validated above; qed") {
-					MTI::AuthorizationRequest =>
-						self.handle_authorization_request(&mut res_iso_msg).await?,
-					MTI::ReversalRequest => self.handle_reversal_request(&mut res_iso_msg).await?,
-					MTI::NetworkManagementRequest =>
-						self.handle_register_account(&mut res_iso_msg).await?,
-					_ => return Err(DomainError::ApiError("Unsupported message type".to_string())),
-				};
+	/// Process the encoded ISO-8583 message with the named spec, the default one if `None`
+	pub async fn process_with_spec(
+		&self,
+		spec_name: Option<&str>,
+		msg: &mut Vec<u8>,
+	) -> Result<(Vec<u8>, IsoMsg), DomainError> {
+		self.process_from(self.specs.get(spec_name)?, msg, MessageOrigin::External, None)
+			.await
+	}
+
+	/// Process the ISO-8583 message composed from its MTI and field values with the named spec,
+	/// the default one if `None`
+	///
+	/// Fields are keyed by their position in the bitmap and formatted as the spec expects them,
+	/// e.g. zero padded amounts.
+	pub async fn process_fields(
+		&self,
+		spec_name: Option<&str>,
+		mti: &str,
+		fields: &BTreeMap<u32, String>,
+	) -> Result<(Vec<u8>, IsoMsg), DomainError> {
+		let spec = self.specs.get(spec_name)?;
+		let mut msg = compose_iso_msg(spec, mti, fields)?;
+
+		self.process_from(spec, &mut msg, MessageOrigin::External, None).await
+	}
+
+	/// Process the encoded ISO-8583 message composed from an on-chain event and enqueue its
+	/// finality
+	///
+	/// Finality of an approved event is enqueued together with the ledger change, the one of a
+	/// declined event right after it is processed. Response code and transaction hash of
+	/// `finality` are taken from the response.
+	pub async fn process_on_chain(
+		&self,
+		msg: &mut Vec<u8>,
+		finality: &FinalityCreate,
+	) -> Result<(Vec<u8>, IsoMsg), DomainError> {
+		let (res_data, res_iso_msg) = self
+			.process_from(self.spec(), msg, MessageOrigin::OnChain, Some(finality))
+			.await?;
 
-				if let Ok(res_data) = res_iso_msg.assemble() {
-					return Ok((res_data, res_iso_msg));
-				}
+		let response_code = res_iso_msg.bmp_child_value(RESPONSE_CODE_FIELD_NUMBER)?;
+		let private_data = res_iso_msg.bmp_child_value(126).unwrap_or_default();
+		let transaction_hash = private_data
+			.trim_start_matches("0x")
+			.get(..64)
+			.unwrap_or(&"0".repeat(64))
+			.to_string();
+
+		// no-op if it was enqueued with the ledger change, or before a replay
+		let finality = self
+			.outbox_controller
+			.create(&FinalityCreate { response_code, transaction_hash, ..finality.clone() })
+			.await?;
+		debug!("Enqueued finality: {:?}", finality);
 
-				Err(DomainError::ApiError("Failed to assemble new ISO message".to_string()))
-			},
+		Ok((res_data, res_iso_msg))
+	}
+
+	/// Enqueue the finality of an on-chain event declined before a message could be composed
+	///
+	/// For events of unknown accounts or invalid amounts, nothing is posted for them. The
+	/// transaction hash of `finality` is kept if it is set, zeros otherwise.
+	pub async fn decline_on_chain(
+		&self,
+		finality: &FinalityCreate,
+		response_code: ResponseCodes,
+	) -> Result<(), DomainError> {
+		let transaction_hash = if finality.transaction_hash.is_empty() {
+			"0".repeat(64)
+		} else {
+			finality.transaction_hash.clone()
+		};
+		let response_code = Into::<&str>::into(response_code).to_string();
+
+		let finality = self
+			.outbox_controller
+			.create(&FinalityCreate { response_code, transaction_hash, ..finality.clone() })
+			.await?;
+		debug!("Enqueued finality of declined event: {:?}", finality);
+
+		Ok(())
+	}
+
+	/// Compose the response declining the encoded request with the named spec, the default one
+	/// if `None`
+	///
+	/// For requests that failed to be processed, so that their sender isn't left waiting. `None`
+	/// if the request can't be parsed or has no response.
+	pub fn decline(
+		&self,
+		spec_name: Option<&str>,
+		msg: &[u8],
+		response_code: ResponseCodes,
+	) -> Option<Vec<u8>> {
+		let spec = self.specs.get(spec_name).ok()?;
+		let iso_msg = spec.parse(&mut msg.to_vec()).ok()?;
+
+		let req_msg_type = iso_msg.get_field_value(&"message_type".to_string()).ok()?;
+		let res_msg_type = MTI::try_from(req_msg_type.as_str()).ok()?.response()?;
+
+		let mut res_iso_msg =
+			new_msg(spec, spec.get_message_from_header(res_msg_type.clone().into()).ok()?);
+		res_iso_msg.set("message_type", res_msg_type.into()).ok()?;
+
+		// echo the fields the sender matches the response with, as far as the request has them
+		for field_number in POPULATED_ISO_MSG_FIELD_NUMBERS[1..]
+			.iter()
+			.chain(&[STAN_FIELD_NUMBER, TERMINAL_ID_FIELD_NUMBER])
+		{
+			if iso_msg.bmp.is_on(*field_number) {
+				res_iso_msg.echo_from(&iso_msg, &[*field_number]).ok()?;
+			}
+		}
+		res_iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, response_code.into()).ok()?;
+
+		res_iso_msg.assemble().ok()
+	}
+
+	/// Compensate an on-chain event whose block was retracted by a reorg
+	///
+	/// The ledger change of the event is moved back and its finality is dropped. The event is
+	/// forgotten first, so that it is processed again if the block replacing it emits it too.
+	pub async fn retract_event(&self, finality: &Finality) -> Result<(), DomainError> {
+		// the event is already processed again on the new fork, nothing is left to compensate
+		let enqueued = self.outbox_controller.find_by_event_id(&finality.event_id).await?;
+		if enqueued.is_some_and(|enqueued| enqueued.event_block_hash != finality.event_block_hash) {
+			return Ok(())
+		}
+
+		self.processed_message_controller
+			.forget(&event_idempotency_key(&finality.event_id))
+			.await?;
+
+		if let Some(transaction) =
+			self.transaction_controller.find_by_on_chain_id(&finality.event_id).await?
+		{
+			if transaction.block_hash.as_ref() == Some(&finality.event_block_hash) {
+				let transaction = self.transaction_controller.retract(&transaction.id).await?;
+				info!("Transaction retracted: {:?}", transaction.hash);
+				self.events.publish(TransactionEventKind::Retracted, &transaction);
+			}
+		}
+
+		// finalities of approved events are dropped with their transactions
+		if self.outbox_controller.find_by_event_id(&finality.event_id).await?.is_some() &&
+			!self.outbox_controller.cancel(&finality.event_id).await?
+		{
+			log::warn!(
+				"Finality of retracted event {} is already submitted, it can't be dropped",
+				finality.event_id
+			);
+		}
+
+		Ok(())
+	}
+
+	/// Process the encoded ISO-8583 message of the given origin
+	///
+	/// Response is composed with the same spec as the request.
+	async fn process_from(
+		&self,
+		spec: &'static Spec,
+		msg: &mut Vec<u8>,
+		origin: MessageOrigin,
+		finality: Option<&FinalityCreate>,
+	) -> Result<(Vec<u8>, IsoMsg), DomainError> {
+		let iso_msg = match spec.parse(msg) {
+			Ok(iso_msg) => iso_msg,
 			Err(e) => {
 				debug!("Failed to parse incoming request - message = \"{}\". \n : error: \n --- \n {} \n ----\n",
                        String::from_utf8_lossy(msg), e);
-				Err(DomainError::ApiError("Failed to parse incoming request".to_string()))
+				return Err(DomainError::ApiError("Failed to parse incoming request".to_string()))
+			},
+		};
+
+		debug!("parsed incoming request - message = \"{}\" successfully. \n : parsed message: \n --- \n {} \n ----\n",
+               iso_msg.msg.name(), iso_msg);
+
+		let Some(key) = idempotency_key(&iso_msg, origin) else {
+			return self.respond(spec, &iso_msg, origin, finality).await;
+		};
+
+		match self.processed_message_controller.claim(&key).await? {
+			Claim::Claimed => {},
+			Claim::Pending =>
+				return Err(DomainError::BadRequest(
+					"Message with the same idempotency key is being processed".to_string(),
+				)),
+			Claim::Processed(response) => {
+				info!("Retransmitted message, returning the stored response");
+				let mut res_iso_msg = spec.parse(&mut response.clone()).map_err(|_| {
+					DomainError::InternalServerError("Stored response can't be parsed".to_string())
+				})?;
+				let unstored_field_numbers: Vec<u32> = UNSTORED_FIELD_NUMBERS
+					.into_iter()
+					.filter(|field_number| iso_msg.bmp.is_on(*field_number))
+					.collect();
+				res_iso_msg.echo_from(&iso_msg, &unstored_field_numbers)?;
+				return Ok((res_iso_msg.assemble()?, res_iso_msg));
 			},
 		}
+
+		let result = self.respond(spec, &iso_msg, origin, finality).await;
+
+		match &result {
+			Ok((_, res_iso_msg)) => {
+				let stored_response =
+					without_fields(spec, res_iso_msg, &UNSTORED_FIELD_NUMBERS)?.assemble()?;
+				self.processed_message_controller.complete(&key, &stored_response).await?;
+			},
+			Err(e) => {
+				// nothing is stored without a response, so the sender can retry
+				log::debug!("Releasing idempotency key of failed message: {:?}", e);
+				self.processed_message_controller.release(&key).await?;
+			},
+		}
+
+		result
+	}
+
+	/// Handle the parsed request and compose the response with the same spec
+	///
+	/// `finality` is enqueued with the ledger change if the request moves funds.
+	async fn respond(
+		&self,
+		spec: &'static Spec,
+		iso_msg: &IsoMsg,
+		origin: MessageOrigin,
+		finality: Option<&FinalityCreate>,
+	) -> Result<(Vec<u8>, IsoMsg), DomainError> {
+		let req_msg_type = iso_msg.get_field_value(&"message_type".to_string())?;
+
+		let Some(res_msg_type) =
+			MTI::try_from(req_msg_type.as_str()).ok().and_then(|mti| mti.response())
+		else {
+			return Err(DomainError::ApiError("Unsupported message type".to_string()))
+		};
+
+		// Create a new response message
+		let mut res_iso_msg =
+			new_msg(spec, spec.get_message_from_header(res_msg_type.clone().into())?);
+
+		// don't copy the fields that we have already set
+		let echoed_field_numbers = match res_msg_type {
+			MTI::ReconciliationResponse => &RECONCILIATION_ISO_MSG_FIELD_NUMBERS[1..],
+			_ => &POPULATED_ISO_MSG_FIELD_NUMBERS[1..],
+		};
+		res_iso_msg.echo_from(iso_msg, echoed_field_numbers)?;
+
+		// STAN and terminal are optional, but they identify retransmissions and responses on
+		// TCP connections, the currency code is optional and validated against the bank account,
+		// the settlement date selects the batch of reconciliation requests
+		for field_number in [
+			STAN_FIELD_NUMBER,
+			TERMINAL_ID_FIELD_NUMBER,
+			CURRENCY_CODE_FIELD_NUMBER,
+			SETTLEMENT_DATE_FIELD_NUMBER,
+		] {
+			if iso_msg.bmp.is_on(field_number) {
+				res_iso_msg.echo_from(iso_msg, &[field_number])?;
+			}
+		}
+
+		let on_chain_id = on_chain_id(iso_msg, origin);
+
+		// handle authorization request
+		match req_msg_type.as_str().try_into().expect("Validated above; qed") {
+			MTI::AuthorizationRequest =>
+				self.handle_authorization_request(&mut res_iso_msg, origin).await?,
+			MTI::FinancialRequest =>
+				self.handle_financial_request(&mut res_iso_msg, origin, on_chain_id, finality)
+					.await?,
+			MTI::FinancialAdvice => self.handle_financial_advice(&mut res_iso_msg).await?,
+			MTI::ReversalRequest =>
+				self.handle_reversal_request(&mut res_iso_msg, origin, on_chain_id, finality)
+					.await?,
+			MTI::NetworkManagementRequest =>
+				self.handle_register_account(&mut res_iso_msg, origin).await?,
+			MTI::ReconciliationRequest =>
+				self.handle_reconciliation_request(&mut res_iso_msg).await?,
+			_ => return Err(DomainError::ApiError("Unsupported message type".to_string())),
+		};
+
+		if let Ok(res_data) = res_iso_msg.assemble() {
+			return Ok((res_data, res_iso_msg));
+		}
+
+		Err(DomainError::ApiError("Failed to assemble new ISO message".to_string()))
 	}
 
 	/// Handle authorization request
 	///
-	/// Extracts necessary fields from the ISO message and performs authorization.
-	async fn handle_authorization_request(&self, iso_msg: &mut IsoMsg) -> Result<(), DomainError> {
+	/// Extracts necessary fields from the ISO message and performs authorization. Approved amount
+	/// is put on hold until it is completed by a financial advice or request.
+	async fn handle_authorization_request(
+		&self,
+		iso_msg: &mut IsoMsg,
+		origin: MessageOrigin,
+	) -> Result<(), DomainError> {
 		iso_msg.set("message_type", MTI::AuthorizationResponse.into())?;
 
 		// extract necessary fields from the ISO message
@@ -94,7 +396,10 @@ impl Iso8583MessageProcessor {
 		);
 
 		if let Ok(Some(bank_account)) = maybe_from_account {
-			let validation_result = self.validate_with_bank_account(iso_msg, &bank_account).await?;
+			let billing = self.billing_amount(iso_msg, bank_account.balance.currency);
+			let validation_result = self
+				.validate_with_bank_account(iso_msg, &bank_account, origin, &billing)
+				.await?;
 
 			// early return if not approved
 			if validation_result != ResponseCodes::Approved {
@@ -102,60 +407,53 @@ impl Iso8583MessageProcessor {
 				return Ok(());
 			}
 
-			let amount: u32 = iso_msg.bmp_child_value(4)?.trim().parse()?;
+			let (amount, conversion) = billing?;
 
-			let event_id = iso_msg.bmp_child_value(126);
+			let risk_result = self.assess_risk(iso_msg, &bank_account, amount).await?;
+			if risk_result != ResponseCodes::Approved {
+				iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, risk_result.into())?;
+				return Ok(());
+			}
+
+			set_billing_fields(iso_msg, amount, conversion)?;
 
-			// perform the transaction
-			let update_beneficiary_account =
-				BankAccountUpdate::Balance { amount, transaction_type: TransactionType::Credit };
+			let recipient_id = match maybe_recipient_account {
+				Ok(Some(recipient_account)) => Some(recipient_account.id),
+				_ => None,
+			};
+
+			let iso_msg_raw = iso_msg.assemble().expect("should be working");
 
+			// reserve the funds, they are moved only when the authorization is completed
 			match self
-				.bank_account_controller
-				.update(&bank_account.id, &update_beneficiary_account)
+				.hold_controller
+				.create(&HoldCreate {
+					id: uuid::Uuid::new_v4(),
+					from: bank_account.id,
+					to: recipient_id,
+					amount,
+					conversion,
+					nonce: bank_account.nonce,
+					iso_msg_raw,
+					expires_at: Utc::now() + self.hold_ttl,
+				})
 				.await
 			{
-				Ok(updated_bank_account) => {
-					info!(
-						"Transaction successful, updated bank account: {:?}",
-						updated_bank_account
-					);
+				Ok(hold) => {
+					info!("Authorization hold placed: {:?}", hold);
 
-					let iso_msg_raw = iso_msg.assemble().expect("should be working");
-					let mut recipient_id = None;
-
-					if let Ok(Some(recipient_account)) = maybe_recipient_account {
-						let update_recipient_account = BankAccountUpdate::Balance {
-							amount,
-							transaction_type: TransactionType::Debit,
-						};
-						self.bank_account_controller
-							.update(&recipient_account.id, &update_recipient_account)
-							.await?;
-						recipient_id = Some(recipient_account.id);
-					}
-
-					// Insert the transaction into the database
-					let transaction = self
-						.transaction_controller
-						.create(&TransactionCreate {
-							id: uuid::Uuid::new_v4(),
-							from: bank_account.id,
-							to: recipient_id,
-							amount,
-							transaction_type: TransactionType::Credit,
-							nonce: bank_account.nonce,
-							iso_msg_raw,
-							on_chain_id: event_id.ok(),
-						})
-						.await?;
-
-					// set the transaction hash in the ISO message
-					iso_msg.set_on(126, &transaction.hash)?;
+					// set the hold hash in the ISO message, completion refers to it
+					iso_msg.set_on(126, &hold.hash)?;
 					iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::Approved.into())?;
 				},
+				Err(DomainError::InsufficientFunds) => {
+					iso_msg.set_on(
+						RESPONSE_CODE_FIELD_NUMBER,
+						ResponseCodes::InsufficientFunds.into(),
+					)?;
+				},
 				Err(e) => {
-					log::error!("Transaction failed: {:?}", e);
+					log::error!("Authorization failed: {:?}", e);
 					iso_msg.set_on(
 						RESPONSE_CODE_FIELD_NUMBER,
 						ResponseCodes::InvalidTransaction.into(),
@@ -169,10 +467,272 @@ impl Iso8583MessageProcessor {
 		Ok(())
 	}
 
+	/// Handle financial request
+	///
+	/// Single-message purchase, unlike authorization it is only approved if it can be settled
+	/// with a known acquirer right away. Funds are posted immediately.
+	///
+	/// If private data refers to an authorization hold, the request completes it instead.
+	async fn handle_financial_request(
+		&self,
+		iso_msg: &mut IsoMsg,
+		origin: MessageOrigin,
+		on_chain_id: Option<String>,
+		finality: Option<&FinalityCreate>,
+	) -> Result<(), DomainError> {
+		iso_msg.set("message_type", MTI::FinancialResponse.into())?;
+
+		if let Some(hold) = self.find_hold(iso_msg).await? {
+			return self.capture_hold(iso_msg, &hold).await;
+		}
+
+		// extract necessary fields from the ISO message
+		let card_number = iso_msg.bmp_child_value(2)?;
+		let acquirer = iso_msg.bmp_child_value(32)?;
+
+		let (maybe_from_account, maybe_acquirer_account) = futures::join!(
+			self.bank_account_controller.find_by_card_number(&card_number),
+			self.bank_account_controller.find_by_card_number(&acquirer)
+		);
+
+		if let Ok(Some(bank_account)) = maybe_from_account {
+			// amount billed to the card, validated once and passed along
+			let billing = self.billing_amount(iso_msg, bank_account.balance.currency);
+			let validation_result = validate_financial_request(iso_msg, &billing)?;
+
+			// early return if not approved
+			if validation_result != ResponseCodes::Approved {
+				iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, validation_result.into())?;
+				return Ok(());
+			}
+
+			let acquirer_account = match maybe_acquirer_account {
+				Ok(Some(acquirer_account)) => acquirer_account,
+				_ => {
+					iso_msg.set_on(
+						RESPONSE_CODE_FIELD_NUMBER,
+						ResponseCodes::InvalidMerchant.into(),
+					)?;
+					return Ok(());
+				},
+			};
+
+			let validation_result = self
+				.validate_with_bank_account(iso_msg, &bank_account, origin, &billing)
+				.await?;
+
+			if validation_result != ResponseCodes::Approved {
+				iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, validation_result.into())?;
+				return Ok(());
+			}
+
+			let (amount, conversion) = billing?;
+
+			let risk_result = self.assess_risk(iso_msg, &bank_account, amount).await?;
+			if risk_result != ResponseCodes::Approved {
+				iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, risk_result.into())?;
+				return Ok(());
+			}
+
+			set_billing_fields(iso_msg, amount, conversion)?;
+
+			self.post_transfer(
+				iso_msg,
+				&bank_account,
+				Some(acquirer_account.id),
+				(amount, conversion),
+				on_chain_id,
+				finality,
+			)
+			.await?;
+		} else {
+			iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidCardNumber.into())?;
+		}
+
+		Ok(())
+	}
+
+	/// Handle financial advice
+	///
+	/// Completion of a previous authorization, private data must refer to its hold.
+	async fn handle_financial_advice(&self, iso_msg: &mut IsoMsg) -> Result<(), DomainError> {
+		iso_msg.set("message_type", MTI::FinancialAdviceResponse.into())?;
+
+		if let Some(hold) = self.find_hold(iso_msg).await? {
+			return self.capture_hold(iso_msg, &hold).await;
+		}
+
+		iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidTransaction.into())?;
+
+		Ok(())
+	}
+
+	/// Find authorization hold referred by the private data of the ISO message
+	///
+	/// First 64 characters of the private data are the hash of the hold.
+	async fn find_hold(&self, iso_msg: &IsoMsg) -> Result<Option<Hold>, DomainError> {
+		let private_data = iso_msg.bmp_child_value(126)?;
+		let private_data = private_data.trim_start_matches("0x");
+
+		if private_data.len() < 64 {
+			return Ok(None);
+		}
+
+		let (hold_hash, _) = private_data.split_at(64);
+
+		self.hold_controller.find_by_hash(hold_hash).await
+	}
+
+	/// Capture an authorization hold for the amount of the completion message
+	///
+	/// Amount can be smaller than the authorized one, the rest of the hold is released.
+	async fn capture_hold(&self, iso_msg: &mut IsoMsg, hold: &Hold) -> Result<(), DomainError> {
+		let card_number = iso_msg.bmp_child_value(2)?;
+
+		let bank_account =
+			match self.bank_account_controller.find_by_card_number(&card_number).await {
+				Ok(Some(bank_account)) if bank_account.id == hold.from => bank_account,
+				_ => {
+					iso_msg.set_on(
+						RESPONSE_CODE_FIELD_NUMBER,
+						ResponseCodes::InvalidCardNumber.into(),
+					)?;
+					return Ok(());
+				},
+			};
+
+		let transaction_timestamp = iso_msg.bmp_child_value(7)?;
+
+		if !utils::validate_timestamp(transaction_timestamp) || !hold.is_capturable(Utc::now()) {
+			iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidTransaction.into())?;
+			return Ok(());
+		}
+
+		// partial captures are converted with the rate of the authorization
+		let (amount, conversion) = match partial_amount(iso_msg, hold.amount, hold.conversion) {
+			Ok((amount, conversion)) if !amount.is_zero() && amount <= hold.amount =>
+				(amount, conversion),
+			_ => {
+				iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidAmount.into())?;
+				return Ok(());
+			},
+		};
+		set_billing_fields(iso_msg, amount, conversion)?;
+
+		let iso_msg_raw = iso_msg.assemble().expect("should be working");
+
+		match self
+			.hold_controller
+			.capture(
+				&hold.id,
+				&TransactionCreate {
+					id: uuid::Uuid::new_v4(),
+					from: bank_account.id,
+					to: hold.to,
+					amount,
+					conversion,
+					transaction_type: TransactionType::Credit,
+					nonce: bank_account.nonce,
+					iso_msg_raw,
+					on_chain_id: None,
+					finality: None,
+				},
+			)
+			.await
+		{
+			Ok(transaction) => {
+				info!("Authorization hold captured: {:?}", transaction);
+				self.events.publish(TransactionEventKind::Posted, &transaction);
+
+				iso_msg.set_on(126, &transaction.hash)?;
+				iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::Approved.into())?;
+			},
+			Err(e) => {
+				log::error!("Capture failed: {:?}", e);
+				iso_msg
+					.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidTransaction.into())?;
+			},
+		}
+
+		Ok(())
+	}
+
+	/// Release all expired authorization holds
+	///
+	/// Returns the number of released holds.
+	pub async fn expire_holds(&self) -> Result<usize, DomainError> {
+		let expired = self.hold_controller.expire(Utc::now()).await?;
+
+		for hold in expired.iter() {
+			info!("Authorization hold expired: {:?}", hold.hash);
+		}
+
+		Ok(expired.len())
+	}
+
+	/// Debit the payer, credit the recipient and record the transaction atomically
+	///
+	/// Payer is billed `amount`, the recipient gets the amount in the currency of the transaction
+	/// if it is converted. Sets the transaction hash and the response code in the ISO message.
+	async fn post_transfer(
+		&self,
+		iso_msg: &mut IsoMsg,
+		bank_account: &BankAccount,
+		recipient_id: Option<uuid::Uuid>,
+		(amount, conversion): (Money, Option<Conversion>),
+		on_chain_id: Option<String>,
+		finality: Option<&FinalityCreate>,
+	) -> Result<(), DomainError> {
+		let iso_msg_raw = iso_msg.assemble().expect("should be working");
+
+		match self
+			.transaction_controller
+			.transfer(&TransactionCreate {
+				id: uuid::Uuid::new_v4(),
+				from: bank_account.id,
+				to: recipient_id,
+				amount,
+				conversion,
+				transaction_type: TransactionType::Credit,
+				nonce: bank_account.nonce,
+				iso_msg_raw,
+				on_chain_id,
+				finality: finality.map(approved),
+			})
+			.await
+		{
+			Ok(transaction) => {
+				info!("Transaction successful: {:?}", transaction);
+				self.events.publish(TransactionEventKind::Posted, &transaction);
+
+				// set the transaction hash in the ISO message
+				iso_msg.set_on(126, &transaction.hash)?;
+				iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::Approved.into())?;
+			},
+			Err(DomainError::InsufficientFunds) => {
+				iso_msg
+					.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InsufficientFunds.into())?;
+			},
+			Err(e) => {
+				log::error!("Transaction failed: {:?}", e);
+				iso_msg
+					.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidTransaction.into())?;
+			},
+		}
+
+		Ok(())
+	}
+
 	/// Handle reversal request
 	///
 	/// Extracts necessary fields from the ISO message and performs reversal.
-	async fn handle_reversal_request(&self, iso_msg: &mut IsoMsg) -> Result<(), DomainError> {
+	async fn handle_reversal_request(
+		&self,
+		iso_msg: &mut IsoMsg,
+		origin: MessageOrigin,
+		on_chain_id: Option<String>,
+		finality: Option<&FinalityCreate>,
+	) -> Result<(), DomainError> {
 		iso_msg.set("message_type", MTI::ReversalResponse.into())?;
 
 		// extract transaction hash from the ISO message
@@ -180,14 +740,21 @@ impl Iso8583MessageProcessor {
 		let private_data = iso_msg.bmp_child_value(126)?;
 
 		// if private_data is not at least 64 characters long, return error
-		if private_data.trim_start_matches("0x").len() < 64 {
+		let Some(tx_hash) = private_data.trim_start_matches("0x").get(..64) else {
 			iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidTransaction.into())?;
 			return Ok(());
-		}
+		};
 
-		let (tx_hash, _) = private_data.split_at(64);
+		let card_number = iso_msg.bmp_child_value(2)?;
+		let Ok(Some(cardholder)) =
+			self.bank_account_controller.find_by_card_number(&card_number).await
+		else {
+			iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidCardNumber.into())?;
+			return Ok(());
+		};
 
-		let validation_result = self.validate(iso_msg).await?;
+		// refunds don't spend the funds of the cardholder, only the card is validated
+		let validation_result = self.validate_card(iso_msg, &cardholder, origin).await?;
 
 		// early return if not approved
 		if validation_result != ResponseCodes::Approved {
@@ -204,43 +771,124 @@ impl Iso8583MessageProcessor {
 				return Ok(());
 			}
 
-			// updates to bank accounts
-			let update_account = BankAccountUpdate::Balance {
-				amount: transaction.amount,
-				transaction_type: TransactionType::Debit,
+			// refunds are paid back to the cardholder by the merchant of field 32 that was paid
+			let merchant_id = match transaction.to {
+				Some(_) => self
+					.bank_account_controller
+					.find_by_card_number(&iso_msg.bmp_child_value(32)?)
+					.await?
+					.map(|merchant| merchant.id),
+				None => None,
 			};
+			if transaction.from != cardholder.id || merchant_id != transaction.to {
+				debug!(
+					"Transaction {:?} is not between the requesting accounts",
+					&transaction.hash
+				);
+				iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::NotPermitted.into())?;
+				return Ok(());
+			}
+
+			// amount to refund, partial reversals leave the rest of the transaction in place and
+			// are converted with the original rate
+			let (amount, conversion) =
+				match partial_amount(iso_msg, transaction.amount, transaction.conversion) {
+					Ok((amount, conversion))
+						if !amount.is_zero() && amount <= transaction.refundable_amount() =>
+						(amount, conversion),
+					result => {
+						debug!("Invalid reversal amount {:?} for {:?}", result, &transaction.hash);
+						iso_msg.set_on(
+							RESPONSE_CODE_FIELD_NUMBER,
+							ResponseCodes::InvalidAmount.into(),
+						)?;
+						return Ok(());
+					},
+				};
+			set_billing_fields(iso_msg, amount, conversion)?;
+
+			let iso_msg_raw = iso_msg.assemble().expect("should be working");
 
-			if self
-				.bank_account_controller
-				.update(&transaction.from, &update_account)
+			// move the funds back as a refund linked to the original transaction
+			match self
+				.transaction_controller
+				.revert(
+					&transaction.id,
+					&TransactionCreate {
+						id: uuid::Uuid::new_v4(),
+						from: transaction.from,
+						to: transaction.to,
+						amount,
+						conversion,
+						transaction_type: TransactionType::from(transaction.transaction_type)
+							.inverse(),
+						nonce: 0,
+						iso_msg_raw,
+						on_chain_id,
+						finality: finality.map(approved),
+					},
+				)
 				.await
-				.is_ok()
 			{
-				if let Some(beneficiary_id) = transaction.to {
-					let update_recipient_account = BankAccountUpdate::Balance {
-						amount: transaction.amount,
-						transaction_type: TransactionType::Credit,
-					};
-					let updated_bank_account = self
-						.bank_account_controller
-						.update(&beneficiary_id, &update_recipient_account)
-						.await?;
-
-					// add `to` accountid` to the ISO message
+				Ok(refund) => {
+					self.events.publish(TransactionEventKind::Reversed, &refund);
+
+					if let Some(beneficiary_id) = transaction.to {
+						let beneficiary_account =
+							self.bank_account_controller.find_by_id(&beneficiary_id).await?.ok_or(
+								DomainError::NotFound("Bank account not found".to_string()),
+							)?;
+
+						// add `to` accountid` to the ISO message
+						iso_msg.set_on(
+							126,
+							&beneficiary_account.account_id.unwrap_or(PALLET_ACCOUNT.to_string()),
+						)?;
+					}
+
+					log::info!(
+						"Transaction reversed: {:?} by {:?}",
+						&transaction.hash,
+						&refund.hash
+					);
+					iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::Approved.into())?;
+				},
+				// the merchant has to pay the refund back
+				Err(DomainError::InsufficientFunds) => {
 					iso_msg.set_on(
-						126,
-						&updated_bank_account.account_id.unwrap_or(PALLET_ACCOUNT.to_string()),
+						RESPONSE_CODE_FIELD_NUMBER,
+						ResponseCodes::InsufficientFunds.into(),
 					)?;
-				}
-
-				// Flags the transaction as reversed
-				self.transaction_controller.update(&transaction.id).await?;
+				},
+				Err(e) => {
+					log::error!("Reversal failed: {:?}", e);
+					iso_msg.set_on(
+						RESPONSE_CODE_FIELD_NUMBER,
+						ResponseCodes::InvalidTransaction.into(),
+					)?;
+				},
+			}
+		} else if let Ok(Some(hold)) = self.hold_controller.find_by_hash(tx_hash).await {
+			// reversal of an authorization that hasn't been completed yet, only the cardholder
+			// the funds are held on can release them
+			if hold.from != cardholder.id {
+				debug!("Hold {:?} is not held on the cardholder", &hold.hash);
+				iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::NotPermitted.into())?;
+				return Ok(());
+			}
 
-				log::info!("Transaction reversed: {:?}", &transaction.hash.as_bytes());
-				iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::Approved.into())?;
-			} else {
-				iso_msg
-					.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidTransaction.into())?;
+			match self.hold_controller.release(&hold.id).await {
+				Ok(_) => {
+					log::info!("Authorization hold released: {:?}", &hold.hash);
+					iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::Approved.into())?;
+				},
+				Err(e) => {
+					log::error!("Reversal failed: {:?}", e);
+					iso_msg.set_on(
+						RESPONSE_CODE_FIELD_NUMBER,
+						ResponseCodes::InvalidTransaction.into(),
+					)?;
+				},
 			}
 		} else {
 			iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidTransaction.into())?;
@@ -256,19 +904,26 @@ impl Iso8583MessageProcessor {
 	/// This is a special request that is used to register the on-chain `AccountId` in the database.
 	///
 	/// The format of the private data is: `0x<AccountId:64>` (hex-encoded ss58 address)
-	async fn handle_register_account(&self, iso_msg: &mut IsoMsg) -> Result<(), DomainError> {
+	async fn handle_register_account(
+		&self,
+		iso_msg: &mut IsoMsg,
+		origin: MessageOrigin,
+	) -> Result<(), DomainError> {
 		iso_msg.set("message_type", MTI::NetworkManagementResponse.into())?;
 
-		// extract `AccountId` from the ISO message
+		// extract `AccountId` from the ISO message, the first 64 hex characters
 		let private_data = iso_msg.bmp_child_value(126)?;
+		let account_id = match private_data.trim_start_matches("0x").get(..64) {
+			Some(account_id) if account_id.bytes().all(|b| b.is_ascii_hexdigit()) =>
+				account_id.to_string(),
+			_ => {
+				iso_msg
+					.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidTransaction.into())?;
+				return Ok(());
+			},
+		};
 
-		// if private_data is not at least 64 characters long, return error
-		if private_data.trim_start_matches("0x").len() < 64 {
-			iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidTransaction.into())?;
-			return Ok(());
-		}
-
-		let validation_result = self.validate(iso_msg).await?;
+		let validation_result = self.validate(iso_msg, origin).await?;
 
 		// early return if not approved
 		if validation_result != ResponseCodes::Approved {
@@ -278,11 +933,9 @@ impl Iso8583MessageProcessor {
 
 		let card_number = iso_msg.bmp_child_value(2)?;
 
-		let (account_id, _) = private_data.trim_start_matches("0x").split_at(64);
-
 		// revert if accoun_id is already registered
 		if let Ok(Some(_bank_account)) =
-			self.bank_account_controller.find_by_account_id(account_id).await
+			self.bank_account_controller.find_by_account_id(&account_id).await
 		{
 			iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::DoNotHonor.into())?;
 			return Ok(());
@@ -297,44 +950,199 @@ impl Iso8583MessageProcessor {
 		self.bank_account_controller
 			.update(
 				&bank_account.id,
-				&BankAccountUpdate::Info { account_id: Some(account_id.to_string()) },
+				&BankAccountUpdate::Info { account_id: Some(account_id.clone()) },
 			)
 			.await?;
 
-		iso_msg.set_on(126, account_id)?;
+		iso_msg.set_on(126, &account_id)?;
 		iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::Approved.into())?;
 
 		Ok(())
 	}
 
+	/// Handle reconciliation request
+	///
+	/// Compares the totals of the acquirer (field 32) with ours: the ones of the batches settled
+	/// on the settlement date (field 15) if it is set, otherwise the ones of the transactions
+	/// that are not settled yet. Our totals are returned in the same fields and the settlement
+	/// code (field 66) tells whether they are in balance.
+	async fn handle_reconciliation_request(&self, iso_msg: &mut IsoMsg) -> Result<(), DomainError> {
+		iso_msg.set("message_type", MTI::ReconciliationResponse.into())?;
+
+		let acquirer = iso_msg.bmp_child_value(32)?;
+
+		let Some(acquirer_account) =
+			self.bank_account_controller.find_by_card_number(&acquirer).await?
+		else {
+			iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidMerchant.into())?;
+			return Ok(());
+		};
+
+		let currency = acquirer_account.balance.currency;
+
+		// totals are in the currency of the acquirer
+		if message_currency(iso_msg, currency) != Ok(currency) {
+			iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidAmount.into())?;
+			return Ok(());
+		}
+
+		let Ok((acquirer_totals, acquirer_net)) = reconciliation_totals(iso_msg, currency) else {
+			iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::InvalidTransaction.into())?;
+			return Ok(());
+		};
+
+		let totals = match optional_field(iso_msg, SETTLEMENT_DATE_FIELD_NUMBER) {
+			Some(settlement_date) => self
+				.settlement_controller
+				.find_by_acquirer(&acquirer_account.id)
+				.await?
+				.iter()
+				.filter(|batch| batch.settlement_date.format("%m%d").to_string() == settlement_date)
+				.try_fold(SettlementTotals::zero(currency), |totals, batch| {
+					totals.checked_add(&batch.totals)
+				})?,
+			None => self.settlement_controller.open_totals(&acquirer_account.id).await?,
+		};
+
+		let settlement_code =
+			if acquirer_totals == totals && acquirer_net == totals.net_minor_units() {
+				SettlementCodes::InBalance
+			} else {
+				log::info!(
+					"Reconciliation of acquirer {} is out of balance, theirs: {:?}, ours: {:?}",
+					acquirer_account.id,
+					acquirer_totals,
+					totals
+				);
+				SettlementCodes::OutOfBalance
+			};
+
+		set_reconciliation_totals(iso_msg, &totals)?;
+		iso_msg.set_on(SETTLEMENT_CODE_FIELD_NUMBER, settlement_code.into())?;
+		iso_msg.set_on(RESPONSE_CODE_FIELD_NUMBER, ResponseCodes::Approved.into())?;
+
+		Ok(())
+	}
+
+	/// Amount of the message in the currency of the bank account and its conversion
+	///
+	/// Amounts in another currency (field 49) are converted with the latest rate, the conversion
+	/// is `None` if the currencies match.
+	fn billing_amount(
+		&self,
+		iso_msg: &IsoMsg,
+		currency: Iso4217,
+	) -> Result<(Money, Option<Conversion>), DomainError> {
+		let amount = message_amount(iso_msg, currency)?;
+
+		if amount.currency == currency {
+			return Ok((amount, None));
+		}
+
+		let (rate, rate_version) =
+			self.rates
+				.rate(amount.currency, currency)
+				.ok_or(DomainError::BadRequest(format!(
+					"No conversion rate from {} to {}",
+					amount.currency, currency
+				)))?;
+
+		Ok((rate.convert(amount, currency)?, Some(Conversion { amount, rate, rate_version })))
+	}
+
 	/// Validate Iso8583 message
 	///
 	/// Does some sanity checks:
 	///
 	/// - Timestamp should be valid
+	/// - Card should be active
 	/// - Card expiration date should match and be in the future
-	/// - CVV should match
-	/// - Amount should be less than or equal to the balance
+	/// - CVV should match, unless it is locked out after too many failed verifications
+	/// - Amount should be convertible to the currency of the bank account and at most its available
+	///   balance
 	///
 	/// Returns the response code according to ISO-8583 specification
-	async fn validate(&self, iso_msg: &IsoMsg) -> Result<ResponseCodes, DomainError> {
+	async fn validate(
+		&self,
+		iso_msg: &IsoMsg,
+		origin: MessageOrigin,
+	) -> Result<ResponseCodes, DomainError> {
 		// extract necessary fields from the ISO message
 		let card_number = iso_msg.bmp_child_value(2)?;
 
 		if let Ok(Some(bank_account)) =
 			self.bank_account_controller.find_by_card_number(&card_number).await
 		{
-			self.validate_with_bank_account(iso_msg, &bank_account).await
+			let billing = self.billing_amount(iso_msg, bank_account.balance.currency);
+			self.validate_with_bank_account(iso_msg, &bank_account, origin, &billing).await
 		} else {
 			Ok(ResponseCodes::InvalidCardNumber)
 		}
 	}
 
-	/// Same as [`self.validate`] but with already queried [`BankAccount`]
+	/// Assess a payment of the bank account with the risk engine
+	///
+	/// `amount` is the amount billed to the card. Returns the response code according to
+	/// ISO-8583 specification
+	async fn assess_risk(
+		&self,
+		iso_msg: &IsoMsg,
+		bank_account: &BankAccount,
+		amount: Money,
+	) -> Result<ResponseCodes, DomainError> {
+		self.risk
+			.assess(&RiskRequest {
+				bank_account,
+				amount,
+				acquirer: iso_msg.bmp_child_value(32)?,
+				terminal: optional_field(iso_msg, TERMINAL_ID_FIELD_NUMBER),
+				now: Utc::now(),
+			})
+			.await
+	}
+
+	/// Same as [`self.validate`] but with already queried [`BankAccount`] and billing amount
+	///
+	/// The card is validated with [`Self::validate_card`] first, then the amount against the
+	/// available balance.
 	async fn validate_with_bank_account(
 		&self,
 		iso_msg: &IsoMsg,
 		bank_account: &BankAccount,
+		origin: MessageOrigin,
+		billing: &Result<(Money, Option<Conversion>), DomainError>,
+	) -> Result<ResponseCodes, DomainError> {
+		let validation_result = self.validate_card(iso_msg, bank_account, origin).await?;
+		if validation_result != ResponseCodes::Approved {
+			return Ok(validation_result);
+		}
+
+		let amount = match billing {
+			Ok((amount, _)) => *amount,
+			Err(e) => {
+				log::info!("Invalid amount: {}", e);
+				return Ok(ResponseCodes::InvalidAmount);
+			},
+		};
+
+		// validate the amount against the funds that are not on hold
+		if amount > bank_account.available_balance {
+			return Ok(ResponseCodes::InsufficientFunds);
+		}
+
+		Ok(ResponseCodes::Approved)
+	}
+
+	/// Validate the transmission time, status, expiration date and CVV of the card
+	///
+	/// CVV is only verified for external messages, on-chain events are authenticated by the
+	/// signature of the account owner. Verifications are recorded for the CVV lockout of the risk
+	/// engine.
+	async fn validate_card(
+		&self,
+		iso_msg: &IsoMsg,
+		bank_account: &BankAccount,
+		origin: MessageOrigin,
 	) -> Result<ResponseCodes, DomainError> {
 		// format is: "{}D{}C{}", card_number, exp_date, cvv
 		let track_2_data = iso_msg.bmp_child_value(35)?;
@@ -343,13 +1151,11 @@ impl Iso8583MessageProcessor {
 		let remainder = parts.next().unwrap_or("");
 		let mut parts = remainder.split('C');
 		let card_expiration = parts.next().unwrap_or("");
-		let cvv = parts.next().unwrap();
+		let cvv = parts.next().unwrap_or("");
 
 		// %m%d%H%M%S format
 		let transaction_timestamp = iso_msg.bmp_child_value(7)?;
 
-		let amount: u32 = iso_msg.bmp_child_value(4)?.trim().parse()?;
-
 		let now = Utc::now();
 
 		// validate the transaction timestamp
@@ -359,6 +1165,14 @@ impl Iso8583MessageProcessor {
 			return Ok(ResponseCodes::InvalidTransaction);
 		}
 
+		// validate the status of the card
+		match bank_account.card_status {
+			CardStatus::Active => {},
+			CardStatus::Lost => return Ok(ResponseCodes::LostCard),
+			CardStatus::Stolen => return Ok(ResponseCodes::StolenCard),
+			CardStatus::Blocked | CardStatus::Closed => return Ok(ResponseCodes::RestrictedCard),
+		}
+
 		// validate the card expiration date
 		if card_expiration != bank_account.card_expiration_date.format("%m%y").to_string() ||
 			bank_account.card_expiration_date <= now
@@ -366,20 +1180,270 @@ impl Iso8583MessageProcessor {
 			return Ok(ResponseCodes::ExpiredCard);
 		}
 
-		// validate the CVV
-		if cvv != bank_account.card_cvv {
-			return Ok(ResponseCodes::DoNotHonor);
-		}
+		// validate the CVV against its salted hash, unless too many verifications failed
+		if origin == MessageOrigin::External {
+			if self.risk.is_locked_out(&bank_account.id, now).await? {
+				return Ok(ResponseCodes::TriesExceeded);
+			}
 
-		// validate the amount
-		if amount > bank_account.balance {
-			return Ok(ResponseCodes::InsufficientFunds);
+			let verified = self.vault.verify_cvv(cvv, &bank_account.card_cvv_hash).await?;
+			self.risk.record_cvv(&bank_account.id, verified, now).await?;
+
+			if !verified {
+				return Ok(ResponseCodes::DoNotHonor);
+			}
 		}
 
 		Ok(ResponseCodes::Approved)
 	}
 }
 
+/// Validate financial request specific fields
+///
+/// - Processing code should be a purchase
+/// - Billing amount should be greater than zero, i.e. convertible to the currency of the bank
+///   account
+fn validate_financial_request(
+	iso_msg: &IsoMsg,
+	billing: &Result<(Money, Option<Conversion>), DomainError>,
+) -> Result<ResponseCodes, DomainError> {
+	let processing_code = iso_msg.bmp_child_value(3)?;
+
+	// first two digits of the processing code are the transaction type
+	if !processing_code.starts_with(PURCHASE_TRANSACTION_TYPE) {
+		return Ok(ResponseCodes::InvalidTransaction);
+	}
+
+	match billing {
+		Ok((amount, _)) if !amount.is_zero() => Ok(ResponseCodes::Approved),
+		_ => Ok(ResponseCodes::InvalidAmount),
+	}
+}
+
+/// Idempotency key of a request, `None` if its retransmissions can't be recognized
+///
+/// Messages composed from on-chain events are identified by the event id, external ones by the
+/// MTI, the terminal (the acquirer if there is none), the STAN and the transmission time. The key
+/// is hashed, since acquirers are identified by their card numbers.
+fn idempotency_key(iso_msg: &IsoMsg, origin: MessageOrigin) -> Option<String> {
+	let key = match origin {
+		MessageOrigin::OnChain =>
+			return Some(event_idempotency_key(&on_chain_id(iso_msg, origin)?)),
+		MessageOrigin::External => {
+			let mti = iso_msg.get_field_value(&"message_type".to_string()).ok()?;
+			let terminal = optional_field(iso_msg, TERMINAL_ID_FIELD_NUMBER)
+				.or_else(|| optional_field(iso_msg, 32))?;
+			let stan = optional_field(iso_msg, STAN_FIELD_NUMBER)?;
+			let transmission_time = optional_field(iso_msg, 7)?;
+
+			format!("message:{}:{}:{}:{}", mti, terminal, stan, transmission_time)
+		},
+	};
+
+	Some(format!("{:x}", Sha256::digest(key.as_bytes())))
+}
+
+/// Copy of the message without the given fields
+///
+/// Fields can't be unset, so the message is composed again with the other fields.
+fn without_fields(
+	spec: &'static Spec,
+	iso_msg: &IsoMsg,
+	field_numbers: &[u32],
+) -> Result<IsoMsg, DomainError> {
+	let mti = iso_msg.get_field_value(&"message_type".to_string())?;
+	let mut copy = new_msg(spec, spec.get_message_from_header(&mti)?);
+	copy.set("message_type", &mti)?;
+
+	// positions 1 and 65 indicate the next bitmaps, they are set with the fields after them
+	let echoed_field_numbers: Vec<u32> = (2..=192)
+		.filter(|field_number| *field_number != 65)
+		.filter(|field_number| iso_msg.bmp.is_on(*field_number))
+		.filter(|field_number| !field_numbers.contains(field_number))
+		.collect();
+	copy.echo_from(iso_msg, &echoed_field_numbers)?;
+
+	Ok(copy)
+}
+
+/// Settlement totals and net position of a reconciliation message
+///
+/// Numbers of credits and debits are in fields 74 and 76, their amounts in fields 86 and 88 and
+/// the net settlement amount in field 97, `C` or `D` followed by the amount.
+fn reconciliation_totals(
+	iso_msg: &IsoMsg,
+	currency: Iso4217,
+) -> Result<(SettlementTotals, i64), DomainError> {
+	let number = |field_number| -> Result<u32, DomainError> {
+		let value = iso_msg.bmp_child_value(field_number)?;
+		value
+			.parse()
+			.map_err(|_| DomainError::BadRequest(format!("Invalid number: {}", value)))
+	};
+
+	let totals = SettlementTotals {
+		credits_number: number(74)?,
+		credits_amount: Money::parse(&iso_msg.bmp_child_value(86)?, currency)?,
+		debits_number: number(76)?,
+		debits_amount: Money::parse(&iso_msg.bmp_child_value(88)?, currency)?,
+	};
+
+	let net_amount = iso_msg.bmp_child_value(97)?;
+	let net = match net_amount.split_at_checked(1) {
+		Some(("C", amount)) => Money::parse(amount, currency)?.minor_units,
+		Some(("D", amount)) => -Money::parse(amount, currency)?.minor_units,
+		_ =>
+			return Err(DomainError::BadRequest(format!(
+				"Invalid net settlement amount: {}",
+				net_amount
+			))),
+	};
+
+	Ok((totals, net))
+}
+
+/// Set the settlement totals of a reconciliation response, see [`reconciliation_totals`]
+fn set_reconciliation_totals(
+	iso_msg: &mut IsoMsg,
+	totals: &SettlementTotals,
+) -> Result<(), DomainError> {
+	let net = totals.net_minor_units();
+
+	iso_msg.set_on(74, &format!("{:010}", totals.credits_number))?;
+	iso_msg.set_on(76, &format!("{:010}", totals.debits_number))?;
+	iso_msg.set_on(86, &format!("{:016}", totals.credits_amount.minor_units))?;
+	iso_msg.set_on(88, &format!("{:016}", totals.debits_amount.minor_units))?;
+	iso_msg.set_on(97, &format!("{}{:016}", if net < 0 { 'D' } else { 'C' }, net.abs()))?;
+
+	Ok(())
+}
+
+/// Amount of the message (field 4) in its currency
+///
+/// Currency code (field 49) is optional, the amount is in the given currency if it is not set.
+fn message_amount(iso_msg: &IsoMsg, currency: Iso4217) -> Result<Money, DomainError> {
+	Money::parse(&iso_msg.bmp_child_value(4)?, message_currency(iso_msg, currency)?)
+}
+
+/// Currency of the message (field 49), the given one if it is not set
+fn message_currency(iso_msg: &IsoMsg, currency: Iso4217) -> Result<Iso4217, DomainError> {
+	match optional_field(iso_msg, CURRENCY_CODE_FIELD_NUMBER) {
+		Some(code) => Iso4217::from_numeric(code.trim())
+			.ok_or(DomainError::BadRequest(format!("Unsupported currency code: {}", code))),
+		None => Ok(currency),
+	}
+}
+
+/// Amount of the message that is a part of a previous amount, e.g. of a capture or reversal
+///
+/// `billing` is the previous amount in the currency of the bank account. If it was converted,
+/// the message is in the currency of the transaction and converted with the original rate.
+fn partial_amount(
+	iso_msg: &IsoMsg,
+	billing: Money,
+	conversion: Option<Conversion>,
+) -> Result<(Money, Option<Conversion>), DomainError> {
+	let Some(conversion) = conversion else {
+		let amount = message_amount(iso_msg, billing.currency)?;
+
+		if amount.currency != billing.currency {
+			return Err(DomainError::BadRequest(format!(
+				"Amount in {} doesn't match the currency {}",
+				amount.currency, billing.currency
+			)));
+		}
+
+		return Ok((amount, None));
+	};
+
+	let amount = message_amount(iso_msg, conversion.amount.currency)?;
+
+	Ok((conversion.billing_part(billing, amount)?, Some(Conversion { amount, ..conversion })))
+}
+
+/// Set the billing amount and conversion rate fields of a converted amount
+///
+/// Billing amount is padded to the length of the amount field.
+fn set_billing_fields(
+	iso_msg: &mut IsoMsg,
+	billing: Money,
+	conversion: Option<Conversion>,
+) -> Result<(), DomainError> {
+	if let Some(conversion) = conversion {
+		let width = iso_msg.bmp_child_value(4)?.len();
+
+		iso_msg.set_on(
+			BILLING_AMOUNT_FIELD_NUMBER,
+			&format!("{:0width$}", billing.minor_units, width = width),
+		)?;
+		iso_msg.set_on(CONVERSION_RATE_FIELD_NUMBER, &conversion.rate.to_field())?;
+	}
+
+	Ok(())
+}
+
+/// Idempotency key of the message composed from an on-chain event
+fn event_idempotency_key(event_id: &str) -> String {
+	format!("{:x}", Sha256::digest(format!("event:{}", event_id).as_bytes()))
+}
+
+/// Id of the on-chain event the message is composed from, stored with the transaction
+fn on_chain_id(iso_msg: &IsoMsg, origin: MessageOrigin) -> Option<String> {
+	match origin {
+		MessageOrigin::OnChain => optional_field(iso_msg, EVENT_ID_FIELD_NUMBER),
+		MessageOrigin::External => None,
+	}
+}
+
+/// Finality of an approved on-chain event, enqueued with the ledger change
+fn approved(finality: &FinalityCreate) -> FinalityCreate {
+	let response_code: &str = ResponseCodes::Approved.into();
+
+	FinalityCreate { response_code: response_code.to_string(), ..finality.clone() }
+}
+
+/// Compose the encoded ISO-8583 message with the MTI and the field values
+///
+/// Every value is checked against the format of its field first, the codec panics on values it
+/// can't encode and values of the wrong length would shift the fields after them.
+fn compose_iso_msg(
+	spec: &'static Spec,
+	mti: &str,
+	fields: &BTreeMap<u32, String>,
+) -> Result<Vec<u8>, DomainError> {
+	let segment = spec
+		.get_message_from_header(mti)
+		.map_err(|_| DomainError::BadRequest(format!("Unknown MTI {}", mti)))?;
+
+	let mut iso_msg = new_msg(spec, segment);
+	iso_msg.set("message_type", mti)?;
+
+	for (position, value) in fields {
+		let format = SpecLoader::field_format(spec, mti, *position)
+			.ok_or_else(|| DomainError::BadRequest(format!("Unknown field {}", position)))?;
+
+		// transmission date and time, MMDDhhmmss
+		let is_valid = format.accepts(value) &&
+			(*position != 7 || (value.len() == 10 && value.bytes().all(|b| b.is_ascii_digit())));
+		if !is_valid {
+			return Err(DomainError::BadRequest(format!("Invalid field {}", position)));
+		}
+
+		iso_msg.set_on(*position, value)?;
+	}
+
+	Ok(iso_msg.assemble()?)
+}
+
+/// Value of a field that is not required by the spec, `None` if it is not set
+fn optional_field(iso_msg: &IsoMsg, field_number: u32) -> Option<String> {
+	if !iso_msg.bmp.is_on(field_number) {
+		return None;
+	}
+
+	iso_msg.bmp_child_value(field_number).ok()
+}
+
 /// Utility functions
 mod utils {
 	use chrono::Datelike;
@@ -388,6 +1452,10 @@ mod utils {
 	/// MMDDHHMMSS format, parse it
 	pub(crate) fn validate_timestamp(timestamp: String) -> bool {
 		// %m%d%H%M%S format
+		if timestamp.len() != 10 || !timestamp.bytes().all(|b| b.is_ascii_digit()) {
+			return false;
+		}
+
 		let (mo, rest) = timestamp.split_at(2);
 		let (dd, rest) = rest.split_at(2);
 		let (_hh, rest) = rest.split_at(2);
@@ -397,6 +1465,6 @@ mod utils {
 		// we allow 30 seconds of difference
 		let now = chrono::Utc::now();
 
-		timestamp.len() == 10 && Ok(now.day()) == dd.parse() && Ok(now.month()) == mo.parse()
+		Ok(now.day()) == dd.parse() && Ok(now.month()) == mo.parse()
 	}
 }
@@ -0,0 +1,219 @@
+//! Reconciliation of the ledger with the on-chain balances
+//!
+//! The offchain worker of the chain syncs the balances of the registered bank accounts into
+//! `system.account` periodically, so both should match once it caught up. The reconciler walks
+//! the bank accounts with an on-chain account, fetches their on-chain balances at the same block
+//! and reports the ones that don't match.
+//!
+//! Discrepancies are only reported: the runtime has no `Balances` dispatchables, so the on-chain
+//! balances can't be corrected without overwriting the whole account storage.
+
+use std::{process::Stdio, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use op_core::{
+	bank_account::{models::BankAccount, traits::BankAccountTrait},
+	money::Money,
+};
+use serde::Serialize;
+use subxt::{utils::AccountId32, OnlineClient, SubstrateConfig};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use super::watcher::iso_8583_chain;
+
+/// Bank account whose on-chain balance doesn't match the ledger
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Discrepancy {
+	/// Unique identifier of the bank account
+	pub bank_account_id: Uuid,
+	/// On-chain account of the bank account, hex encoded
+	pub account_id: String,
+	/// Ledger balance of the bank account
+	pub ledger_balance: Money,
+	/// Ledger balance in on-chain units, `None` if it can't be represented on-chain
+	pub expected_on_chain: Option<u128>,
+	/// Free balance of the on-chain account, `None` if the account doesn't exist on-chain
+	pub on_chain_balance: Option<u128>,
+}
+
+/// Result of a reconciliation of the ledger with the on-chain balances
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReconciliationReport {
+	/// Time the reconciliation is run
+	pub checked_at: DateTime<Utc>,
+	/// Block the on-chain balances are read at
+	pub block_hash: String,
+	/// Decimals of the on-chain amounts per major unit of the currency
+	pub chain_decimals: u32,
+	/// Number of bank accounts checked
+	pub bank_accounts: usize,
+	/// Bank accounts whose balances don't match
+	pub discrepancies: Vec<Discrepancy>,
+}
+
+impl ReconciliationReport {
+	/// Compare the ledger balances of the bank accounts with their on-chain balances
+	pub fn new(
+		block_hash: String,
+		chain_decimals: u32,
+		balances: &[(BankAccount, Option<u128>)],
+	) -> Self {
+		Self {
+			checked_at: Utc::now(),
+			block_hash,
+			chain_decimals,
+			bank_accounts: balances.len(),
+			discrepancies: balances
+				.iter()
+				.filter_map(|(bank_account, on_chain_balance)| {
+					compare(bank_account, *on_chain_balance, chain_decimals)
+				})
+				.collect(),
+		}
+	}
+
+	/// Whether every on-chain balance matches the ledger
+	pub fn is_consistent(&self) -> bool {
+		self.discrepancies.is_empty()
+	}
+}
+
+/// Compare the ledger balance of a registered bank account with its on-chain balance
+pub fn compare(
+	bank_account: &BankAccount,
+	on_chain_balance: Option<u128>,
+	chain_decimals: u32,
+) -> Option<Discrepancy> {
+	let expected_on_chain = bank_account.balance.to_chain_units(chain_decimals).ok();
+
+	if expected_on_chain.is_some() && expected_on_chain == on_chain_balance {
+		return None;
+	}
+
+	Some(Discrepancy {
+		bank_account_id: bank_account.id,
+		account_id: bank_account.account_id.clone().unwrap_or_default(),
+		ledger_balance: bank_account.balance,
+		expected_on_chain,
+		on_chain_balance,
+	})
+}
+
+/// Reconciler of the ledger with the on-chain balances
+pub struct Reconciler {
+	/// Substrate client
+	client: Arc<OnlineClient<SubstrateConfig>>,
+	/// Bank account controller
+	bank_account: Arc<dyn BankAccountTrait>,
+	/// Decimals of the on-chain amounts per major unit of the currency
+	chain_decimals: u32,
+}
+
+impl Reconciler {
+	/// Create a new reconciler
+	pub fn new(
+		client: Arc<OnlineClient<SubstrateConfig>>,
+		bank_account: Arc<dyn BankAccountTrait>,
+		chain_decimals: u32,
+	) -> Self {
+		Self { client, bank_account, chain_decimals }
+	}
+
+	/// Reconcile at the given interval, reports with discrepancies are logged and alerted
+	pub async fn start(self, interval_secs: u64, alert_command: Option<String>) {
+		let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+		loop {
+			interval.tick().await;
+
+			match self.reconcile().await {
+				Ok(report) if report.is_consistent() => log::debug!(
+					"Ledger reconciled with {} on-chain balances at block {}",
+					report.bank_accounts,
+					report.block_hash
+				),
+				Ok(report) => {
+					log_discrepancies(&report);
+
+					if let Some(command) = &alert_command {
+						if let Err(e) = alert(command, &report).await {
+							log::error!("Could not run the reconciliation alert: {}", e);
+						}
+					}
+				},
+				Err(e) => log::error!("Could not reconcile the ledger: {}", e),
+			}
+		}
+	}
+
+	/// Fetch the on-chain balances of the registered bank accounts and compare them with the
+	/// ledger
+	pub async fn reconcile(&self) -> anyhow::Result<ReconciliationReport> {
+		let block = self.client.blocks().at_latest().await?;
+		let storage = block.storage();
+		let mut balances = vec![];
+
+		for bank_account in self.bank_account.find_registered().await? {
+			// a malformed on-chain account is reported as a missing one
+			let on_chain_balance = match account_id(&bank_account) {
+				Ok(account_id) => {
+					let query = iso_8583_chain::storage().system().account(&account_id);
+					storage.fetch(&query).await?.map(|account| account.data.free)
+				},
+				Err(e) => {
+					log::warn!("Bank account {} can't be reconciled: {}", bank_account.id, e);
+					None
+				},
+			};
+
+			balances.push((bank_account, on_chain_balance));
+		}
+
+		Ok(ReconciliationReport::new(hex::encode(block.hash()), self.chain_decimals, &balances))
+	}
+}
+
+/// Log the discrepancies of a report
+pub fn log_discrepancies(report: &ReconciliationReport) {
+	for discrepancy in report.discrepancies.iter() {
+		log::warn!(
+			"Bank account {} has balance {} ({:?} on-chain units), on-chain account {} has {:?}",
+			discrepancy.bank_account_id,
+			discrepancy.ledger_balance,
+			discrepancy.expected_on_chain,
+			discrepancy.account_id,
+			discrepancy.on_chain_balance
+		);
+	}
+}
+
+/// Run the alert command with the JSON report on its standard input
+pub async fn alert(command: &str, report: &ReconciliationReport) -> anyhow::Result<()> {
+	let mut child = tokio::process::Command::new("sh")
+		.arg("-c")
+		.arg(command)
+		.stdin(Stdio::piped())
+		.spawn()?;
+
+	if let Some(mut stdin) = child.stdin.take() {
+		stdin.write_all(&serde_json::to_vec_pretty(report)?).await?;
+	}
+
+	let status = child.wait().await?;
+	if !status.success() {
+		anyhow::bail!("alert command exited with {}", status);
+	}
+
+	Ok(())
+}
+
+/// On-chain account of a registered bank account
+fn account_id(bank_account: &BankAccount) -> anyhow::Result<AccountId32> {
+	let account_id = bank_account.account_id.as_deref().unwrap_or_default();
+	let bytes: [u8; 32] = hex::decode(account_id.trim_start_matches("0x"))?
+		.try_into()
+		.map_err(|_| anyhow::anyhow!("invalid on-chain account {}", account_id))?;
+
+	Ok(AccountId32(bytes))
+}
@@ -2,134 +2,433 @@
 use async_trait::async_trait;
 use chrono::{Months, Utc};
 use iso8583_rs::iso8583::iso_spec::IsoMsg;
-use jsonrpsee::{core::RpcResult, proc_macros::rpc, server::Server};
-use jsonrpsee_types::error::ErrorCode;
+use jsonrpsee::{
+	core::{RpcResult, SubscriptionResult},
+	proc_macros::rpc,
+	server::{PendingSubscriptionSink, Server, SubscriptionMessage, SubscriptionSink},
+};
+use jsonrpsee_types::{error::ErrorCode, ErrorObject, ErrorObjectOwned};
 use log::info;
 use op_core::{
-	bank_account::models::{BankAccount, BankAccountCreate},
+	bank_account::models::{
+		BankAccount, BankAccountCreate, BankAccountUpdate, CardStatus, CardStatusChange,
+	},
 	error::DomainError,
-	transaction::models::Transaction,
+	money::{Iso4217, Money},
+	transaction::models::{Transaction, TransactionPage, TransactionQuery},
+	vault::mask_card_number,
 };
-use std::{error::Error, net::SocketAddr, sync::Arc};
-use subxt::{utils::AccountId32, OnlineClient, SubstrateConfig};
-use subxt_signer::{sr25519, sr25519::Signature};
+use serde_json::json;
+use std::{collections::BTreeMap, error::Error, net::SocketAddr, sync::Arc};
+use subxt::utils::AccountId32;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
 
-use super::processor::Iso8583MessageProcessor;
+use super::{processor::Iso8583MessageProcessor, submitter::TransactionSubmitter};
 use crate::{
+	auth::{AuthError, Authenticator, BalancesRequestVerifier, Principal, RpcAuth},
+	events::{BalanceUpdate, TransactionEvent},
 	services::watcher::iso_8583_chain,
 	types::{
-		constants::{DEV_ACCOUNTS, RESPONSE_CODE_FIELD_NUMBER},
-		MTI,
+		constants::{
+			CARD_VALIDITY_MONTHS, DEV_ACCOUNTS, FORBIDDEN_ERROR_CODE, RESPONSE_CODE_FIELD_NUMBER,
+			STAN_FIELD_NUMBER, UNAUTHORIZED_ERROR_CODE,
+		},
+		IsoFields, MTI,
 	},
 };
 
 /// PCIDSS Compliant Oracle RPC API
+///
+/// Every method but `get_batch_balances` is authenticated with its trailing `auth` parameter,
+/// see [`crate::auth`].
 #[rpc(server, client, namespace = "pcidss")]
 pub trait OracleApi {
 	/// Submit ISO8583 message for processing
+	///
+	/// The message is parsed with the named spec, the default one if omitted. Acquirers,
+	/// merchants and admins only
 	#[method(name = "submit_iso8583")]
-	async fn submit_iso8583(&self, iso_msg: Vec<u8>) -> RpcResult<Vec<u8>>;
+	async fn submit_iso8583(
+		&self,
+		iso_msg: Vec<u8>,
+		spec: Option<String>,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<Vec<u8>>;
 
-	/// Get transactions by on-chain account id
+	/// Submit ISO8583 message given by its MTI and field values for processing
+	///
+	/// Fields are keyed by their position in the bitmap and formatted as the spec expects them,
+	/// the response is returned the same way with the description of its response code.
+	/// Acquirers, merchants and admins only
+	#[method(name = "submit_iso8583_fields")]
+	async fn submit_iso8583_fields(
+		&self,
+		mti: String,
+		fields: BTreeMap<u32, String>,
+		spec: Option<String>,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<IsoFields>;
+
+	/// Get transactions by on-chain account id, owner of the account and admins only
 	#[method(name = "get_transactions")]
-	async fn get_transactions(&self, account_id: String) -> RpcResult<Option<Vec<Transaction>>>;
+	async fn get_transactions(
+		&self,
+		account_id: String,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<Option<Vec<Transaction>>>;
+
+	/// Get a page of the transaction history by on-chain account id, filtered and sorted by the
+	/// query, newest first by default. Owner of the account and admins only
+	#[method(name = "query_transactions")]
+	async fn query_transactions(
+		&self,
+		account_id: String,
+		query: Option<TransactionQuery>,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<TransactionPage>;
 
-	/// Get bank account by on-chain account id
+	/// Get bank account by on-chain account id, owner of the account only
 	#[method(name = "get_bank_account")]
-	async fn get_bank_account(&self, account_id: String) -> RpcResult<Option<BankAccount>>;
+	async fn get_bank_account(
+		&self,
+		account_id: String,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<Option<BankAccount>>;
 
-	/// Get balance by on-chain account id
+	/// Get balance by on-chain account id, in the minor units of the currency of the account
 	///
-	/// Only the OCW can call this method
+	/// Only the OCW can call this method, with the signature of a SCALE encoded
+	/// [`crate::auth::BalancesRequest`]
 	#[method(name = "get_batch_balances")]
 	async fn get_batch_balances(
 		&self,
 		signature: Vec<u8>,
-		account_ids: Vec<String>,
-	) -> RpcResult<Option<Vec<(String, u32)>>>;
+		payload: Vec<u8>,
+	) -> RpcResult<Option<Vec<(String, i64)>>>;
+
+	/// Change the status of the card of a bank account: active, blocked, lost, stolen or closed
+	///
+	/// Admins only
+	#[method(name = "set_card_status")]
+	async fn set_card_status(
+		&self,
+		bank_account_id: Uuid,
+		status: CardStatus,
+		reason: Option<String>,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<BankAccount>;
+
+	/// Renew the card of a bank account, it expires `CARD_VALIDITY_MONTHS` from now. The card
+	/// number is kept, the CVV too if no new one is given
+	///
+	/// Admins only
+	#[method(name = "renew_card")]
+	async fn renew_card(
+		&self,
+		bank_account_id: Uuid,
+		card_cvv: Option<String>,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<BankAccount>;
+
+	/// Reissue the card of a bank account with a new card number, e.g. after it is lost or
+	/// stolen. The card is active again and expires `CARD_VALIDITY_MONTHS` from now
+	///
+	/// Admins only
+	#[method(name = "reissue_card")]
+	async fn reissue_card(
+		&self,
+		bank_account_id: Uuid,
+		card_number: String,
+		card_cvv: String,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<BankAccount>;
+
+	/// Get the card status history of a bank account, oldest first
+	///
+	/// Admins only
+	#[method(name = "get_card_status_history")]
+	async fn get_card_status_history(
+		&self,
+		bank_account_id: Uuid,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<Vec<CardStatusChange>>;
+
+	/// Subscribe to the transactions posted, reversed or retracted for an on-chain account id,
+	/// owner of the account only
+	#[subscription(
+		name = "subscribeTransactions" => "transaction",
+		unsubscribe = "unsubscribeTransactions",
+		item = TransactionEvent
+	)]
+	async fn subscribe_transactions(
+		&self,
+		account_id: String,
+		auth: Option<RpcAuth>,
+	) -> SubscriptionResult;
+
+	/// Subscribe to the balance of an on-chain account id, sent after every transaction of the
+	/// account. Owner of the account only
+	#[subscription(
+		name = "subscribeBalance" => "balance",
+		unsubscribe = "unsubscribeBalance",
+		item = BalanceUpdate
+	)]
+	async fn subscribe_balance(
+		&self,
+		account_id: String,
+		auth: Option<RpcAuth>,
+	) -> SubscriptionResult;
 }
 
 /// PCIDSS Compliant Oracle RPC API implementation
 pub struct OracleApiImpl {
 	/// ISO8583 message processor
 	pub processor: Arc<Iso8583MessageProcessor>,
-	/// Client to interact with the chain
-	pub client: Arc<OnlineClient<SubstrateConfig>>,
-	/// Oracle signer account
-	pub keypair: sr25519::Keypair,
-	/// OCW signer account
-	pub signer: sr25519::PublicKey,
+	/// Submitter of the extrinsics signed by the oracle
+	pub submitter: Arc<TransactionSubmitter>,
+	/// Verifier of the balance requests of the OCW
+	pub balances_verifier: Arc<BalancesRequestVerifier>,
+	/// Authenticator of the requests
+	pub authenticator: Arc<Authenticator>,
 }
 
 impl OracleApiImpl {
-	/// Send a register extrinsic to the chain
-	async fn register_on_chain(&self, iso_msg: IsoMsg) {
-		let response_code =
-			iso_msg.bmp_child_value(RESPONSE_CODE_FIELD_NUMBER).unwrap_or("12".to_string());
-		if response_code == *"00" {
-			if let Ok(t) = iso_msg.get_field_value(&"message_type".to_string()) {
-				let msg_type = t.as_str().try_into().unwrap_or(MTI::AuthorizationRequest);
-				if msg_type == MTI::NetworkManagementResponse {
-					// send `register` extrinsic to the chain
-					if let Ok(account_hex) = iso_msg.bmp_child_value(126) {
-						log::debug!("Registering account: {:?}", &account_hex);
-
-						let account = AccountId32(
-							hex::decode(account_hex)
-								.expect("valid hex; qed") // TODO: safe unwrap
-								.try_into()
-								.expect("valid; qed"),
-						);
-
-						let tx = iso_8583_chain::tx().iso8583().register(account, 0);
-						if let Err(e) =
-							self.client.tx().sign_and_submit_default(&tx, &self.keypair).await
-						{
-							log::error!("Failed to submit transaction: {:?}", e);
-						}
-					}
-				}
-			}
+	/// Authorize a request to the method, `owner_of` is the on-chain account it is about
+	fn authorize(
+		&self,
+		method: &str,
+		params: serde_json::Value,
+		auth: Option<RpcAuth>,
+		owner_of: Option<&str>,
+	) -> RpcResult<Principal> {
+		self.authenticator
+			.authorize(method, &params, auth.as_ref(), owner_of, Utc::now())
+			.map_err(auth_error)
+	}
+
+	/// Update a bank account with an admin method
+	async fn admin_update(
+		&self,
+		principal: &Principal,
+		bank_account_id: &Uuid,
+		bank_account_update: &BankAccountUpdate,
+	) -> RpcResult<BankAccount> {
+		log::info!("Bank account {} updated by {}", bank_account_id, principal.name);
+
+		self.processor
+			.bank_account_controller
+			.update(bank_account_id, bank_account_update)
+			.await
+			.map_err(|err| error_code(err).into())
+	}
+
+	/// Accept a subscription of the owner of the account to the events of its bank account
+	///
+	/// The subscription is rejected if the client isn't the owner or the account is unknown.
+	async fn accept_account_subscription(
+		&self,
+		method: &str,
+		pending: PendingSubscriptionSink,
+		account_id: &str,
+		auth: Option<RpcAuth>,
+	) -> Option<(SubscriptionSink, broadcast::Receiver<TransactionEvent>, BankAccount)> {
+		if let Err(err) = self.authorize(method, json!([account_id]), auth, Some(account_id)) {
+			pending.reject(err).await;
+			return None
+		}
+
+		let bank_account =
+			match self.processor.bank_account_controller.find_by_account_id(account_id).await {
+				Ok(Some(bank_account)) => bank_account,
+				Ok(None) => {
+					pending.reject(ErrorCode::InvalidParams).await;
+					return None
+				},
+				Err(err) => {
+					pending.reject(error_code(err)).await;
+					return None
+				},
+			};
+
+		// subscribe before accepting, so that no event is missed in between
+		let events = self.processor.events.subscribe();
+		let sink = pending.accept().await.ok()?;
+
+		Some((sink, events, bank_account))
+	}
+}
+
+/// Wait for the next event of the bank account, `None` once the subscription is closed
+async fn next_event(
+	sink: &SubscriptionSink,
+	events: &mut broadcast::Receiver<TransactionEvent>,
+	bank_account_id: &Uuid,
+) -> Option<TransactionEvent> {
+	loop {
+		tokio::select! {
+			_ = sink.closed() => return None,
+			event = events.recv() => match event {
+				Ok(event) if event.concerns(bank_account_id) => return Some(event),
+				Ok(_) => {},
+				Err(RecvError::Lagged(skipped)) => {
+					log::warn!("Subscriber of {} missed {} events", bank_account_id, skipped);
+				},
+				Err(RecvError::Closed) => return None,
+			},
 		}
 	}
 }
 
+/// Error code of a domain error
+fn error_code(err: DomainError) -> ErrorCode {
+	log::debug!("Error: {:?}", err);
+
+	match err {
+		DomainError::ApiError(_) => ErrorCode::InternalError,
+		DomainError::InternalServerError(_) => ErrorCode::InternalError,
+		DomainError::BadRequest(_) => ErrorCode::InvalidParams,
+		DomainError::NotFound(_) => ErrorCode::InvalidParams,
+		DomainError::InsufficientFunds => ErrorCode::InvalidParams,
+	}
+}
+
+/// Error object of an authentication or authorization error
+fn auth_error(err: AuthError) -> ErrorObjectOwned {
+	let code = match err {
+		AuthError::Unauthorized(_) => UNAUTHORIZED_ERROR_CODE,
+		AuthError::Forbidden(_) => FORBIDDEN_ERROR_CODE,
+	};
+
+	ErrorObject::owned(code, err.to_string(), None::<()>)
+}
+
+/// Expiration date of a renewed or reissued card
+fn card_expiration_date() -> chrono::DateTime<Utc> {
+	Utc::now()
+		.checked_add_months(Months::new(CARD_VALIDITY_MONTHS))
+		.expect("valid date")
+}
+
+/// Log a processed message by its MTI, STAN and response code, never with the card data
+pub(crate) fn log_processed(iso_msg: &IsoMsg) {
+	log::info!(
+		"Processed ISO8583 message: MTI {}, STAN {}, response code {}",
+		iso_msg.get_field_value(&"message_type".to_string()).unwrap_or_default(),
+		iso_msg.bmp_child_value(STAN_FIELD_NUMBER).unwrap_or_default(),
+		iso_msg.bmp_child_value(RESPONSE_CODE_FIELD_NUMBER).unwrap_or_default(),
+	);
+}
+
+/// Send a register extrinsic to the chain if the account registration was approved
+pub(crate) async fn register_on_chain(
+	submitter: &TransactionSubmitter,
+	iso_msg: IsoMsg,
+) -> Result<(), DomainError> {
+	let response_code =
+		iso_msg.bmp_child_value(RESPONSE_CODE_FIELD_NUMBER).unwrap_or("12".to_string());
+	let msg_type = iso_msg
+		.get_field_value(&"message_type".to_string())
+		.ok()
+		.and_then(|t| MTI::try_from(t.as_str()).ok());
+
+	if response_code != "00" || msg_type != Some(MTI::NetworkManagementResponse) {
+		return Ok(())
+	}
+
+	// send `register` extrinsic to the chain
+	let Ok(account_hex) = iso_msg.bmp_child_value(126) else { return Ok(()) };
+	log::debug!("Registering account: {:?}", &account_hex);
+
+	let account: [u8; 32] = hex::decode(account_hex.trim_start_matches("0x"))
+		.ok()
+		.and_then(|account| account.try_into().ok())
+		.ok_or_else(|| DomainError::BadRequest(format!("Invalid account id: {}", account_hex)))?;
+
+	let tx = iso_8583_chain::tx().iso8583().register(AccountId32(account), 0);
+	submitter
+		.submit(&tx)
+		.await
+		.map_err(|e| DomainError::ApiError(format!("Failed to submit transaction: {:?}", e)))?;
+
+	Ok(())
+}
+
 #[async_trait]
 impl OracleApiServer for OracleApiImpl {
-	async fn submit_iso8583(&self, iso_msg: Vec<u8>) -> RpcResult<Vec<u8>> {
-		log::debug!("Received ISO8583 message: {:?}", iso_msg);
+	async fn submit_iso8583(
+		&self,
+		iso_msg: Vec<u8>,
+		spec: Option<String>,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<Vec<u8>> {
+		let principal =
+			self.authorize("pcidss_submit_iso8583", json!([iso_msg, spec]), auth, None)?;
+		log::debug!("Received ISO8583 message of {} bytes from {}", iso_msg.len(), principal.name);
 
 		let mut iso_msg = iso_msg;
 
-		match self.processor.process(&mut iso_msg).await {
+		match self.processor.process_with_spec(spec.as_deref(), &mut iso_msg).await {
 			Ok((raw_iso_msg, iso_msg)) => {
-				log::info!("Processed ISO8583 message: {:?}", raw_iso_msg);
-				Self::register_on_chain(self, iso_msg).await;
+				log_processed(&iso_msg);
+				if let Err(err) = register_on_chain(&self.submitter, iso_msg).await {
+					log::error!("Failed to register the account on-chain: {:?}", err);
+				}
 				Ok(raw_iso_msg)
 			},
 			Err(err) => {
 				log::error!("Failed to process ISO8583 message: {:?}", err.to_string());
-				let error_code = match err {
-					DomainError::ApiError(_) => ErrorCode::InternalError,
-					DomainError::InternalServerError(_) => ErrorCode::InternalError,
-					DomainError::BadRequest(_) => ErrorCode::InvalidParams,
-					DomainError::NotFound(_) => ErrorCode::InvalidParams,
-				};
-
-				Err(error_code.into())
+				Err(error_code(err).into())
 			},
 		}
 	}
 
-	async fn get_transactions(&self, account_id: String) -> RpcResult<Option<Vec<Transaction>>> {
+	async fn submit_iso8583_fields(
+		&self,
+		mti: String,
+		fields: BTreeMap<u32, String>,
+		spec: Option<String>,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<IsoFields> {
+		let params = json!([mti, fields, spec]);
+		let principal = self.authorize("pcidss_submit_iso8583_fields", params, auth, None)?;
+		log::debug!("Received ISO8583 {} message fields from {}", mti, principal.name);
+
+		match self.processor.process_fields(spec.as_deref(), &mti, &fields).await {
+			Ok((_, iso_msg)) => {
+				log_processed(&iso_msg);
+				let response = IsoFields::from_iso_msg(&iso_msg).map_err(error_code)?;
+				if let Err(err) = register_on_chain(&self.submitter, iso_msg).await {
+					log::error!("Failed to register the account on-chain: {:?}", err);
+				}
+				Ok(response)
+			},
+			Err(err) => {
+				log::error!("Failed to process ISO8583 message: {:?}", err.to_string());
+				// invalid fields are reported to the client, they don't carry card data
+				Err(match err {
+					DomainError::BadRequest(msg) =>
+						ErrorObject::owned(ErrorCode::InvalidParams.code(), msg, None::<()>),
+					err => error_code(err).into(),
+				})
+			},
+		}
+	}
+
+	async fn get_transactions(
+		&self,
+		account_id: String,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<Option<Vec<Transaction>>> {
 		log::debug!("Received get_transactions request: {:?}", account_id);
+		self.authorize("pcidss_get_transactions", json!([account_id]), auth, Some(&account_id))?;
 
 		let bank_account = self
 			.processor
 			.bank_account_controller
 			.find_by_account_id(&account_id)
 			.await
-			.map_err(|_| ErrorCode::InvalidParams)?
+			.map_err(error_code)?
 			.ok_or(ErrorCode::InvalidParams)?;
 
 		let transactions = self
@@ -137,19 +436,43 @@ impl OracleApiServer for OracleApiImpl {
 			.transaction_controller
 			.find_by_bank_account_id(&bank_account.id)
 			.await
-			.map_err(|err| match err {
-				DomainError::ApiError(_) => ErrorCode::InternalError,
-				DomainError::InternalServerError(_) => ErrorCode::InternalError,
-				DomainError::BadRequest(_) => ErrorCode::InvalidParams,
-				DomainError::NotFound(_) => ErrorCode::InvalidParams,
-			})
-			.unwrap_or_default();
+			.map_err(error_code)?;
 
 		Ok(Some(transactions))
 	}
 
-	async fn get_bank_account(&self, account_id: String) -> RpcResult<Option<BankAccount>> {
+	async fn query_transactions(
+		&self,
+		account_id: String,
+		query: Option<TransactionQuery>,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<TransactionPage> {
+		log::debug!("Received query_transactions request: {:?} {:?}", account_id, query);
+		let params = json!([account_id, query]);
+		self.authorize("pcidss_query_transactions", params, auth, Some(&account_id))?;
+
+		let bank_account = self
+			.processor
+			.bank_account_controller
+			.find_by_account_id(&account_id)
+			.await
+			.map_err(error_code)?
+			.ok_or(ErrorCode::InvalidParams)?;
+
+		self.processor
+			.transaction_controller
+			.find_page(&bank_account.id, &query.unwrap_or_default())
+			.await
+			.map_err(|err| error_code(err).into())
+	}
+
+	async fn get_bank_account(
+		&self,
+		account_id: String,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<Option<BankAccount>> {
 		log::debug!("Received get_bank_account request: {:?}", account_id);
+		self.authorize("pcidss_get_bank_account", json!([account_id]), auth, Some(&account_id))?;
 
 		let ba = self
 			.processor
@@ -168,30 +491,13 @@ impl OracleApiServer for OracleApiImpl {
 	async fn get_batch_balances(
 		&self,
 		signature: Vec<u8>,
-		account_ids: Vec<String>,
-	) -> RpcResult<Option<Vec<(String, u32)>>> {
-		let signature = signature.try_into().map_err(|_| ErrorCode::InvalidParams)?;
-
-		// message is JSON serialized array of account ids, so we need
-		// to include the brackets and quotes in the message
-		let message = {
-			let mut message = Vec::new();
-			message.push(b'[');
-			for account_id in &account_ids {
-				message.push(b'"');
-				message.extend_from_slice(account_id.as_bytes());
-				message.push(b'"');
-				message.push(b',');
-			}
-			message.pop();
-			message.push(b']');
-			message
-		};
-
-		if !sr25519::verify(&Signature(signature), &message[..], &self.signer) {
-			log::error!("Invalid signature");
-			return Err(ErrorCode::InvalidParams.into());
-		}
+		payload: Vec<u8>,
+	) -> RpcResult<Option<Vec<(String, i64)>>> {
+		let request = self.balances_verifier.verify(&payload, &signature).map_err(|err| {
+			log::error!("Invalid balances request: {}", err);
+			auth_error(err)
+		})?;
+		let account_ids = request.account_ids.iter().map(hex::encode);
 
 		let mut balances = Vec::new();
 
@@ -207,22 +513,143 @@ impl OracleApiServer for OracleApiImpl {
 			})?;
 
 			if let Some(ba) = ba {
-				balances.push((account_id, ba.balance));
+				balances.push((account_id, ba.balance.minor_units));
 			}
 		}
 
 		Ok(Some(balances))
 	}
+
+	async fn set_card_status(
+		&self,
+		bank_account_id: Uuid,
+		status: CardStatus,
+		reason: Option<String>,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<BankAccount> {
+		let params = json!([bank_account_id, status, reason]);
+		let principal = self.authorize("pcidss_set_card_status", params, auth, None)?;
+
+		self.admin_update(
+			&principal,
+			&bank_account_id,
+			&BankAccountUpdate::Status { status, reason },
+		)
+		.await
+	}
+
+	async fn renew_card(
+		&self,
+		bank_account_id: Uuid,
+		card_cvv: Option<String>,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<BankAccount> {
+		let params = json!([bank_account_id, card_cvv]);
+		let principal = self.authorize("pcidss_renew_card", params, auth, None)?;
+		let card_expiration_date = card_expiration_date();
+
+		self.admin_update(
+			&principal,
+			&bank_account_id,
+			&BankAccountUpdate::Renew { card_expiration_date, card_cvv },
+		)
+		.await
+	}
+
+	async fn reissue_card(
+		&self,
+		bank_account_id: Uuid,
+		card_number: String,
+		card_cvv: String,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<BankAccount> {
+		let params = json!([bank_account_id, card_number, card_cvv]);
+		let principal = self.authorize("pcidss_reissue_card", params, auth, None)?;
+		let card_expiration_date = card_expiration_date();
+
+		self.admin_update(
+			&principal,
+			&bank_account_id,
+			&BankAccountUpdate::Reissue { card_number, card_cvv, card_expiration_date },
+		)
+		.await
+	}
+
+	async fn get_card_status_history(
+		&self,
+		bank_account_id: Uuid,
+		auth: Option<RpcAuth>,
+	) -> RpcResult<Vec<CardStatusChange>> {
+		self.authorize("pcidss_get_card_status_history", json!([bank_account_id]), auth, None)?;
+
+		self.processor
+			.bank_account_controller
+			.find_status_history(&bank_account_id)
+			.await
+			.map_err(|err| error_code(err).into())
+	}
+
+	async fn subscribe_transactions(
+		&self,
+		pending: PendingSubscriptionSink,
+		account_id: String,
+		auth: Option<RpcAuth>,
+	) -> SubscriptionResult {
+		let method = "pcidss_subscribeTransactions";
+		let Some((sink, mut events, bank_account)) =
+			self.accept_account_subscription(method, pending, &account_id, auth).await
+		else {
+			return Ok(())
+		};
+
+		while let Some(event) = next_event(&sink, &mut events, &bank_account.id).await {
+			sink.send(SubscriptionMessage::from_json(&event)?).await?;
+		}
+
+		Ok(())
+	}
+
+	async fn subscribe_balance(
+		&self,
+		pending: PendingSubscriptionSink,
+		account_id: String,
+		auth: Option<RpcAuth>,
+	) -> SubscriptionResult {
+		let method = "pcidss_subscribeBalance";
+		let Some((sink, mut events, bank_account)) =
+			self.accept_account_subscription(method, pending, &account_id, auth).await
+		else {
+			return Ok(())
+		};
+
+		while let Some(event) = next_event(&sink, &mut events, &bank_account.id).await {
+			let Some(bank_account) =
+				self.processor.bank_account_controller.find_by_id(&bank_account.id).await?
+			else {
+				return Err("Bank account not found".into())
+			};
+
+			let update = BalanceUpdate {
+				account_id: account_id.clone(),
+				balance: bank_account.balance,
+				available_balance: bank_account.available_balance,
+				transaction_hash: event.transaction.hash,
+			};
+			sink.send(SubscriptionMessage::from_json(&update)?).await?;
+		}
+
+		Ok(())
+	}
 }
 
 /// Run ISO8583 Message Processor
 pub async fn run(
 	processor: Arc<Iso8583MessageProcessor>,
-	client: Arc<OnlineClient<SubstrateConfig>>,
-	keypair: sr25519::Keypair,
+	submitter: Arc<TransactionSubmitter>,
 	rpc_port: u16,
 	dev_mode: bool,
-	ocw_signer: sr25519::PublicKey,
+	authenticator: Arc<Authenticator>,
+	balances_verifier: Arc<BalancesRequestVerifier>,
 ) -> anyhow::Result<(), Box<dyn Error>> {
 	if dev_mode {
 		info!("Running in dev mode, inserting dev accounts");
@@ -241,7 +668,7 @@ pub async fn run(
 				card_holder_last_name: account.0.to_string(),
 				card_cvv: account.2.to_string(),
 				card_expiration_date: expiration_date,
-				balance: account.3,
+				balance: Money::new(account.3, Iso4217::default()),
 				account_id: account.4.map(|s| s.to_string()),
 			};
 
@@ -249,8 +676,8 @@ pub async fn run(
 
 			match bank_account {
 				Ok(bank_account) => {
-					assert_eq!(bank_account.card_number, account.1);
-					assert_eq!(bank_account.balance, account.3);
+					assert_eq!(bank_account.card_number_masked, mask_card_number(account.1));
+					assert_eq!(bank_account.balance.minor_units, account.3);
 					assert_eq!(bank_account.nonce, 0);
 					info!("Inserted dev account: {:?}", bank_account);
 				},
@@ -262,7 +689,7 @@ pub async fn run(
 	}
 
 	// Run RPC server
-	let addr = run_server(processor, client, keypair, rpc_port, ocw_signer).await?;
+	let addr = run_server(processor, submitter, rpc_port, authenticator, balances_verifier).await?;
 	let url = format!("ws://{}", addr);
 
 	log::info!("RPC server listening on {}", url);
@@ -273,15 +700,15 @@ pub async fn run(
 /// Run RPC server for ISO8583 Message Processor
 async fn run_server(
 	processor: Arc<Iso8583MessageProcessor>,
-	client: Arc<OnlineClient<SubstrateConfig>>,
-	keypair: sr25519::Keypair,
+	submitter: Arc<TransactionSubmitter>,
 	rpc_port: u16,
-	ocw_signer: sr25519::PublicKey,
+	authenticator: Arc<Authenticator>,
+	balances_verifier: Arc<BalancesRequestVerifier>,
 ) -> anyhow::Result<SocketAddr> {
 	let server = Server::builder().build(format!("0.0.0.0:{}", rpc_port)).await?;
 
 	let addr = server.local_addr()?;
-	let oracle_impl = OracleApiImpl { processor, signer: ocw_signer, client, keypair };
+	let oracle_impl = OracleApiImpl { processor, submitter, authenticator, balances_verifier };
 
 	let server_handle = server.start(oracle_impl.into_rpc());
 
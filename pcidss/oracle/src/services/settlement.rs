@@ -0,0 +1,100 @@
+//! Settlement worker closes the settlement window of the acquirers once a day
+//!
+//! At the cut-off the transactions of every acquirer that are not settled yet are assigned to a
+//! batch, and a clearing file is written for each batch. Acquirers compare their totals with
+//! ours with reconciliation messages (0500).
+
+use std::{
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use chrono::{DateTime, Days, NaiveTime, Utc};
+use op_core::settlement::{models::SettlementBatch, traits::SettlementTrait};
+
+/// Worker that settles the transactions of the acquirers at the daily cut-off
+pub struct SettlementWorker {
+	/// Settlement controller
+	settlement: Arc<dyn SettlementTrait>,
+	/// Time of the day (UTC) the settlement window is closed at
+	cutoff_time: NaiveTime,
+	/// Directory the clearing files are written to
+	clearing_dir: PathBuf,
+}
+
+impl SettlementWorker {
+	/// Create a new settlement worker
+	pub fn new(
+		settlement: Arc<dyn SettlementTrait>,
+		cutoff_time: NaiveTime,
+		clearing_dir: impl AsRef<Path>,
+	) -> Self {
+		Self { settlement, cutoff_time, clearing_dir: clearing_dir.as_ref().to_path_buf() }
+	}
+
+	/// Settle the transactions at every cut-off
+	pub async fn start(self) {
+		loop {
+			let cutoff = next_cutoff(Utc::now(), self.cutoff_time);
+			log::info!("Next settlement cut-off at {}", cutoff);
+
+			let delay = (cutoff - Utc::now()).to_std().unwrap_or_default();
+			tokio::time::sleep(delay).await;
+
+			if let Err(e) = self.settle(cutoff).await {
+				log::error!("Could not settle the transactions of {}: {}", cutoff, e);
+			}
+		}
+	}
+
+	/// Close the settlement window at the cut-off and write the clearing files of the batches
+	///
+	/// Batches are stored before their clearing files are written, a file that can't be written
+	/// is logged and doesn't prevent the other ones from being written.
+	pub async fn settle(&self, cutoff: DateTime<Utc>) -> anyhow::Result<Vec<SettlementBatch>> {
+		let batches = self.settlement.cut_off(cutoff).await?;
+
+		for batch in batches.iter() {
+			match self.write_clearing_file(batch).await {
+				Ok(path) => log::info!(
+					"Settled batch {} of acquirer {}, net position {}, clearing file {}",
+					batch.id,
+					batch.acquirer_id,
+					batch.totals.net_minor_units(),
+					path.display()
+				),
+				Err(e) =>
+					log::error!("Could not write the clearing file of batch {}: {}", batch.id, e),
+			}
+		}
+
+		Ok(batches)
+	}
+
+	/// Write the clearing file of a batch and return its path
+	async fn write_clearing_file(&self, batch: &SettlementBatch) -> anyhow::Result<PathBuf> {
+		let transactions = self.settlement.find_transactions(&batch.id).await?;
+		let path = self.clearing_dir.join(clearing_file_name(batch));
+
+		std::fs::create_dir_all(&self.clearing_dir)?;
+		std::fs::write(&path, batch.clearing_file(&transactions))?;
+
+		Ok(path)
+	}
+}
+
+/// First cut-off at the time of the day after `now`
+pub fn next_cutoff(now: DateTime<Utc>, cutoff_time: NaiveTime) -> DateTime<Utc> {
+	let today = now.date_naive().and_time(cutoff_time).and_utc();
+
+	if today > now {
+		today
+	} else {
+		today.checked_add_days(Days::new(1)).expect("date in range; qed")
+	}
+}
+
+/// Name of the clearing file of a batch, `<settlement date>_<acquirer>_<batch>.csv`
+pub fn clearing_file_name(batch: &SettlementBatch) -> String {
+	format!("{}_{}_{}.csv", batch.settlement_date.format("%Y%m%d"), batch.acquirer_id, batch.id)
+}
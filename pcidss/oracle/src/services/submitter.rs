@@ -0,0 +1,126 @@
+//! Transaction submitter owns the oracle signer and its nonce
+//!
+//! The RPC server, the TCP listener and the outbox worker submit extrinsics with the same signer
+//! at the same time. Looking the nonce up on every submission hands out the same nonce twice, so
+//! it is tracked locally instead: submissions are serialized and every one takes the next nonce.
+//! The nonce is synced from the node, including the transactions in its pool, on the first
+//! submission and after every failed one.
+//!
+//! The runtime has no `Utility` pallet, so queued calls are not batched. They are submitted
+//! back-to-back with consecutive nonces instead, without waiting for each other to be included.
+
+use std::sync::Arc;
+
+use subxt::{
+	backend::rpc::{rpc_params, RpcClient},
+	tx::{TxPayload, TxProgress},
+	utils::AccountId32,
+	OnlineClient, SubstrateConfig,
+};
+use subxt_signer::sr25519::Keypair;
+use tokio::sync::Mutex;
+
+/// Nonce of the signer tracked locally, `None` until it is synced from the node
+#[derive(Debug, Default)]
+pub(crate) struct NonceTracker {
+	next: Option<u64>,
+}
+
+impl NonceTracker {
+	/// Nonce of the next submission, `None` if it has to be synced first
+	pub(crate) fn next(&self) -> Option<u64> {
+		self.next
+	}
+
+	/// Record the nonce of an accepted submission
+	pub(crate) fn used(&mut self, nonce: u64) {
+		self.next = Some(self.next.map_or(nonce + 1, |next| next.max(nonce + 1)));
+	}
+
+	/// Forget the nonce, it is synced again before the next submission
+	pub(crate) fn reset(&mut self) {
+		self.next = None;
+	}
+}
+
+/// Shared submitter of the extrinsics signed by the oracle
+pub struct TransactionSubmitter {
+	/// Substrate client
+	client: Arc<OnlineClient<SubstrateConfig>>,
+	/// Raw RPC client, used to sync the nonce with the transaction pool
+	rpc_client: RpcClient,
+	/// Keypair for signing transactions
+	keypair: Keypair,
+	/// Nonce of the signer, held for the whole submission so that submissions are serialized
+	nonce: Mutex<NonceTracker>,
+}
+
+impl TransactionSubmitter {
+	/// Create a new submitter, the nonce is synced on the first submission
+	pub fn new(
+		client: Arc<OnlineClient<SubstrateConfig>>,
+		rpc_client: RpcClient,
+		keypair: Keypair,
+	) -> Self {
+		Self { client, rpc_client, keypair, nonce: Mutex::new(NonceTracker::default()) }
+	}
+
+	/// On-chain account of the signer
+	pub fn account_id(&self) -> AccountId32 {
+		AccountId32(self.keypair.public_key().0)
+	}
+
+	/// Sign the call with the next nonce, submit it and watch its progress
+	///
+	/// The nonce is synced again if the submission fails.
+	pub async fn submit<Call: TxPayload>(
+		&self,
+		call: &Call,
+	) -> Result<TxProgress<SubstrateConfig, OnlineClient<SubstrateConfig>>, subxt::Error> {
+		let mut tracker = self.nonce.lock().await;
+
+		let nonce = match tracker.next() {
+			Some(nonce) => nonce,
+			None => self.sync_nonce().await?,
+		};
+
+		let result = match self.client.tx().create_signed_with_nonce(
+			call,
+			&self.keypair,
+			nonce,
+			Default::default(),
+		) {
+			Ok(tx) => tx.submit_and_watch().await,
+			Err(e) => Err(e),
+		};
+
+		match &result {
+			Ok(_) => tracker.used(nonce),
+			Err(e) => {
+				log::warn!("Submission with nonce {} failed, resyncing: {}", nonce, e);
+				tracker.reset();
+			},
+		}
+
+		result
+	}
+
+	/// Sync the nonce again before the next submission
+	///
+	/// Called when a submitted extrinsic is dropped or invalidated, since the nonces after it
+	/// can't be included anymore.
+	pub async fn resync(&self) {
+		self.nonce.lock().await.reset();
+	}
+
+	/// Next nonce of the signer, counting the transactions in the pool of the node
+	async fn sync_nonce(&self) -> Result<u64, subxt::Error> {
+		let nonce: u64 = self
+			.rpc_client
+			.request("system_accountNextIndex", rpc_params![self.account_id()])
+			.await?;
+		log::debug!("Synced signer nonce: {}", nonce);
+
+		Ok(nonce)
+	}
+}
@@ -0,0 +1,364 @@
+//! Native ISO-8583 listener over TCP
+//!
+//! Terminals and switches keep persistent connections and frame every message with a length
+//! header. Many messages can be in flight on a single connection: they are processed
+//! concurrently and every response is written as soon as it is ready, so clients match
+//! responses to requests by STAN (field 11). Messages without a STAN or with one that is in
+//! flight are declined right away.
+//!
+//! The first message of a connection is the API key of the client instead, optionally followed by
+//! a space and the name of the spec the messages of the connection are parsed with. The
+//! connection is closed unless the key may submit ISO-8583 messages over the RPC and the spec is
+//! loaded.
+
+use std::{
+	collections::HashSet,
+	io,
+	sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use op_core::error::DomainError;
+use tokio::{
+	io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+	net::{TcpListener, TcpStream},
+	sync::{mpsc, Semaphore},
+};
+
+use super::{
+	processor::Iso8583MessageProcessor,
+	rpc::{log_processed, register_on_chain},
+	submitter::TransactionSubmitter,
+};
+use crate::{
+	auth::{Authenticator, Principal, RpcAuth},
+	types::{constants::STAN_FIELD_NUMBER, ResponseCodes},
+};
+
+/// RPC method whose roles and permissions the connections are authorized with
+const AUTHORIZED_METHOD: &str = "pcidss_submit_iso8583";
+
+/// Length header that precedes every message on a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LengthHeader {
+	/// 2 bytes, big-endian binary length
+	Binary2,
+	/// 4 ASCII digits, zero padded
+	Ascii4,
+}
+
+impl LengthHeader {
+	/// Size of the header in bytes
+	pub fn size(&self) -> usize {
+		match self {
+			LengthHeader::Binary2 => 2,
+			LengthHeader::Ascii4 => 4,
+		}
+	}
+
+	/// Maximum length of a message that fits into the header
+	pub fn max_len(&self) -> usize {
+		match self {
+			LengthHeader::Binary2 => u16::MAX as usize,
+			LengthHeader::Ascii4 => 9999,
+		}
+	}
+
+	/// Encode the length of a message
+	pub fn encode(&self, len: usize) -> io::Result<Vec<u8>> {
+		if len > self.max_len() {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!("Message of {} bytes does not fit into the length header", len),
+			));
+		}
+
+		Ok(match self {
+			LengthHeader::Binary2 => (len as u16).to_be_bytes().to_vec(),
+			LengthHeader::Ascii4 => format!("{:04}", len).into_bytes(),
+		})
+	}
+
+	/// Decode the length of a message from the header
+	pub fn decode(&self, header: &[u8]) -> io::Result<usize> {
+		let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid length header");
+
+		match self {
+			LengthHeader::Binary2 =>
+				Ok(u16::from_be_bytes(header.try_into().map_err(|_| invalid())?) as usize),
+			LengthHeader::Ascii4 => {
+				if header.len() != 4 || !header.iter().all(u8::is_ascii_digit) {
+					return Err(invalid());
+				}
+
+				std::str::from_utf8(header)
+					.map_err(|_| invalid())?
+					.parse()
+					.map_err(|_| invalid())
+			},
+		}
+	}
+}
+
+/// Read the next message from the connection, `None` once the peer closed it
+///
+/// Messages of zero length are keep-alives and are skipped.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+	reader: &mut R,
+	length_header: LengthHeader,
+) -> io::Result<Option<Vec<u8>>> {
+	loop {
+		let mut header = vec![0u8; length_header.size()];
+		match reader.read_exact(&mut header).await {
+			Ok(_) => {},
+			Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+			Err(e) => return Err(e),
+		}
+
+		let len = length_header.decode(&header)?;
+		if len == 0 {
+			continue;
+		}
+
+		let mut msg = vec![0u8; len];
+		reader.read_exact(&mut msg).await?;
+
+		return Ok(Some(msg));
+	}
+}
+
+/// Write a message with its length header to the connection
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+	writer: &mut W,
+	length_header: LengthHeader,
+	msg: &[u8],
+) -> io::Result<()> {
+	writer
+		.write_all(&[length_header.encode(msg.len())?, msg.to_vec()].concat())
+		.await?;
+	writer.flush().await
+}
+
+/// TCP listener that feeds framed ISO-8583 messages to the processor
+pub struct TcpListenerService {
+	/// ISO-8583 message processor
+	processor: Arc<Iso8583MessageProcessor>,
+	/// Authenticator of the API keys the connections start with
+	authenticator: Arc<Authenticator>,
+	/// Length header of the messages
+	length_header: LengthHeader,
+	/// Name of the spec of the connections that don't select one, the default one if `None`
+	spec: Option<String>,
+	/// Maximum number of messages processed concurrently per connection
+	max_in_flight: usize,
+	/// Submitter used to register approved accounts on chain, like the RPC does
+	submitter: Option<Arc<TransactionSubmitter>>,
+}
+
+impl TcpListenerService {
+	/// Create a new listener with the default spec and no on-chain registration
+	pub fn new(
+		processor: Arc<Iso8583MessageProcessor>,
+		authenticator: Arc<Authenticator>,
+		length_header: LengthHeader,
+	) -> Self {
+		Self {
+			processor,
+			authenticator,
+			length_header,
+			spec: None,
+			max_in_flight: 64,
+			submitter: None,
+		}
+	}
+
+	/// Parse the messages of connections that don't select a spec with the named one
+	pub fn with_spec(mut self, spec: Option<String>) -> Self {
+		self.spec = spec;
+		self
+	}
+
+	/// Limit the number of messages processed concurrently per connection
+	pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+		self.max_in_flight = max_in_flight.max(1);
+		self
+	}
+
+	/// Register accounts on chain once their registration is approved
+	pub fn with_chain(mut self, submitter: Arc<TransactionSubmitter>) -> Self {
+		self.submitter = Some(submitter);
+		self
+	}
+
+	/// Bind to the host and port and serve connections
+	pub async fn start(self, host: &str, port: u16) -> io::Result<()> {
+		let listener = TcpListener::bind((host, port)).await?;
+		log::info!("ISO-8583 TCP listener listening on {}", listener.local_addr()?);
+
+		self.serve(listener).await
+	}
+
+	/// Serve connections of the listener, every connection gets its own task
+	pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+		let service = Arc::new(self);
+
+		loop {
+			let (stream, peer) = listener.accept().await?;
+			log::debug!("Accepted ISO-8583 connection from {}", peer);
+
+			let service = Arc::clone(&service);
+			tokio::spawn(async move {
+				if let Err(e) = service.handle_connection(stream).await {
+					log::error!("ISO-8583 connection from {} failed: {}", peer, e);
+				}
+				log::debug!("Closed ISO-8583 connection from {}", peer);
+			});
+		}
+	}
+
+	/// Read messages until the peer closes the connection, then wait for the in-flight ones
+	async fn handle_connection(self: Arc<Self>, stream: TcpStream) -> io::Result<()> {
+		let peer = stream.peer_addr()?;
+		let (mut reader, mut writer) = stream.into_split();
+
+		let Some(hello) = read_frame(&mut reader, self.length_header).await? else { return Ok(()) };
+		let (principal, spec) = match self.open(&hello) {
+			Ok(opened) => opened,
+			Err(e) => {
+				log::warn!("Rejected ISO-8583 connection from {}: {}", peer, e);
+				return Ok(())
+			},
+		};
+		log::debug!(
+			"Authenticated ISO-8583 connection from {} as {} with spec {:?}",
+			peer,
+			principal.name,
+			spec
+		);
+
+		let (responses, mut responses_rx) = mpsc::channel::<Vec<u8>>(self.max_in_flight);
+		let length_header = self.length_header;
+		let writer_task = tokio::spawn(async move {
+			while let Some(response) = responses_rx.recv().await {
+				write_frame(&mut writer, length_header, &response).await?;
+			}
+			writer.shutdown().await
+		});
+
+		let permits = Arc::new(Semaphore::new(self.max_in_flight));
+		let in_flight = Arc::new(Mutex::new(HashSet::new()));
+
+		while let Some(msg) = read_frame(&mut reader, length_header).await? {
+			let permit = Arc::clone(&permits).acquire_owned().await.expect("never closed; qed");
+
+			// responses can't be matched to these messages, they are declined right away
+			let Some(stan) = self.stan(spec.as_deref(), &msg) else {
+				log::warn!(
+					"Declined unparsable ISO-8583 message or one without STAN from {}",
+					peer
+				);
+				self.decline(spec.as_deref(), &msg, ResponseCodes::FormatError, &responses)
+					.await;
+				continue;
+			};
+
+			if !in_flight.lock().expect("not poisoned; qed").insert(stan.clone()) {
+				log::warn!("Declined ISO-8583 message with in-flight STAN {} from {}", stan, peer);
+				let response_code = ResponseCodes::DuplicateTransmission;
+				self.decline(spec.as_deref(), &msg, response_code, &responses).await;
+				continue;
+			}
+
+			let service = Arc::clone(&self);
+			let spec = spec.clone();
+			let responses = responses.clone();
+			let in_flight = Arc::clone(&in_flight);
+			tokio::spawn(async move {
+				if let Some(response) = service.process(spec.as_deref(), msg).await {
+					// the peer might be gone already
+					let _ = responses.send(response).await;
+				}
+
+				in_flight.lock().expect("not poisoned; qed").remove(&stan);
+				drop(permit);
+			});
+		}
+
+		// writer stops once all in-flight messages dropped their sender
+		drop(responses);
+		writer_task.await.map_err(io::Error::other)?
+	}
+
+	/// Authorize the client of a connection by the API key of its first message and select the
+	/// spec named after it, the one of the listener if there is none
+	fn open(&self, hello: &[u8]) -> Result<(Principal, Option<String>), String> {
+		let hello = std::str::from_utf8(hello).map_err(|_| "invalid API key".to_string())?;
+		let mut parts = hello.split_whitespace();
+		let api_key = parts.next().unwrap_or_default();
+		let spec = parts.next().map(str::to_string).or_else(|| self.spec.clone());
+
+		let principal = self
+			.authenticator
+			.authorize(
+				AUTHORIZED_METHOD,
+				&serde_json::Value::Null,
+				Some(&RpcAuth::ApiKey(api_key.to_string())),
+				None,
+				Utc::now(),
+			)
+			.map_err(|e| e.to_string())?;
+		self.processor.specs.get(spec.as_deref()).map_err(|e| e.to_string())?;
+
+		Ok((principal, spec))
+	}
+
+	/// STAN of the message, `None` if it can't be parsed with the named spec or has no STAN
+	fn stan(&self, spec: Option<&str>, msg: &[u8]) -> Option<String> {
+		let spec = self.processor.specs.get(spec).ok()?;
+		let iso_msg = spec.parse(&mut msg.to_vec()).ok()?;
+
+		if !iso_msg.bmp.is_on(STAN_FIELD_NUMBER) {
+			return None;
+		}
+
+		iso_msg.bmp_child_value(STAN_FIELD_NUMBER).ok()
+	}
+
+	/// Process the message with the named spec, failed messages are declined with a format error
+	/// if they are malformed or unsupported, a system error otherwise
+	async fn process(&self, spec: Option<&str>, msg: Vec<u8>) -> Option<Vec<u8>> {
+		match self.processor.process_with_spec(spec, &mut msg.clone()).await {
+			Ok((raw_iso_msg, iso_msg)) => {
+				log_processed(&iso_msg);
+				if let Some(submitter) = &self.submitter {
+					if let Err(err) = register_on_chain(submitter, iso_msg).await {
+						log::error!("Failed to register the account on-chain: {:?}", err);
+					}
+				}
+				Some(raw_iso_msg)
+			},
+			Err(err) => {
+				log::error!("Failed to process ISO8583 message: {:?}", err.to_string());
+
+				let response_code = match err {
+					DomainError::ApiError(_) => ResponseCodes::FormatError,
+					_ => ResponseCodes::SystemError,
+				};
+				self.processor.decline(spec, &msg, response_code)
+			},
+		}
+	}
+	/// Decline a message that isn't processed, without a response if it can't be parsed
+	async fn decline(
+		&self,
+		spec: Option<&str>,
+		msg: &[u8],
+		response_code: ResponseCodes,
+		responses: &mpsc::Sender<Vec<u8>>,
+	) {
+		if let Some(response) = self.processor.decline(spec, msg, response_code) {
+			// the peer might be gone already
+			let _ = responses.send(response).await;
+		}
+	}
+}
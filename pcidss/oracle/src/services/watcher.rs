@@ -1,82 +1,144 @@
 //! Watcher service subscribes to Substrate chain to maintain constant sync between the chain and
 //! the oracle
 use crate::types::{
-	constants::{PALLET_ACCOUNT, PALLET_NAME},
-	MTI,
+	constants::{
+		CURRENCY_CODE_FIELD_NUMBER, EVENT_ID_FIELD_NUMBER, PALLET_ACCOUNT, PALLET_NAME,
+		WATCHER_CURSOR_NAME,
+	},
+	ResponseCodes, MTI,
 };
 
-use self::iso_8583_chain::runtime_types::bounded_collections::bounded_vec::BoundedVec;
-
 use super::processor::Iso8583MessageProcessor;
-use iso8583_rs::iso8583::{
-	iso_spec::{new_msg, IsoMsg},
-	IsoError,
-};
-use iso_8583_chain::{
-	iso8583::events::{InitiateRevert, InitiateTransfer},
-	runtime_types::pallet_iso_8583::types::{
-		FinalisedTransaction, ISO8583FailureReason, ISO8583Status,
-	},
+use iso8583_rs::iso8583::{iso_spec::new_msg, IsoError};
+use iso_8583_chain::iso8583::events::{InitiateRevert, InitiateTransfer};
+use op_core::{
+	bank_account::models::BankAccount,
+	block_cursor::{models::TrackedBlock, traits::BlockCursorTrait},
+	money::Money,
+	outbox::models::FinalityCreate,
 };
-use op_core::bank_account::models::BankAccount;
-use std::{fmt::Write, str::FromStr, sync::Arc};
+use std::{fmt::Write, ops::RangeInclusive, sync::Arc};
 use subxt::{
-	config::substrate::H256, events::EventDetails, utils::AccountId32, OnlineClient,
-	SubstrateConfig,
+	backend::legacy::LegacyRpcMethods,
+	blocks::Block,
+	config::substrate::H256,
+	events::{EventDetails, StaticEvent},
+	utils::AccountId32,
+	OnlineClient, SubstrateConfig,
 };
-use subxt_signer::sr25519::Keypair;
+use uuid::Uuid;
 
 #[subxt::subxt(runtime_metadata_path = "./iso8583-chain.scale")]
 pub mod iso_8583_chain {}
 
-/// Service for consuming events and submitting finalities of ISO8583 messages on-chain
+/// Service for consuming events and enqueuing finalities of ISO8583 messages
+///
+/// Finalities are submitted on-chain by the [`OutboxWorker`](super::outbox::OutboxWorker).
+/// Progress is persisted as a block cursor, so that the blocks finalized while the oracle was down
+/// are replayed on startup. Replayed events are answered with their stored responses by the
+/// processor, so they are never posted twice.
+///
+/// With `confirmations` set, the watcher follows the best chain instead and processes blocks once
+/// they are that many blocks deep. Hashes of the processed blocks are tracked until they are
+/// finalized. If one of them is retracted by a reorg, the events of it and of the blocks after it
+/// are compensated and the blocks of the new fork are processed instead.
 pub struct WatcherService {
-	/// Keypair for signing transactions
-	pub keypair: Keypair,
 	/// ISO8583 message processor
 	pub processor: Arc<Iso8583MessageProcessor>,
 	/// Substrate client
 	pub client: Arc<OnlineClient<SubstrateConfig>>,
+	/// Legacy RPC methods, used to look up finalized blocks by number
+	pub rpc: LegacyRpcMethods<SubstrateConfig>,
+	/// Cursor of the last fully processed block
+	pub block_cursor: Arc<dyn BlockCursorTrait>,
+	/// Block to start from if there is no cursor yet, the finalized head if `None`
+	pub start_block: Option<u32>,
+	/// Confirmations of best blocks before they are processed, only finalized blocks if `None`
+	pub confirmations: Option<u32>,
+	/// Decimals of on-chain amounts per major unit of the currency
+	pub chain_decimals: u32,
 }
 
 impl WatcherService {
 	/// Create a new watcher service
 	pub(crate) async fn new(
-		keypair: Keypair,
 		processor: Arc<Iso8583MessageProcessor>,
 		client: Arc<OnlineClient<SubstrateConfig>>,
+		rpc: LegacyRpcMethods<SubstrateConfig>,
+		block_cursor: Arc<dyn BlockCursorTrait>,
+		start_block: Option<u32>,
+		chain_decimals: u32,
 	) -> Result<Self, &'static str> {
-		Ok(Self { keypair, processor, client })
+		Ok(Self {
+			processor,
+			client,
+			rpc,
+			block_cursor,
+			start_block,
+			confirmations: None,
+			chain_decimals,
+		})
+	}
+
+	/// Process best blocks once they have the given number of confirmations
+	pub(crate) fn with_confirmations(mut self, confirmations: Option<u32>) -> Self {
+		self.confirmations = confirmations;
+		self
 	}
 
 	/// Start the main processing loop
 	pub async fn start(&self) -> anyhow::Result<()> {
-		// Subscribe to the oracle module
+		let failed = self.block_cursor.find_failed_retractions().await?;
+		if !failed.is_empty() {
+			log::warn!(
+				"Compensation of {} retracted blocks failed, see the failed_retraction table",
+				failed.len()
+			);
+		}
+
+		match self.confirmations {
+			Some(confirmations) => self.follow_best(confirmations).await,
+			None => self.follow_finalized().await,
+		}
+	}
+
+	/// Follow the finalized blocks
+	///
+	/// Catches up with the finalized head first, then follows the new finalized blocks. Every new
+	/// block catches up from the cursor as well, so blocks that failed are retried.
+	async fn follow_finalized(&self) -> anyhow::Result<()> {
+		// Subscribe before catching up, so that no block is missed in between
 		let mut blocks_sub = self.client.blocks().subscribe_finalized().await?;
 
-		// For each block, look for oracle events
+		let head = self.client.blocks().at_latest().await?.number();
+		self.catch_up(head).await?;
+
 		while let Some(block_result) = blocks_sub.next().await {
 			match block_result {
-				Ok(block) => match block.events().await {
-					// get block here
-					Ok(events) => {
-						let block_number = block.number();
-						for event_result in events.iter() {
-							match event_result {
-								Ok(event) => {
-									if let Err(e) = self.process_event(block_number, &event).await {
-										log::error!(
-											"Error processing event: {:?} {:?}",
-											e,
-											event.index()
-										);
-									}
-								},
-								Err(e) => log::error!("Error decoding event: {}", e),
-							}
-						}
+				Ok(block) =>
+					if let Err(e) = self.catch_up(block.number()).await {
+						log::error!("Error processing blocks up to {}: {}", block.number(), e);
 					},
-					Err(e) => log::error!("Error retrieving events: {}", e),
+				Err(e) => log::error!("Error processing block: {}", e),
+			}
+		}
+		Ok(())
+	}
+
+	/// Follow the best blocks, processing them once they have `confirmations` blocks on top
+	///
+	/// Reorgs are checked on every new best block, before the confirmed blocks are processed.
+	async fn follow_best(&self, confirmations: u32) -> anyhow::Result<()> {
+		// the current best block is the first one of the subscription, so nothing is missed
+		let mut blocks_sub = self.client.blocks().subscribe_best().await?;
+
+		while let Some(block_result) = blocks_sub.next().await {
+			match block_result {
+				Ok(block) => {
+					let target = block.number().saturating_sub(confirmations);
+					if let Err(e) = self.follow(target).await {
+						log::error!("Error processing blocks up to {}: {}", target, e);
+					}
 				},
 				Err(e) => log::error!("Error processing block: {}", e),
 			}
@@ -84,10 +146,132 @@ impl WatcherService {
 		Ok(())
 	}
 
+	/// Compensate the retracted blocks, then process the best blocks up to and including `target`
+	async fn follow(&self, target: u32) -> anyhow::Result<()> {
+		self.handle_reorgs().await?;
+		self.catch_up(target).await
+	}
+
+	/// Compare the tracked blocks with the best chain and compensate the retracted ones
+	///
+	/// The cursor is moved back before the first retracted block, so that the blocks of the new
+	/// fork are processed next. Tracked blocks are forgotten once they are finalized.
+	async fn handle_reorgs(&self) -> anyhow::Result<()> {
+		let tracked = self.block_cursor.find_tracked().await?;
+		if tracked.is_empty() {
+			return Ok(())
+		}
+
+		// finalized before the comparison, so that only compared blocks are forgotten
+		let finalized = self.client.blocks().at_latest().await?.number();
+
+		let mut canonical = Vec::with_capacity(tracked.len());
+		for block in &tracked {
+			let hash = self.rpc.chain_get_block_hash(Some(block.block_number.into())).await?;
+			canonical.push(hash.map(|hash| format!("{:?}", hash)));
+		}
+
+		if let Some(fork) = first_retracted(&tracked, &canonical) {
+			log::warn!("Blocks from {} were retracted, compensating their events", fork);
+
+			// the latest events are compensated first, reversals before what they revert. A block
+			// that fails is recorded and left behind, the watcher would be stuck on it otherwise
+			for block in tracked.iter().rev().filter(|block| block.block_number >= fork) {
+				if let Err(e) = self.retract_block(block).await {
+					log::error!(
+						"Could not compensate the events of retracted block {} {}: {}",
+						block.block_number,
+						block.block_hash,
+						e
+					);
+					self.block_cursor.record_failed_retraction(block, &e.to_string()).await?;
+				}
+			}
+
+			self.block_cursor.untrack_from(fork).await?;
+			self.block_cursor.rewind(WATCHER_CURSOR_NAME, fork.saturating_sub(1)).await?;
+		}
+
+		self.block_cursor.untrack(finalized).await?;
+
+		Ok(())
+	}
+
+	/// Compensate the events of a retracted block
+	async fn retract_block(&self, block: &TrackedBlock) -> anyhow::Result<()> {
+		let finalities = self
+			.processor
+			.outbox_controller
+			.find_by_event_block_hash(&block.block_hash)
+			.await?;
+
+		for finality in finalities.iter().rev() {
+			log::info!("Retracting event {} of block {}", finality.event_id, block.block_hash);
+			self.processor.retract_event(finality).await?;
+		}
+
+		Ok(())
+	}
+
+	/// Process the blocks after the cursor up to and including `head`
+	async fn catch_up(&self, head: u32) -> anyhow::Result<()> {
+		let cursor = self.block_cursor.get(WATCHER_CURSOR_NAME).await?;
+		let blocks = blocks_to_process(cursor, self.start_block, head);
+
+		if blocks.clone().count() > 1 {
+			log::info!("Catching up with finalized blocks {:?}", blocks);
+		}
+
+		for block_number in blocks {
+			let hash = self
+				.rpc
+				.chain_get_block_hash(Some(block_number.into()))
+				.await?
+				.ok_or_else(|| anyhow::anyhow!("Block {} not found", block_number))?;
+
+			self.process_block(&self.client.blocks().at(hash).await?).await?;
+		}
+
+		Ok(())
+	}
+
+	/// Process the events of a block and advance the cursor past it
+	///
+	/// Events that can't be decoded are logged and skipped. Other errors are returned before the
+	/// cursor is advanced, so that the block is retried, its events processed before are answered
+	/// with their stored responses then. Blocks that are not finalized yet are tracked before the
+	/// cursor is advanced.
+	async fn process_block(
+		&self,
+		block: &Block<SubstrateConfig, OnlineClient<SubstrateConfig>>,
+	) -> anyhow::Result<()> {
+		let block_number = block.number();
+		let block_hash = format!("{:?}", block.hash());
+
+		for event_result in block.events().await?.iter() {
+			match event_result {
+				Ok(event) =>
+					self.process_event(block_number, &block_hash, &event).await.map_err(|e| {
+						anyhow::anyhow!("Error processing event {}: {}", event.index(), e)
+					})?,
+				Err(e) => log::error!("Error decoding event: {}", e),
+			}
+		}
+
+		if self.confirmations.is_some() {
+			self.block_cursor.track(&TrackedBlock { block_number, block_hash }).await?;
+		}
+
+		self.block_cursor.advance(WATCHER_CURSOR_NAME, block_number).await?;
+
+		Ok(())
+	}
+
 	/// Process a single event
 	pub(crate) async fn process_event(
 		&self,
 		block_number: u32,
+		block_hash: &str,
 		event: &EventDetails<SubstrateConfig>,
 	) -> anyhow::Result<(), Box<dyn std::error::Error>> {
 		if event.pallet_name().contains(PALLET_NAME) {
@@ -96,21 +280,19 @@ impl WatcherService {
 			let event_id = format!("{}-{}", block_number, event.index());
 			match event_name {
 				x if x.contains("InitiateTransfer") => {
-					let decoded_event =
-						event.as_event::<InitiateTransfer>()?.ok_or("Could not decode event")?;
-
-					// spread struct properties into variables
-					let InitiateTransfer { from, to, amount } = decoded_event;
+					let Some(InitiateTransfer { from, to, amount }) = decode(event, &event_id)
+					else {
+						return Ok(())
+					};
 
-					Self::process_transfer(self, from, to, amount, &event_id).await?
+					Self::process_transfer(self, from, to, amount, &event_id, block_hash).await?
 				},
 				x if x.contains("InitiateRevert") => {
-					let decoded_event =
-						event.as_event::<InitiateRevert>()?.ok_or("Could not decode event")?;
+					let Some(InitiateRevert { who, hash }) = decode(event, &event_id) else {
+						return Ok(())
+					};
 
-					let InitiateRevert { who, hash } = decoded_event;
-
-					Self::process_revert(self, who, hash, &event_id).await?
+					Self::process_revert(self, who, hash, &event_id, block_hash).await?
 				},
 				_ => (),
 			}
@@ -119,31 +301,28 @@ impl WatcherService {
 		Ok(())
 	}
 
-	/// Given a `from` and `to` bank account, compose an ISO8583 message
-	pub(crate) fn compose_iso_msg(
+	/// Given a `from` and `to` bank account, compose an ISO8583 message of given type
+	///
+	/// `hash` is the hash of the original transaction, only set for reversals.
+	pub(crate) async fn compose_iso_msg(
 		&self,
+		mti: MTI,
 		from: &BankAccount,
 		to: Option<&BankAccount>,
 		hash: Option<&str>,
-		amount: u128,
+		amount: Money,
 		event_id: &str,
 	) -> anyhow::Result<Vec<u8>, IsoError> {
-		// if tx_hash is set, then it's a revert
-		let mti = {
-			if hash.is_some() {
-				MTI::ReversalRequest
-			} else {
-				MTI::AuthorizationRequest
-			}
-		};
-
-		let spec = self.processor.spec;
+		let spec = self.processor.spec();
 		let mut msg = new_msg(spec, spec.get_message_from_header(mti.clone().into())?);
 
+		// card numbers are only stored encrypted
+		let from_card_number = self.detokenize(from).await?;
+
 		msg.set("message_type", mti.into())?;
-		msg.set_on(2, &from.card_number)?;
+		msg.set_on(2, &from_card_number)?;
 		msg.set_on(3, "000000")?;
-		msg.set_on(4, &format!("{:020}", amount))?;
+		msg.set_on(4, &format!("{:020}", amount.minor_units))?;
 
 		let now = chrono::Utc::now();
 
@@ -151,22 +330,19 @@ impl WatcherService {
 		msg.set_on(12, &format!("{}", now.format("%H%M%S")))?;
 
 		if let Some(to) = to {
-			msg.set_on(32, &to.card_number)?;
+			msg.set_on(32, &self.detokenize(to).await?)?;
 		} else {
-			msg.set_on(32, &from.card_number)?;
+			msg.set_on(32, &from_card_number)?;
 		}
 
+		// CVV is never stored, on-chain events are authenticated by the signature instead
 		msg.set_on(
 			35,
-			&format!(
-				"{}D{}C{}",
-				from.card_number,
-				from.card_expiration_date.format("%m%y"),
-				from.card_cvv
-			),
+			&format!("{}D{}", from_card_number, from.card_expiration_date.format("%m%y")),
 		)?;
 
-		msg.set_on(127, event_id)?;
+		msg.set_on(CURRENCY_CODE_FIELD_NUMBER, amount.currency.numeric())?;
+		msg.set_on(EVENT_ID_FIELD_NUMBER, event_id)?;
 
 		if let Some(hash) = hash {
 			msg.set_on(126, hash)?;
@@ -177,33 +353,74 @@ impl WatcherService {
 		let iso_msg_raw = msg.assemble()?;
 		Ok(iso_msg_raw)
 	}
+
+	/// Decrypt the card number of a bank account
+	async fn detokenize(&self, bank_account: &BankAccount) -> Result<String, IsoError> {
+		self.processor
+			.vault
+			.detokenize(&bank_account.card_data)
+			.await
+			.map(|card| card.card_number)
+			.map_err(|e| IsoError { msg: e.to_string() })
+	}
 }
 
 // Separate utility functions into a separate module
 impl WatcherService {
 	/// Process a transfer event
+	///
+	/// Transfers of unknown bank accounts or invalid amounts are declined without a message.
 	pub(crate) async fn process_transfer(
 		&self,
 		from: AccountId32,
 		to: AccountId32,
 		amount: u128,
 		event_id: &str,
+		event_block_hash: &str,
 	) -> anyhow::Result<(), Box<dyn std::error::Error>> {
 		let (from_hex, to_hex) = (hex::encode(from.0), hex::encode(to.0));
 
+		let finality = FinalityCreate {
+			id: Uuid::new_v4(),
+			event_id: event_id.to_string(),
+			event_block_hash: event_block_hash.to_string(),
+			from: from_hex.clone(),
+			to: to_hex.clone(),
+			amount,
+			response_code: String::new(),
+			transaction_hash: String::new(),
+		};
+
 		let (from_bank_account, to_bank_account) = futures::join!(
 			self.processor.bank_account_controller.find_by_account_id(from_hex.as_str()),
 			self.processor.bank_account_controller.find_by_account_id(to_hex.as_str())
 		);
 
-		let from_bank_account = from_bank_account?.ok_or("From bank account not found")?;
-		let to_bank_account = to_bank_account?.ok_or("To bank account not found")?;
+		let (Some(from_bank_account), Some(to_bank_account)) =
+			(from_bank_account?, to_bank_account?)
+		else {
+			log::warn!("Declining transfer {} of unknown bank accounts", event_id);
+			return Ok(self
+				.processor
+				.decline_on_chain(&finality, ResponseCodes::InvalidCardNumber)
+				.await?)
+		};
 
-		// account for 6 decimal places
-		let offchain_amount = amount / 1_000_000;
-		if offchain_amount == 0 {
-			return Err("Amount must be greater than 0".into());
-		}
+		// amounts with leftover precision are rejected, they can't be posted to the ledger
+		let offchain_amount = match Money::from_chain_units(
+			amount,
+			from_bank_account.balance.currency,
+			self.chain_decimals,
+		) {
+			Ok(offchain_amount) if !offchain_amount.is_zero() => offchain_amount,
+			_ => {
+				log::warn!("Declining transfer {} of invalid amount {}", event_id, amount);
+				return Ok(self
+					.processor
+					.decline_on_chain(&finality, ResponseCodes::InvalidAmount)
+					.await?)
+			},
+		};
 
 		log::debug!(
 			"Processing transaction from: {}, to: {}, amount: {}, event_id: {}",
@@ -213,29 +430,47 @@ impl WatcherService {
 			&event_id
 		);
 
-		// compose ISO8583 message
-		let mut iso_msg_raw = self
+		let mut iso_msg_raw = match self
 			.compose_iso_msg(
+				ON_CHAIN_TRANSFER_MTI,
 				&from_bank_account,
 				Some(&to_bank_account),
 				None,
 				offchain_amount,
 				event_id,
 			)
-			.map_err(|_| "Could not compose ISO8583 message")?;
+			.await
+		{
+			Ok(iso_msg_raw) => iso_msg_raw,
+			Err(e) => {
+				log::error!(
+					"Could not compose ISO8583 message of transfer {}: {}",
+					event_id,
+					e.msg
+				);
+				return Ok(self
+					.processor
+					.decline_on_chain(&finality, ResponseCodes::SystemError)
+					.await?)
+			},
+		};
 
-		let (_, iso_msg) = self.processor.process(&mut iso_msg_raw).await?;
+		self.processor.process_on_chain(&mut iso_msg_raw, &finality).await?;
 
-		// submit finality
-		self.submit_finality(from, to, amount, iso_msg, event_id).await
+		Ok(())
 	}
 
 	/// Process a revert event
+	///
+	/// The event doesn't carry an amount, so the remaining refundable amount of the transaction
+	/// is reverted. Reverts of unknown bank accounts or transactions are declined without a
+	/// message, nothing is moved by them.
 	pub(crate) async fn process_revert(
 		&self,
 		from: AccountId32,
 		hash: H256,
 		event_id: &str,
+		event_block_hash: &str,
 	) -> anyhow::Result<(), Box<dyn std::error::Error>> {
 		let (who_hex, hash_hex): (String, String) = (
 			from.0.iter().fold(String::new(), |mut output, b| {
@@ -250,96 +485,139 @@ impl WatcherService {
 
 		log::debug!("Reverting transaction from: {}, hash: {}", who_hex, hash_hex);
 
+		let declined = FinalityCreate {
+			id: Uuid::new_v4(),
+			event_id: event_id.to_string(),
+			event_block_hash: event_block_hash.to_string(),
+			from: PALLET_ACCOUNT.to_lowercase(),
+			to: hex::encode(from.0),
+			amount: 0,
+			response_code: String::new(),
+			transaction_hash: hex::encode(hash.0),
+		};
+		let decline = |response_code: ResponseCodes, reason: &str| {
+			log::warn!("Declining revert {}: {}", event_id, reason);
+			self.processor.decline_on_chain(&declined, response_code)
+		};
+
 		let (from_bank_account, maybe_transaction) = futures::join!(
 			self.processor.bank_account_controller.find_by_account_id(who_hex.as_str()),
 			self.processor.transaction_controller.find_by_hash(hash_hex.as_str())
 		);
 
-		let from_bank_account = from_bank_account?.ok_or("From bank account not found")?;
+		let Some(from_bank_account) = from_bank_account? else {
+			return Ok(decline(ResponseCodes::InvalidCardNumber, "unknown bank account").await?)
+		};
 
-		// simply try to unwrap the transaction, if it's not found, then it's an error
-		// we do this to avoid doing unnecessary ISO8583 processing and as a naive DDOS
+		// unknown transactions are declined before any ISO8583 processing, as a naive DDOS
 		// protection
-		let transaction = maybe_transaction?.ok_or("Transaction not found")?;
+		let Some(transaction) = maybe_transaction? else {
+			return Ok(decline(ResponseCodes::InvalidTransaction, "unknown transaction").await?)
+		};
 
 		if transaction.from != from_bank_account.id {
-			return Err("Transaction does not belong to the bank account".into());
+			return Ok(decline(
+				ResponseCodes::NotPermitted,
+				"transaction does not belong to the bank account",
+			)
+			.await?)
 		}
 
-		let mut iso_msg_raw = self
-			.compose_iso_msg(&from_bank_account, None, Some(&hash_hex), 0, event_id)
-			.map_err(|_| "Could not compose ISO8583 message")?;
-
-		let (_, iso_msg) = self.processor.process(&mut iso_msg_raw).await?;
-
-		let updated_from = iso_msg.bmp_child_value(127).unwrap_or(PALLET_ACCOUNT.to_string());
-
-		self.submit_finality(
-			AccountId32(
-				hex::decode(updated_from)
-					.expect("valid hex; qed")
-					.try_into()
-					.expect("valid; qed"),
-			),
-			from,
-			transaction.amount as u128 * 1_000_000,
-			iso_msg,
-			event_id,
-		)
-		.await
-	}
+		let offchain_amount = transaction.refundable_amount();
+		if offchain_amount.is_zero() {
+			return Ok(decline(ResponseCodes::InvalidAmount, "nothing to revert").await?)
+		}
 
-	/// Submit a processed ISO8583 message on-chain
-	///
-	/// This is called after an ISO-8583 message has been processed by the ISO8583 message
-	/// processor, both from the watcher service or the RPC server
-	pub(crate) async fn submit_finality(
-		&self,
-		from: AccountId32,
-		to: AccountId32,
-		amount: u128,
-		iso_msg: IsoMsg,
-		event_id: &str,
-	) -> anyhow::Result<(), Box<dyn std::error::Error>> {
-		let private_data =
-			iso_msg.bmp_child_value(126).map_err(|_| "Could not get private data")?;
-
-		let (tx_hash, _) = private_data.split_at(64);
-
-		let response_code =
-			iso_msg.bmp_child_value(39).map_err(|_| "Could not get response code")?;
-
-		let status = match &response_code[..] {
-			"00" => ISO8583Status::Approved,
-			"05" => ISO8583Status::Failed(ISO8583FailureReason::DoNotHonor),
-			"12" => ISO8583Status::Failed(ISO8583FailureReason::InvalidTransaction),
-			"14" => ISO8583Status::Failed(ISO8583FailureReason::InvalidCardNumber),
-			"51" => ISO8583Status::Failed(ISO8583FailureReason::InsufficientFunds),
-			"54" => ISO8583Status::Failed(ISO8583FailureReason::ExpiredCard),
-			_ => ISO8583Status::Failed(ISO8583FailureReason::Other),
+		// funds are returned by the beneficiary, or by the pallet if it is not on-chain
+		let beneficiary = match transaction.to {
+			Some(to) => self.processor.bank_account_controller.find_by_id(&to).await?,
+			None => None,
 		};
 
-		let finalised_transacton = FinalisedTransaction {
-			hash: H256::from_str(tx_hash.strip_prefix("0x").unwrap_or(tx_hash))
-				.expect("valid hash; qed"),
-			event_id: BoundedVec::<u8>(event_id.as_bytes().to_vec()),
-			from,
-			to,
-			amount,
-			status,
+		let mut iso_msg_raw = match self
+			.compose_iso_msg(
+				MTI::ReversalRequest,
+				&from_bank_account,
+				beneficiary.as_ref(),
+				Some(&hash_hex),
+				offchain_amount,
+				event_id,
+			)
+			.await
+		{
+			Ok(iso_msg_raw) => iso_msg_raw,
+			Err(e) => {
+				log::error!("Could not compose ISO8583 message of revert {}: {}", event_id, e.msg);
+				return Ok(decline(ResponseCodes::SystemError, "message can't be composed").await?)
+			},
 		};
 
-		log::debug!("Submitting finality: {:?}", finalised_transacton);
+		let updated_from = beneficiary
+			.and_then(|bank_account| bank_account.account_id)
+			.unwrap_or(PALLET_ACCOUNT.to_string());
 
-		let finalised_transaction_tx =
-			iso_8583_chain::tx().iso8583().submit_finality(finalised_transacton);
+		let finality = FinalityCreate {
+			from: updated_from.to_lowercase(),
+			amount: offchain_amount.to_chain_units(self.chain_decimals)?,
+			transaction_hash: String::new(),
+			..declined.clone()
+		};
 
-		// don't wait for the transaction to be included in a block, submit and forget
-		self.client
-			.tx()
-			.sign_and_submit_default(&finalised_transaction_tx, &self.keypair)
-			.await?;
+		self.processor.process_on_chain(&mut iso_msg_raw, &finality).await?;
 
 		Ok(())
 	}
 }
+
+/// MTI of the messages composed from on-chain transfers
+///
+/// Transfers are final once their block is processed, so they are posted right away as financial
+/// requests. Authorization requests would only put the amount on hold until a completion that
+/// never comes for on-chain transfers.
+pub(crate) const ON_CHAIN_TRANSFER_MTI: MTI = MTI::FinancialRequest;
+
+/// Decode an event of the pallet, `None` if it doesn't match the metadata
+///
+/// Such events are logged and skipped, processing them again would fail the same way.
+fn decode<E: StaticEvent>(event: &EventDetails<SubstrateConfig>, event_id: &str) -> Option<E> {
+	match event.as_event::<E>() {
+		Ok(Some(decoded_event)) => Some(decoded_event),
+		Ok(None) => {
+			log::error!("Event {} is not a {}", event_id, E::EVENT);
+			None
+		},
+		Err(e) => {
+			log::error!("Could not decode event {}: {}", event_id, e);
+			None
+		},
+	}
+}
+
+/// First tracked block whose hash is not the one of the best chain anymore
+///
+/// `canonical` holds the hashes of the best chain at the numbers of the `tracked` blocks, `None`
+/// if the best chain is shorter now.
+pub(crate) fn first_retracted(
+	tracked: &[TrackedBlock],
+	canonical: &[Option<String>],
+) -> Option<u32> {
+	tracked
+		.iter()
+		.zip(canonical)
+		.find(|(block, hash)| hash.as_deref() != Some(block.block_hash.as_str()))
+		.map(|(block, _)| block.block_number)
+}
+
+/// Blocks to process up to and including `head`
+///
+/// Starts after the cursor, or at `start_block` (the head if `None`) if there is no cursor yet.
+pub(crate) fn blocks_to_process(
+	cursor: Option<u32>,
+	start_block: Option<u32>,
+	head: u32,
+) -> RangeInclusive<u32> {
+	match cursor {
+		Some(cursor) => cursor.saturating_add(1)..=head,
+		None => start_block.unwrap_or(head)..=head,
+	}
+}
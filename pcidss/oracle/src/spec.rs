@@ -0,0 +1,194 @@
+//! ISO-8583 specification loading
+
+use std::{collections::HashMap, sync::Mutex};
+
+use iso8583_rs::iso8583::{
+	field::Encoding,
+	iso_spec::Spec,
+	yaml_de::{YField, YSpec},
+};
+use op_core::error::DomainError;
+
+/// Specification embedded into the binary, used when no specification file is given
+pub const EMBEDDED_SPEC: &str = include_str!("../../spec.yaml");
+
+/// Name of the specification that is used when none is selected
+pub const DEFAULT_SPEC_NAME: &str = "default";
+
+/// Specifications parsed so far, each one is only leaked once
+static PARSED_SPECS: Mutex<Vec<ParsedSpec>> = Mutex::new(Vec::new());
+
+/// Specification parsed from YAML, with the formats of the fields the codec doesn't expose
+struct ParsedSpec {
+	/// YAML the specification is parsed from
+	yaml: String,
+	/// Specification
+	spec: &'static Spec,
+	/// Messages of the specification
+	layout: SpecLayout,
+}
+
+/// Messages of a specification, as defined in YAML
+#[derive(serde::Deserialize)]
+struct SpecLayout {
+	messages: Vec<MessageLayout>,
+}
+
+/// Message of a specification, as defined in YAML
+#[derive(serde::Deserialize)]
+struct MessageLayout {
+	/// MTIs of the message
+	selector: Vec<String>,
+	/// Top level fields, the bitmap being one of them
+	fields: Vec<YField>,
+}
+
+/// Format of a field of a message
+#[derive(Debug, Clone, Copy)]
+pub struct FieldFormat {
+	/// Encoding of the value
+	pub encoding: Encoding,
+	/// Length of a fixed field, number of bytes of the length indicator of a variable one
+	pub len: u32,
+	/// Encoding of the length indicator, `None` for fixed fields
+	pub len_encoding: Option<Encoding>,
+}
+
+impl FieldFormat {
+	/// Can the value be encoded in the field?
+	///
+	/// Values are ASCII, hex for binary fields, of the exact length of fixed fields and not
+	/// longer than what the length indicator of variable fields can hold.
+	pub fn accepts(&self, value: &str) -> bool {
+		if !value.is_ascii() {
+			return false
+		}
+
+		let len = match self.encoding {
+			Encoding::ASCII | Encoding::EBCDIC => value.len(),
+			Encoding::BINARY | Encoding::BCD => {
+				if !value.len().is_multiple_of(2) || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+					return false
+				}
+				value.len() / 2
+			},
+		};
+
+		let max_len = match (self.len_encoding, self.len) {
+			(None, _) => return len == self.len as usize,
+			(Some(Encoding::ASCII | Encoding::EBCDIC), 1..=3) => 10usize.pow(self.len) - 1,
+			(Some(Encoding::BCD), 1..=2) => 10usize.pow(2 * self.len) - 1,
+			(Some(Encoding::BINARY), 1..=2) => 256usize.pow(self.len) - 1,
+			_ => return false,
+		};
+
+		len <= max_len
+	}
+}
+
+/// Named ISO-8583 specifications of a processor
+///
+/// One of them is the default, the others can be selected per connection, e.g. one per acquirer.
+#[derive(Clone)]
+pub struct SpecLoader {
+	/// Specifications by name
+	specs: HashMap<String, &'static Spec>,
+	/// Name of the default specification
+	default: String,
+}
+
+impl SpecLoader {
+	/// Create a new loader with the default specification
+	pub fn new(name: &str, spec: &'static Spec) -> Self {
+		Self { specs: HashMap::from([(name.to_string(), spec)]), default: name.to_string() }
+	}
+
+	/// Create a new loader with the embedded specification as the default
+	pub fn embedded() -> Result<Self, DomainError> {
+		Ok(Self::new(DEFAULT_SPEC_NAME, Self::parse(EMBEDDED_SPEC)?))
+	}
+
+	/// Add a named specification, replaces the one with the same name
+	pub fn with_spec(mut self, name: &str, spec: &'static Spec) -> Self {
+		self.specs.insert(name.to_string(), spec);
+		self
+	}
+
+	/// Load a specification from a YAML file
+	///
+	/// Specifications live as long as the process, messages parsed with them borrow them
+	/// statically.
+	pub fn load(path: &str) -> Result<&'static Spec, DomainError> {
+		let yaml = std::fs::read_to_string(path).map_err(|e| {
+			DomainError::InternalServerError(format!("Could not read spec {}: {}", path, e))
+		})?;
+
+		Self::parse(&yaml)
+	}
+
+	/// Parse a specification from YAML
+	///
+	/// The same YAML always gives the same specification, it is only parsed the first time.
+	pub fn parse(yaml: &str) -> Result<&'static Spec, DomainError> {
+		let mut parsed = PARSED_SPECS.lock().expect("not poisoned; qed");
+		if let Some(parsed) = parsed.iter().find(|parsed| parsed.yaml == yaml) {
+			return Ok(parsed.spec)
+		}
+
+		let invalid =
+			|e: serde_yaml::Error| DomainError::InternalServerError(format!("Invalid spec: {}", e));
+		let spec: YSpec = serde_yaml::from_str(yaml).map_err(invalid)?;
+		let layout: SpecLayout = serde_yaml::from_str(yaml).map_err(invalid)?;
+
+		let spec: &'static Spec = Box::leak(Box::new(spec.into()));
+		parsed.push(ParsedSpec { yaml: yaml.to_string(), spec, layout });
+
+		Ok(spec)
+	}
+
+	/// Format of the field at the position of the bitmap of the message with the MTI, `None` if
+	/// the specification doesn't define it
+	pub fn field_format(spec: &'static Spec, mti: &str, position: u32) -> Option<FieldFormat> {
+		let parsed = PARSED_SPECS.lock().expect("not poisoned; qed");
+		let parsed = parsed.iter().find(|parsed| std::ptr::eq(parsed.spec, spec))?;
+
+		let field = parsed
+			.layout
+			.messages
+			.iter()
+			.find(|message| message.selector.iter().any(|selector| selector == mti))?
+			.fields
+			.iter()
+			.filter_map(|field| field.children.as_ref())
+			.flatten()
+			.find(|field| field.position == Some(position))?;
+
+		Some(FieldFormat {
+			encoding: field.data_encoding,
+			len: field.len,
+			len_encoding: (field.field_type == "Variable").then_some(field.len_encoding).flatten(),
+		})
+	}
+
+	/// Default specification
+	pub fn default_spec(&self) -> &'static Spec {
+		self.specs[&self.default]
+	}
+
+	/// Specification by name, the default one if no name is given
+	pub fn get(&self, name: Option<&str>) -> Result<&'static Spec, DomainError> {
+		match name {
+			Some(name) => self
+				.specs
+				.get(name)
+				.copied()
+				.ok_or(DomainError::BadRequest(format!("Unknown spec {}", name))),
+			None => Ok(self.default_spec()),
+		}
+	}
+
+	/// Names of the loaded specifications
+	pub fn names(&self) -> Vec<&str> {
+		self.specs.keys().map(String::as_str).collect()
+	}
+}
@@ -0,0 +1,350 @@
+//! Tests for the authentication and authorization of the RPC requests
+
+use std::collections::HashSet;
+
+use chrono::{Duration, Utc};
+use serde_json::json;
+use subxt::ext::codec::Encode;
+use subxt_signer::sr25519::{dev, Keypair};
+
+use crate::auth::{
+	AuthConfig, AuthError, Authenticator, BalancesRequest, BalancesRequestVerifier, Role, RpcAuth,
+};
+
+/// Signs the request with the keypair at the given timestamp
+fn sign(keypair: &Keypair, method: &str, params: &serde_json::Value, timestamp: i64) -> RpcAuth {
+	let payload = crate::auth::signing_payload(method, params, timestamp);
+
+	RpcAuth::Signed {
+		signer: hex::encode(keypair.public_key().0),
+		timestamp,
+		signature: hex::encode(keypair.sign(&payload).0),
+	}
+}
+
+fn authenticator() -> Authenticator {
+	let config = AuthConfig::parse(&format!(
+		r#"
+clock_skew_secs: 30
+api_keys:
+  - {{ name: acquirer-a, key_sha256: "{}", role: acquirer }}
+signers:
+  - {{ name: backoffice, public_key: "{}", role: admin }}
+"#,
+		crate::auth::hash_api_key("acquirer-key"),
+		hex::encode(dev::bob().public_key().0)
+	))
+	.unwrap()
+	.with_api_key("merchant", "merchant-key", Role::Merchant);
+
+	Authenticator::new(config).unwrap()
+}
+
+/// Tests API keys are authorized by role
+#[test]
+fn test_api_keys() {
+	let auth = authenticator();
+	let now = Utc::now();
+	let params = json!([[1, 2, 3], null]);
+	let api_key = |key: &str| Some(RpcAuth::ApiKey(key.to_string()));
+
+	let principal = auth
+		.authorize("pcidss_submit_iso8583", &params, api_key("acquirer-key").as_ref(), None, now)
+		.unwrap();
+	assert_eq!(principal.name, "acquirer-a");
+	assert_eq!(principal.role, Some(Role::Acquirer));
+
+	let principal = auth
+		.authorize("pcidss_submit_iso8583", &params, api_key("merchant-key").as_ref(), None, now)
+		.unwrap();
+	assert_eq!(principal.role, Some(Role::Merchant));
+
+	// missing or unknown API key
+	assert!(matches!(
+		auth.authorize("pcidss_submit_iso8583", &params, None, None, now),
+		Err(AuthError::Unauthorized(_))
+	));
+	assert!(matches!(
+		auth.authorize("pcidss_submit_iso8583", &params, api_key("other-key").as_ref(), None, now),
+		Err(AuthError::Unauthorized(_))
+	));
+
+	// admin and owner methods
+	let params = json!([uuid::Uuid::new_v4(), "blocked", null]);
+	assert_eq!(
+		auth.authorize(
+			"pcidss_set_card_status",
+			&params,
+			api_key("acquirer-key").as_ref(),
+			None,
+			now
+		),
+		Err(AuthError::Forbidden("pcidss_set_card_status".to_string()))
+	);
+
+	let account_id = hex::encode(dev::alice().public_key().0);
+	assert!(matches!(
+		auth.authorize(
+			"pcidss_get_bank_account",
+			&json!([account_id]),
+			api_key("merchant-key").as_ref(),
+			Some(&account_id),
+			now
+		),
+		Err(AuthError::Forbidden(_))
+	));
+}
+
+/// Tests signed requests authenticate owners and signers with a role
+#[test]
+fn test_signed_requests() {
+	let auth = authenticator();
+	let now = Utc::now();
+	let alice = dev::alice();
+	let alice_account = hex::encode(alice.public_key().0);
+	let charlie_account = hex::encode(dev::charlie().public_key().0);
+
+	// owner of the account
+	let params = json!([alice_account]);
+	let signed = sign(&alice, "pcidss_get_bank_account", &params, now.timestamp());
+	let principal = auth
+		.authorize("pcidss_get_bank_account", &params, Some(&signed), Some(&alice_account), now)
+		.unwrap();
+	assert_eq!(principal.role, None);
+	assert_eq!(principal.account_id, Some(alice_account.clone()));
+
+	// hex prefix and case of the account don't matter
+	let prefixed = format!("0x{}", alice_account.to_uppercase());
+	let params = json!([prefixed]);
+	let signed = sign(&alice, "pcidss_get_transactions", &params, now.timestamp());
+	assert!(auth
+		.authorize("pcidss_get_transactions", &params, Some(&signed), Some(&prefixed), now)
+		.is_ok());
+
+	// not the owner
+	let params = json!([charlie_account]);
+	let signed = sign(&alice, "pcidss_get_bank_account", &params, now.timestamp());
+	assert_eq!(
+		auth.authorize(
+			"pcidss_get_bank_account",
+			&params,
+			Some(&signed),
+			Some(&charlie_account),
+			now
+		),
+		Err(AuthError::Forbidden("pcidss_get_bank_account".to_string()))
+	);
+
+	// owners have no role
+	let params = json!([[1, 2, 3], null]);
+	let signed = sign(&alice, "pcidss_submit_iso8583", &params, now.timestamp());
+	assert!(matches!(
+		auth.authorize("pcidss_submit_iso8583", &params, Some(&signed), None, now),
+		Err(AuthError::Forbidden(_))
+	));
+
+	// signer with the admin role
+	let params = json!([uuid::Uuid::new_v4()]);
+	let signed = sign(&dev::bob(), "pcidss_get_card_status_history", &params, now.timestamp());
+	let principal = auth
+		.authorize("pcidss_get_card_status_history", &params, Some(&signed), None, now)
+		.unwrap();
+	assert_eq!(principal.name, "backoffice");
+	assert_eq!(principal.role, Some(Role::Admin));
+
+	// signature of another method or other params
+	let params = json!([alice_account]);
+	let signed = sign(&alice, "pcidss_get_transactions", &params, now.timestamp());
+	assert_eq!(
+		auth.authorize(
+			"pcidss_get_bank_account",
+			&params,
+			Some(&signed),
+			Some(&alice_account),
+			now
+		),
+		Err(AuthError::Unauthorized("invalid signature".to_string()))
+	);
+
+	let signed =
+		sign(&alice, "pcidss_get_bank_account", &json!([charlie_account]), now.timestamp());
+	assert_eq!(
+		auth.authorize(
+			"pcidss_get_bank_account",
+			&params,
+			Some(&signed),
+			Some(&alice_account),
+			now
+		),
+		Err(AuthError::Unauthorized("invalid signature".to_string()))
+	);
+
+	// payloads wrapped by wallets
+	let timestamp = now.timestamp() - 1;
+	let payload = crate::auth::signing_payload("pcidss_get_bank_account", &params, timestamp);
+	let wrapped = [b"<Bytes>".as_slice(), &payload, b"</Bytes>"].concat();
+	let signed = RpcAuth::Signed {
+		signer: alice_account.clone(),
+		timestamp,
+		signature: hex::encode(alice.sign(&wrapped).0),
+	};
+	assert!(auth
+		.authorize("pcidss_get_bank_account", &params, Some(&signed), Some(&alice_account), now)
+		.is_ok());
+}
+
+/// Tests stale and replayed signed requests are rejected
+#[test]
+fn test_replay_protection() {
+	let auth = authenticator();
+	let now = Utc::now();
+	let alice = dev::alice();
+	let alice_account = hex::encode(alice.public_key().0);
+	let params = json!([alice_account]);
+	let method = "pcidss_get_bank_account";
+
+	let signed = sign(&alice, method, &params, now.timestamp());
+	assert!(auth
+		.authorize(method, &params, Some(&signed), Some(&alice_account), now)
+		.is_ok());
+	assert_eq!(
+		auth.authorize(method, &params, Some(&signed), Some(&alice_account), now),
+		Err(AuthError::Unauthorized("replayed request".to_string()))
+	);
+
+	// still rejected as stale once forgotten
+	let later = now + Duration::seconds(31);
+	assert_eq!(
+		auth.authorize(method, &params, Some(&signed), Some(&alice_account), later),
+		Err(AuthError::Unauthorized("stale request".to_string()))
+	);
+
+	// requests from the future
+	let signed = sign(&alice, method, &params, now.timestamp() + 31);
+	assert_eq!(
+		auth.authorize(method, &params, Some(&signed), Some(&alice_account), now),
+		Err(AuthError::Unauthorized("stale request".to_string()))
+	);
+
+	// a new signature of the same request is accepted
+	let signed = sign(&alice, method, &params, now.timestamp() + 1);
+	assert!(auth
+		.authorize(method, &params, Some(&signed), Some(&alice_account), now)
+		.is_ok());
+}
+
+/// Tests the roles of a method can be configured
+#[test]
+fn test_permissions() {
+	let config = AuthConfig::parse(
+		r#"
+permissions:
+  pcidss_get_transactions: [merchant, admin]
+  pcidss_submit_iso8583: [acquirer]
+"#,
+	)
+	.unwrap()
+	.with_api_key("merchant", "merchant-key", Role::Merchant);
+	let auth = Authenticator::new(config).unwrap();
+	let api_key = Some(RpcAuth::ApiKey("merchant-key".to_string()));
+	let now = Utc::now();
+
+	assert!(auth
+		.authorize("pcidss_get_transactions", &json!(["00"]), api_key.as_ref(), Some("00"), now)
+		.is_ok());
+	assert!(matches!(
+		auth.authorize("pcidss_submit_iso8583", &json!([[], null]), api_key.as_ref(), None, now),
+		Err(AuthError::Forbidden(_))
+	));
+	// permissions are per method, the fields variant keeps its default roles
+	let params = json!(["0200", {}, null]);
+	assert!(auth
+		.authorize("pcidss_submit_iso8583_fields", &params, api_key.as_ref(), None, now)
+		.is_ok());
+
+	// unknown fields and invalid signers
+	assert!(AuthConfig::parse("api_key: []").is_err());
+	let config =
+		AuthConfig::parse("signers: [{ name: a, public_key: '00', role: admin }]").unwrap();
+	assert!(Authenticator::new(config).is_err());
+}
+
+/// Signs the balance request with the keypair, returns the payload and the signature
+fn sign_request(keypair: &Keypair, request: &BalancesRequest) -> (Vec<u8>, Vec<u8>) {
+	let payload = request.encode();
+	let signature = keypair.sign(&payload).0.to_vec();
+
+	(payload, signature)
+}
+
+/// Tests balance requests of the offchain workers are only accepted once, while fresh and from
+/// an authorized key
+#[test]
+fn test_balances_requests() {
+	let verifier = BalancesRequestVerifier::new(10);
+	let (alice, bob) = (dev::alice(), dev::bob());
+	verifier.set_keys(HashSet::from([alice.public_key().0, bob.public_key().0]));
+	verifier.set_best_block(100);
+
+	let unauthorized = |message: &str| Err(AuthError::Unauthorized(message.to_string()));
+	let account_ids = vec![dev::charlie().public_key().0, dev::dave().public_key().0];
+
+	// several authorized keys
+	let request = BalancesRequest::new(alice.public_key().0, 100, 1, account_ids.clone());
+	let (payload, signature) = sign_request(&alice, &request);
+	assert_eq!(verifier.verify(&payload, &signature), Ok(request));
+
+	let request = BalancesRequest::new(bob.public_key().0, 95, 1, account_ids.clone());
+	let (payload, signature) = sign_request(&bob, &request);
+	assert!(verifier.verify(&payload, &signature).is_ok());
+
+	// replayed payload, or reused nonce
+	assert_eq!(verifier.verify(&payload, &signature), unauthorized("replayed request"));
+
+	let request = BalancesRequest::new(bob.public_key().0, 96, 1, account_ids.clone());
+	let (payload, signature) = sign_request(&bob, &request);
+	assert_eq!(verifier.verify(&payload, &signature), unauthorized("replayed request"));
+
+	// stale requests
+	for block_number in [89, 111] {
+		let request =
+			BalancesRequest::new(alice.public_key().0, block_number, 2, account_ids.clone());
+		let (payload, signature) = sign_request(&alice, &request);
+		assert_eq!(verifier.verify(&payload, &signature), unauthorized("stale request"));
+	}
+
+	// nonces can be reused once their requests are stale
+	verifier.set_best_block(120);
+	let request = BalancesRequest::new(bob.public_key().0, 120, 1, account_ids.clone());
+	let (payload, signature) = sign_request(&bob, &request);
+	assert!(verifier.verify(&payload, &signature).is_ok());
+
+	// keys that are not authorized, or not the signer
+	let request = BalancesRequest::new(dev::eve().public_key().0, 120, 3, account_ids.clone());
+	let (payload, signature) = sign_request(&dev::eve(), &request);
+	assert_eq!(verifier.verify(&payload, &signature), unauthorized("unknown signer"));
+
+	let request = BalancesRequest::new(alice.public_key().0, 120, 3, account_ids.clone());
+	let (payload, signature) = sign_request(&bob, &request);
+	assert_eq!(verifier.verify(&payload, &signature), unauthorized("invalid signature"));
+
+	verifier.set_keys(HashSet::from([bob.public_key().0]));
+	let (payload, signature) = sign_request(&alice, &request);
+	assert_eq!(verifier.verify(&payload, &signature), unauthorized("unknown signer"));
+
+	// other domains and versions
+	let request = BalancesRequest { domain: b"other".to_vec(), ..request };
+	let (payload, signature) = sign_request(&bob, &request);
+	assert_eq!(verifier.verify(&payload, &signature), unauthorized("invalid domain"));
+
+	let request = BalancesRequest { version: 2, ..request };
+	let (payload, signature) = sign_request(&bob, &request);
+	assert_eq!(verifier.verify(&payload, &signature), unauthorized("unsupported request version"));
+
+	// trailing bytes
+	let request = BalancesRequest::new(bob.public_key().0, 120, 4, account_ids);
+	let mut payload = request.encode();
+	payload.push(0);
+	let signature = bob.sign(&payload).0.to_vec();
+	assert_eq!(verifier.verify(&payload, &signature), unauthorized("invalid request"));
+}
@@ -0,0 +1,218 @@
+//! Tests for the card lifecycle: status changes, renewals and reissues
+
+use chrono::{Months, Utc};
+use iso8583_rs::iso8583::iso_spec::IsoMsg;
+use op_core::bank_account::models::{BankAccountUpdate, CardStatus};
+
+use crate::{
+	tests::{mock::*, prelude::*},
+	types::{DevAccount, MTI},
+};
+
+/// Alice's card reissued with a new card number and CVV
+const ALICE_REISSUED: DevAccount = ("Alice", "4169812345678990", "321", 1000, None);
+
+/// Tests the status of the card is checked before the payment and recorded in its history
+#[tokio::test]
+async fn test_card_status() {
+	let api = MockProcessorImpl::new(Some("card_status_db".to_string())).await;
+	let controller = &api.processor.bank_account_controller;
+	let status = |status, reason: &str| BankAccountUpdate::Status {
+		status,
+		reason: Some(reason.to_string()),
+	};
+
+	let alice = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let charlie = get_bank_account_by_card_number(&api, CHARLIE.1).await;
+
+	// RESTRICTED CARD
+	controller
+		.update(&alice.id, &status(CardStatus::Blocked, "frozen by holder"))
+		.await
+		.unwrap();
+	assert_eq!(
+		process_payment(&api.processor, MTI::FinancialRequest, ALICE, 100, |_| {})
+			.await
+			.0,
+		"62"
+	);
+
+	controller
+		.update(&alice.id, &status(CardStatus::Active, "unfrozen"))
+		.await
+		.unwrap();
+	assert_eq!(
+		process_payment(&api.processor, MTI::FinancialRequest, ALICE, 100, |_| {})
+			.await
+			.0,
+		"00"
+	);
+
+	// LOST CARD
+	controller
+		.update(&alice.id, &status(CardStatus::Lost, "reported lost"))
+		.await
+		.unwrap();
+	assert_eq!(
+		process_payment(&api.processor, MTI::FinancialRequest, ALICE, 100, |_| {})
+			.await
+			.0,
+		"41"
+	);
+
+	// lost cards can't be reactivated
+	assert!(controller
+		.update(&alice.id, &status(CardStatus::Active, "found"))
+		.await
+		.is_err());
+
+	// STOLEN CARD
+	controller
+		.update(&charlie.id, &status(CardStatus::Stolen, "reported stolen"))
+		.await
+		.unwrap();
+	assert_eq!(
+		process_payment(&api.processor, MTI::FinancialRequest, CHARLIE, 100, |_| {})
+			.await
+			.0,
+		"43"
+	);
+
+	// reissued card has a new card number, the old one is unknown
+	let card_expiration_date = Utc::now().checked_add_months(Months::new(48)).unwrap();
+	let reissued = controller
+		.update(
+			&alice.id,
+			&BankAccountUpdate::Reissue {
+				card_number: ALICE_REISSUED.1.to_string(),
+				card_cvv: ALICE_REISSUED.2.to_string(),
+				card_expiration_date,
+			},
+		)
+		.await
+		.unwrap();
+
+	assert_eq!(reissued.card_status, CardStatus::Active);
+	assert_eq!(reissued.card_number_masked, "416981******8990");
+	assert_eq!(reissued.balance.minor_units, ALICE.3 - 100);
+	assert_eq!(reissued.nonce, alice.nonce + 1);
+
+	assert_eq!(
+		process_payment(&api.processor, MTI::FinancialRequest, ALICE, 100, |_| {})
+			.await
+			.0,
+		"14"
+	);
+	assert_eq!(
+		process_payment(&api.processor, MTI::FinancialRequest, ALICE_REISSUED, 100, |_| {})
+			.await
+			.0,
+		"00"
+	);
+
+	let card = api.processor.vault.detokenize(&reissued.card_data).await.unwrap();
+	assert_eq!(card.card_number, ALICE_REISSUED.1);
+	assert_eq!(card.card_holder_first_name, ALICE.0);
+
+	// history of the changes, with the card they apply to
+	let history = controller.find_status_history(&alice.id).await.unwrap();
+	let changes: Vec<_> = history
+		.iter()
+		.map(|change| (change.from_status, change.to_status, change.card_number_masked.as_str()))
+		.collect();
+
+	assert_eq!(
+		changes,
+		vec![
+			(CardStatus::Active, CardStatus::Blocked, "416981******8901"),
+			(CardStatus::Blocked, CardStatus::Active, "416981******8901"),
+			(CardStatus::Active, CardStatus::Lost, "416981******8901"),
+			(CardStatus::Lost, CardStatus::Active, "416981******8901"),
+		]
+	);
+	assert_eq!(history[0].reason.as_deref(), Some("frozen by holder"));
+	assert_eq!(history[3].reason.as_deref(), Some("Reissued as 416981******8990"));
+
+	// closed cards can't be used nor reissued
+	controller
+		.update(&alice.id, &status(CardStatus::Closed, "account closed"))
+		.await
+		.unwrap();
+	assert_eq!(
+		process_payment(&api.processor, MTI::FinancialRequest, ALICE_REISSUED, 100, |_| {})
+			.await
+			.0,
+		"62"
+	);
+	assert!(controller
+		.update(
+			&alice.id,
+			&BankAccountUpdate::Reissue {
+				card_number: "4169812345678991".to_string(),
+				card_cvv: "321".to_string(),
+				card_expiration_date,
+			},
+		)
+		.await
+		.is_err());
+}
+
+/// Tests a renewed card is only accepted with its new expiration date and CVV
+#[tokio::test]
+async fn test_card_renewal() {
+	let api = MockProcessorImpl::new(Some("card_renewal_db".to_string())).await;
+	let dave = get_bank_account_by_card_number(&api, DAVE.1).await;
+
+	let card_expiration_date =
+		dave.card_expiration_date.checked_add_months(Months::new(12)).unwrap();
+	let renewed = api
+		.processor
+		.bank_account_controller
+		.update(
+			&dave.id,
+			&BankAccountUpdate::Renew { card_expiration_date, card_cvv: Some("999".to_string()) },
+		)
+		.await
+		.unwrap();
+
+	assert_eq!(renewed.card_number_masked, dave.card_number_masked);
+	assert_eq!(renewed.card_expiration_date, card_expiration_date);
+
+	let expiration = card_expiration_date.format("%m%y");
+
+	// EXPIRED CARD with the previous expiration date
+	assert_eq!(
+		process_payment(&api.processor, MTI::FinancialRequest, DAVE, 100, |_| {})
+			.await
+			.0,
+		"54"
+	);
+
+	// DO NOT HONOR with the previous CVV
+	let track_2_data = format!("{}D{}C{}", DAVE.1, expiration, DAVE.2);
+	let set_track_2_data = |msg: &mut IsoMsg| msg.set_on(35, &track_2_data).unwrap();
+	assert_eq!(
+		process_payment(&api.processor, MTI::FinancialRequest, DAVE, 100, set_track_2_data)
+			.await
+			.0,
+		"05"
+	);
+
+	let track_2_data = format!("{}D{}C999", DAVE.1, expiration);
+	let set_track_2_data = |msg: &mut IsoMsg| msg.set_on(35, &track_2_data).unwrap();
+	assert_eq!(
+		process_payment(&api.processor, MTI::FinancialRequest, DAVE, 100, set_track_2_data)
+			.await
+			.0,
+		"00"
+	);
+
+	// renewals don't change the status
+	assert!(api
+		.processor
+		.bank_account_controller
+		.find_status_history(&dave.id)
+		.await
+		.unwrap()
+		.is_empty());
+}
@@ -0,0 +1,75 @@
+//! Tests for the transaction events published by the processor
+use tokio::sync::broadcast::error::TryRecvError;
+
+use crate::{
+	events::TransactionEventKind,
+	tests::{mock::*, prelude::*},
+	types::MTI,
+};
+
+/// Tests posted and reversed transactions are published to the subscribers
+#[tokio::test]
+async fn test_transaction_events() {
+	let api = MockProcessorImpl::new(Some("transaction_events_db".to_string())).await;
+	let spec = api.processor.spec();
+
+	let alice = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let acquirer = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
+	let charlie = get_bank_account_by_card_number(&api, CHARLIE.1).await;
+
+	let mut events = api.processor.events.subscribe();
+
+	// Alice pays 100 to the acquirer
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let posted = events.try_recv().unwrap();
+	assert_eq!(posted.kind, TransactionEventKind::Posted);
+	assert_eq!(posted.transaction.amount.minor_units, 100);
+	assert!(posted.concerns(&alice.id));
+	assert!(posted.concerns(&acquirer.id));
+	assert!(!posted.concerns(&charlie.id));
+
+	// declined payments aren't published
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, &format!("{:020}", ALICE.3 + 1)).unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+	assert_ne!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(events.try_recv().unwrap_err(), TryRecvError::Empty);
+
+	// Alice gets 30 back
+	let mut reversal_msg = get_new_iso_msg(spec, MTI::ReversalRequest, ALICE);
+	reversal_msg.set_on(4, "00000000000000000030").unwrap();
+	reversal_msg.set_on(126, &posted.transaction.hash).unwrap();
+
+	let mut msg_raw = reversal_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let reversed = events.try_recv().unwrap();
+	assert_eq!(reversed.kind, TransactionEventKind::Reversed);
+	assert_eq!(reversed.transaction.parent_id, Some(posted.transaction.id));
+	assert_eq!(reversed.transaction.amount.minor_units, 30);
+	assert!(reversed.concerns(&alice.id));
+	assert!(reversed.concerns(&acquirer.id));
+
+	// events are serialized with the snake case kind
+	let json = serde_json::to_value(&reversed).unwrap();
+	assert_eq!(json["kind"], "reversed");
+	assert_eq!(json["transaction"]["hash"], reversed.transaction.hash.as_str());
+
+	// publishing without subscribers is fine
+	drop(events);
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000010").unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+}
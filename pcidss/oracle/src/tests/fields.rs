@@ -0,0 +1,130 @@
+//! Tests for ISO-8583 messages submitted as field values
+use std::collections::BTreeMap;
+
+use iso8583_rs::iso8583::field::Encoding;
+use op_core::error::DomainError;
+
+use crate::{
+	spec::FieldFormat,
+	tests::{mock::*, prelude::*},
+	types::{IsoFields, MTI},
+};
+
+/// Fields of a financial request of the dev account for the amount
+fn financial_request(api: &MockProcessorImpl, amount: i64) -> BTreeMap<u32, String> {
+	let mut msg = get_new_iso_msg(api.processor.spec(), MTI::FinancialRequest, ALICE);
+	msg.set_on(4, &format!("{:020}", amount)).unwrap();
+
+	IsoFields::from_iso_msg(&msg).unwrap().fields
+}
+
+/// Tests messages are composed from their fields and responses are returned as fields
+#[tokio::test]
+async fn test_submit_fields() {
+	let api = MockProcessorImpl::new(Some("submit_fields_db".to_string())).await;
+
+	let fields = financial_request(&api, 100);
+	assert_eq!(fields[&4], "00000000000000000100");
+
+	let (_, msg) = api.processor.process_fields(None, "0200", &fields).await.unwrap();
+	let response = IsoFields::from_iso_msg(&msg).unwrap();
+
+	assert_eq!(response.mti, "0210");
+	assert_eq!(response.fields[&4], "00000000000000000100");
+	assert_eq!(response.fields[&39], "00");
+	assert_eq!(response.response_code.as_deref(), Some("00"));
+	assert_eq!(response.response_description.as_deref(), Some("Approved"));
+
+	let alice = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert_eq!(alice.balance.minor_units, ALICE.3 - 100);
+
+	// declined messages are described too
+	let fields = financial_request(&api, ALICE.3);
+	let (_, msg) = api.processor.process_fields(None, "0200", &fields).await.unwrap();
+	let response = IsoFields::from_iso_msg(&msg).unwrap();
+
+	assert_eq!(response.response_code.as_deref(), Some("51"));
+	assert_eq!(response.response_description.as_deref(), Some("Insufficient funds"));
+
+	// fields are keyed by position in JSON
+	let json = serde_json::to_value(&response).unwrap();
+	assert_eq!(json["mti"], "0210");
+	assert_eq!(json["fields"]["39"], "51");
+}
+
+/// Tests invalid MTIs and fields are rejected before the message is processed
+#[tokio::test]
+async fn test_submit_invalid_fields() {
+	let api = MockProcessorImpl::new(Some("submit_invalid_fields_db".to_string())).await;
+	let fields = financial_request(&api, 100);
+
+	let invalid = |position: u32, value: &str| {
+		let mut fields = fields.clone();
+		fields.insert(position, value.to_string());
+		fields
+	};
+
+	let cases = [
+		("0999", fields.clone()),
+		// not defined by the spec
+		("0200", invalid(150, "0")),
+		// too short and too long fixed fields
+		("0200", invalid(4, "100")),
+		("0200", invalid(3, "0000000")),
+		// length indicator overflow
+		("0200", invalid(2, &"4".repeat(100))),
+		("0200", invalid(126, "é")),
+		// transmission date and time
+		("0200", invalid(7, "01311200")),
+		("0200", invalid(7, "013112000a")),
+	];
+
+	for (mti, fields) in cases {
+		assert!(matches!(
+			api.processor.process_fields(None, mti, &fields).await,
+			Err(DomainError::BadRequest(_))
+		));
+	}
+
+	assert!(matches!(
+		api.processor.process_fields(Some("unknown"), "0200", &fields).await,
+		Err(DomainError::BadRequest(_))
+	));
+
+	let alice = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let alice_txs = get_transactions_by_id(&api, &alice.id).await;
+
+	assert_eq!(alice.balance.minor_units, ALICE.3);
+	assert!(alice_txs.is_empty());
+}
+
+/// Tests values are checked against the format of their field
+#[test]
+fn test_field_formats() {
+	let fixed = FieldFormat { encoding: Encoding::ASCII, len: 4, len_encoding: None };
+	assert!(fixed.accepts("0200"));
+	assert!(!fixed.accepts("020"));
+	assert!(!fixed.accepts("02000"));
+	assert!(!fixed.accepts("02é"));
+
+	let binary = FieldFormat { encoding: Encoding::BINARY, len: 2, len_encoding: None };
+	assert!(binary.accepts("0aFF"));
+	assert!(!binary.accepts("0aF"));
+	assert!(!binary.accepts("0aFG"));
+
+	let llvar =
+		FieldFormat { encoding: Encoding::ASCII, len: 2, len_encoding: Some(Encoding::ASCII) };
+	assert!(llvar.accepts(""));
+	assert!(llvar.accepts(&"0".repeat(99)));
+	assert!(!llvar.accepts(&"0".repeat(100)));
+
+	let binary_len =
+		FieldFormat { encoding: Encoding::BINARY, len: 1, len_encoding: Some(Encoding::BINARY) };
+	assert!(binary_len.accepts(&"ab".repeat(255)));
+	assert!(!binary_len.accepts(&"ab".repeat(256)));
+
+	// length indicators the codec can't encode
+	let lllllvar =
+		FieldFormat { encoding: Encoding::ASCII, len: 5, len_encoding: Some(Encoding::ASCII) };
+	assert!(!lllllvar.accepts("0"));
+}
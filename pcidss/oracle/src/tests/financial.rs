@@ -0,0 +1,191 @@
+//! Tests for single-message financial transactions
+
+use crate::{
+	tests::{mock::*, prelude::*},
+	types::{ResponseCodes, MTI},
+};
+
+/// Tests 0200 purchase round-trip and its settlement
+#[tokio::test]
+async fn test_financial_request() {
+	let api = MockProcessorImpl::new(Some("financial_db".to_string())).await;
+
+	let spec = api.processor.spec();
+
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (mut res_raw, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.get_field_value(&"message_type".to_string()).unwrap(), "0210");
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	// response can be parsed back with the same spec
+	let parsed_msg = spec.parse(&mut res_raw).unwrap();
+
+	assert_eq!(parsed_msg.get_field_value(&"message_type".to_string()).unwrap(), "0210");
+	for field in [2, 3, 4, 7, 12, 32, 35, 39, 126] {
+		assert_eq!(parsed_msg.bmp_child_value(field).unwrap(), msg.bmp_child_value(field).unwrap());
+	}
+
+	// funds are posted right away
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let acquirer = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
+
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 100);
+	assert_eq!(acquirer.balance.minor_units, ACQUIRER.3 + 100);
+
+	let alice_txs = get_transactions_by_id(&api, &alice_account.id).await;
+
+	assert_eq!(alice_txs.len(), 1);
+	assert_eq!(alice_txs[0].amount.minor_units, 100);
+	assert_eq!(alice_txs[0].to, Some(acquirer.id));
+	assert_eq!(alice_txs[0].hash, msg.bmp_child_value(126).unwrap());
+
+	// and can be reversed like any other transaction
+	let mut reversal_msg = get_new_iso_msg(spec, MTI::ReversalRequest, ALICE);
+	reversal_msg.set_on(4, "00000000000000000100").unwrap();
+	reversal_msg.set_on(126, &alice_txs[0].hash).unwrap();
+
+	let mut msg_raw = reversal_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(get_bank_account_by_card_number(&api, ALICE.1).await.balance.minor_units, ALICE.3);
+}
+
+/// Tests 0200 specific validation rules
+#[tokio::test]
+async fn test_financial_request_validation() {
+	let api = MockProcessorImpl::new(Some("financial_validation_db".to_string())).await;
+
+	let spec = api.processor.spec();
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let alice_txs = get_transactions_by_id(&api, &alice_account.id).await;
+
+	// INVALID TRANSACTION
+	// Only purchases are supported
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(3, "010000").unwrap();
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+
+	assert_noop(
+		&api,
+		ALICE,
+		&new_msg,
+		ResponseCodes::InvalidTransaction,
+		alice_account.clone(),
+		alice_txs.clone(),
+	)
+	.await;
+
+	// INVALID AMOUNT
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, &"0".repeat(20)).unwrap();
+
+	assert_noop(
+		&api,
+		ALICE,
+		&new_msg,
+		ResponseCodes::InvalidAmount,
+		alice_account.clone(),
+		alice_txs.clone(),
+	)
+	.await;
+
+	// INVALID MERCHANT
+	// Acquirer must be known to settle the purchase
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(32, "654321").unwrap();
+
+	assert_noop(
+		&api,
+		ALICE,
+		&new_msg,
+		ResponseCodes::InvalidMerchant,
+		alice_account.clone(),
+		alice_txs.clone(),
+	)
+	.await;
+
+	// INSUFFICIENT FUNDS
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000001100").unwrap();
+
+	assert_noop(
+		&api,
+		ALICE,
+		&new_msg,
+		ResponseCodes::InsufficientFunds,
+		alice_account.clone(),
+		alice_txs.clone(),
+	)
+	.await;
+
+	// EXPIRED CARD
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, EVE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+
+	let eve_account = get_bank_account_by_card_number(&api, EVE.1).await;
+
+	assert_noop(&api, EVE, &new_msg, ResponseCodes::ExpiredCard, eve_account, vec![]).await;
+}
+
+/// Tests the currency code in field 49 has to match the bank account unless it can be converted
+#[tokio::test]
+async fn test_financial_request_currency() {
+	let api = MockProcessorImpl::new(Some("financial_currency_db".to_string())).await;
+
+	let spec = api.processor.spec();
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let alice_txs = get_transactions_by_id(&api, &alice_account.id).await;
+
+	// another currency than the bank account without a conversion rate
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(49, "826").unwrap();
+
+	assert_noop(
+		&api,
+		ALICE,
+		&new_msg,
+		ResponseCodes::InvalidAmount,
+		alice_account.clone(),
+		alice_txs.clone(),
+	)
+	.await;
+
+	// unsupported currency
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(49, "999").unwrap();
+
+	assert_noop(
+		&api,
+		ALICE,
+		&new_msg,
+		ResponseCodes::InvalidAmount,
+		alice_account.clone(),
+		alice_txs.clone(),
+	)
+	.await;
+
+	// the currency of the bank account
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(49, "840").unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(msg.bmp_child_value(49).unwrap(), "840");
+	assert_eq!(
+		get_bank_account_by_card_number(&api, ALICE.1).await.balance.minor_units,
+		ALICE.3 - 100
+	);
+}
@@ -0,0 +1,291 @@
+//! Tests for conversion of amounts in other currencies than the one of the bank account
+
+use std::sync::Arc;
+
+use chrono::{Months, Utc};
+use op_core::{
+	bank_account::models::BankAccountCreate,
+	error::DomainError,
+	fx::{Conversion, FxRate},
+	money::{Iso4217, Money},
+};
+
+use crate::{
+	fx::RateTable,
+	services::processor::Iso8583MessageProcessor,
+	tests::{mock::*, prelude::*},
+	types::{DevAccount, ResponseCodes, MTI},
+};
+
+/// Card of a bank account in euros
+pub(crate) const EURO_CARD: DevAccount = ("Zoe", "4169812345678920", "321", 100_000, None);
+
+/// Latest rate from USD to EUR in the test rates
+const USD_EUR_RATE: FxRate = FxRate { value: 9216, decimals: 4 };
+
+/// Create the bank account of [`EURO_CARD`]
+pub(crate) async fn create_euro_account(api: &MockProcessorImpl) {
+	api.processor
+		.bank_account_controller
+		.create(&BankAccountCreate {
+			id: uuid::Uuid::new_v4(),
+			card_number: EURO_CARD.1.to_string(),
+			card_holder_first_name: EURO_CARD.0.to_string(),
+			card_holder_last_name: EURO_CARD.0.to_string(),
+			card_cvv: EURO_CARD.2.to_string(),
+			card_expiration_date: Utc::now()
+				.checked_add_months(Months::new(48))
+				.expect("valid date"),
+			balance: Money::new(EURO_CARD.3, Iso4217::Eur),
+			account_id: None,
+		})
+		.await
+		.unwrap();
+}
+
+/// Tests the latest version of the rates is used
+#[test]
+fn test_rate_table() {
+	let rates = RateTable::load(TEST_RATES_PATH).unwrap();
+
+	assert_eq!(rates.version(), Some(2));
+	assert_eq!(rates.rate(Iso4217::Usd, Iso4217::Eur), Some((USD_EUR_RATE, 2)));
+	assert_eq!(rates.rate(Iso4217::Usd, Iso4217::Gbp), None);
+
+	let rates = RateTable::parse_json(
+		r#"[
+			{ "version": 1, "from": "GBP", "to": "USD", "rate": "1.2700" },
+			{ "version": 1, "from": "USD", "to": "JPY", "rate": "149.5" }
+		]"#,
+	)
+	.unwrap();
+
+	assert_eq!(rates.version(), Some(1));
+	assert_eq!(
+		rates.rate(Iso4217::Usd, Iso4217::Jpy),
+		Some((FxRate { value: 1495, decimals: 1 }, 1))
+	);
+
+	assert_eq!(RateTable::default().rate(Iso4217::Usd, Iso4217::Eur), None);
+
+	// invalid tables
+	assert!(matches!(
+		RateTable::parse_csv("version,from,to,rate\n1,USD,EUR,0.9\n1,USD,EUR,0.91"),
+		Err(DomainError::InternalServerError(_))
+	));
+	assert!(RateTable::parse_csv("1,USD,EUR,0.9").is_err());
+	assert!(RateTable::parse_csv("version,from,to,rate\n1,USD,XXX,0.9").is_err());
+	assert!(RateTable::parse_json(
+		r#"[{ "version": 1, "from": "USD", "to": "EUR", "rate": "-1" }]"#
+	)
+	.is_err());
+}
+
+/// Tests a purchase in another currency is billed in the currency of the bank account
+#[tokio::test]
+async fn test_converted_purchase() {
+	let api = MockProcessorImpl::new(Some("fx_purchase_db".to_string())).await;
+	create_euro_account(&api).await;
+
+	let spec = api.processor.spec();
+
+	// 10.00 USD is 9.216 EUR, rounded to 9.22 EUR
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, EURO_CARD);
+	new_msg.set_on(4, "00000000000000001000").unwrap();
+	new_msg.set_on(49, "840").unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (mut res_raw, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(msg.bmp_child_value(6).unwrap(), "00000000000000000922");
+	assert_eq!(msg.bmp_child_value(10).unwrap(), "40009216");
+
+	// response can be parsed back with the same spec
+	let parsed_msg = spec.parse(&mut res_raw).unwrap();
+	for field in [4, 6, 10, 49] {
+		assert_eq!(parsed_msg.bmp_child_value(field).unwrap(), msg.bmp_child_value(field).unwrap());
+	}
+
+	// the bank account is billed in euros, the acquirer is paid in dollars
+	let euro_account = get_bank_account_by_card_number(&api, EURO_CARD.1).await;
+	let acquirer = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
+
+	assert_eq!(euro_account.balance, Money::new(EURO_CARD.3 - 922, Iso4217::Eur));
+	assert_eq!(acquirer.balance, Money::new(ACQUIRER.3 + 1000, Iso4217::Usd));
+
+	let txs = get_transactions_by_id(&api, &euro_account.id).await;
+
+	assert_eq!(txs.len(), 1);
+	assert_eq!(txs[0].amount, Money::new(922, Iso4217::Eur));
+	assert_eq!(
+		txs[0].conversion,
+		Some(Conversion {
+			amount: Money::new(1000, Iso4217::Usd),
+			rate: USD_EUR_RATE,
+			rate_version: 2
+		})
+	);
+
+	// amounts in the currency of the bank account are not converted
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(49, "840").unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert!(msg.bmp_child_value(6).is_err());
+	assert!(msg.bmp_child_value(10).is_err());
+
+	// no rate from GBP to EUR
+	let txs = get_transactions_by_id(&api, &euro_account.id).await;
+
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, EURO_CARD);
+	new_msg.set_on(4, "00000000000000001000").unwrap();
+	new_msg.set_on(49, "826").unwrap();
+
+	assert_noop(&api, EURO_CARD, &new_msg, ResponseCodes::InvalidAmount, euro_account, txs).await;
+}
+
+/// Tests reversals are converted with the rate of the original transaction
+#[tokio::test]
+async fn test_converted_reversal() {
+	let api = MockProcessorImpl::new(Some("fx_reversal_db".to_string())).await;
+	create_euro_account(&api).await;
+
+	let spec = api.processor.spec();
+
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, EURO_CARD);
+	new_msg.set_on(4, "00000000000000001000").unwrap();
+	new_msg.set_on(49, "840").unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	// rates change after the purchase
+	let processor = Iso8583MessageProcessor {
+		rates: Arc::new(
+			RateTable::parse_csv("version,from,to,rate\n3,USD,EUR,0.5000\n3,EUR,USD,2.0000")
+				.unwrap(),
+		),
+		..(*api.processor).clone()
+	};
+
+	let euro_account = get_bank_account_by_card_number(&api, EURO_CARD.1).await;
+	let purchase = get_transactions_by_id(&api, &euro_account.id).await.remove(0);
+
+	// 4.00 USD is 3.6864 EUR with the original rate
+	let mut reversal_msg = get_new_iso_msg(spec, MTI::ReversalRequest, EURO_CARD);
+	reversal_msg.set_on(4, "00000000000000000400").unwrap();
+	reversal_msg.set_on(49, "840").unwrap();
+	reversal_msg.set_on(126, &purchase.hash).unwrap();
+
+	let mut msg_raw = reversal_msg.assemble().unwrap();
+	let (_, msg) = processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(msg.bmp_child_value(6).unwrap(), "00000000000000000369");
+	assert_eq!(msg.bmp_child_value(10).unwrap(), "40009216");
+
+	assert_eq!(
+		get_bank_account_by_card_number(&api, EURO_CARD.1).await.balance,
+		Money::new(EURO_CARD.3 - 922 + 369, Iso4217::Eur)
+	);
+	assert_eq!(
+		get_bank_account_by_card_number(&api, ACQUIRER.1).await.balance,
+		Money::new(ACQUIRER.3 + 1000 - 400, Iso4217::Usd)
+	);
+
+	// more than the rest of the purchase
+	let euro_account = get_bank_account_by_card_number(&api, EURO_CARD.1).await;
+	let txs = get_transactions_by_id(&api, &euro_account.id).await;
+
+	let mut reversal_msg = get_new_iso_msg(spec, MTI::ReversalRequest, EURO_CARD);
+	reversal_msg.set_on(4, "00000000000000000601").unwrap();
+	reversal_msg.set_on(49, "840").unwrap();
+	reversal_msg.set_on(126, &purchase.hash).unwrap();
+
+	assert_noop(&api, EURO_CARD, &reversal_msg, ResponseCodes::InvalidAmount, euro_account, txs)
+		.await;
+
+	// the rest of the purchase restores both balances
+	let mut reversal_msg = get_new_iso_msg(spec, MTI::ReversalRequest, EURO_CARD);
+	reversal_msg.set_on(4, "00000000000000000600").unwrap();
+	reversal_msg.set_on(49, "840").unwrap();
+	reversal_msg.set_on(126, &purchase.hash).unwrap();
+
+	let mut msg_raw = reversal_msg.assemble().unwrap();
+	let (_, msg) = processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(msg.bmp_child_value(6).unwrap(), "00000000000000000553");
+
+	let euro_account = get_bank_account_by_card_number(&api, EURO_CARD.1).await;
+
+	assert_eq!(euro_account.balance, Money::new(EURO_CARD.3, Iso4217::Eur));
+	assert_eq!(
+		get_bank_account_by_card_number(&api, ACQUIRER.1).await.balance,
+		Money::new(ACQUIRER.3, Iso4217::Usd)
+	);
+
+	let txs = get_transactions_by_id(&api, &euro_account.id).await;
+
+	assert_eq!(txs.len(), 3);
+	assert!(txs[0].reversed);
+	assert!(txs[1..]
+		.iter()
+		.all(|refund| refund.conversion.map(|c| c.rate_version) == Some(2)));
+}
+
+/// Tests captures are converted with the rate of the authorization
+#[tokio::test]
+async fn test_converted_capture() {
+	let api = MockProcessorImpl::new(Some("fx_capture_db".to_string())).await;
+	create_euro_account(&api).await;
+
+	let spec = api.processor.spec();
+
+	let mut auth_msg = get_new_iso_msg(spec, MTI::AuthorizationRequest, EURO_CARD);
+	auth_msg.set_on(4, "00000000000000001000").unwrap();
+	auth_msg.set_on(49, "840").unwrap();
+
+	let mut msg_raw = auth_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(msg.bmp_child_value(6).unwrap(), "00000000000000000922");
+
+	let hold_hash = msg.bmp_child_value(126).unwrap();
+	let hold = api.processor.hold_controller.find_by_hash(&hold_hash).await.unwrap().unwrap();
+
+	assert_eq!(hold.amount, Money::new(922, Iso4217::Eur));
+	assert_eq!(
+		get_bank_account_by_card_number(&api, EURO_CARD.1).await.available_balance,
+		Money::new(EURO_CARD.3 - 922, Iso4217::Eur)
+	);
+
+	// captures are in the currency of the authorization, 5.00 USD is 4.608 EUR
+	let mut capture_msg = get_new_iso_msg(spec, MTI::FinancialAdvice, EURO_CARD);
+	capture_msg.set_on(4, "00000000000000000500").unwrap();
+	capture_msg.set_on(49, "840").unwrap();
+	capture_msg.set_on(126, &hold_hash).unwrap();
+
+	let mut msg_raw = capture_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(msg.bmp_child_value(6).unwrap(), "00000000000000000461");
+
+	let euro_account = get_bank_account_by_card_number(&api, EURO_CARD.1).await;
+
+	assert_eq!(euro_account.balance, Money::new(EURO_CARD.3 - 461, Iso4217::Eur));
+	assert_eq!(euro_account.available_balance, euro_account.balance);
+	assert_eq!(
+		get_bank_account_by_card_number(&api, ACQUIRER.1).await.balance,
+		Money::new(ACQUIRER.3 + 500, Iso4217::Usd)
+	);
+}
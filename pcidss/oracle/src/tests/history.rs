@@ -0,0 +1,179 @@
+//! Tests for the paginated transaction history
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use op_core::{
+	money::{Iso4217, Money},
+	transaction::models::{
+		SortOrder, Transaction, TransactionCreate, TransactionDirection, TransactionQuery,
+	},
+	types::TransactionType,
+};
+use uuid::Uuid;
+
+use crate::tests::{mock::*, prelude::*};
+
+/// Time the `n`th transaction of the tests is posted at
+fn posted_at(n: i64) -> DateTime<Utc> {
+	Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap() + Duration::days(n)
+}
+
+/// Ids of the transactions
+fn ids(transactions: &[Transaction]) -> Vec<Uuid> {
+	transactions.iter().map(|transaction| transaction.id).collect()
+}
+
+/// Tests the history of a bank account is paginated, filtered and sorted
+#[tokio::test]
+async fn test_transaction_history() {
+	let api = MockProcessorImpl::new(Some("transaction_history_db".to_string())).await;
+	let controller = &api.processor.transaction_controller;
+	let client = api.pg_pool.get().await.unwrap();
+
+	let alice = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let charlie = get_bank_account_by_card_number(&api, CHARLIE.1).await;
+	let acquirer = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
+
+	// alice pays 10, 20, 30, 40 and 50 to the acquirer, then charlie pays 25 to alice
+	let transfers = [10, 20, 30, 40, 50]
+		.map(|amount| (alice.id, acquirer.id, amount))
+		.into_iter()
+		.chain([(charlie.id, alice.id, 25)]);
+
+	let mut transactions = vec![];
+	for (n, (from, to, amount)) in transfers.enumerate() {
+		let transaction = controller
+			.transfer(&TransactionCreate {
+				id: Uuid::new_v4(),
+				from,
+				to: Some(to),
+				amount: Money::new(amount, Iso4217::default()),
+				conversion: None,
+				transaction_type: TransactionType::Credit,
+				nonce: n as u32,
+				iso_msg_raw: vec![48, 50, 48, 48],
+				on_chain_id: None,
+				finality: None,
+			})
+			.await
+			.unwrap();
+
+		client
+			.execute(
+				"UPDATE bank_transaction SET created_at = $1, updated_at = $1 WHERE id = $2",
+				&[&posted_at(n as i64), &transaction.id],
+			)
+			.await
+			.unwrap();
+
+		transactions.push(controller.find_by_id(&transaction.id).await.unwrap().unwrap());
+	}
+
+	// timestamps are exposed
+	assert_eq!(transactions[0].created_at, posted_at(0));
+
+	client
+		.execute(
+			"UPDATE bank_transaction SET reversed = true, updated_at = $1 WHERE id = $2",
+			&[&Utc::now(), &transactions[1].id],
+		)
+		.await
+		.unwrap();
+	let reversed = controller.find_by_id(&transactions[1].id).await.unwrap().unwrap();
+	assert_eq!(reversed.created_at, posted_at(1));
+	assert!(reversed.updated_at > reversed.created_at);
+
+	let newest_first: Vec<Uuid> = ids(&transactions).into_iter().rev().collect();
+	let query = |query: TransactionQuery| async move {
+		controller.find_page(&alice.id, &query).await.unwrap()
+	};
+
+	// newest first by default, in a single page
+	let page = query(TransactionQuery::default()).await;
+	assert_eq!(ids(&page.transactions), newest_first);
+	assert_eq!(page.next_cursor, None);
+
+	// pages resume after the cursor, in both orders
+	for (order, expected) in
+		[(SortOrder::Desc, newest_first.clone()), (SortOrder::Asc, ids(&transactions))]
+	{
+		let mut pages = vec![];
+		let mut after = None;
+		loop {
+			let page = query(TransactionQuery {
+				after,
+				limit: Some(4),
+				order: Some(order),
+				..Default::default()
+			})
+			.await;
+			pages.push(ids(&page.transactions));
+
+			match page.next_cursor {
+				Some(cursor) => after = Some(cursor),
+				None => break,
+			}
+		}
+
+		assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), vec![4, 2]);
+		assert_eq!(pages.concat(), expected);
+	}
+
+	// date range, the end is excluded
+	let page = query(TransactionQuery {
+		since: Some(posted_at(1)),
+		until: Some(posted_at(4)),
+		order: Some(SortOrder::Asc),
+		..Default::default()
+	})
+	.await;
+	assert_eq!(ids(&page.transactions), ids(&transactions[1..4]));
+
+	// direction
+	let page = query(TransactionQuery {
+		direction: Some(TransactionDirection::Incoming),
+		..Default::default()
+	})
+	.await;
+	assert_eq!(ids(&page.transactions), vec![transactions[5].id]);
+
+	let page = query(TransactionQuery {
+		direction: Some(TransactionDirection::Outgoing),
+		..Default::default()
+	})
+	.await;
+	assert_eq!(page.transactions.len(), 5);
+
+	// reversed status
+	let page = query(TransactionQuery { reversed: Some(true), ..Default::default() }).await;
+	assert_eq!(ids(&page.transactions), vec![transactions[1].id]);
+
+	let page = query(TransactionQuery { reversed: Some(false), ..Default::default() }).await;
+	assert_eq!(page.transactions.len(), 5);
+
+	// amount range, bounds included
+	let page = query(TransactionQuery {
+		min_amount: Some(20),
+		max_amount: Some(40),
+		order: Some(SortOrder::Asc),
+		..Default::default()
+	})
+	.await;
+	assert_eq!(
+		ids(&page.transactions),
+		vec![transactions[1].id, transactions[2].id, transactions[3].id, transactions[5].id]
+	);
+
+	// history of the recipient
+	let page = controller
+		.find_page(
+			&acquirer.id,
+			&TransactionQuery {
+				direction: Some(TransactionDirection::Incoming),
+				min_amount: Some(30),
+				..Default::default()
+			},
+		)
+		.await
+		.unwrap();
+	assert_eq!(ids(&page.transactions), newest_first[1..4]);
+}
@@ -0,0 +1,185 @@
+//! Tests for authorization holds and their completion
+
+use chrono::{Duration, Utc};
+use iso8583_rs::iso8583::iso_spec::IsoMsg;
+use op_core::hold::models::HoldStatus;
+
+use crate::{
+	tests::{mock::*, prelude::*},
+	types::{DevAccount, ResponseCodes, MTI},
+};
+
+/// Sends an authorization request and returns the hash of the placed hold
+async fn authorize(api: &MockProcessorImpl, account: DevAccount, amount: u32) -> String {
+	let (response_code, msg) =
+		process_payment(&api.processor, MTI::AuthorizationRequest, account, amount, |_| {}).await;
+
+	assert_eq!(response_code, "00");
+	msg.bmp_child_value(126).unwrap()
+}
+
+/// Sends a completion message of given type for a hold and returns the response code
+async fn complete(
+	api: &MockProcessorImpl,
+	mti: MTI,
+	account: DevAccount,
+	hold_hash: &str,
+	amount: u32,
+) -> String {
+	let set_hold_hash = |msg: &mut IsoMsg| msg.set_on(126, hold_hash).unwrap();
+	process_payment(&api.processor, mti, account, amount, set_hold_hash).await.0
+}
+
+/// Tests that authorization reserves funds and completion captures them
+#[tokio::test]
+async fn test_hold_capture() {
+	let api = MockProcessorImpl::new(Some("hold_capture_db".to_string())).await;
+
+	let hold_hash = authorize(&api, ALICE, 600).await;
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let hold = api.processor.hold_controller.find_by_hash(&hold_hash).await.unwrap().unwrap();
+
+	assert_eq!(hold.status, HoldStatus::Active);
+	assert_eq!(hold.amount.minor_units, 600);
+	assert_eq!(alice_account.balance.minor_units, ALICE.3);
+	assert_eq!(alice_account.available_balance.minor_units, ALICE.3 - 600);
+
+	// INSUFFICIENT FUNDS
+	// Held funds can't be spent again
+	let mut new_msg = get_new_iso_msg(api.processor.spec(), MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000500").unwrap();
+
+	assert_noop(&api, ALICE, &new_msg, ResponseCodes::InsufficientFunds, alice_account, vec![])
+		.await;
+
+	// INVALID AMOUNT
+	// Completion can't capture more than authorized
+	assert_eq!(complete(&api, MTI::FinancialAdvice, ALICE, &hold_hash, 700).await, "13");
+
+	// INVALID CARD NUMBER
+	// Completion must come from the same card
+	assert_eq!(complete(&api, MTI::FinancialAdvice, CHARLIE, &hold_hash, 400).await, "14");
+
+	// Capture less than authorized, the rest is released
+	assert_eq!(complete(&api, MTI::FinancialAdvice, ALICE, &hold_hash, 400).await, "00");
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let acquirer = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
+	let hold = api.processor.hold_controller.find_by_hash(&hold_hash).await.unwrap().unwrap();
+	let alice_txs = get_transactions_by_id(&api, &alice_account.id).await;
+
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 400);
+	assert_eq!(alice_account.available_balance.minor_units, ALICE.3 - 400);
+	assert_eq!(acquirer.balance.minor_units, ACQUIRER.3 + 400);
+	assert_eq!(alice_txs.len(), 1);
+	assert_eq!(alice_txs[0].amount.minor_units, 400);
+	assert_eq!(hold.status, HoldStatus::Captured);
+	assert_eq!(hold.transaction_id, Some(alice_txs[0].id));
+
+	// INVALID TRANSACTION
+	// Hold can only be captured once
+	assert_eq!(complete(&api, MTI::FinancialAdvice, ALICE, &hold_hash, 100).await, "12");
+
+	// Financial request can complete an authorization too
+	let hold_hash = authorize(&api, CHARLIE, 345).await;
+
+	assert_eq!(complete(&api, MTI::FinancialRequest, CHARLIE, &hold_hash, 345).await, "00");
+
+	let charlie_account = get_bank_account_by_card_number(&api, CHARLIE.1).await;
+
+	assert_eq!(charlie_account.balance.minor_units, CHARLIE.3 - 345);
+	assert_eq!(charlie_account.available_balance.minor_units, CHARLIE.3 - 345);
+
+	// Advice without a hold is rejected
+	assert_eq!(complete(&api, MTI::FinancialAdvice, ALICE, &"0".repeat(99), 100).await, "12");
+}
+
+/// Tests that reversal of an authorization releases its hold
+#[tokio::test]
+async fn test_hold_reversal() {
+	let api = MockProcessorImpl::new(Some("hold_reversal_db".to_string())).await;
+
+	let hold_hash = authorize(&api, ALICE, 100).await;
+
+	// the hold can't be released by another cardholder
+	let mut foreign_msg = get_new_iso_msg(api.processor.spec(), MTI::ReversalRequest, CHARLIE);
+	foreign_msg.set_on(4, "00000000000000000100").unwrap();
+	foreign_msg.set_on(126, &hold_hash).unwrap();
+
+	let mut msg_raw = foreign_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "57");
+
+	let hold = api.processor.hold_controller.find_by_hash(&hold_hash).await.unwrap().unwrap();
+	assert_eq!(hold.status, HoldStatus::Active);
+
+	let mut reversal_msg = get_new_iso_msg(api.processor.spec(), MTI::ReversalRequest, ALICE);
+	reversal_msg.set_on(4, "00000000000000000100").unwrap();
+	reversal_msg.set_on(126, &hold_hash).unwrap();
+
+	let mut msg_raw = reversal_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let hold = api.processor.hold_controller.find_by_hash(&hold_hash).await.unwrap().unwrap();
+
+	assert_eq!(alice_account.balance.minor_units, ALICE.3);
+	assert_eq!(alice_account.available_balance.minor_units, ALICE.3);
+	assert_eq!(hold.status, HoldStatus::Released);
+
+	// released hold can't be captured or released again
+	assert_eq!(complete(&api, MTI::FinancialAdvice, ALICE, &hold_hash, 100).await, "12");
+	assert_noop(
+		&api,
+		ALICE,
+		&reversal_msg,
+		ResponseCodes::InvalidTransaction,
+		alice_account,
+		vec![],
+	)
+	.await;
+}
+
+/// Tests that stale holds are released by the expiry sweep
+#[tokio::test]
+async fn test_hold_expiry() {
+	let api = MockProcessorImpl::new(Some("hold_expiry_db".to_string())).await;
+
+	let hold_hash = authorize(&api, ALICE, 100).await;
+	let other_hold_hash = authorize(&api, ALICE, 50).await;
+	let charlie_hold_hash = authorize(&api, CHARLIE, 30).await;
+
+	// nothing to expire yet
+	assert_eq!(api.processor.expire_holds().await.unwrap(), 0);
+
+	let expired = api
+		.processor
+		.hold_controller
+		.expire(Utc::now() + Duration::hours(HOLD_TTL_HOURS) + Duration::seconds(1))
+		.await
+		.unwrap();
+
+	// holds of the same account are released together
+	let mut expired_hashes: Vec<_> = expired.iter().map(|hold| hold.hash.clone()).collect();
+	let mut hashes = vec![hold_hash.clone(), other_hold_hash, charlie_hold_hash];
+	expired_hashes.sort();
+	hashes.sort();
+
+	assert_eq!(expired_hashes, hashes);
+	assert!(expired.iter().all(|hold| hold.status == HoldStatus::Expired));
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let charlie_account = get_bank_account_by_card_number(&api, CHARLIE.1).await;
+
+	assert_eq!(alice_account.balance.minor_units, ALICE.3);
+	assert_eq!(alice_account.available_balance.minor_units, ALICE.3);
+	assert_eq!(charlie_account.balance.minor_units, CHARLIE.3);
+	assert_eq!(charlie_account.available_balance.minor_units, CHARLIE.3);
+
+	// expired hold can't be captured
+	assert_eq!(complete(&api, MTI::FinancialAdvice, ALICE, &hold_hash, 100).await, "12");
+}
@@ -0,0 +1,183 @@
+//! Tests for idempotent processing of retransmitted messages
+
+use op_core::{error::DomainError, processed_message::models::Claim};
+
+use crate::{
+	tests::{mock::*, prelude::*},
+	types::{
+		constants::{EVENT_ID_FIELD_NUMBER, STAN_FIELD_NUMBER, TERMINAL_ID_FIELD_NUMBER},
+		MTI,
+	},
+};
+
+/// Tests that a retransmitted message gets the stored response and is posted only once
+#[tokio::test]
+async fn test_retransmission() {
+	let api = MockProcessorImpl::new(Some("idempotency_db".to_string())).await;
+	let spec = api.processor.spec();
+
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(STAN_FIELD_NUMBER, "000001").unwrap();
+	new_msg.set_on(TERMINAL_ID_FIELD_NUMBER, "TERM0001").unwrap();
+
+	let (response, msg) = api.processor.process(&mut new_msg.assemble().unwrap()).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(msg.bmp_child_value(STAN_FIELD_NUMBER).unwrap(), "000001");
+	assert_eq!(msg.bmp_child_value(TERMINAL_ID_FIELD_NUMBER).unwrap(), "TERM0001");
+
+	// same STAN, terminal and transmission time
+	let (retransmission_response, retransmission_msg) =
+		api.processor.process(&mut new_msg.assemble().unwrap()).await.unwrap();
+
+	assert_eq!(retransmission_response, response);
+	assert_eq!(retransmission_msg.bmp_child_value(126).unwrap(), msg.bmp_child_value(126).unwrap());
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 100);
+	assert_eq!(get_transactions_by_id(&api, &alice_account.id).await.len(), 1);
+
+	// another terminal can use the same STAN
+	new_msg.set_on(TERMINAL_ID_FIELD_NUMBER, "TERM0002").unwrap();
+	let (other_response, _) =
+		api.processor.process(&mut new_msg.assemble().unwrap()).await.unwrap();
+
+	assert_ne!(other_response, response);
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 200);
+	assert_eq!(get_transactions_by_id(&api, &alice_account.id).await.len(), 2);
+
+	// messages without STAN can't be recognized, so they are processed again
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	api.processor.process(&mut new_msg.assemble().unwrap()).await.unwrap();
+	api.processor.process(&mut new_msg.assemble().unwrap()).await.unwrap();
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 400);
+}
+
+/// Tests that the stored responses contain neither the card number nor the CVV
+#[tokio::test]
+async fn test_stored_response_without_card_data() {
+	let api = MockProcessorImpl::new(Some("idempotency_card_data_db".to_string())).await;
+	let spec = api.processor.spec();
+
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(STAN_FIELD_NUMBER, "000001").unwrap();
+
+	let (response, msg) = api.processor.process(&mut new_msg.assemble().unwrap()).await.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(msg.bmp_child_value(2).unwrap(), ALICE.1);
+
+	let client = api.pg_pool.get().await.unwrap();
+	let stored_response: Vec<u8> = client
+		.query_one("SELECT response FROM processed_message", &[])
+		.await
+		.unwrap()
+		.get("response");
+
+	let stored_response = String::from_utf8_lossy(&stored_response);
+	assert!(!stored_response.contains(ALICE.1));
+	assert!(!stored_response.contains(&format!("C{}", ALICE.2)));
+
+	// the card data of the retransmitted request is echoed
+	let (retransmission_response, retransmission_msg) =
+		api.processor.process(&mut new_msg.assemble().unwrap()).await.unwrap();
+
+	assert_eq!(retransmission_response, response);
+	assert_eq!(retransmission_msg.bmp_child_value(2).unwrap(), ALICE.1);
+	assert_eq!(retransmission_msg.bmp_child_value(35).unwrap(), msg.bmp_child_value(35).unwrap());
+}
+
+/// Tests that an on-chain event seen twice is posted only once
+#[tokio::test]
+async fn test_on_chain_event_replay() {
+	let api = MockProcessorImpl::new(Some("idempotency_event_db".to_string())).await;
+	let spec = api.processor.spec();
+
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(EVENT_ID_FIELD_NUMBER, "42-1").unwrap();
+
+	let finality = get_finality("42-1");
+
+	let (response, msg) = api
+		.processor
+		.process_on_chain(&mut new_msg.assemble().unwrap(), &finality)
+		.await
+		.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	// e.g. the watcher restarted, the transmission time differs
+	new_msg.set_on(7, &"1".repeat(10)).unwrap();
+	let (replay_response, _) = api
+		.processor
+		.process_on_chain(&mut new_msg.assemble().unwrap(), &get_finality("42-1"))
+		.await
+		.unwrap();
+
+	assert_eq!(replay_response, response);
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let alice_txs = get_transactions_by_id(&api, &alice_account.id).await;
+
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 100);
+	assert_eq!(alice_txs.len(), 1);
+	assert_eq!(alice_txs[0].on_chain_id, Some("42-1".to_string()));
+
+	// finality is enqueued once as well
+	let enqueued = api.processor.outbox_controller.find_by_event_id("42-1").await.unwrap().unwrap();
+	assert_eq!(enqueued.id, finality.id);
+	assert_eq!(enqueued.transaction_hash, alice_txs[0].hash);
+
+	// event id is ignored for external messages
+	new_msg.set_on(7, &chrono::Utc::now().format("%m%d%H%M%S").to_string()).unwrap();
+	let (_, msg) = api.processor.process(&mut new_msg.assemble().unwrap()).await.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let alice_txs = get_transactions_by_id(&api, &alice_account.id).await;
+	assert_eq!(alice_txs.len(), 2);
+	assert_eq!(alice_txs[1].on_chain_id, None);
+}
+
+/// Tests that a message is rejected while its duplicate is being processed
+#[tokio::test]
+async fn test_pending_claim() {
+	let api = MockProcessorImpl::new(Some("idempotency_pending_db".to_string())).await;
+	let spec = api.processor.spec();
+	let controller = &api.processor.processed_message_controller;
+
+	let key = "0".repeat(64);
+	assert_eq!(controller.claim(&key).await.unwrap(), Claim::Claimed);
+	assert_eq!(controller.claim(&key).await.unwrap(), Claim::Pending);
+
+	controller.complete(&key, b"0110").await.unwrap();
+	assert_eq!(controller.claim(&key).await.unwrap(), Claim::Processed(b"0110".to_vec()));
+	assert!(controller.complete(&key, b"0110").await.is_err());
+
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(STAN_FIELD_NUMBER, "000001").unwrap();
+
+	// concurrent duplicates, the one that loses the claim is rejected unless the other one is
+	// already processed
+	let (mut first_raw, mut second_raw) =
+		(new_msg.assemble().unwrap(), new_msg.assemble().unwrap());
+	let (first, second) = futures::join!(
+		api.processor.process(&mut first_raw),
+		api.processor.process(&mut second_raw)
+	);
+
+	match (first, second) {
+		(Ok((first, _)), Ok((second, _))) => assert_eq!(first, second),
+		(Ok(_), Err(e)) | (Err(e), Ok(_)) => assert!(matches!(e, DomainError::BadRequest(_))),
+		(Err(_), Err(_)) => panic!("one of the duplicates has to be processed"),
+	}
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 100);
+}
@@ -0,0 +1,115 @@
+//! Tests for the double-entry journal of the ledger
+
+use iso8583_rs::iso8583::iso_spec::IsoMsg;
+use op_api::ledger::PgLedger;
+use op_core::{
+	ledger::{models::LedgerAccount, traits::LedgerTrait},
+	money::Iso4217,
+};
+
+use crate::{
+	tests::{fx::*, mock::*, prelude::*},
+	types::MTI,
+};
+
+/// Tests every balance change is journaled with balanced postings
+#[tokio::test]
+async fn test_ledger_journal() {
+	let api = MockProcessorImpl::new(Some("ledger_journal_db".to_string())).await;
+	let ledger = PgLedger::new(api.pg_pool.clone());
+
+	// opening balances of the dev accounts
+	let report = ledger.check().await.unwrap();
+	assert!(report.is_consistent(), "{:?}", report);
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let entries = ledger
+		.find_by_account(&LedgerAccount::BankAccount(alice_account.id))
+		.await
+		.unwrap();
+
+	assert_eq!(entries.len(), 1);
+	assert_eq!(entries[0].amount, ALICE.3);
+	assert_eq!(entries[0].transaction_id, None);
+
+	// purchase and partial reversal
+	let (response_code, msg) =
+		process_payment(&api.processor, MTI::FinancialRequest, ALICE, 500, |_| {}).await;
+	assert_eq!(response_code, "00");
+
+	let hash = msg.bmp_child_value(126).unwrap();
+	let set_hash = |msg: &mut IsoMsg| msg.set_on(126, &hash).unwrap();
+	let (response_code, _) =
+		process_payment(&api.processor, MTI::ReversalRequest, ALICE, 200, set_hash).await;
+	assert_eq!(response_code, "00");
+
+	// authorization and partial capture
+	let (response_code, msg) =
+		process_payment(&api.processor, MTI::AuthorizationRequest, ALICE, 300, |_| {}).await;
+	assert_eq!(response_code, "00");
+
+	let hash = msg.bmp_child_value(126).unwrap();
+	let set_hash = |msg: &mut IsoMsg| msg.set_on(126, &hash).unwrap();
+	let (response_code, _) =
+		process_payment(&api.processor, MTI::FinancialAdvice, ALICE, 200, set_hash).await;
+	assert_eq!(response_code, "00");
+
+	// purchase in dollars billed in euros through the conversion account
+	create_euro_account(&api).await;
+
+	let set_dollars = |msg: &mut IsoMsg| msg.set_on(49, "840").unwrap();
+	let (response_code, _) =
+		process_payment(&api.processor, MTI::FinancialRequest, EURO_CARD, 1000, set_dollars).await;
+	assert_eq!(response_code, "00");
+
+	let report = ledger.check().await.unwrap();
+	assert!(report.is_consistent(), "{:?}", report);
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let euro_account = get_bank_account_by_card_number(&api, EURO_CARD.1).await;
+	let mut txs = get_transactions_by_id(&api, &alice_account.id).await;
+	txs.extend(get_transactions_by_id(&api, &euro_account.id).await);
+
+	assert_eq!(txs.len(), 4);
+
+	for tx in txs.iter() {
+		let entries = ledger.find_by_transaction_id(&tx.id).await.unwrap();
+		let fx_entries: Vec<_> = entries
+			.iter()
+			.filter(|entry| entry.account == LedgerAccount::FxConversion)
+			.collect();
+
+		assert_eq!(fx_entries.is_empty(), tx.conversion.is_none());
+
+		// balanced in each currency
+		for currency in [Iso4217::Usd, Iso4217::Eur] {
+			assert_eq!(
+				entries
+					.iter()
+					.filter(|entry| entry.currency == currency)
+					.map(|entry| entry.amount)
+					.sum::<i64>(),
+				0
+			);
+		}
+	}
+
+	// balances changed outside of the ledger are reported
+	let client = api.pg_pool.get().await.unwrap();
+	client
+		.execute(
+			"UPDATE bank_account SET balance = balance + 1 WHERE id = $1",
+			&[&alice_account.id],
+		)
+		.await
+		.unwrap();
+
+	let report = ledger.check().await.unwrap();
+
+	assert!(!report.is_consistent());
+	assert!(report.unbalanced_postings.is_empty());
+	assert_eq!(report.mismatches.len(), 1);
+	assert_eq!(report.mismatches[0].bank_account_id, alice_account.id);
+	assert_eq!(report.mismatches[0].balance.minor_units, alice_account.balance.minor_units + 1);
+	assert_eq!(report.mismatches[0].ledger_balance, alice_account.balance);
+}
@@ -2,46 +2,119 @@
 
 use std::sync::Arc;
 
-use crate::{services::processor::Iso8583MessageProcessor, types::constants::DEV_ACCOUNTS};
+use crate::{
+	events::EventBus,
+	fx::RateTable,
+	risk::{RiskEngine, RiskRules},
+	services::processor::Iso8583MessageProcessor,
+	spec::SpecLoader,
+	types::constants::{DEV_ACCOUNTS, EVENT_BUS_CAPACITY},
+};
 use chrono::{Months, Utc};
-use op_api::{bank_account::PgBankAccount, transaction::PgTransaction};
+use deadpool_postgres::Pool;
+use op_api::{
+	bank_account::PgBankAccount, hold::PgHold, key_provider::FileKeyProvider, outbox::PgOutbox,
+	processed_message::PgProcessedMessage, risk::PgRisk, settlement::PgSettlement,
+	transaction::PgTransaction,
+};
 use op_core::{
 	bank_account::{models::BankAccountCreate, traits::BankAccountTrait},
+	hold::traits::HoldTrait,
+	money::{Iso4217, Money},
+	outbox::traits::OutboxTrait,
 	postgres::mock_init,
+	processed_message::traits::ProcessedMessageTrait,
+	settlement::traits::SettlementTrait,
 	transaction::traits::TransactionTrait,
+	vault::{mask_card_number, Vault, KEY_LENGTH},
 };
 
 #[subxt::subxt(runtime_metadata_path = "./iso8583-chain.scale")]
 pub mod iso_8583_chain {}
 
+/// Name of the ISO-8583 spec used in tests
+pub const TEST_SPEC_NAME: &str = "test";
+
+/// Path of the ISO-8583 spec used in tests
+pub const TEST_SPEC_PATH: &str = "./src/tests/test_spec.yaml";
+
+/// Time in hours after which authorization holds expire in tests
+pub const HOLD_TTL_HOURS: i64 = 1;
+
+/// Path of the conversion rates used in tests
+pub const TEST_RATES_PATH: &str = "./src/tests/test_rates.csv";
+
+/// Master key of the card vault in tests
+pub const VAULT_KEY: [u8; KEY_LENGTH] = [42; KEY_LENGTH];
+
+/// Vault with the test master key as version 1 and `rotated_key` as version 2, if any
+pub fn get_vault(rotated_key: Option<[u8; KEY_LENGTH]>) -> Vault {
+	let mut keys = std::collections::BTreeMap::from([(1, VAULT_KEY)]);
+	if let Some(rotated_key) = rotated_key {
+		keys.insert(2, rotated_key);
+	}
+
+	Vault::new(Arc::new(FileKeyProvider::new(keys).expect("valid keys")))
+}
+
 /// Mock implementation of the Oracle API server.
 #[derive(Clone)]
 pub struct MockProcessorImpl {
 	/// ISO8583 message processor
 	pub processor: Arc<Iso8583MessageProcessor>,
+	/// Database connection pool
+	pub pg_pool: Arc<Pool>,
 }
 
 impl MockProcessorImpl {
 	/// Creates a new instance of the mock processor
 	pub async fn new(db_name: Option<String>) -> Self {
+		let specs = SpecLoader::new(
+			TEST_SPEC_NAME,
+			SpecLoader::load(TEST_SPEC_PATH).expect("valid test spec"),
+		);
+
+		Self::with_specs(db_name, specs).await
+	}
+
+	/// Creates a new instance of the mock processor with the given ISO-8583 specs
+	pub async fn with_specs(db_name: Option<String>, specs: SpecLoader) -> Self {
 		let pg_pool = mock_init(db_name.unwrap_or("mockdb".to_string()))
 			.await
 			.expect("Error to init database to tests");
 		let pg_pool = Arc::new(pg_pool);
 
-		let bank_account_trait: Arc<dyn BankAccountTrait> =
-			Arc::new(PgBankAccount::new(pg_pool.clone()));
-		let transaction_trait: Arc<dyn TransactionTrait> =
-			Arc::new(PgTransaction::new(pg_pool.clone()));
+		let vault = Arc::new(get_vault(None));
 
-		std::env::set_var("SPEC_FILE", "./src/tests/test_spec.yaml");
+		let bank_account = PgBankAccount::new(pg_pool.clone(), vault.clone());
+		assert_eq!(bank_account.tokenize_plaintext_cards().await.unwrap(), 0);
 
-		let iso8583_spec = iso8583_rs::iso8583::iso_spec::spec("");
+		let bank_account_trait: Arc<dyn BankAccountTrait> = Arc::new(bank_account);
+		let transaction_trait: Arc<dyn TransactionTrait> =
+			Arc::new(PgTransaction::new(pg_pool.clone()));
+		let hold_trait: Arc<dyn HoldTrait> = Arc::new(PgHold::new(pg_pool.clone()));
+		let processed_message_trait: Arc<dyn ProcessedMessageTrait> =
+			Arc::new(PgProcessedMessage::new(pg_pool.clone()));
+		let outbox_trait: Arc<dyn OutboxTrait> = Arc::new(PgOutbox::new(pg_pool.clone()));
+		let settlement_trait: Arc<dyn SettlementTrait> =
+			Arc::new(PgSettlement::new(pg_pool.clone()));
 
 		let processor = Iso8583MessageProcessor {
-			spec: iso8583_spec,
+			specs,
 			bank_account_controller: bank_account_trait,
 			transaction_controller: transaction_trait,
+			hold_controller: hold_trait,
+			processed_message_controller: processed_message_trait,
+			outbox_controller: outbox_trait,
+			settlement_controller: settlement_trait,
+			hold_ttl: chrono::Duration::hours(HOLD_TTL_HOURS),
+			vault,
+			rates: Arc::new(RateTable::load(TEST_RATES_PATH).expect("valid test rates")),
+			risk: Arc::new(RiskEngine::new(
+				RiskRules::default(),
+				Arc::new(PgRisk::new(pg_pool.clone())),
+			)),
+			events: Arc::new(EventBus::new(EVENT_BUS_CAPACITY)),
 		};
 
 		// insert dev accounts
@@ -59,18 +132,19 @@ impl MockProcessorImpl {
 				card_holder_last_name: account.0.to_string(),
 				card_cvv: account.2.to_string(),
 				card_expiration_date: expiration_date,
-				balance: account.3,
+				balance: Money::new(account.3, Iso4217::default()),
 				account_id: account.4.map(|s| s.to_string()),
 			};
 
 			let bank_account =
 				processor.bank_account_controller.create(&bank_account_create).await.unwrap();
 
-			assert_eq!(bank_account.card_number, account.1);
-			assert_eq!(bank_account.balance, account.3);
+			assert_eq!(bank_account.card_number_masked, mask_card_number(account.1));
+			assert_eq!(bank_account.balance.minor_units, account.3);
+			assert_eq!(bank_account.available_balance.minor_units, account.3);
 		}
 
-		Self { processor: Arc::new(processor) }
+		Self { processor: Arc::new(processor), pg_pool }
 	}
 }
 
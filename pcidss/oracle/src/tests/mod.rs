@@ -1,19 +1,46 @@
 //! Unit tests (Substrate style)
+mod auth;
+mod card;
+mod events;
+mod fields;
+mod financial;
+mod fx;
+mod history;
+mod hold;
+mod idempotency;
+mod ledger;
 #[cfg(test)]
 mod mock;
+mod outbox;
 mod payment;
+mod reconciliation;
 mod register;
+mod reorg;
 mod reversal;
+mod risk;
+mod settlement;
+mod spec;
+mod submitter;
+mod tcp;
+mod transfer;
+mod vault;
+mod watcher;
 
 #[cfg(test)]
 mod prelude {
 	use chrono::Months;
 	use iso8583_rs::iso8583::iso_spec::{new_msg, IsoMsg, Spec};
-	use op_core::{bank_account::models::BankAccount, transaction::models::Transaction};
+	use op_core::{
+		bank_account::models::BankAccount, outbox::models::FinalityCreate,
+		transaction::models::Transaction,
+	};
 	use uuid::Uuid;
 
 	use super::mock::MockProcessorImpl;
-	use crate::types::{constants::DEV_ACCOUNTS, DevAccount, ResponseCodes, MTI};
+	use crate::{
+		services::processor::Iso8583MessageProcessor,
+		types::{constants::DEV_ACCOUNTS, DevAccount, ResponseCodes, MTI},
+	};
 
 	pub const ALICE: DevAccount = DEV_ACCOUNTS[0];
 	pub const _BOB: DevAccount = DEV_ACCOUNTS[1];
@@ -43,6 +70,21 @@ mod prelude {
 		api.processor.transaction_controller.find_by_bank_account_id(id).await.unwrap()
 	}
 
+	/// Creates new finality of an on-chain event, its response code and transaction hash are set
+	/// by the processor
+	pub(crate) fn get_finality(event_id: &str) -> FinalityCreate {
+		FinalityCreate {
+			id: Uuid::new_v4(),
+			event_id: event_id.to_string(),
+			event_block_hash: format!("0x{}", "a".repeat(64)),
+			from: "01".repeat(32),
+			to: "02".repeat(32),
+			amount: 100_000_000,
+			response_code: String::new(),
+			transaction_hash: String::new(),
+		}
+	}
+
 	/// Creates new mock ISO-8583 message
 	///
 	/// # Cases
@@ -93,6 +135,28 @@ mod prelude {
 		msg
 	}
 
+	/// Sends a message of the account for the amount and returns the response code and the
+	/// response
+	///
+	/// `customize` sets the fields that differ from [`get_new_iso_msg`], e.g. the hash of a
+	/// previous transaction in field 126.
+	pub(crate) async fn process_payment(
+		processor: &Iso8583MessageProcessor,
+		mti: MTI,
+		account: DevAccount,
+		amount: u32,
+		customize: impl FnOnce(&mut IsoMsg),
+	) -> (String, IsoMsg) {
+		let mut new_msg = get_new_iso_msg(processor.spec(), mti, account);
+		new_msg.set_on(4, &format!("{:020}", amount)).unwrap();
+		customize(&mut new_msg);
+
+		let mut msg_raw = new_msg.assemble().unwrap();
+		let (_, msg) = processor.process(&mut msg_raw).await.unwrap();
+
+		(msg.bmp_child_value(39).unwrap(), msg)
+	}
+
 	/// Assert ISO-8583 message processing failed with given Response Code
 	/// and storage has not been altered
 	pub(crate) async fn assert_noop(
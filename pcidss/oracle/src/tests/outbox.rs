@@ -0,0 +1,177 @@
+//! Tests for the finality outbox
+
+use chrono::{Duration, Utc};
+use op_core::{
+	error::DomainError,
+	money::{Iso4217, Money},
+	outbox::models::{FinalityCreate, FinalityStatus},
+	transaction::models::TransactionCreate,
+	types::TransactionType,
+};
+
+use crate::{
+	tests::{mock::*, prelude::*},
+	types::{constants::EVENT_ID_FIELD_NUMBER, MTI},
+};
+
+/// Tests that finalities of on-chain events are enqueued with the response of the event
+#[tokio::test]
+async fn test_finality_enqueued() {
+	let api = MockProcessorImpl::new(Some("outbox_enqueue_db".to_string())).await;
+	let spec = api.processor.spec();
+	let outbox = &api.processor.outbox_controller;
+
+	// approved transfer is enqueued with the hash of the transaction
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(EVENT_ID_FIELD_NUMBER, "1-1").unwrap();
+
+	let (_, msg) = api
+		.processor
+		.process_on_chain(&mut new_msg.assemble().unwrap(), &get_finality("1-1"))
+		.await
+		.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let alice_txs = get_transactions_by_id(&api, &alice_account.id).await;
+
+	let finality = outbox.find_by_event_id("1-1").await.unwrap().unwrap();
+	assert_eq!(finality.response_code, "00");
+	assert_eq!(finality.transaction_hash, alice_txs[0].hash);
+	assert_eq!(finality.amount, 100_000_000);
+	assert_eq!(finality.status, FinalityStatus::Pending);
+	assert_eq!(finality.attempts, 0);
+
+	// declined transfer is enqueued without a transaction
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000010000").unwrap();
+	new_msg.set_on(EVENT_ID_FIELD_NUMBER, "1-2").unwrap();
+
+	let (_, msg) = api
+		.processor
+		.process_on_chain(&mut new_msg.assemble().unwrap(), &get_finality("1-2"))
+		.await
+		.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "51");
+
+	let finality = outbox.find_by_event_id("1-2").await.unwrap().unwrap();
+	assert_eq!(finality.response_code, "51");
+	assert_eq!(finality.transaction_hash, "0".repeat(64));
+	assert_eq!(get_transactions_by_id(&api, &alice_account.id).await.len(), 1);
+
+	// approved reversal is enqueued with the hash of the reverted transaction
+	let mut new_msg = get_new_iso_msg(spec, MTI::ReversalRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(126, &alice_txs[0].hash).unwrap();
+	new_msg.set_on(EVENT_ID_FIELD_NUMBER, "2-1").unwrap();
+
+	let (_, msg) = api
+		.processor
+		.process_on_chain(&mut new_msg.assemble().unwrap(), &get_finality("2-1"))
+		.await
+		.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let finality = outbox.find_by_event_id("2-1").await.unwrap().unwrap();
+	assert_eq!(finality.response_code, "00");
+	assert_eq!(finality.transaction_hash, alice_txs[0].hash);
+
+	// all of them wait for submission
+	let due = outbox.find_due(Utc::now(), 10).await.unwrap();
+	assert_eq!(due.len(), 3);
+}
+
+/// Tests that a finality is not enqueued if the ledger change is rolled back
+#[tokio::test]
+async fn test_finality_rolled_back() {
+	let api = MockProcessorImpl::new(Some("outbox_rollback_db".to_string())).await;
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let acquirer_account = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
+
+	let result = api
+		.processor
+		.transaction_controller
+		.transfer(&TransactionCreate {
+			id: uuid::Uuid::new_v4(),
+			from: alice_account.id,
+			to: Some(acquirer_account.id),
+			amount: Money::new(ALICE.3 + 1, Iso4217::default()),
+			conversion: None,
+			transaction_type: TransactionType::Credit,
+			nonce: alice_account.nonce,
+			iso_msg_raw: vec![48, 50, 48, 48],
+			on_chain_id: Some("1-1".to_string()),
+			finality: Some(FinalityCreate {
+				response_code: "00".to_string(),
+				..get_finality("1-1")
+			}),
+		})
+		.await;
+
+	assert_eq!(result, Err(DomainError::InsufficientFunds));
+	assert_eq!(api.processor.outbox_controller.find_by_event_id("1-1").await.unwrap(), None);
+}
+
+/// Tests submission, backoff, failure and retry of a finality
+#[tokio::test]
+async fn test_finality_lifecycle() {
+	let api = MockProcessorImpl::new(Some("outbox_lifecycle_db".to_string())).await;
+	let outbox = &api.processor.outbox_controller;
+
+	let finality = outbox
+		.create(&FinalityCreate {
+			response_code: "00".to_string(),
+			transaction_hash: "a".repeat(64),
+			..get_finality("1-1")
+		})
+		.await
+		.unwrap();
+
+	// the same event is enqueued once
+	let duplicate = outbox.create(&get_finality("1-1")).await.unwrap();
+	assert_eq!(duplicate, finality);
+
+	let now = Utc::now();
+
+	assert_eq!(outbox.find_due(now, 10).await.unwrap(), vec![finality.clone()]);
+
+	// submitted finalities are due again once they time out
+	let retry_at = now + Duration::seconds(60);
+	let submitted = outbox.mark_submitted(&finality.id, "0x01", retry_at).await.unwrap();
+	assert_eq!(submitted.status, FinalityStatus::Submitted);
+	assert_eq!(submitted.extrinsic_hash, Some("0x01".to_string()));
+	assert!(outbox.find_due(now, 10).await.unwrap().is_empty());
+	assert_eq!(outbox.find_due(retry_at, 10).await.unwrap().len(), 1);
+
+	// failed attempts are retried later
+	let retry_at = submitted.retry_at(now);
+	let pending = outbox.mark_failed_attempt(&finality.id, "dropped", retry_at).await.unwrap();
+	assert_eq!(pending.status, FinalityStatus::Pending);
+	assert_eq!(pending.attempts, 1);
+	assert_eq!(pending.last_error, Some("dropped".to_string()));
+	assert!(outbox.find_due(now, 10).await.unwrap().is_empty());
+
+	// until they fail for good
+	let failed = outbox.mark_failed_attempt(&finality.id, "invalid", None).await.unwrap();
+	assert_eq!(failed.status, FinalityStatus::Failed);
+	assert_eq!(failed.attempts, 2);
+	assert_eq!(outbox.find_failed().await.unwrap(), vec![failed]);
+	assert!(outbox.find_due(now + Duration::days(1), 10).await.unwrap().is_empty());
+
+	// failed finalities are requeued manually
+	assert_eq!(outbox.retry_failed().await.unwrap(), 1);
+	assert!(outbox.find_failed().await.unwrap().is_empty());
+
+	let requeued = outbox.find_due(Utc::now(), 10).await.unwrap();
+	assert_eq!(requeued.len(), 1);
+	assert_eq!(requeued[0].attempts, 0);
+
+	let finalized = outbox.mark_finalized(&finality.id, "0x02").await.unwrap();
+	assert_eq!(finalized.status, FinalityStatus::Finalized);
+	assert_eq!(finalized.block_hash, Some("0x02".to_string()));
+	assert!(outbox.find_due(now + Duration::days(1), 10).await.unwrap().is_empty());
+
+	// finalized ones can't fail anymore
+	assert!(outbox.mark_failed_attempt(&finality.id, "late", None).await.is_err());
+}
@@ -10,7 +10,7 @@ use crate::{
 async fn test_payment() {
 	let api = MockProcessorImpl::new(None).await;
 
-	let spec = api.processor.spec;
+	let spec = api.processor.spec();
 
 	let mut new_msg = get_new_iso_msg(spec, MTI::AuthorizationRequest, ALICE);
 	new_msg.set_on(4, "00000000000000000100").unwrap();
@@ -20,6 +20,24 @@ async fn test_payment() {
 
 	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
 	assert_eq!(msg.bmp_child_value(4).unwrap(), "00000000000000000100");
+	// hold hash is set
+	assert_ne!(msg.bmp_child_value(126).unwrap(), "0".repeat(99));
+
+	// funds are only reserved until the authorization is completed
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+
+	assert_eq!(alice_account.balance.minor_units, ALICE.3);
+	assert_eq!(alice_account.available_balance.minor_units, ALICE.3 - 100);
+	assert!(get_transactions_by_id(&api, &alice_account.id).await.is_empty());
+
+	let mut completion_msg = get_new_iso_msg(spec, MTI::FinancialAdvice, ALICE);
+	completion_msg.set_on(4, "00000000000000000100").unwrap();
+	completion_msg.set_on(126, &msg.bmp_child_value(126).unwrap()).unwrap();
+
+	let mut msg_raw = completion_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
 	// transaction hash is set
 	assert_ne!(msg.bmp_child_value(126).unwrap(), "0".repeat(99));
 
@@ -29,7 +47,7 @@ async fn test_payment() {
 	assert_eq!(alice_txs.len(), 1);
 	let alice_tx = &alice_txs[0];
 
-	assert_eq!(alice_tx.amount, 100);
+	assert_eq!(alice_tx.amount.minor_units, 100);
 	assert_eq!(alice_tx.from, alice_account.id);
 	assert!(alice_tx.to.is_some());
 	assert_eq!(alice_tx.transaction_type, 1); // Credit transaction
@@ -119,8 +137,8 @@ async fn test_payment() {
 	)
 	.await;
 
-	// And now finally, DAVE makes big payment
-	let mut new_msg = get_new_iso_msg(spec, MTI::AuthorizationRequest, DAVE);
+	// And now finally, DAVE makes big single-message payment
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, DAVE);
 	new_msg.set_on(4, "00000000000000100000").unwrap();
 
 	let mut msg_raw = new_msg.assemble().unwrap();
@@ -131,7 +149,7 @@ async fn test_payment() {
 
 	let dave_account = get_bank_account_by_card_number(&api, DAVE.1).await;
 
-	assert_eq!(dave_account.balance, DAVE.3 - 100_000);
+	assert_eq!(dave_account.balance.minor_units, DAVE.3 - 100_000);
 
 	let dave_txs = api
 		.processor
@@ -143,12 +161,12 @@ async fn test_payment() {
 	assert_eq!(dave_txs.len(), 1);
 	let dave_tx = &dave_txs[0];
 
-	assert_eq!(dave_tx.amount, 100_000);
+	assert_eq!(dave_tx.amount.minor_units, 100_000);
 
 	// Settlement is `on-us` since merchant is hard coded as `ACQUIRER`
 	let acquirer = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
 
-	assert_eq!(acquirer.balance, ACQUIRER.3 + 100_000 + 100);
+	assert_eq!(acquirer.balance.minor_units, ACQUIRER.3 + 100_000 + 100);
 
 	for (alice_tx, dave_tx) in alice_txs.iter().zip(dave_txs.iter()) {
 		assert_eq!(alice_tx.to, Some(acquirer.id));
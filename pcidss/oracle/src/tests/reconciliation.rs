@@ -0,0 +1,83 @@
+//! Tests for the reconciliation of the ledger with the on-chain balances
+
+use op_core::{
+	bank_account::models::BankAccount,
+	money::{Iso4217, Money},
+};
+
+use crate::{
+	services::reconciliation::{alert, compare, ReconciliationReport},
+	tests::{mock::*, prelude::*},
+	types::constants::DEV_ACCOUNTS,
+};
+
+/// Tests the registered bank accounts are compared with their on-chain balances
+#[tokio::test]
+async fn test_reconciliation() {
+	let api = MockProcessorImpl::new(Some("reconciliation_db".to_string())).await;
+
+	// only the bank accounts with an on-chain account are reconciled
+	let registered = api.processor.bank_account_controller.find_registered().await.unwrap();
+
+	assert_eq!(registered.len(), DEV_ACCOUNTS.iter().filter(|account| account.4.is_some()).count());
+	assert!(registered.windows(2).all(|pair| pair[0].id < pair[1].id));
+
+	let alice = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let charlie = get_bank_account_by_card_number(&api, CHARLIE.1).await;
+	let dave = get_bank_account_by_card_number(&api, DAVE.1).await;
+
+	// one cent is 10^6 on-chain units with 8 decimals
+	let report = ReconciliationReport::new(
+		"00".to_string(),
+		8,
+		&[
+			(alice.clone(), Some(ALICE.3 as u128 * 1_000_000)),
+			(charlie.clone(), Some(CHARLIE.3 as u128 * 1_000_000 + 1)),
+			(dave.clone(), None),
+		],
+	);
+
+	assert!(!report.is_consistent());
+	assert_eq!(report.bank_accounts, 3);
+	assert_eq!(report.discrepancies.len(), 2);
+
+	assert_eq!(report.discrepancies[0].bank_account_id, charlie.id);
+	assert_eq!(report.discrepancies[0].account_id, CHARLIE.4.unwrap());
+	assert_eq!(report.discrepancies[0].expected_on_chain, Some(CHARLIE.3 as u128 * 1_000_000));
+	assert_eq!(report.discrepancies[0].on_chain_balance, Some(CHARLIE.3 as u128 * 1_000_000 + 1));
+
+	// missing on-chain account
+	assert_eq!(report.discrepancies[1].bank_account_id, dave.id);
+	assert_eq!(report.discrepancies[1].on_chain_balance, None);
+
+	let json = serde_json::to_value(&report).unwrap();
+	assert_eq!(
+		json["discrepancies"][0]["ledger_balance"],
+		serde_json::to_value(charlie.balance).unwrap()
+	);
+	assert_eq!(json["discrepancies"][1]["on_chain_balance"], serde_json::Value::Null);
+
+	// balances that can't be represented on-chain never match
+	let one_cent = BankAccount { balance: Money::new(1, Iso4217::Usd), ..alice };
+
+	assert_eq!(compare(&one_cent, Some(0), 1).unwrap().expected_on_chain, None);
+	assert_eq!(compare(&one_cent, Some(1_000_000), 8), None);
+}
+
+/// Tests the alert command gets the JSON report on its standard input
+#[tokio::test]
+async fn test_reconciliation_alert() {
+	let report = ReconciliationReport::new("00".to_string(), 8, &[]);
+	let path = std::env::temp_dir().join(format!("alert-{}.json", uuid::Uuid::new_v4()));
+
+	alert(&format!("cat > {}", path.display()), &report).await.unwrap();
+
+	let alerted: serde_json::Value =
+		serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+	assert_eq!(alerted, serde_json::to_value(&report).unwrap());
+
+	// failing commands are reported
+	assert!(alert("exit 1", &report).await.is_err());
+
+	std::fs::remove_file(&path).unwrap();
+}
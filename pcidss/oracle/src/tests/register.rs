@@ -11,7 +11,7 @@ async fn test_register() {
 	env_logger::init();
 	let api = MockProcessorImpl::new(Some("register_db".to_string())).await;
 
-	let spec = api.processor.spec;
+	let spec = api.processor.spec();
 
 	let mti = MTI::NetworkManagementRequest;
 	let mut new_msg = get_new_iso_msg(spec, mti.clone(), ALICE);
@@ -51,4 +51,23 @@ async fn test_register() {
 		charlie_account.account_id,
 		Some(CHARLIE.4.unwrap().trim_start_matches("0x").to_string())
 	);
+
+	// supply a non-hex account id
+	let mut new_msg = get_new_iso_msg(spec, MTI::NetworkManagementRequest, CHARLIE);
+	new_msg.set_on(4, &"0".repeat(20)).unwrap();
+	new_msg.set_on(126, &"z".repeat(64)).unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	// Assert processing results
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "12");
+
+	let charlie_account = get_bank_account_by_card_number(&api, CHARLIE.1).await;
+
+	assert_eq!(
+		charlie_account.account_id,
+		Some(CHARLIE.4.unwrap().trim_start_matches("0x").to_string())
+	);
 }
@@ -0,0 +1,195 @@
+//! Tests for compensating on-chain events of retracted blocks
+
+use op_core::outbox::models::FinalityCreate;
+
+use crate::{
+	tests::{mock::*, prelude::*},
+	types::{constants::EVENT_ID_FIELD_NUMBER, MTI},
+};
+
+/// Finality of an event in another block than the default one of [`get_finality`]
+fn get_forked_finality(event_id: &str) -> FinalityCreate {
+	FinalityCreate { event_block_hash: format!("0x{}", "b".repeat(64)), ..get_finality(event_id) }
+}
+
+/// Tests that a retracted transfer is moved back and processed again on the new fork
+#[tokio::test]
+async fn test_retract_transfer() {
+	let api = MockProcessorImpl::new(Some("reorg_transfer_db".to_string())).await;
+	let spec = api.processor.spec();
+	let outbox = &api.processor.outbox_controller;
+
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(EVENT_ID_FIELD_NUMBER, "5-1").unwrap();
+	let iso_msg_raw = new_msg.assemble().unwrap();
+
+	let (_, msg) = api
+		.processor
+		.process_on_chain(&mut iso_msg_raw.clone(), &get_finality("5-1"))
+		.await
+		.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let acquirer_account = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 100);
+
+	let transaction = get_transactions_by_id(&api, &alice_account.id).await.remove(0);
+	assert_eq!(transaction.block_hash, Some(get_finality("5-1").event_block_hash));
+
+	// the block of the event is retracted
+	let finality = outbox.find_by_event_id("5-1").await.unwrap().unwrap();
+	api.processor.retract_event(&finality).await.unwrap();
+
+	assert_eq!(get_bank_account_by_card_number(&api, ALICE.1).await.balance.minor_units, ALICE.3);
+	assert_eq!(
+		get_bank_account_by_card_number(&api, ACQUIRER.1).await.balance.minor_units,
+		acquirer_account.balance.minor_units - 100
+	);
+	assert!(get_transactions_by_id(&api, &alice_account.id).await[0].retracted);
+	assert_eq!(
+		api.processor.transaction_controller.find_by_on_chain_id("5-1").await.unwrap(),
+		None
+	);
+	assert_eq!(outbox.find_by_event_id("5-1").await.unwrap(), None);
+
+	// the new fork emits the same event, it is processed again
+	let (_, msg) = api
+		.processor
+		.process_on_chain(&mut iso_msg_raw.clone(), &get_forked_finality("5-1"))
+		.await
+		.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(
+		get_bank_account_by_card_number(&api, ALICE.1).await.balance.minor_units,
+		ALICE.3 - 100
+	);
+
+	let transaction = api
+		.processor
+		.transaction_controller
+		.find_by_on_chain_id("5-1")
+		.await
+		.unwrap()
+		.unwrap();
+	assert_eq!(transaction.block_hash, Some(get_forked_finality("5-1").event_block_hash));
+
+	// a stale finality of the old fork leaves the new one alone
+	api.processor.retract_event(&finality).await.unwrap();
+	assert_eq!(
+		get_bank_account_by_card_number(&api, ALICE.1).await.balance.minor_units,
+		ALICE.3 - 100
+	);
+	assert!(outbox.find_by_event_id("5-1").await.unwrap().is_some());
+
+	// and it is still answered with the stored response
+	let (_, msg) = api
+		.processor
+		.process_on_chain(&mut iso_msg_raw.clone(), &get_forked_finality("5-1"))
+		.await
+		.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(
+		get_bank_account_by_card_number(&api, ALICE.1).await.balance.minor_units,
+		ALICE.3 - 100
+	);
+
+	// retracted transactions can't be retracted twice
+	assert!(api
+		.processor
+		.transaction_controller
+		.retract(&get_transactions_by_id(&api, &alice_account.id).await[0].id)
+		.await
+		.is_err());
+}
+
+/// Tests that the reverted transaction is refundable again once the reversal is retracted
+#[tokio::test]
+async fn test_retract_reversal() {
+	let api = MockProcessorImpl::new(Some("reorg_reversal_db".to_string())).await;
+	let spec = api.processor.spec();
+	let outbox = &api.processor.outbox_controller;
+
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(EVENT_ID_FIELD_NUMBER, "5-1").unwrap();
+	api.processor
+		.process_on_chain(&mut new_msg.assemble().unwrap(), &get_finality("5-1"))
+		.await
+		.unwrap();
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let transaction = get_transactions_by_id(&api, &alice_account.id).await.remove(0);
+
+	let mut new_msg = get_new_iso_msg(spec, MTI::ReversalRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(126, &transaction.hash).unwrap();
+	new_msg.set_on(EVENT_ID_FIELD_NUMBER, "6-1").unwrap();
+
+	let (_, msg) = api
+		.processor
+		.process_on_chain(&mut new_msg.assemble().unwrap(), &get_forked_finality("6-1"))
+		.await
+		.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(get_bank_account_by_card_number(&api, ALICE.1).await.balance.minor_units, ALICE.3);
+
+	// only the block of the reversal is retracted
+	let finality = outbox.find_by_event_id("6-1").await.unwrap().unwrap();
+	api.processor.retract_event(&finality).await.unwrap();
+
+	assert_eq!(
+		get_bank_account_by_card_number(&api, ALICE.1).await.balance.minor_units,
+		ALICE.3 - 100
+	);
+
+	let parent = api
+		.processor
+		.transaction_controller
+		.find_by_on_chain_id("5-1")
+		.await
+		.unwrap()
+		.unwrap();
+	assert!(!parent.reversed);
+	assert!(!parent.retracted);
+	assert_eq!(parent.refundable_amount().minor_units, 100);
+	assert!(outbox.find_by_event_id("5-1").await.unwrap().is_some());
+
+	// then the block of the transfer as well
+	let finality = outbox.find_by_event_id("5-1").await.unwrap().unwrap();
+	api.processor.retract_event(&finality).await.unwrap();
+
+	assert_eq!(get_bank_account_by_card_number(&api, ALICE.1).await.balance.minor_units, ALICE.3);
+	assert_eq!(outbox.find_by_event_id("5-1").await.unwrap(), None);
+}
+
+/// Tests that a declined event only drops its finality
+#[tokio::test]
+async fn test_retract_declined() {
+	let api = MockProcessorImpl::new(Some("reorg_declined_db".to_string())).await;
+	let spec = api.processor.spec();
+	let outbox = &api.processor.outbox_controller;
+
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000010000").unwrap();
+	new_msg.set_on(EVENT_ID_FIELD_NUMBER, "5-1").unwrap();
+
+	let (_, msg) = api
+		.processor
+		.process_on_chain(&mut new_msg.assemble().unwrap(), &get_finality("5-1"))
+		.await
+		.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "51");
+
+	let finality = outbox.find_by_event_id("5-1").await.unwrap().unwrap();
+	assert_eq!(
+		outbox.find_by_event_block_hash(&finality.event_block_hash).await.unwrap(),
+		vec![finality.clone()]
+	);
+
+	api.processor.retract_event(&finality).await.unwrap();
+
+	assert_eq!(outbox.find_by_event_id("5-1").await.unwrap(), None);
+	assert_eq!(get_bank_account_by_card_number(&api, ALICE.1).await.balance.minor_units, ALICE.3);
+}
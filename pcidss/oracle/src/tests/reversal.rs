@@ -10,9 +10,9 @@ async fn test_reversals_success() {
 	let api = MockProcessorImpl::new(Some("testdb".to_string())).await;
 
 	// make a basic transaction payment from Alice
-	let spec = api.processor.spec;
+	let spec = api.processor.spec();
 
-	let mut new_msg = get_new_iso_msg(spec, MTI::AuthorizationRequest, ALICE);
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
 	new_msg.set_on(4, "00000000000000000100").unwrap();
 
 	let mut msg_raw = new_msg.assemble().unwrap();
@@ -26,7 +26,7 @@ async fn test_reversals_success() {
 	let alice_tx = &alice_txs[0];
 
 	assert_eq!(alice_txs.len(), 1);
-	assert_eq!(alice_tx.amount, 100);
+	assert_eq!(alice_tx.amount.minor_units, 100);
 
 	// make a reversal transaction from Alice
 	let mut reversal_new_msg = get_new_iso_msg(spec, MTI::ReversalRequest, ALICE);
@@ -47,15 +47,18 @@ async fn test_reversals_success() {
 	let acquirer_account = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
 
 	// balances should be the same as before
-	assert_eq!(alice_account.balance, ALICE.3);
-	assert_eq!(acquirer_account.balance, ACQUIRER.3);
+	assert_eq!(alice_account.balance.minor_units, ALICE.3);
+	assert_eq!(acquirer_account.balance.minor_units, ACQUIRER.3);
 
 	// get alice txs again
 	let alice_txs = get_transactions_by_id(&api, &alice_account.id).await;
 
-	// tx len unchanged, but tx is flagged as reversed now
-	assert_eq!(alice_txs.len(), 1);
+	// original tx is flagged as reversed and the refund is linked to it
+	assert_eq!(alice_txs.len(), 2);
 	assert!(alice_txs[0].reversed);
+	assert_eq!(alice_txs[0].refunded_amount.minor_units, 100);
+	assert_eq!(alice_txs[1].parent_id, Some(alice_txs[0].id));
+	assert_eq!(alice_txs[1].amount.minor_units, 100);
 
 	// VALIDATION TESTS
 	// Try to reverse a transaction that doesn't exist
@@ -89,3 +92,191 @@ async fn test_reversals_success() {
 	)
 	.await;
 }
+
+/// Tests partial reversals of a payment
+#[tokio::test]
+async fn test_partial_reversals() {
+	let api = MockProcessorImpl::new(Some("partial_reversal_db".to_string())).await;
+
+	let spec = api.processor.spec();
+
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let alice_tx = get_transactions_by_id(&api, &alice_account.id).await.remove(0);
+
+	// refund 30 out of 100
+	let mut reversal_msg = get_new_iso_msg(spec, MTI::ReversalRequest, ALICE);
+	reversal_msg.set_on(4, "00000000000000000030").unwrap();
+	reversal_msg.set_on(126, &alice_tx.hash).unwrap();
+
+	let mut msg_raw = reversal_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let acquirer_account = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
+
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 70);
+	assert_eq!(acquirer_account.balance.minor_units, ACQUIRER.3 + 70);
+
+	let alice_txs = get_transactions_by_id(&api, &alice_account.id).await;
+
+	assert_eq!(alice_txs.len(), 2);
+	assert!(!alice_txs[0].reversed);
+	assert_eq!(alice_txs[0].refundable_amount().minor_units, 70);
+	assert_eq!(alice_txs[1].parent_id, Some(alice_tx.id));
+	assert_eq!(alice_txs[1].amount.minor_units, 30);
+
+	// VALIDATION TESTS
+	// Only the merchant that was paid can refund
+	let mut new_msg = get_new_iso_msg(spec, MTI::ReversalRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000010").unwrap();
+	new_msg.set_on(32, CHARLIE.1).unwrap();
+	new_msg.set_on(126, &alice_tx.hash).unwrap();
+
+	assert_noop(
+		&api,
+		ALICE,
+		&new_msg,
+		ResponseCodes::NotPermitted,
+		alice_account.clone(),
+		alice_txs.clone(),
+	)
+	.await;
+
+	// Only the cardholder who paid can be refunded
+	let mut new_msg = get_new_iso_msg(spec, MTI::ReversalRequest, CHARLIE);
+	new_msg.set_on(4, "00000000000000000010").unwrap();
+	new_msg.set_on(126, &alice_tx.hash).unwrap();
+
+	let charlie_account = get_bank_account_by_card_number(&api, CHARLIE.1).await;
+	let charlie_txs = get_transactions_by_id(&api, &charlie_account.id).await;
+
+	assert_noop(&api, CHARLIE, &new_msg, ResponseCodes::NotPermitted, charlie_account, charlie_txs)
+		.await;
+
+	// Can't refund more than what is left
+	let mut new_msg = get_new_iso_msg(spec, MTI::ReversalRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000071").unwrap();
+	new_msg.set_on(126, &alice_tx.hash).unwrap();
+
+	assert_noop(
+		&api,
+		ALICE,
+		&new_msg,
+		ResponseCodes::InvalidAmount,
+		alice_account.clone(),
+		alice_txs.clone(),
+	)
+	.await;
+
+	// Refunds themselves can't be reversed
+	let mut new_msg = get_new_iso_msg(spec, MTI::ReversalRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000030").unwrap();
+	new_msg.set_on(126, &alice_txs[1].hash).unwrap();
+
+	assert_noop(
+		&api,
+		ALICE,
+		&new_msg,
+		ResponseCodes::InvalidAmount,
+		alice_account.clone(),
+		alice_txs.clone(),
+	)
+	.await;
+
+	// refunding the rest reverses the transaction fully
+	reversal_msg.set_on(4, "00000000000000000070").unwrap();
+
+	let mut msg_raw = reversal_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let acquirer_account = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
+
+	assert_eq!(alice_account.balance.minor_units, ALICE.3);
+	assert_eq!(acquirer_account.balance.minor_units, ACQUIRER.3);
+
+	let alice_txs = get_transactions_by_id(&api, &alice_account.id).await;
+
+	assert_eq!(alice_txs.len(), 3);
+	assert!(alice_txs[0].reversed);
+	assert_eq!(alice_txs[0].refundable_amount().minor_units, 0);
+}
+
+/// Tests that cardholders are refunded regardless of their balance, but only if the merchant can
+/// pay the refund back
+#[tokio::test]
+async fn test_refund_of_spent_funds() {
+	let api = MockProcessorImpl::new(Some("refund_spent_funds_db".to_string())).await;
+
+	let spec = api.processor.spec();
+
+	// Alice spends all her funds
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, &format!("{:020}", ALICE.3)).unwrap();
+
+	let (_, msg) = api.processor.process(&mut new_msg.assemble().unwrap()).await.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert!(alice_account.available_balance.is_zero());
+
+	let alice_tx = get_transactions_by_id(&api, &alice_account.id).await.remove(0);
+
+	// the hash may be prefixed with `0x`
+	let mut reversal_msg = get_new_iso_msg(spec, MTI::ReversalRequest, ALICE);
+	reversal_msg.set_on(4, &format!("{:020}", ALICE.3)).unwrap();
+	reversal_msg.set_on(126, &format!("0x{}", alice_tx.hash)).unwrap();
+
+	let (_, msg) = api.processor.process(&mut reversal_msg.assemble().unwrap()).await.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert_eq!(alice_account.available_balance.minor_units, ALICE.3);
+
+	// the merchant spent the payment in the meantime
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+
+	let (_, msg) = api.processor.process(&mut new_msg.assemble().unwrap()).await.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let acquirer_account = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
+	let client = api.pg_pool.get().await.unwrap();
+	client
+		.execute(
+			"UPDATE bank_account SET balance = 0, available_balance = 0 WHERE id = $1",
+			&[&acquirer_account.id],
+		)
+		.await
+		.unwrap();
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let alice_txs = get_transactions_by_id(&api, &alice_account.id).await;
+	let alice_tx = alice_txs.iter().find(|tx| !tx.reversed && tx.parent_id.is_none()).unwrap();
+
+	let mut reversal_msg = get_new_iso_msg(spec, MTI::ReversalRequest, ALICE);
+	reversal_msg.set_on(4, "00000000000000000100").unwrap();
+	reversal_msg.set_on(126, &alice_tx.hash).unwrap();
+
+	assert_noop(
+		&api,
+		ALICE,
+		&reversal_msg,
+		ResponseCodes::InsufficientFunds,
+		alice_account.clone(),
+		alice_txs.clone(),
+	)
+	.await;
+}
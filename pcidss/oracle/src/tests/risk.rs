@@ -0,0 +1,178 @@
+//! Tests for the risk engine in the authorization path
+
+use iso8583_rs::iso8583::iso_spec::IsoMsg;
+use op_api::risk::PgRisk;
+use std::sync::Arc;
+
+use crate::{
+	risk::{RiskEngine, RiskRules},
+	services::processor::Iso8583MessageProcessor,
+	tests::{mock::*, prelude::*},
+	types::MTI,
+};
+
+/// Processor of the mock with the given risk rules
+fn with_rules(api: &MockProcessorImpl, rules: &str) -> Iso8583MessageProcessor {
+	Iso8583MessageProcessor {
+		risk: Arc::new(RiskEngine::new(
+			RiskRules::parse(rules).unwrap(),
+			Arc::new(PgRisk::new(api.pg_pool.clone())),
+		)),
+		..(*api.processor).clone()
+	}
+}
+
+/// Tests single and daily amount limits and the velocity of payments
+#[tokio::test]
+async fn test_risk_limits() {
+	let api = MockProcessorImpl::new(Some("risk_limits_db".to_string())).await;
+	let processor = with_rules(
+		&api,
+		r#"
+max_amount: { USD: 600 }
+daily_limit: { USD: 800 }
+velocity: { max_transactions: 3, window_secs: 3600 }
+"#,
+	);
+
+	// EXCEEDS AMOUNT LIMIT
+	assert_eq!(
+		process_payment(&processor, MTI::FinancialRequest, ALICE, 700, |_| {}).await.0,
+		"61"
+	);
+
+	// authorization holds count towards the daily spending
+	assert_eq!(
+		process_payment(&processor, MTI::FinancialRequest, ALICE, 500, |_| {}).await.0,
+		"00"
+	);
+	assert_eq!(
+		process_payment(&processor, MTI::AuthorizationRequest, ALICE, 200, |_| {})
+			.await
+			.0,
+		"00"
+	);
+
+	// EXCEEDS DAILY LIMIT
+	assert_eq!(
+		process_payment(&processor, MTI::FinancialRequest, ALICE, 200, |_| {}).await.0,
+		"61"
+	);
+	assert_eq!(process_payment(&processor, MTI::FinancialRequest, ALICE, 50, |_| {}).await.0, "00");
+
+	// EXCEEDS FREQUENCY LIMIT
+	assert_eq!(process_payment(&processor, MTI::FinancialRequest, ALICE, 1, |_| {}).await.0, "65");
+
+	// limits are per card
+	assert_eq!(
+		process_payment(&processor, MTI::FinancialRequest, CHARLIE, 600, |_| {}).await.0,
+		"00"
+	);
+
+	// without rules the payment is approved
+	assert_eq!(
+		process_payment(&api.processor, MTI::FinancialRequest, ALICE, 1, |_| {}).await.0,
+		"00"
+	);
+}
+
+/// Tests payments of blocked acquirers and merchants are declined as suspected fraud
+#[tokio::test]
+async fn test_risk_blocklists() {
+	let api = MockProcessorImpl::new(Some("risk_blocklists_db".to_string())).await;
+	let processor = with_rules(&api, r#"{ "blocked_merchants": ["TERM0666"] }"#);
+
+	let terminal =
+		|terminal: &'static str| move |msg: &mut IsoMsg| msg.set_on(41, terminal).unwrap();
+
+	assert_eq!(
+		process_payment(&processor, MTI::FinancialRequest, ALICE, 100, terminal("TERM0666"))
+			.await
+			.0,
+		"59"
+	);
+	assert_eq!(
+		process_payment(&processor, MTI::AuthorizationRequest, ALICE, 100, terminal("TERM0666"))
+			.await
+			.0,
+		"59"
+	);
+	assert_eq!(
+		process_payment(&processor, MTI::FinancialRequest, ALICE, 100, terminal("TERM0001"))
+			.await
+			.0,
+		"00"
+	);
+
+	let processor = with_rules(&api, &format!("blocked_acquirers: [\"{}\"]", ACQUIRER.1));
+	assert_eq!(
+		process_payment(&processor, MTI::FinancialRequest, ALICE, 100, |_| {}).await.0,
+		"59"
+	);
+
+	// unknown rules are rejected
+	assert!(RiskRules::parse("blocked_cards: []").is_err());
+}
+
+/// Tests the CVV verifications are locked out after consecutive failures
+#[tokio::test]
+async fn test_risk_cvv_lockout() {
+	let api = MockProcessorImpl::new(Some("risk_cvv_lockout_db".to_string())).await;
+	let processor = with_rules(&api, "cvv_lockout: { max_attempts: 2, lockout_secs: 3600 }");
+
+	let wrong_cvv = |msg: &mut IsoMsg| {
+		let track_2_data = msg.bmp_child_value(35).unwrap();
+		let (card, _) = track_2_data.rsplit_once('C').unwrap();
+		msg.set_on(35, &format!("{}C999", card)).unwrap();
+	};
+
+	// a successful verification resets the failures
+	assert_eq!(
+		process_payment(&processor, MTI::FinancialRequest, ALICE, 100, wrong_cvv)
+			.await
+			.0,
+		"05"
+	);
+	assert_eq!(
+		process_payment(&processor, MTI::FinancialRequest, ALICE, 100, |_| {}).await.0,
+		"00"
+	);
+
+	// TRIES EXCEEDED, even with the right CVV
+	assert_eq!(
+		process_payment(&processor, MTI::FinancialRequest, ALICE, 100, wrong_cvv)
+			.await
+			.0,
+		"05"
+	);
+	assert_eq!(
+		process_payment(&processor, MTI::AuthorizationRequest, ALICE, 100, wrong_cvv)
+			.await
+			.0,
+		"05"
+	);
+	assert_eq!(
+		process_payment(&processor, MTI::FinancialRequest, ALICE, 100, |_| {}).await.0,
+		"75"
+	);
+
+	// other cards are not locked out
+	assert_eq!(
+		process_payment(&processor, MTI::FinancialRequest, CHARLIE, 100, |_| {}).await.0,
+		"00"
+	);
+
+	// lockout is over once the last failure is old enough
+	api.pg_pool
+		.get()
+		.await
+		.unwrap()
+		.execute("UPDATE cvv_failure SET last_failed_at = now() - interval '2 hours'", &[])
+		.await
+		.unwrap();
+
+	assert_eq!(
+		process_payment(&processor, MTI::FinancialRequest, ALICE, 100, |_| {}).await.0,
+		"00"
+	);
+}
@@ -0,0 +1,155 @@
+//! Tests for the settlement of the acquirers and the reconciliation of their totals
+
+use chrono::{NaiveTime, TimeZone, Utc};
+use iso8583_rs::iso8583::iso_spec::{new_msg, IsoMsg};
+use op_api::settlement::PgSettlement;
+use op_core::settlement::traits::SettlementTrait;
+use std::sync::Arc;
+
+use crate::{
+	services::settlement::{clearing_file_name, next_cutoff, SettlementWorker},
+	tests::{mock::*, prelude::*},
+	types::MTI,
+};
+
+/// Sends a reconciliation request with the totals of the acquirer and returns the response
+///
+/// Totals are the number and amount of the credits and of the debits.
+async fn reconcile(
+	api: &MockProcessorImpl,
+	acquirer: &str,
+	settlement_date: Option<&str>,
+	totals: (u32, i64, u32, i64),
+) -> IsoMsg {
+	let spec = api.processor.spec();
+	let mut msg =
+		new_msg(spec, spec.get_message_from_header(MTI::ReconciliationRequest.into()).unwrap());
+	let (credits_number, credits_amount, debits_number, debits_amount) = totals;
+	let net = credits_amount - debits_amount;
+
+	msg.set("message_type", MTI::ReconciliationRequest.into()).unwrap();
+	msg.set_on(7, &Utc::now().format("%m%d%H%M%S").to_string()).unwrap();
+	msg.set_on(32, acquirer).unwrap();
+	msg.set_on(74, &format!("{:010}", credits_number)).unwrap();
+	msg.set_on(76, &format!("{:010}", debits_number)).unwrap();
+	msg.set_on(86, &format!("{:016}", credits_amount)).unwrap();
+	msg.set_on(88, &format!("{:016}", debits_amount)).unwrap();
+	msg.set_on(97, &format!("{}{:016}", if net < 0 { 'D' } else { 'C' }, net.abs()))
+		.unwrap();
+
+	if let Some(settlement_date) = settlement_date {
+		msg.set_on(15, settlement_date).unwrap();
+	}
+
+	let mut msg_raw = msg.assemble().unwrap();
+	let (_, res) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(res.get_field_value(&"message_type".to_string()).unwrap(), "0510");
+	res
+}
+
+/// Tests the cut-off settles the transactions of the acquirer in a batch with a clearing file
+#[tokio::test]
+async fn test_settlement() {
+	let api = MockProcessorImpl::new(Some("settlement_db".to_string())).await;
+	let settlement = Arc::new(PgSettlement::new(api.pg_pool.clone()));
+	let acquirer = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
+
+	// purchase and partial reversal
+	let (response_code, msg) =
+		process_payment(&api.processor, MTI::FinancialRequest, ALICE, 500, |_| {}).await;
+	assert_eq!(response_code, "00");
+
+	let (response_code, _) =
+		process_payment(&api.processor, MTI::FinancialRequest, CHARLIE, 300, |_| {}).await;
+	assert_eq!(response_code, "00");
+
+	let hold_hash = msg.bmp_child_value(126).unwrap();
+	let set_hash = |msg: &mut IsoMsg| msg.set_on(126, &hold_hash).unwrap();
+	let (response_code, _) =
+		process_payment(&api.processor, MTI::ReversalRequest, ALICE, 200, set_hash).await;
+	assert_eq!(response_code, "00");
+
+	// totals of the open window are in balance
+	let res = reconcile(&api, ACQUIRER.1, None, (2, 800, 1, 200)).await;
+
+	assert_eq!(res.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(res.bmp_child_value(66).unwrap(), "1");
+	assert_eq!(res.bmp_child_value(97).unwrap(), "C0000000000000600");
+
+	// totals are settled at the cut-off
+	let clearing_dir = std::env::temp_dir().join(format!("clearing-{}", uuid::Uuid::new_v4()));
+	let worker = SettlementWorker::new(settlement.clone(), NaiveTime::MIN, clearing_dir.as_path());
+
+	let cutoff = Utc::now();
+	let batches = worker.settle(cutoff).await.unwrap();
+
+	assert_eq!(batches.len(), 1);
+	assert_eq!(batches[0].acquirer_id, acquirer.id);
+	assert_eq!(batches[0].settlement_date, cutoff.date_naive());
+	assert_eq!(
+		(batches[0].totals.credits_number, batches[0].totals.credits_amount.minor_units),
+		(2, 800)
+	);
+	assert_eq!(
+		(batches[0].totals.debits_number, batches[0].totals.debits_amount.minor_units),
+		(1, 200)
+	);
+
+	let clearing_file =
+		std::fs::read_to_string(clearing_dir.join(clearing_file_name(&batches[0]))).unwrap();
+	let lines: Vec<&str> = clearing_file.lines().collect();
+
+	assert_eq!(lines.len(), 5);
+	assert!(lines[0].starts_with(&format!("H,{},{}", batches[0].id, acquirer.id)));
+	assert_eq!(lines.iter().filter(|line| line.starts_with("D,")).count(), 3);
+	assert_eq!(lines[4], "T,2,800,1,200,C600");
+
+	std::fs::remove_dir_all(&clearing_dir).unwrap();
+
+	// settled transactions are not settled again, new ones are in the open window
+	assert!(settlement.cut_off(Utc::now()).await.unwrap().is_empty());
+
+	let (response_code, _) =
+		process_payment(&api.processor, MTI::FinancialRequest, ALICE, 100, |_| {}).await;
+	assert_eq!(response_code, "00");
+
+	let totals = settlement.open_totals(&acquirer.id).await.unwrap();
+	assert_eq!((totals.credits_number, totals.credits_amount.minor_units), (1, 100));
+	assert_eq!((totals.debits_number, totals.debits_amount.minor_units), (0, 0));
+
+	// totals of the settled batch are out of balance, ours are returned
+	let settlement_date = cutoff.format("%m%d").to_string();
+	let res = reconcile(&api, ACQUIRER.1, Some(&settlement_date), (2, 800, 0, 0)).await;
+
+	assert_eq!(res.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(res.bmp_child_value(15).unwrap(), settlement_date);
+	assert_eq!(res.bmp_child_value(66).unwrap(), "2");
+	assert_eq!(res.bmp_child_value(76).unwrap(), "0000000001");
+	assert_eq!(res.bmp_child_value(88).unwrap(), "0000000000000200");
+
+	// no batch on another day
+	let res = reconcile(&api, ACQUIRER.1, Some("0230"), (0, 0, 0, 0)).await;
+	assert_eq!(res.bmp_child_value(66).unwrap(), "1");
+
+	// UNKNOWN ACQUIRER
+	let res = reconcile(&api, "654321", None, (0, 0, 0, 0)).await;
+	assert_eq!(res.bmp_child_value(39).unwrap(), "03");
+}
+
+/// Tests the next cut-off is the first one after now
+#[test]
+fn test_next_cutoff() {
+	let cutoff_time = NaiveTime::from_hms_opt(22, 0, 0).unwrap();
+	let now = Utc.with_ymd_and_hms(2024, 3, 1, 21, 59, 59).unwrap();
+
+	assert_eq!(next_cutoff(now, cutoff_time), Utc.with_ymd_and_hms(2024, 3, 1, 22, 0, 0).unwrap());
+	assert_eq!(
+		next_cutoff(Utc.with_ymd_and_hms(2024, 3, 1, 22, 0, 0).unwrap(), cutoff_time),
+		Utc.with_ymd_and_hms(2024, 3, 2, 22, 0, 0).unwrap()
+	);
+	assert_eq!(
+		next_cutoff(now, NaiveTime::MIN),
+		Utc.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap()
+	);
+}
@@ -0,0 +1,73 @@
+//! Tests for named ISO-8583 specs
+
+use op_core::error::DomainError;
+
+use crate::{
+	spec::SpecLoader,
+	tests::{mock::*, prelude::*},
+	types::MTI,
+};
+
+/// Name of the spec of an acquirer that sends 12 digit amounts
+const SHORT_AMOUNT_SPEC_NAME: &str = "short_amount";
+
+/// Tests that messages are parsed with the spec selected by name
+#[tokio::test]
+async fn test_named_specs() {
+	let test_spec = std::fs::read_to_string(TEST_SPEC_PATH).unwrap();
+	let short_amount_spec = SpecLoader::parse(&test_spec.replace("len: 20", "len: 12")).unwrap();
+
+	// specs are parsed once per YAML
+	let test_spec = SpecLoader::parse(&test_spec).unwrap();
+	assert!(std::ptr::eq(SpecLoader::load(TEST_SPEC_PATH).unwrap(), test_spec));
+	assert!(!std::ptr::eq(short_amount_spec, test_spec));
+
+	let specs = SpecLoader::new(TEST_SPEC_NAME, SpecLoader::load(TEST_SPEC_PATH).unwrap())
+		.with_spec(SHORT_AMOUNT_SPEC_NAME, short_amount_spec);
+
+	let mut names = specs.names();
+	names.sort();
+	assert_eq!(names, vec![SHORT_AMOUNT_SPEC_NAME, TEST_SPEC_NAME]);
+
+	let api = MockProcessorImpl::with_specs(Some("spec_db".to_string()), specs).await;
+
+	// default spec
+	let mut new_msg = get_new_iso_msg(api.processor.spec(), MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process_with_spec(None, &mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	// named spec, the response is composed with it as well
+	let mut new_msg = get_new_iso_msg(short_amount_spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "000000000050").unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (_, msg) = api
+		.processor
+		.process_with_spec(Some(SHORT_AMOUNT_SPEC_NAME), &mut msg_raw)
+		.await
+		.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(msg.bmp_child_value(4).unwrap(), "000000000050");
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 150);
+
+	// the message does not fit the default spec
+	let mut msg_raw = new_msg.assemble().unwrap();
+	assert!(api.processor.process(&mut msg_raw).await.is_err());
+
+	// unknown spec
+	let mut msg_raw = new_msg.assemble().unwrap();
+	assert!(matches!(
+		api.processor.process_with_spec(Some("unknown"), &mut msg_raw).await,
+		Err(DomainError::BadRequest(_))
+	));
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 150);
+}
@@ -0,0 +1,29 @@
+//! Tests for the shared transaction submitter
+
+use crate::services::submitter::NonceTracker;
+
+/// Tests that nonces are handed out in order and synced again after a reset
+#[test]
+fn test_nonce_tracker() {
+	let mut tracker = NonceTracker::default();
+
+	// synced on the first submission
+	assert_eq!(tracker.next(), None);
+
+	tracker.used(7);
+	assert_eq!(tracker.next(), Some(8));
+
+	tracker.used(8);
+	assert_eq!(tracker.next(), Some(9));
+
+	// a stale nonce never moves the tracker back
+	tracker.used(3);
+	assert_eq!(tracker.next(), Some(9));
+
+	tracker.reset();
+	assert_eq!(tracker.next(), None);
+
+	// the node might report a lower nonce after some submissions were dropped
+	tracker.used(5);
+	assert_eq!(tracker.next(), Some(6));
+}
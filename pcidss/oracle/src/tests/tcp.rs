@@ -0,0 +1,276 @@
+//! Tests for the native ISO-8583 TCP listener
+
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use iso8583_rs::iso8583::iso_spec::new_msg;
+use tokio::{
+	io::AsyncWriteExt,
+	net::{TcpListener, TcpStream},
+};
+
+use crate::{
+	auth::{AuthConfig, Authenticator, Role},
+	services::tcp::{read_frame, write_frame, LengthHeader, TcpListenerService},
+	spec::SpecLoader,
+	tests::{mock::*, prelude::*},
+	types::{constants::STAN_FIELD_NUMBER, MTI},
+};
+
+/// API key of an acquirer, allowed to submit messages
+const ACQUIRER_API_KEY: &str = "acquirer-key";
+
+/// API key of an offchain worker, not allowed to submit messages
+const OCW_API_KEY: &str = "ocw-key";
+
+/// Name of the spec of an acquirer that sends 12 digit amounts
+const SHORT_AMOUNT_SPEC_NAME: &str = "short_amount";
+
+/// Start a listener on a random local port and connect with the API key
+async fn start_listener(
+	api: &MockProcessorImpl,
+	length_header: LengthHeader,
+	api_key: &str,
+) -> TcpStream {
+	let addr = serve(api, length_header).await;
+	connect(addr, length_header, api_key).await
+}
+
+/// Serve connections on a random local port
+async fn serve(api: &MockProcessorImpl, length_header: LengthHeader) -> SocketAddr {
+	let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let addr = listener.local_addr().unwrap();
+
+	let config = AuthConfig::default()
+		.with_api_key("acquirer", ACQUIRER_API_KEY, Role::Acquirer)
+		.with_api_key("ocw", OCW_API_KEY, Role::Ocw);
+	let authenticator = Arc::new(Authenticator::new(config).unwrap());
+
+	let service = TcpListenerService::new(Arc::clone(&api.processor), authenticator, length_header);
+	tokio::spawn(service.serve(listener));
+
+	addr
+}
+
+/// Connect to the listener and send the first message, the API key and optionally the spec
+async fn connect(addr: SocketAddr, length_header: LengthHeader, hello: &str) -> TcpStream {
+	let mut stream = TcpStream::connect(addr).await.unwrap();
+	write_frame(&mut stream, length_header, hello.as_bytes()).await.unwrap();
+
+	stream
+}
+
+#[test]
+fn test_length_header() {
+	assert_eq!(LengthHeader::Binary2.encode(300).unwrap(), vec![1, 44]);
+	assert_eq!(LengthHeader::Binary2.decode(&[1, 44]).unwrap(), 300);
+	assert_eq!(LengthHeader::Ascii4.encode(300).unwrap(), b"0300".to_vec());
+	assert_eq!(LengthHeader::Ascii4.decode(b"0300").unwrap(), 300);
+
+	assert!(LengthHeader::Ascii4.encode(10_000).is_err());
+	assert!(LengthHeader::Binary2.encode(70_000).is_err());
+	assert!(LengthHeader::Ascii4.decode(b"03a0").is_err());
+	assert!(LengthHeader::Binary2.decode(&[1]).is_err());
+}
+
+/// Tests that pipelined requests are answered and can be matched by STAN
+#[tokio::test]
+async fn test_tcp_pipelining() {
+	let api = MockProcessorImpl::new(Some("tcp_db".to_string())).await;
+	let spec = api.processor.spec();
+
+	for (length_header, stan_prefix) in [(LengthHeader::Binary2, "1"), (LengthHeader::Ascii4, "2")]
+	{
+		let mut stream = start_listener(&api, length_header, ACQUIRER_API_KEY).await;
+
+		// keep-alive is skipped
+		stream.write_all(&length_header.encode(0).unwrap()).await.unwrap();
+
+		let mut requests = HashMap::new();
+		for amount in [10, 20, 30] {
+			let stan = format!("{}{:05}", stan_prefix, amount);
+
+			let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+			new_msg.set_on(4, &format!("{:020}", amount)).unwrap();
+			new_msg.set_on(STAN_FIELD_NUMBER, &stan).unwrap();
+
+			write_frame(&mut stream, length_header, &new_msg.assemble().unwrap())
+				.await
+				.unwrap();
+			requests.insert(stan, format!("{:020}", amount));
+		}
+
+		stream.shutdown().await.unwrap();
+
+		// responses can arrive in any order
+		let mut responses = HashMap::new();
+		while let Some(mut response) = read_frame(&mut stream, length_header).await.unwrap() {
+			let msg = spec.parse(&mut response).unwrap();
+
+			assert_eq!(msg.get_field_value(&"message_type".to_string()).unwrap(), "0210");
+			assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+			responses.insert(
+				msg.bmp_child_value(STAN_FIELD_NUMBER).unwrap(),
+				msg.bmp_child_value(4).unwrap(),
+			);
+		}
+
+		assert_eq!(responses, requests);
+	}
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 2 * 60);
+	assert_eq!(get_transactions_by_id(&api, &alice_account.id).await.len(), 6);
+}
+
+/// Tests that connections without a permitted API key are closed without processing
+#[tokio::test]
+async fn test_tcp_authentication() {
+	let api = MockProcessorImpl::new(Some("tcp_auth_db".to_string())).await;
+	let spec = api.processor.spec();
+	let length_header = LengthHeader::Binary2;
+
+	for api_key in ["unknown-key", OCW_API_KEY] {
+		let mut stream = start_listener(&api, length_header, api_key).await;
+
+		let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+		new_msg.set_on(4, "00000000000000000010").unwrap();
+		new_msg.set_on(STAN_FIELD_NUMBER, "000001").unwrap();
+
+		// the connection might be closed already
+		let _ = write_frame(&mut stream, length_header, &new_msg.assemble().unwrap()).await;
+
+		assert!(matches!(read_frame(&mut stream, length_header).await, Ok(None) | Err(_)));
+	}
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert_eq!(alice_account.balance.minor_units, ALICE.3);
+}
+
+/// Tests that messages failing to be processed are declined instead of left unanswered
+#[tokio::test]
+async fn test_tcp_decline_failed() {
+	let api = MockProcessorImpl::new(Some("tcp_decline_db".to_string())).await;
+	let spec = api.processor.spec();
+	let length_header = LengthHeader::Ascii4;
+
+	let mut stream = start_listener(&api, length_header, ACQUIRER_API_KEY).await;
+
+	// financial request without a card number
+	let mut msg =
+		new_msg(spec, spec.get_message_from_header(MTI::FinancialRequest.into()).unwrap());
+	msg.set("message_type", MTI::FinancialRequest.into()).unwrap();
+	msg.set_on(4, "00000000000000000010").unwrap();
+	msg.set_on(STAN_FIELD_NUMBER, "000002").unwrap();
+
+	write_frame(&mut stream, length_header, &msg.assemble().unwrap()).await.unwrap();
+	stream.shutdown().await.unwrap();
+
+	let mut response = read_frame(&mut stream, length_header).await.unwrap().unwrap();
+	let response = spec.parse(&mut response).unwrap();
+
+	assert_eq!(response.get_field_value(&"message_type".to_string()).unwrap(), "0210");
+	assert_eq!(response.bmp_child_value(STAN_FIELD_NUMBER).unwrap(), "000002");
+	assert_eq!(response.bmp_child_value(4).unwrap(), "00000000000000000010");
+	assert_eq!(response.bmp_child_value(39).unwrap(), "30");
+
+	assert_eq!(read_frame(&mut stream, length_header).await.unwrap(), None);
+}
+
+/// Tests that every connection parses its messages with the spec it selects
+#[tokio::test]
+async fn test_tcp_spec_per_connection() {
+	let test_spec = std::fs::read_to_string(TEST_SPEC_PATH).unwrap();
+	let short_amount_spec = SpecLoader::parse(&test_spec.replace("len: 20", "len: 12")).unwrap();
+	let specs = SpecLoader::new(TEST_SPEC_NAME, SpecLoader::load(TEST_SPEC_PATH).unwrap())
+		.with_spec(SHORT_AMOUNT_SPEC_NAME, short_amount_spec);
+
+	let api = MockProcessorImpl::with_specs(Some("tcp_spec_db".to_string()), specs).await;
+	let length_header = LengthHeader::Binary2;
+	let addr = serve(&api, length_header).await;
+
+	let default_stream = connect(addr, length_header, ACQUIRER_API_KEY).await;
+	let short_amount_stream =
+		connect(addr, length_header, &format!("{} {}", ACQUIRER_API_KEY, SHORT_AMOUNT_SPEC_NAME))
+			.await;
+
+	for (mut stream, spec, amount, stan) in [
+		(default_stream, api.processor.spec(), format!("{:020}", 10), "000001"),
+		(short_amount_stream, short_amount_spec, format!("{:012}", 20), "000002"),
+	] {
+		let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+		new_msg.set_on(4, &amount).unwrap();
+		new_msg.set_on(STAN_FIELD_NUMBER, stan).unwrap();
+
+		write_frame(&mut stream, length_header, &new_msg.assemble().unwrap())
+			.await
+			.unwrap();
+
+		// the response is composed with the spec of the connection as well
+		let mut response = read_frame(&mut stream, length_header).await.unwrap().unwrap();
+		let response = spec.parse(&mut response).unwrap();
+
+		assert_eq!(response.bmp_child_value(39).unwrap(), "00");
+		assert_eq!(response.bmp_child_value(4).unwrap(), amount);
+	}
+
+	// connections selecting an unknown spec are closed
+	let mut stream = connect(addr, length_header, &format!("{} unknown", ACQUIRER_API_KEY)).await;
+
+	let mut new_msg = get_new_iso_msg(api.processor.spec(), MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000010").unwrap();
+	new_msg.set_on(STAN_FIELD_NUMBER, "000003").unwrap();
+
+	// the connection might be closed already
+	let _ = write_frame(&mut stream, length_header, &new_msg.assemble().unwrap()).await;
+	assert!(matches!(read_frame(&mut stream, length_header).await, Ok(None) | Err(_)));
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 30);
+}
+
+/// Tests that messages whose responses can't be matched are declined without being processed
+#[tokio::test]
+async fn test_tcp_decline_unmatched() {
+	let api = MockProcessorImpl::new(Some("tcp_unmatched_db".to_string())).await;
+	let spec = api.processor.spec();
+	let length_header = LengthHeader::Binary2;
+
+	let mut stream = start_listener(&api, length_header, ACQUIRER_API_KEY).await;
+
+	// no STAN
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000010").unwrap();
+	write_frame(&mut stream, length_header, &new_msg.assemble().unwrap())
+		.await
+		.unwrap();
+
+	let mut response = read_frame(&mut stream, length_header).await.unwrap().unwrap();
+	let response = spec.parse(&mut response).unwrap();
+	assert_eq!(response.get_field_value(&"message_type".to_string()).unwrap(), "0210");
+	assert_eq!(response.bmp_child_value(39).unwrap(), "30");
+
+	// the same STAN twice, the second one is read while the first one is in flight
+	new_msg.set_on(STAN_FIELD_NUMBER, "000001").unwrap();
+	let frame = [
+		length_header.encode(new_msg.assemble().unwrap().len()).unwrap(),
+		new_msg.assemble().unwrap(),
+	]
+	.concat();
+	stream.write_all(&[frame.clone(), frame].concat()).await.unwrap();
+	stream.shutdown().await.unwrap();
+
+	let mut response_codes = vec![];
+	while let Some(mut response) = read_frame(&mut stream, length_header).await.unwrap() {
+		let response = spec.parse(&mut response).unwrap();
+		assert_eq!(response.bmp_child_value(STAN_FIELD_NUMBER).unwrap(), "000001");
+		response_codes.push(response.bmp_child_value(39).unwrap());
+	}
+
+	// the duplicate is declined before the original is answered
+	assert_eq!(response_codes, vec!["94", "00"]);
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 10);
+	assert_eq!(get_transactions_by_id(&api, &alice_account.id).await.len(), 1);
+}
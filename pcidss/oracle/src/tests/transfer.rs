@@ -0,0 +1,105 @@
+//! Tests for concurrent balance transfers
+
+use crate::{
+	tests::{mock::*, prelude::*},
+	types::MTI,
+};
+
+/// Number of requests submitted at the same time
+const PARALLEL_REQUESTS: u32 = 50;
+
+/// Tests parallel purchases against one bank account
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_parallel_purchases() {
+	let api = MockProcessorImpl::new(Some("transfer_db".to_string())).await;
+
+	let spec = api.processor.spec();
+	let amount: i64 = 500;
+
+	let handles = (0..PARALLEL_REQUESTS).map(|_| {
+		let processor = api.processor.clone();
+		let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, CHARLIE);
+		new_msg.set_on(4, &format!("{:020}", amount)).unwrap();
+
+		tokio::spawn(async move {
+			let mut msg_raw = new_msg.assemble().unwrap();
+			let (_, msg) = processor.process(&mut msg_raw).await.unwrap();
+			msg.bmp_child_value(39).unwrap()
+		})
+	});
+
+	let response_codes: Vec<String> = futures::future::join_all(handles)
+		.await
+		.into_iter()
+		.map(|r| r.unwrap())
+		.collect();
+
+	// Charlie can only afford `CHARLIE.3 / amount` payments, the rest is declined
+	let approved = response_codes.iter().filter(|code| *code == "00").count() as u32;
+	let declined = response_codes.iter().filter(|code| *code == "51").count() as u32;
+
+	assert_eq!(approved as i64, CHARLIE.3 / amount);
+	assert_eq!(declined, PARALLEL_REQUESTS - approved);
+
+	// no update is lost and no transfer is half-applied
+	let charlie_account = get_bank_account_by_card_number(&api, CHARLIE.1).await;
+	let acquirer_account = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
+
+	assert_eq!(charlie_account.balance.minor_units, CHARLIE.3 - approved as i64 * amount);
+	assert_eq!(charlie_account.nonce, approved);
+	assert_eq!(acquirer_account.balance.minor_units, ACQUIRER.3 + approved as i64 * amount);
+
+	let charlie_txs = get_transactions_by_id(&api, &charlie_account.id).await;
+	assert_eq!(charlie_txs.len() as u32, approved);
+
+	// every transaction has a unique hash, even though the ISO messages are the same
+	let mut hashes: Vec<&String> = charlie_txs.iter().map(|tx| &tx.hash).collect();
+	hashes.sort();
+	hashes.dedup();
+	assert_eq!(hashes.len() as u32, approved);
+}
+
+/// Tests that a transaction can only be reversed once, even if reversals race
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_parallel_reversals() {
+	let api = MockProcessorImpl::new(Some("transfer_reversal_db".to_string())).await;
+
+	let spec = api.processor.spec();
+
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let alice_tx = get_transactions_by_id(&api, &alice_account.id).await.remove(0);
+
+	let handles = (0..PARALLEL_REQUESTS).map(|_| {
+		let processor = api.processor.clone();
+		let mut reversal_msg = get_new_iso_msg(spec, MTI::ReversalRequest, ALICE);
+		reversal_msg.set_on(4, "00000000000000000100").unwrap();
+		reversal_msg.set_on(126, &alice_tx.hash).unwrap();
+
+		tokio::spawn(async move {
+			let mut msg_raw = reversal_msg.assemble().unwrap();
+			let (_, msg) = processor.process(&mut msg_raw).await.unwrap();
+			msg.bmp_child_value(39).unwrap()
+		})
+	});
+
+	let response_codes: Vec<String> = futures::future::join_all(handles)
+		.await
+		.into_iter()
+		.map(|r| r.unwrap())
+		.collect();
+
+	assert_eq!(response_codes.iter().filter(|code| *code == "00").count(), 1);
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let acquirer_account = get_bank_account_by_card_number(&api, ACQUIRER.1).await;
+
+	assert_eq!(alice_account.balance.minor_units, ALICE.3);
+	assert_eq!(acquirer_account.balance.minor_units, ACQUIRER.3);
+}
@@ -0,0 +1,164 @@
+//! Tests for card data tokenization
+use std::sync::Arc;
+
+use chrono::{Months, Utc};
+use op_api::bank_account::PgBankAccount;
+use op_core::{bank_account::traits::BankAccountTrait, vault::KEY_LENGTH};
+
+use crate::{
+	tests::{mock::*, prelude::*},
+	types::{constants::DEV_ACCOUNTS, ResponseCodes, MTI},
+};
+
+/// Tests that card data is only stored tokenized and never exposed
+#[tokio::test]
+async fn test_card_tokenization() {
+	let api = MockProcessorImpl::new(Some("vault_db".to_string())).await;
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+
+	// only the masked card number is readable
+	assert_eq!(alice_account.card_number_masked, "416981******8901");
+	assert_ne!(alice_account.card_number_hash, ALICE.1);
+	assert_ne!(alice_account.card_cvv_hash, ALICE.2);
+	assert_eq!(alice_account.card_data.key_version, 1);
+
+	let card = api.processor.vault.detokenize(&alice_account.card_data).await.unwrap();
+
+	assert_eq!(card.card_number, ALICE.1);
+	assert_eq!(card.card_holder_first_name, ALICE.0);
+	assert!(api
+		.processor
+		.vault
+		.verify_cvv(ALICE.2, &alice_account.card_cvv_hash)
+		.await
+		.unwrap());
+
+	// RPC models don't expose the tokens
+	let serialized = serde_json::to_value(&alice_account).unwrap();
+
+	assert_eq!(serialized["card_number_masked"], "416981******8901");
+	assert!(serialized.get("card_number_hash").is_none());
+	assert!(serialized.get("card_data").is_none());
+	assert!(serialized.get("card_cvv_hash").is_none());
+	assert!(!serialized.to_string().contains(ALICE.1));
+}
+
+/// Tests that CVV can only be skipped for on-chain events
+#[tokio::test]
+async fn test_cvv_verification() {
+	let api = MockProcessorImpl::new(Some("vault_cvv_db".to_string())).await;
+
+	let spec = api.processor.spec();
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let alice_txs = get_transactions_by_id(&api, &alice_account.id).await;
+
+	// track 2 data without CVV
+	let mut new_msg = get_new_iso_msg(spec, MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(32, ACQUIRER.1).unwrap();
+
+	let track_2_data = new_msg.bmp_child_value(35).unwrap();
+	let (track_2_data, _) = track_2_data.split_once('C').unwrap();
+	new_msg.set_on(35, track_2_data).unwrap();
+
+	assert_noop(
+		&api,
+		ALICE,
+		&new_msg,
+		ResponseCodes::DoNotHonor,
+		alice_account.clone(),
+		alice_txs.clone(),
+	)
+	.await;
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (_, msg) = api
+		.processor
+		.process_on_chain(&mut msg_raw, &get_finality("1-1"))
+		.await
+		.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+	assert_eq!(
+		get_bank_account_by_card_number(&api, ALICE.1).await.balance.minor_units,
+		ALICE.3 - 100
+	);
+}
+
+/// Tests tokenization of plaintext cards and re-encryption to a new key version
+#[tokio::test]
+async fn test_key_rotation() {
+	let api = MockProcessorImpl::new(Some("vault_rotation_db".to_string())).await;
+
+	let client = api.pg_pool.get().await.unwrap();
+	client
+		.execute(
+			"INSERT INTO bank_account (id, card_number, card_holder_first_name, card_holder_last_name, card_expiration_date, card_cvv, balance, available_balance) VALUES ($1, $2, $3, $4, $5, $6, $7, $7)",
+			&[
+				&uuid::Uuid::new_v4(),
+				&"4169812345670000",
+				&"Frank",
+				&"Frank",
+				&Utc::now().checked_add_months(Months::new(48)).unwrap(),
+				&"321",
+				&100_i64,
+			],
+		)
+		.await
+		.unwrap();
+
+	let rotated_vault = Arc::new(get_vault(Some([7; KEY_LENGTH])));
+	let bank_account = PgBankAccount::new(api.pg_pool.clone(), rotated_vault.clone());
+
+	// plaintext card is tokenized with the current key version
+	assert_eq!(bank_account.tokenize_plaintext_cards().await.unwrap(), 1);
+
+	let frank_account =
+		bank_account.find_by_card_number("4169812345670000").await.unwrap().unwrap();
+	let card = rotated_vault.detokenize(&frank_account.card_data).await.unwrap();
+
+	assert_eq!(frank_account.card_data.key_version, 2);
+	assert_eq!(card.card_number, "4169812345670000");
+	assert_eq!(card.card_holder_last_name, "Frank");
+	assert!(rotated_vault.verify_cvv("321", &frank_account.card_cvv_hash).await.unwrap());
+
+	// dev accounts are re-encrypted in batches
+	let stale = DEV_ACCOUNTS.len() as i64;
+	assert_eq!(bank_account.count_stale_cards().await.unwrap(), stale);
+
+	let mut reencrypted = 0;
+	loop {
+		let batch = bank_account.reencrypt_batch(4).await.unwrap();
+		assert!(batch <= 4);
+		if batch == 0 {
+			break;
+		}
+		reencrypted += batch;
+	}
+
+	assert_eq!(reencrypted as i64, stale);
+	assert_eq!(bank_account.count_stale_cards().await.unwrap(), 0);
+
+	// lookups and CVV hashes survive the rotation
+	let alice_account = bank_account.find_by_card_number(ALICE.1).await.unwrap().unwrap();
+	let card = rotated_vault.detokenize(&alice_account.card_data).await.unwrap();
+
+	assert_eq!(alice_account.card_data.key_version, 2);
+	assert_eq!(card.card_number, ALICE.1);
+	assert_eq!(card.card_holder_first_name, ALICE.0);
+
+	// old vault doesn't know the new key version
+	assert!(api.processor.vault.detokenize(&alice_account.card_data).await.is_err());
+
+	// but requests keep being processed
+	let mut new_msg = get_new_iso_msg(api.processor.spec(), MTI::FinancialRequest, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(32, ACQUIRER.1).unwrap();
+
+	let mut msg_raw = new_msg.assemble().unwrap();
+	let (_, msg) = api.processor.process(&mut msg_raw).await.unwrap();
+
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+}
@@ -0,0 +1,209 @@
+//! Tests for the block cursor of the watcher and the messages it composes
+
+use op_api::block_cursor::PgBlockCursor;
+use op_core::{
+	block_cursor::{models::TrackedBlock, traits::BlockCursorTrait},
+	outbox::models::FinalityCreate,
+};
+
+use crate::{
+	services::watcher::{blocks_to_process, first_retracted, ON_CHAIN_TRANSFER_MTI},
+	tests::{mock::*, prelude::*},
+	types::{
+		constants::{EVENT_ID_FIELD_NUMBER, WATCHER_CURSOR_NAME},
+		ResponseCodes, MTI,
+	},
+};
+
+/// Tracked block with a hash made of the given digit
+fn tracked_block(block_number: u32, digit: char) -> TrackedBlock {
+	TrackedBlock { block_number, block_hash: format!("0x{}", digit.to_string().repeat(64)) }
+}
+
+#[test]
+fn test_blocks_to_process() {
+	// first run starts at the head, or at the given block
+	assert_eq!(blocks_to_process(None, None, 10), 10..=10);
+	assert_eq!(blocks_to_process(None, Some(3), 10), 3..=10);
+
+	// later runs resume after the cursor, the start block is ignored
+	assert_eq!(blocks_to_process(Some(5), Some(3), 10), 6..=10);
+
+	// nothing to do if the cursor is at the head
+	assert!(blocks_to_process(Some(10), None, 10).is_empty());
+}
+
+#[tokio::test]
+async fn test_block_cursor() {
+	let api = MockProcessorImpl::new(Some("block_cursor_db".to_string())).await;
+	let block_cursor = PgBlockCursor::new(api.pg_pool.clone());
+
+	assert_eq!(block_cursor.get(WATCHER_CURSOR_NAME).await.unwrap(), None);
+
+	block_cursor.advance(WATCHER_CURSOR_NAME, 5).await.unwrap();
+	assert_eq!(block_cursor.get(WATCHER_CURSOR_NAME).await.unwrap(), Some(5));
+
+	block_cursor.advance(WATCHER_CURSOR_NAME, 7).await.unwrap();
+	assert_eq!(block_cursor.get(WATCHER_CURSOR_NAME).await.unwrap(), Some(7));
+
+	// never moves backwards
+	block_cursor.advance(WATCHER_CURSOR_NAME, 6).await.unwrap();
+	assert_eq!(block_cursor.get(WATCHER_CURSOR_NAME).await.unwrap(), Some(7));
+
+	// cursors are independent
+	assert_eq!(block_cursor.get("other").await.unwrap(), None);
+}
+
+#[test]
+fn test_first_retracted() {
+	let tracked = vec![tracked_block(5, 'a'), tracked_block(6, 'b'), tracked_block(7, 'c')];
+	let mut canonical: Vec<Option<String>> =
+		tracked.iter().map(|block| Some(block.block_hash.clone())).collect();
+
+	// nothing is retracted while the best chain contains all of them
+	assert_eq!(first_retracted(&tracked, &canonical), None);
+
+	// the best chain is shorter now
+	canonical[2] = None;
+	assert_eq!(first_retracted(&tracked, &canonical), Some(7));
+
+	// the first block with another hash is the fork point
+	canonical[1] = Some(tracked_block(6, 'd').block_hash);
+	assert_eq!(first_retracted(&tracked, &canonical), Some(6));
+}
+
+#[tokio::test]
+async fn test_tracked_blocks() {
+	let api = MockProcessorImpl::new(Some("tracked_blocks_db".to_string())).await;
+	let block_cursor = PgBlockCursor::new(api.pg_pool.clone());
+
+	for block in [tracked_block(6, 'b'), tracked_block(5, 'a'), tracked_block(7, 'c')] {
+		block_cursor.track(&block).await.unwrap();
+	}
+	block_cursor.advance(WATCHER_CURSOR_NAME, 7).await.unwrap();
+
+	// lowest first
+	assert_eq!(
+		block_cursor.find_tracked().await.unwrap(),
+		vec![tracked_block(5, 'a'), tracked_block(6, 'b'), tracked_block(7, 'c')]
+	);
+
+	// blocks of the new fork replace the retracted ones
+	block_cursor.untrack_from(6).await.unwrap();
+	block_cursor.rewind(WATCHER_CURSOR_NAME, 5).await.unwrap();
+	assert_eq!(block_cursor.get(WATCHER_CURSOR_NAME).await.unwrap(), Some(5));
+
+	block_cursor.track(&tracked_block(6, 'd')).await.unwrap();
+	assert_eq!(
+		block_cursor.find_tracked().await.unwrap(),
+		vec![tracked_block(5, 'a'), tracked_block(6, 'd')]
+	);
+
+	// rewinding never moves the cursor forward
+	block_cursor.rewind(WATCHER_CURSOR_NAME, 9).await.unwrap();
+	assert_eq!(block_cursor.get(WATCHER_CURSOR_NAME).await.unwrap(), Some(5));
+
+	// finalized blocks are forgotten
+	block_cursor.untrack(5).await.unwrap();
+	assert_eq!(block_cursor.find_tracked().await.unwrap(), vec![tracked_block(6, 'd')]);
+}
+
+/// Tests that retracted blocks whose events couldn't be compensated are recorded
+#[tokio::test]
+async fn test_failed_retractions() {
+	let api = MockProcessorImpl::new(Some("failed_retraction_db".to_string())).await;
+	let block_cursor = PgBlockCursor::new(api.pg_pool.clone());
+
+	assert!(block_cursor.find_failed_retractions().await.unwrap().is_empty());
+
+	block_cursor
+		.record_failed_retraction(&tracked_block(6, 'b'), "first")
+		.await
+		.unwrap();
+	block_cursor
+		.record_failed_retraction(&tracked_block(5, 'a'), "second")
+		.await
+		.unwrap();
+
+	// the same block fails again
+	block_cursor
+		.record_failed_retraction(&tracked_block(6, 'b'), "third")
+		.await
+		.unwrap();
+
+	let failed = block_cursor.find_failed_retractions().await.unwrap();
+	assert_eq!(
+		failed
+			.iter()
+			.map(|failed| (&failed.block, failed.error.as_str()))
+			.collect::<Vec<_>>(),
+		vec![(&tracked_block(5, 'a'), "second"), (&tracked_block(6, 'b'), "third")]
+	);
+}
+
+/// Tests that on-chain transfers are posted right away instead of being put on hold
+#[tokio::test]
+async fn test_on_chain_transfer_mti() {
+	let api = MockProcessorImpl::new(Some("on_chain_transfer_db".to_string())).await;
+	let spec = api.processor.spec();
+
+	assert_eq!(ON_CHAIN_TRANSFER_MTI, MTI::FinancialRequest);
+
+	let mut new_msg = get_new_iso_msg(spec, ON_CHAIN_TRANSFER_MTI, ALICE);
+	new_msg.set_on(4, "00000000000000000100").unwrap();
+	new_msg.set_on(EVENT_ID_FIELD_NUMBER, "1-1").unwrap();
+
+	let (_, msg) = api
+		.processor
+		.process_on_chain(&mut new_msg.assemble().unwrap(), &get_finality("1-1"))
+		.await
+		.unwrap();
+
+	assert_eq!(msg.get_field_value(&"message_type".to_string()).unwrap(), "0210");
+	assert_eq!(msg.bmp_child_value(39).unwrap(), "00");
+
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	let alice_holds = api
+		.processor
+		.hold_controller
+		.find_by_bank_account_id(&alice_account.id)
+		.await
+		.unwrap();
+
+	assert_eq!(alice_account.balance.minor_units, ALICE.3 - 100);
+	assert_eq!(alice_account.available_balance.minor_units, ALICE.3 - 100);
+	assert!(alice_holds.is_empty());
+}
+
+/// Tests that events declined before a message is composed still get a finality
+#[tokio::test]
+async fn test_declined_on_chain_event() {
+	let api = MockProcessorImpl::new(Some("declined_on_chain_event_db".to_string())).await;
+
+	api.processor
+		.decline_on_chain(&get_finality("3-1"), ResponseCodes::InvalidCardNumber)
+		.await
+		.unwrap();
+
+	let finality = api.processor.outbox_controller.find_by_event_id("3-1").await.unwrap().unwrap();
+	assert_eq!(finality.response_code, "14");
+	assert_eq!(finality.transaction_hash, "0".repeat(64));
+
+	// the hash of the reverted transaction is kept
+	let hash = "ab".repeat(32);
+	api.processor
+		.decline_on_chain(
+			&FinalityCreate { transaction_hash: hash.clone(), ..get_finality("3-2") },
+			ResponseCodes::InvalidAmount,
+		)
+		.await
+		.unwrap();
+
+	let finality = api.processor.outbox_controller.find_by_event_id("3-2").await.unwrap().unwrap();
+	assert_eq!(finality.response_code, "13");
+	assert_eq!(finality.transaction_hash, hash);
+
+	// nothing is posted
+	let alice_account = get_bank_account_by_card_number(&api, ALICE.1).await;
+	assert_eq!(alice_account.balance.minor_units, ALICE.3);
+}
@@ -1,5 +1,10 @@
 //! Types used in the PCIDSS Gateway.
 
+use std::collections::BTreeMap;
+
+use iso8583_rs::iso8583::iso_spec::IsoMsg;
+use op_core::error::DomainError;
+
 /// Message type indicator for the ISO-8583 message, 1987 version
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MTI {
@@ -11,10 +16,18 @@ pub enum MTI {
 	FinancialRequest,
 	/// 0210 - Financial response
 	FinancialResponse,
+	/// 0220 - Financial advice, completion of a previous authorization
+	FinancialAdvice,
+	/// 0230 - Financial advice response
+	FinancialAdviceResponse,
 	/// 0400 - Reversal request
 	ReversalRequest,
 	/// 0410 - Reversal response
 	ReversalResponse,
+	/// 0500 - Reconciliation request, the acquirer compares its settlement totals with ours
+	ReconciliationRequest,
+	/// 0510 - Reconciliation response
+	ReconciliationResponse,
 	/// 0800 - Network management request
 	NetworkManagementRequest,
 	/// 0810 - Network management response
@@ -29,8 +42,12 @@ impl Into<&str> for MTI {
 			MTI::AuthorizationResponse => "0110",
 			MTI::FinancialRequest => "0200",
 			MTI::FinancialResponse => "0210",
+			MTI::FinancialAdvice => "0220",
+			MTI::FinancialAdviceResponse => "0230",
 			MTI::ReversalRequest => "0400",
 			MTI::ReversalResponse => "0410",
+			MTI::ReconciliationRequest => "0500",
+			MTI::ReconciliationResponse => "0510",
 			MTI::NetworkManagementRequest => "0800",
 			MTI::NetworkManagementResponse => "0810",
 		}
@@ -45,8 +62,12 @@ impl TryFrom<&str> for MTI {
 			"0110" => Ok(MTI::AuthorizationResponse),
 			"0200" => Ok(MTI::FinancialRequest),
 			"0210" => Ok(MTI::FinancialResponse),
+			"0220" => Ok(MTI::FinancialAdvice),
+			"0230" => Ok(MTI::FinancialAdviceResponse),
 			"0400" => Ok(MTI::ReversalRequest),
 			"0410" => Ok(MTI::ReversalResponse),
+			"0500" => Ok(MTI::ReconciliationRequest),
+			"0510" => Ok(MTI::ReconciliationResponse),
 			"0800" => Ok(MTI::NetworkManagementRequest),
 			"0810" => Ok(MTI::NetworkManagementResponse),
 			_ => Err(()),
@@ -54,21 +75,62 @@ impl TryFrom<&str> for MTI {
 	}
 }
 
+impl MTI {
+	/// MTI of the response to the request, `None` if it isn't a request
+	pub fn response(&self) -> Option<MTI> {
+		match self {
+			MTI::AuthorizationRequest => Some(MTI::AuthorizationResponse),
+			MTI::FinancialRequest => Some(MTI::FinancialResponse),
+			MTI::FinancialAdvice => Some(MTI::FinancialAdviceResponse),
+			MTI::ReversalRequest => Some(MTI::ReversalResponse),
+			MTI::NetworkManagementRequest => Some(MTI::NetworkManagementResponse),
+			MTI::ReconciliationRequest => Some(MTI::ReconciliationResponse),
+			_ => None,
+		}
+	}
+}
+
 /// Response codes for the ISO-8583 message, 1987 version
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ResponseCodes {
 	// 00 - Approved
 	Approved,
+	// 03 - Invalid merchant, acquirer is not known
+	InvalidMerchant,
 	// 05 - Do not honor
 	DoNotHonor,
 	// 12 - Invalid transaction
 	InvalidTransaction,
+	// 13 - Invalid amount
+	InvalidAmount,
 	// 14 - Invalid PAN
 	InvalidCardNumber,
+	// 30 - Format error, the message can't be processed
+	FormatError,
+	// 41 - Lost card, pick up
+	LostCard,
+	// 43 - Stolen card, pick up
+	StolenCard,
 	// 51 - Insufficient funds, if it underflows
 	InsufficientFunds,
 	// 54 - Expired card
 	ExpiredCard,
+	// 57 - Transaction not permitted to cardholder, e.g. reversals of someone else's payments
+	NotPermitted,
+	// 59 - Suspected fraud, merchant or acquirer is blocked
+	SuspectedFraud,
+	// 61 - Exceeds withdrawal amount limit, single or daily
+	ExceedsAmountLimit,
+	// 62 - Restricted card, blocked or closed
+	RestrictedCard,
+	// 65 - Exceeds withdrawal frequency limit
+	ExceedsFrequencyLimit,
+	// 75 - Allowable number of PIN tries exceeded, CVV verification is locked out
+	TriesExceeded,
+	// 94 - Duplicate transmission, a message with the same STAN is in flight
+	DuplicateTransmission,
+	// 96 - System malfunction, the message failed to be processed
+	SystemError,
 }
 
 #[allow(clippy::from_over_into)]
@@ -76,18 +138,152 @@ impl Into<&str> for ResponseCodes {
 	fn into(self) -> &'static str {
 		match self {
 			ResponseCodes::Approved => "00",
+			ResponseCodes::InvalidMerchant => "03",
 			ResponseCodes::DoNotHonor => "05",
 			ResponseCodes::InvalidTransaction => "12",
+			ResponseCodes::InvalidAmount => "13",
 			ResponseCodes::InvalidCardNumber => "14",
+			ResponseCodes::FormatError => "30",
+			ResponseCodes::LostCard => "41",
+			ResponseCodes::StolenCard => "43",
 			ResponseCodes::InsufficientFunds => "51",
 			ResponseCodes::ExpiredCard => "54",
+			ResponseCodes::NotPermitted => "57",
+			ResponseCodes::SuspectedFraud => "59",
+			ResponseCodes::ExceedsAmountLimit => "61",
+			ResponseCodes::RestrictedCard => "62",
+			ResponseCodes::ExceedsFrequencyLimit => "65",
+			ResponseCodes::TriesExceeded => "75",
+			ResponseCodes::DuplicateTransmission => "94",
+			ResponseCodes::SystemError => "96",
+		}
+	}
+}
+
+impl TryFrom<&str> for ResponseCodes {
+	type Error = ();
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		match value {
+			"00" => Ok(ResponseCodes::Approved),
+			"03" => Ok(ResponseCodes::InvalidMerchant),
+			"05" => Ok(ResponseCodes::DoNotHonor),
+			"12" => Ok(ResponseCodes::InvalidTransaction),
+			"13" => Ok(ResponseCodes::InvalidAmount),
+			"14" => Ok(ResponseCodes::InvalidCardNumber),
+			"30" => Ok(ResponseCodes::FormatError),
+			"41" => Ok(ResponseCodes::LostCard),
+			"43" => Ok(ResponseCodes::StolenCard),
+			"51" => Ok(ResponseCodes::InsufficientFunds),
+			"54" => Ok(ResponseCodes::ExpiredCard),
+			"57" => Ok(ResponseCodes::NotPermitted),
+			"59" => Ok(ResponseCodes::SuspectedFraud),
+			"61" => Ok(ResponseCodes::ExceedsAmountLimit),
+			"62" => Ok(ResponseCodes::RestrictedCard),
+			"65" => Ok(ResponseCodes::ExceedsFrequencyLimit),
+			"75" => Ok(ResponseCodes::TriesExceeded),
+			"94" => Ok(ResponseCodes::DuplicateTransmission),
+			"96" => Ok(ResponseCodes::SystemError),
+			_ => Err(()),
+		}
+	}
+}
+
+impl ResponseCodes {
+	/// Human-readable description of the response code
+	pub fn description(&self) -> &'static str {
+		match self {
+			ResponseCodes::Approved => "Approved",
+			ResponseCodes::InvalidMerchant => "Invalid merchant",
+			ResponseCodes::DoNotHonor => "Do not honor",
+			ResponseCodes::InvalidTransaction => "Invalid transaction",
+			ResponseCodes::InvalidAmount => "Invalid amount",
+			ResponseCodes::InvalidCardNumber => "Invalid card number",
+			ResponseCodes::FormatError => "Format error",
+			ResponseCodes::LostCard => "Lost card, pick up",
+			ResponseCodes::StolenCard => "Stolen card, pick up",
+			ResponseCodes::InsufficientFunds => "Insufficient funds",
+			ResponseCodes::ExpiredCard => "Expired card",
+			ResponseCodes::NotPermitted => "Transaction not permitted to cardholder",
+			ResponseCodes::SuspectedFraud => "Suspected fraud",
+			ResponseCodes::ExceedsAmountLimit => "Exceeds withdrawal amount limit",
+			ResponseCodes::RestrictedCard => "Restricted card",
+			ResponseCodes::ExceedsFrequencyLimit => "Exceeds withdrawal frequency limit",
+			ResponseCodes::TriesExceeded => "Allowable number of PIN tries exceeded",
+			ResponseCodes::DuplicateTransmission => "Duplicate transmission",
+			ResponseCodes::SystemError => "System malfunction",
+		}
+	}
+}
+
+/// ISO-8583 message as its MTI and field values, keyed by their position in the bitmap
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IsoFields {
+	/// Message type indicator, e.g. `0210`
+	pub mti: String,
+	/// Values of the fields as the spec formats them, e.g. zero padded amounts
+	pub fields: BTreeMap<u32, String>,
+	/// Response code (field 39), if any
+	pub response_code: Option<String>,
+	/// Human-readable description of the response code, if it is known
+	pub response_description: Option<String>,
+}
+
+impl IsoFields {
+	/// Fields of a parsed or composed ISO-8583 message
+	pub fn from_iso_msg(iso_msg: &IsoMsg) -> Result<Self, DomainError> {
+		let mti = iso_msg.get_field_value(&"message_type".to_string())?;
+		let bitmap = iso_msg.msg.field_by_name(&"bitmap".to_string())?;
+
+		let fields: BTreeMap<u32, String> = bitmap
+			.children()
+			.iter()
+			.map(|field| field.position())
+			.filter(|position| iso_msg.bmp.is_on(*position))
+			.filter_map(|position| Some((position, iso_msg.bmp_child_value(position).ok()?)))
+			.collect();
+
+		let response_code = fields.get(&constants::RESPONSE_CODE_FIELD_NUMBER).cloned();
+		let response_description = response_code
+			.as_deref()
+			.and_then(|code| ResponseCodes::try_from(code).ok())
+			.map(|code| code.description().to_string());
+
+		Ok(Self { mti, fields, response_code, response_description })
+	}
+}
+
+/// Settlement codes of reconciliation responses, 1987 version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettlementCodes {
+	// 1 - In balance, the totals of the acquirer match ours
+	InBalance,
+	// 2 - Out of balance
+	OutOfBalance,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<&str> for SettlementCodes {
+	fn into(self) -> &'static str {
+		match self {
+			SettlementCodes::InBalance => "1",
+			SettlementCodes::OutOfBalance => "2",
 		}
 	}
 }
 
+/// Origin of an ISO-8583 message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageOrigin {
+	/// Terminal or RPC client, card data has to be verified
+	External,
+	/// Composed by the watcher from an on-chain event signed by the account owner, CVV is not
+	/// available since it is never stored
+	OnChain,
+}
+
 /// Represents truncated version of dev accounts
-/// Explicitly used in tests and dev mode
-pub(crate) type DevAccount = (&'static str, &'static str, &'static str, u32, Option<&'static str>);
+/// Explicitly used in tests and dev mode, balances are in the minor units of the default currency
+pub(crate) type DevAccount = (&'static str, &'static str, &'static str, i64, Option<&'static str>);
 
 /// Constants used in the app
 pub mod constants {
@@ -112,9 +308,82 @@ pub mod constants {
 		126, // Private data
 	];
 
+	/// Field numbers of reconciliation messages that are echoed in the response, the totals are
+	/// replaced with ours
+	pub const RECONCILIATION_ISO_MSG_FIELD_NUMBERS: [u32; 8] = [
+		0,  // Message Type Indicator or MTI
+		7,  // Transmission date
+		32, // Acquiring institution ID
+		74, // Number of credits
+		76, // Number of debits
+		86, // Amount of the credits
+		88, // Amount of the debits
+		97, // Net settlement amount, `C` or `D` followed by the amount
+	];
+
+	/// Settlement date field, `MMDD` business day of the settlement batch
+	pub const SETTLEMENT_DATE_FIELD_NUMBER: u32 = 15;
+
+	/// Settlement code field of reconciliation responses, whether the totals are in balance
+	pub const SETTLEMENT_CODE_FIELD_NUMBER: u32 = 66;
+
 	/// Response Code field
 	pub const RESPONSE_CODE_FIELD_NUMBER: u32 = 39;
 
+	/// System trace audit number (STAN) field, echoed in the response if present
+	pub const STAN_FIELD_NUMBER: u32 = 11;
+
+	/// Card acceptor terminal identification field, echoed in the response if present
+	pub const TERMINAL_ID_FIELD_NUMBER: u32 = 41;
+
+	/// Currency code field, numeric ISO-4217 code of the amount
+	pub const CURRENCY_CODE_FIELD_NUMBER: u32 = 49;
+
+	/// Cardholder billing amount field, the amount converted to the currency of the bank account
+	pub const BILLING_AMOUNT_FIELD_NUMBER: u32 = 6;
+
+	/// Cardholder billing conversion rate field, the rate the billing amount is converted with
+	pub const CONVERSION_RATE_FIELD_NUMBER: u32 = 10;
+
+	/// Private data field that carries the id of the on-chain event a message is composed from
+	pub const EVENT_ID_FIELD_NUMBER: u32 = 127;
+
+	/// Name of the block cursor of the watcher
+	pub const WATCHER_CURSOR_NAME: &str = "watcher";
+
+	/// Interval in seconds between sweeps of expired authorization holds
+	pub const HOLD_EXPIRY_INTERVAL_SECS: u64 = 60;
+
+	/// Interval in seconds between polls of the finality outbox
+	pub const OUTBOX_POLL_INTERVAL_SECS: u64 = 6;
+
+	/// Maximum number of finalities submitted per poll of the outbox
+	pub const OUTBOX_BATCH_SIZE: i64 = 50;
+
+	/// Time in seconds after which a submitted finality that is not finalized is retried
+	pub const FINALIZATION_TIMEOUT_SECS: i64 = 600;
+
+	/// Validity in months of renewed and reissued cards
+	pub const CARD_VALIDITY_MONTHS: u32 = 48;
+
+	/// Transaction type of a purchase, first two digits of the processing code (field 3)
+	pub const PURCHASE_TRANSACTION_TYPE: &str = "00";
+
+	/// JSON-RPC error code of requests with missing or invalid credentials
+	pub const UNAUTHORIZED_ERROR_CODE: i32 = -32001;
+
+	/// JSON-RPC error code of requests the client is not allowed to make
+	pub const FORBIDDEN_ERROR_CODE: i32 = -32003;
+
+	/// Interval in seconds between syncs of the offchain worker keys and the best block
+	pub const OCW_KEYS_SYNC_INTERVAL_SECS: u64 = 6;
+
+	/// API key of the merchant role injected in development mode
+	pub const DEV_API_KEY: &str = "dev-api-key";
+
+	/// Number of transaction events kept for subscribers lagging behind
+	pub const EVENT_BUS_CAPACITY: usize = 1024;
+
 	// Development accounts
 	pub const DEV_ACCOUNTS: [crate::types::DevAccount; 9] = [
 		// Healthy account